@@ -0,0 +1,247 @@
+use axum::body::to_bytes;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use serde_json::Value;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::task::AbortHandle;
+use tracing::debug;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::ResponsesToolCallKind;
+use crate::capture::Capture;
+use crate::metrics::RequestMetricsGuard;
+use crate::model::IncomingApi;
+use crate::response_utils::json_success_response;
+use crate::routing::RouteTarget;
+use crate::session::SessionTurn;
+use crate::state::AppState;
+
+#[derive(Default)]
+pub(crate) struct BackgroundResponses {
+    tasks: Mutex<HashMap<String, AbortHandle>>,
+}
+
+impl BackgroundResponses {
+    // Spawned under the lock so the task cannot deregister before its handle is recorded.
+    pub(crate) fn spawn<F>(&self, response_id: String, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock().unwrap_or_else(|err| err.into_inner());
+        let handle = tokio::spawn(task).abort_handle();
+        tasks.insert(response_id, handle);
+    }
+
+    // Exactly one of the task and a cancel request wins here and writes the final status.
+    fn take(&self, response_id: &str) -> Option<AbortHandle> {
+        self.tasks
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(response_id)
+    }
+
+    pub(crate) fn cancel(&self, response_id: &str) -> bool {
+        match self.take(response_id) {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub(crate) async fn update_pending_status(state: &AppState, response_id: &str, status: &str) {
+    let mut sessions = state.sessions.write().await;
+    let Some(mut record) = sessions.get_session(response_id) else {
+        return;
+    };
+    if !matches!(record.status(), Some("queued" | "in_progress")) {
+        return;
+    }
+    if let Some(response) = record.response.as_mut() {
+        response["status"] = json!(status);
+    }
+    if let Err(err) = sessions.store_session(record) {
+        warn!("session store write failed: response_id={response_id}, error={err:#}");
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn start_background_response(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    body: String,
+    incoming_path: Option<String>,
+    incoming_route: String,
+    route_target: RouteTarget,
+    request_value: Value,
+    tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
+    request_metrics: RequestMetricsGuard,
+    capture: Option<Arc<Capture>>,
+) -> Response {
+    let response_id = format!("resp_bridge_{}", Uuid::now_v7());
+    let (upstream_payload, session_turn) = match crate::build_upstream_payload_with_session(
+        &state,
+        &request_value,
+        IncomingApi::Responses,
+        &route_target,
+        false,
+        &response_id,
+    )
+    .await
+    {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+    let model = crate::upstream_payload_model(&upstream_payload);
+    let session_turn = session_turn.unwrap_or_else(|| {
+        SessionTurn::new(
+            state.sessions.clone(),
+            route_target.router_name.clone(),
+            response_id.clone(),
+            model.clone(),
+            upstream_payload
+                .get("messages")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default(),
+        )
+    });
+    let queued = json!({
+        "id": response_id,
+        "object": "response",
+        "created_at": session_turn.created_at(),
+        "status": "queued",
+        "background": true,
+        "model": model,
+        "output": [],
+        "usage": null,
+        "error": null,
+    });
+    session_turn.store_pending(queued.clone()).await;
+    debug!(
+        "background response queued: router={}, response_id={response_id}",
+        route_target.router_name
+    );
+
+    let task = run_background_response(
+        state.clone(),
+        headers,
+        body,
+        incoming_path,
+        incoming_route,
+        route_target,
+        request_value,
+        tool_call_kinds_by_name,
+        request_metrics,
+        capture,
+        session_turn,
+        queued.clone(),
+    );
+    state.background.spawn(response_id, task);
+    json_success_response(queued)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn run_background_response(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    body: String,
+    incoming_path: Option<String>,
+    incoming_route: String,
+    route_target: RouteTarget,
+    request_value: Value,
+    tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
+    request_metrics: RequestMetricsGuard,
+    capture: Option<Arc<Capture>>,
+    session_turn: SessionTurn,
+    mut response: Value,
+) {
+    let response_id = response["id"].as_str().unwrap_or_default().to_string();
+    update_pending_status(&state, &response_id, "in_progress").await;
+
+    let served = crate::forward_to_upstream_candidates(
+        &state,
+        &headers,
+        &body,
+        IncomingApi::Responses,
+        incoming_path.as_deref(),
+        &incoming_route,
+        &route_target,
+        &request_value,
+        false,
+        response_id.clone(),
+        tool_call_kinds_by_name,
+        request_metrics,
+        capture.as_deref(),
+        false,
+    )
+    .await;
+    let result = background_result(served).await;
+    if state.background.take(&response_id).is_none() {
+        // Cancelled while the upstream answered; the cancel request owns the status.
+        return;
+    }
+
+    match result {
+        Ok(mut completed) => {
+            completed["id"] = json!(response_id);
+            completed["created_at"] = response["created_at"].clone();
+            completed["background"] = json!(true);
+            debug!(
+                "background response completed: router={}, response_id={response_id}",
+                route_target.router_name
+            );
+            session_turn.complete_response(completed).await;
+        }
+        Err((code, message)) => {
+            warn!(
+                "background response failed: router={}, response_id={response_id}, code={code}, message={message}",
+                route_target.router_name
+            );
+            response["status"] = json!("failed");
+            response["error"] = json!({
+                "code": code,
+                "message": message,
+            });
+            session_turn.store_pending(response).await;
+        }
+    }
+}
+
+async fn background_result(served: Response) -> Result<Value, (String, String)> {
+    let body = to_bytes(served.into_body(), usize::MAX)
+        .await
+        .map_err(|err| {
+            (
+                "server_error".to_string(),
+                format!("failed to read upstream response: {err}"),
+            )
+        })?;
+    let value = serde_json::from_slice::<Value>(&body).map_err(|err| {
+        (
+            "upstream_decode_error".to_string(),
+            format!("failed to decode upstream JSON: {err}"),
+        )
+    })?;
+    match value.get("error").filter(|error| error.is_object()) {
+        Some(error) => Err((
+            error
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or("upstream_error")
+                .to_string(),
+            error
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        )),
+        None => Ok(value),
+    }
+}
@@ -0,0 +1,325 @@
+use anyhow::Result;
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::routing::RouteTarget;
+
+pub(crate) const DEFAULT_EJECT_AFTER_FAILURES: u32 = 3;
+pub(crate) const DEFAULT_EJECT_COOLDOWN_MS: u64 = 30_000;
+const MAX_STICKY_ENTRIES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LoadBalancingStrategy {
+    Failover,
+    RoundRobin,
+    Weighted,
+    LeastInFlight,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct LoadBalancingConfig {
+    pub(crate) strategy: Option<LoadBalancingStrategy>,
+    pub(crate) eject_after_failures: Option<u32>,
+    pub(crate) cooldown_ms: Option<u64>,
+    pub(crate) sticky_sessions: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LoadBalancingPolicy {
+    pub(crate) strategy: LoadBalancingStrategy,
+    pub(crate) eject_after_failures: u32,
+    pub(crate) cooldown: Duration,
+    pub(crate) sticky_sessions: bool,
+}
+
+impl LoadBalancingPolicy {
+    pub(crate) fn from_config(
+        config: Option<&LoadBalancingConfig>,
+        context: &str,
+    ) -> Result<Option<Self>> {
+        let Some(config) = config else {
+            return Ok(None);
+        };
+        let eject_after_failures = config
+            .eject_after_failures
+            .unwrap_or(DEFAULT_EJECT_AFTER_FAILURES);
+        if eject_after_failures == 0 {
+            return Err(anyhow!(
+                "{context} load_balancing.eject_after_failures must be at least 1"
+            ));
+        }
+        Ok(Some(Self {
+            strategy: config.strategy.unwrap_or(LoadBalancingStrategy::RoundRobin),
+            eject_after_failures,
+            cooldown: Duration::from_millis(
+                config.cooldown_ms.unwrap_or(DEFAULT_EJECT_COOLDOWN_MS),
+            ),
+            sticky_sessions: config.sticky_sessions.unwrap_or(true),
+        }))
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct LoadBalancer {
+    state: Mutex<BalancerState>,
+}
+
+#[derive(Debug, Default)]
+struct BalancerState {
+    routers: HashMap<String, RouterBalanceState>,
+    sticky_by_response_id: HashMap<String, (String, String)>,
+    sticky_order: VecDeque<String>,
+}
+
+#[derive(Debug, Default)]
+struct RouterBalanceState {
+    next_index: usize,
+    endpoints: HashMap<String, EndpointState>,
+}
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    in_flight: usize,
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+    current_weight: i64,
+}
+
+pub(crate) struct InFlightGuard {
+    balancer: Arc<LoadBalancer>,
+    router_name: String,
+    upstream_url: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut state = self.balancer.lock();
+        if let Some(endpoint) = state
+            .routers
+            .get_mut(&self.router_name)
+            .and_then(|router| router.endpoints.get_mut(&self.upstream_url))
+        {
+            endpoint.in_flight = endpoint.in_flight.saturating_sub(1);
+        }
+    }
+}
+
+impl LoadBalancer {
+    fn lock(&self) -> std::sync::MutexGuard<'_, BalancerState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn order_candidates(
+        &self,
+        target: &RouteTarget,
+        previous_response_id: Option<&str>,
+    ) -> Vec<RouteTarget> {
+        let mut primary = target.clone();
+        let fallbacks = std::mem::take(&mut primary.fallbacks);
+        let mut candidates = std::iter::once(primary)
+            .chain(fallbacks)
+            .collect::<Vec<_>>();
+        let Some(policy) = target.load_balancing.as_ref() else {
+            return candidates;
+        };
+
+        let now = Instant::now();
+        let mut state = self.lock();
+        let sticky_url = previous_response_id
+            .filter(|_| policy.sticky_sessions)
+            .and_then(|id| state.sticky_by_response_id.get(id))
+            .filter(|(router_name, _)| *router_name == target.router_name)
+            .map(|(_, url)| url.clone());
+        let router = state.routers.entry(target.router_name.clone()).or_default();
+
+        let is_healthy = |router: &RouterBalanceState, url: &str| {
+            router
+                .endpoints
+                .get(url)
+                .and_then(|endpoint| endpoint.ejected_until)
+                .is_none_or(|until| until <= now)
+        };
+        let (mut healthy, ejected): (Vec<_>, Vec<_>) = candidates
+            .drain(..)
+            .partition(|candidate| is_healthy(router, &candidate.upstream_url));
+
+        if let Some(position) = sticky_url.and_then(|url| {
+            healthy
+                .iter()
+                .position(|candidate| candidate.upstream_url == url)
+        }) {
+            let sticky = healthy.remove(position);
+            healthy.insert(0, sticky);
+        } else if !healthy.is_empty() {
+            match policy.strategy {
+                LoadBalancingStrategy::Failover => {}
+                LoadBalancingStrategy::RoundRobin => {
+                    let offset = router.next_index % healthy.len();
+                    router.next_index = router.next_index.wrapping_add(1);
+                    healthy.rotate_left(offset);
+                }
+                LoadBalancingStrategy::Weighted => {
+                    if let Some(position) = pick_weighted(router, &healthy) {
+                        let picked = healthy.remove(position);
+                        healthy.insert(0, picked);
+                    }
+                }
+                LoadBalancingStrategy::LeastInFlight => {
+                    healthy.sort_by_key(|candidate| {
+                        router
+                            .endpoints
+                            .get(&candidate.upstream_url)
+                            .map_or(0, |endpoint| endpoint.in_flight)
+                    });
+                }
+            }
+        }
+
+        healthy.extend(ejected);
+        healthy
+    }
+
+    pub(crate) fn start_request(
+        self: &Arc<Self>,
+        router_name: &str,
+        upstream_url: &str,
+    ) -> InFlightGuard {
+        self.lock()
+            .routers
+            .entry(router_name.to_string())
+            .or_default()
+            .endpoints
+            .entry(upstream_url.to_string())
+            .or_default()
+            .in_flight += 1;
+        InFlightGuard {
+            balancer: self.clone(),
+            router_name: router_name.to_string(),
+            upstream_url: upstream_url.to_string(),
+        }
+    }
+
+    pub(crate) fn record_success(&self, router_name: &str, upstream_url: &str) {
+        let mut state = self.lock();
+        let endpoint = state
+            .routers
+            .entry(router_name.to_string())
+            .or_default()
+            .endpoints
+            .entry(upstream_url.to_string())
+            .or_default();
+        endpoint.consecutive_failures = 0;
+        endpoint.ejected_until = None;
+    }
+
+    pub(crate) fn record_failure(
+        &self,
+        router_name: &str,
+        upstream_url: &str,
+        policy: &LoadBalancingPolicy,
+    ) -> bool {
+        let mut state = self.lock();
+        let endpoint = state
+            .routers
+            .entry(router_name.to_string())
+            .or_default()
+            .endpoints
+            .entry(upstream_url.to_string())
+            .or_default();
+        endpoint.consecutive_failures = endpoint.consecutive_failures.saturating_add(1);
+        if endpoint.consecutive_failures < policy.eject_after_failures {
+            return false;
+        }
+        endpoint.consecutive_failures = 0;
+        endpoint.ejected_until = Some(Instant::now() + policy.cooldown);
+        true
+    }
+
+    pub(crate) fn retain_endpoints(&self, endpoints: &HashMap<String, HashSet<String>>) {
+        let mut state = self.lock();
+        state
+            .routers
+            .retain(|router_name, router| match endpoints.get(router_name) {
+                Some(urls) => {
+                    router.endpoints.retain(|url, _| urls.contains(url));
+                    true
+                }
+                None => false,
+            });
+        let BalancerState {
+            sticky_by_response_id,
+            sticky_order,
+            ..
+        } = &mut *state;
+        sticky_by_response_id.retain(|_, (router_name, url)| {
+            endpoints
+                .get(router_name)
+                .is_some_and(|urls| urls.contains(url))
+        });
+        sticky_order.retain(|id| sticky_by_response_id.contains_key(id));
+    }
+
+    pub(crate) fn remember_response(
+        &self,
+        response_id: &str,
+        router_name: &str,
+        upstream_url: &str,
+    ) {
+        let mut state = self.lock();
+        let previous = state.sticky_by_response_id.insert(
+            response_id.to_string(),
+            (router_name.to_string(), upstream_url.to_string()),
+        );
+        if previous.is_some() {
+            state.sticky_order.retain(|id| id != response_id);
+        }
+        state.sticky_order.push_back(response_id.to_string());
+        while state.sticky_by_response_id.len() > MAX_STICKY_ENTRIES {
+            let Some(oldest_id) = state.sticky_order.pop_front() else {
+                break;
+            };
+            state.sticky_by_response_id.remove(&oldest_id);
+        }
+    }
+}
+
+fn pick_weighted(router: &mut RouterBalanceState, candidates: &[RouteTarget]) -> Option<usize> {
+    let total = candidates
+        .iter()
+        .map(|candidate| i64::from(candidate.weight))
+        .sum::<i64>();
+    if total == 0 {
+        return None;
+    }
+
+    let mut best: Option<(usize, i64)> = None;
+    for (position, candidate) in candidates.iter().enumerate() {
+        if candidate.weight == 0 {
+            continue;
+        }
+        let endpoint = router
+            .endpoints
+            .entry(candidate.upstream_url.clone())
+            .or_default();
+        endpoint.current_weight += i64::from(candidate.weight);
+        if best.is_none_or(|(_, weight)| endpoint.current_weight > weight) {
+            best = Some((position, endpoint.current_weight));
+        }
+    }
+
+    let (position, _) = best?;
+    if let Some(endpoint) = router.endpoints.get_mut(&candidates[position].upstream_url) {
+        endpoint.current_weight -= total;
+    }
+    Some(position)
+}
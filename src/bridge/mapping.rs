@@ -17,6 +17,58 @@ pub(crate) fn map_chat_to_responses_request(request: &Value, stream: bool) -> Re
         .and_then(Value::as_array)
         .ok_or_else(|| anyhow!("missing `messages` array"))?;
 
+    let input = chat_messages_to_responses_input(messages);
+
+    let tools = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .map(|v| normalize_responses_tools(v.clone()))
+        .unwrap_or_default();
+    let tool_choice = request
+        .get("tool_choice")
+        .cloned()
+        .map(normalize_responses_tool_choice)
+        .unwrap_or_else(|| Value::String("auto".to_string()));
+    let parallel_tool_calls = request
+        .get("parallel_tool_calls")
+        .and_then(Value::as_bool)
+        .unwrap_or(true);
+
+    let mut out = json!({
+        "model": model,
+        "input": input,
+        "stream": stream,
+        "tools": tools,
+        "tool_choice": tool_choice,
+        "parallel_tool_calls": parallel_tool_calls,
+    });
+
+    if let Some(response_format) = request.get("response_format").cloned()
+        && !response_format.is_null()
+        && let Some(obj) = out.as_object_mut()
+    {
+        obj.insert(
+            "text".to_string(),
+            json!({
+                "format": response_format,
+            }),
+        );
+    }
+
+    if out
+        .get("tools")
+        .and_then(Value::as_array)
+        .is_some_and(Vec::is_empty)
+        && let Some(obj) = out.as_object_mut()
+    {
+        obj.remove("tools");
+        obj.remove("tool_choice");
+    }
+
+    Ok(out)
+}
+
+pub(crate) fn chat_messages_to_responses_input(messages: &[Value]) -> Vec<Value> {
     let mut input = Vec::new();
     for (message_index, message) in messages.iter().enumerate() {
         let role = message
@@ -85,55 +137,7 @@ pub(crate) fn map_chat_to_responses_request(request: &Value, stream: bool) -> Re
             "content": content,
         }));
     }
-
-    let tools = request
-        .get("tools")
-        .and_then(Value::as_array)
-        .map(|v| normalize_responses_tools(v.clone()))
-        .unwrap_or_default();
-    let tool_choice = request
-        .get("tool_choice")
-        .cloned()
-        .map(normalize_responses_tool_choice)
-        .unwrap_or_else(|| Value::String("auto".to_string()));
-    let parallel_tool_calls = request
-        .get("parallel_tool_calls")
-        .and_then(Value::as_bool)
-        .unwrap_or(true);
-
-    let mut out = json!({
-        "model": model,
-        "input": input,
-        "stream": stream,
-        "tools": tools,
-        "tool_choice": tool_choice,
-        "parallel_tool_calls": parallel_tool_calls,
-    });
-
-    if let Some(response_format) = request.get("response_format").cloned()
-        && !response_format.is_null()
-        && let Some(obj) = out.as_object_mut()
-    {
-        obj.insert(
-            "text".to_string(),
-            json!({
-                "format": response_format,
-            }),
-        );
-    }
-
-    if out
-        .get("tools")
-        .and_then(Value::as_array)
-        .is_some_and(Vec::is_empty)
-    {
-        if let Some(obj) = out.as_object_mut() {
-            obj.remove("tools");
-            obj.remove("tool_choice");
-        }
-    }
-
-    Ok(out)
+    input
 }
 
 pub(crate) fn map_anthropic_messages_to_chat_request(
@@ -537,14 +541,13 @@ fn anthropic_user_message_to_chat_messages(content: Option<&Value>) -> Vec<Value
         };
 
     match content {
-        Some(Value::String(text)) => {
-            if !text.trim().is_empty() {
-                messages.push(json!({
-                    "role": "user",
-                    "content": text,
-                }));
-            }
+        Some(Value::String(text)) if !text.trim().is_empty() => {
+            messages.push(json!({
+                "role": "user",
+                "content": text,
+            }));
         }
+        Some(Value::String(_)) => {}
         Some(Value::Array(items)) => {
             for item in items {
                 match item.get("type").and_then(Value::as_str).unwrap_or_default() {
@@ -914,12 +917,11 @@ fn normalize_anthropic_tool_parameters(tool_name: &str, mut parameters: Value) -
     obj.entry("properties".to_string())
         .or_insert_with(|| json!({}));
 
-    if tool_name == "ExitPlanMode" {
-        obj.insert("additionalProperties".to_string(), Value::Bool(false));
-    } else if obj
-        .get("additionalProperties")
-        .and_then(Value::as_object)
-        .is_some_and(serde_json::Map::is_empty)
+    if tool_name == "ExitPlanMode"
+        || obj
+            .get("additionalProperties")
+            .and_then(Value::as_object)
+            .is_some_and(serde_json::Map::is_empty)
     {
         obj.insert("additionalProperties".to_string(), Value::Bool(false));
     }
@@ -1079,18 +1081,17 @@ pub(crate) fn chat_json_to_responses_json(
                         &call_id,
                         tool_call_kinds_by_name,
                     );
-                    if enable_provider_specific_fields {
-                        if let Some(provider_specific_fields) = tc
+                    if enable_provider_specific_fields
+                        && let Some(provider_specific_fields) = tc
                             .get("provider_specific_fields")
                             .cloned()
                             .or_else(|| function.get("provider_specific_fields").cloned())
-                            && let Some(obj) = item.as_object_mut()
-                        {
-                            obj.insert(
-                                "provider_specific_fields".to_string(),
-                                provider_specific_fields,
-                            );
-                        }
+                        && let Some(obj) = item.as_object_mut()
+                    {
+                        obj.insert(
+                            "provider_specific_fields".to_string(),
+                            provider_specific_fields,
+                        );
                     }
 
                     output_items.push(item);
@@ -1116,19 +1117,94 @@ pub(crate) fn chat_json_to_responses_json(
         "output": output_items,
         "usage": usage_json,
     });
-    if enable_provider_specific_fields {
-        if let Some(provider_specific_fields) = chat.get("provider_specific_fields").cloned()
-            && let Some(obj) = response.as_object_mut()
-        {
-            obj.insert(
-                "provider_specific_fields".to_string(),
-                provider_specific_fields,
-            );
-        }
+    if enable_provider_specific_fields
+        && let Some(provider_specific_fields) = chat.get("provider_specific_fields").cloned()
+        && let Some(obj) = response.as_object_mut()
+    {
+        obj.insert(
+            "provider_specific_fields".to_string(),
+            provider_specific_fields,
+        );
     }
     response
 }
 
+pub(crate) fn assistant_chat_message_from_responses_output(output: &[Value]) -> Option<Value> {
+    let mut text_parts = Vec::new();
+    let mut reasoning_parts = Vec::new();
+    let mut tool_calls = Vec::new();
+
+    for item in output {
+        match item.get("type").and_then(Value::as_str).unwrap_or_default() {
+            "message" => text_parts.extend(
+                item.get("content")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part.get("text").and_then(Value::as_str))
+                    .filter(|text| !text.is_empty())
+                    .map(ToString::to_string),
+            ),
+            "reasoning" => reasoning_parts.extend(
+                item.get("summary")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part.get("text").and_then(Value::as_str))
+                    .filter(|text| !text.trim().is_empty())
+                    .map(ToString::to_string),
+            ),
+            "function_call" | "custom_tool_call" => {
+                let mut tool_call = json!({
+                    "id": item.get("call_id").and_then(Value::as_str).unwrap_or_default(),
+                    "type": "function",
+                    "function": {
+                        "name": item.get("name").and_then(Value::as_str).unwrap_or("unknown_function"),
+                        "arguments": item
+                            .get("arguments")
+                            .or_else(|| item.get("input"))
+                            .map(function_arguments_to_text)
+                            .unwrap_or_else(|| "{}".to_string()),
+                    }
+                });
+                if let Some(provider_specific_fields) = item.get("provider_specific_fields")
+                    && let Some(obj) = tool_call.as_object_mut()
+                {
+                    obj.insert(
+                        "provider_specific_fields".to_string(),
+                        provider_specific_fields.clone(),
+                    );
+                }
+                tool_calls.push(tool_call);
+            }
+            _ => {}
+        }
+    }
+
+    if text_parts.is_empty() && reasoning_parts.is_empty() && tool_calls.is_empty() {
+        return None;
+    }
+    let mut message = json!({
+        "role": "assistant",
+        "content": if text_parts.is_empty() {
+            Value::Null
+        } else {
+            Value::String(text_parts.join("\n"))
+        },
+    });
+    let obj = message.as_object_mut()?;
+    if !reasoning_parts.is_empty() {
+        obj.insert(
+            "reasoning_content".to_string(),
+            Value::String(reasoning_parts.join("\n\n")),
+        );
+    }
+    if !tool_calls.is_empty() {
+        obj.insert("tool_calls".to_string(), Value::Array(tool_calls));
+    }
+    Some(message)
+}
+
 pub(crate) fn responses_json_to_chat_json(response: Value, fallback_model: &str) -> Value {
     let mut content_parts = Vec::new();
     let mut tool_calls = Vec::new();
@@ -1226,85 +1302,84 @@ pub(crate) fn chat_json_to_anthropic_json(chat: Value, fallback_model: &str) ->
         .get("choices")
         .and_then(Value::as_array)
         .and_then(|items| items.first())
+        && let Some(message) = choice.get("message")
     {
-        if let Some(message) = choice.get("message") {
-            let has_tool_calls = message
-                .get("tool_calls")
-                .and_then(Value::as_array)
-                .is_some_and(|tool_calls| !tool_calls.is_empty());
-            stop_reason = if has_tool_calls {
-                Some("tool_use".to_string())
-            } else {
-                match choice.get("finish_reason").and_then(Value::as_str) {
-                    Some("stop") => Some("end_turn".to_string()),
-                    Some("length") => Some("max_tokens".to_string()),
-                    Some("tool_calls") => Some("tool_use".to_string()),
-                    Some(reason) if !reason.trim().is_empty() => Some("stop_sequence".to_string()),
-                    _ => None,
-                }
-            };
+        let has_tool_calls = message
+            .get("tool_calls")
+            .and_then(Value::as_array)
+            .is_some_and(|tool_calls| !tool_calls.is_empty());
+        stop_reason = if has_tool_calls {
+            Some("tool_use".to_string())
+        } else {
+            match choice.get("finish_reason").and_then(Value::as_str) {
+                Some("stop") => Some("end_turn".to_string()),
+                Some("length") => Some("max_tokens".to_string()),
+                Some("tool_calls") => Some("tool_use".to_string()),
+                Some(reason) if !reason.trim().is_empty() => Some("stop_sequence".to_string()),
+                _ => None,
+            }
+        };
 
-            if let Some(reasoning) = message.get("reasoning_content").and_then(Value::as_str)
-                && !reasoning.trim().is_empty()
-            {
-                content.push(json!({
-                    "type": "thinking",
-                    "thinking": reasoning,
-                }));
-                if let Some(signature) = message.get("signature").and_then(Value::as_str)
-                    && !signature.trim().is_empty()
-                    && let Some(last) = content.last_mut()
-                    && let Some(obj) = last.as_object_mut()
-                {
-                    obj.insert(
-                        "signature".to_string(),
-                        Value::String(signature.to_string()),
-                    );
-                }
-            } else if let Some(signature) = message.get("signature").and_then(Value::as_str)
+        if let Some(reasoning) = message.get("reasoning_content").and_then(Value::as_str)
+            && !reasoning.trim().is_empty()
+        {
+            content.push(json!({
+                "type": "thinking",
+                "thinking": reasoning,
+            }));
+            if let Some(signature) = message.get("signature").and_then(Value::as_str)
                 && !signature.trim().is_empty()
+                && let Some(last) = content.last_mut()
+                && let Some(obj) = last.as_object_mut()
             {
+                obj.insert(
+                    "signature".to_string(),
+                    Value::String(signature.to_string()),
+                );
+            }
+        } else if let Some(signature) = message.get("signature").and_then(Value::as_str)
+            && !signature.trim().is_empty()
+        {
+            content.push(json!({
+                "type": "thinking",
+                "thinking": "",
+                "signature": signature,
+            }));
+        }
+
+        if let Some(text) = message.get("content").and_then(Value::as_str) {
+            if !text.trim().is_empty() {
                 content.push(json!({
-                    "type": "thinking",
-                    "thinking": "",
-                    "signature": signature,
+                    "type": "text",
+                    "text": text,
                 }));
             }
-
-            if let Some(text) = message.get("content").and_then(Value::as_str) {
-                if !text.trim().is_empty() {
-                    content.push(json!({
-                        "type": "text",
-                        "text": text,
-                    }));
-                }
-            } else {
-                let text = message
-                    .get("content")
-                    .map(function_output_to_text)
-                    .unwrap_or_default();
-                if !text.trim().is_empty() {
-                    content.push(json!({
-                        "type": "text",
-                        "text": text,
-                    }));
-                }
+        } else {
+            let text = message
+                .get("content")
+                .map(function_output_to_text)
+                .unwrap_or_default();
+            if !text.trim().is_empty() {
+                content.push(json!({
+                    "type": "text",
+                    "text": text,
+                }));
             }
+        }
 
-            if let Some(tool_calls) = message.get("tool_calls").and_then(Value::as_array) {
-                for tc in tool_calls {
-                    let arguments = tc
-                        .get("function")
-                        .and_then(|f| f.get("arguments"))
-                        .cloned()
-                        .unwrap_or_else(|| json!({}));
-                    content.push(json!({
+        if let Some(tool_calls) = message.get("tool_calls").and_then(Value::as_array) {
+            for tc in tool_calls {
+                let arguments = tc
+                    .get("function")
+                    .and_then(|f| f.get("arguments"))
+                    .cloned()
+                    .unwrap_or_else(|| json!({}));
+                content.push(json!({
                         "type": "tool_use",
                         "id": tc.get("id").and_then(Value::as_str).unwrap_or_default(),
                         "name": tc.get("function").and_then(|f| f.get("name")).and_then(Value::as_str).unwrap_or("unknown_function"),
                         "input": parse_json_or_string(arguments),
                     }));
-                }
             }
         }
     }
@@ -1692,11 +1767,10 @@ pub(crate) fn map_responses_to_chat_request_with_stream(
         .get("tools")
         .and_then(Value::as_array)
         .is_some_and(Vec::is_empty)
+        && let Some(obj) = chat_request.as_object_mut()
     {
-        if let Some(obj) = chat_request.as_object_mut() {
-            obj.remove("tools");
-            obj.remove("tool_choice");
-        }
+        obj.remove("tools");
+        obj.remove("tool_choice");
     }
 
     apply_responses_request_extensions(request, &mut chat_request)?;
@@ -2479,6 +2553,1473 @@ pub(crate) fn normalize_tool_choice(
     Value::String("auto".to_string())
 }
 
+const DEFAULT_ANTHROPIC_MAX_TOKENS: u64 = 8192;
+const MIN_ANTHROPIC_THINKING_BUDGET: u64 = 1024;
+
+pub(crate) fn map_responses_to_anthropic_messages_request(
+    request: &Value,
+    stream: bool,
+) -> Result<Value> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("missing `model`"))?;
+
+    let mut system_parts = Vec::new();
+    if let Some(instructions) = request.get("instructions").and_then(Value::as_str)
+        && !instructions.trim().is_empty()
+    {
+        system_parts.push(instructions.to_string());
+    }
+
+    let mut messages = Vec::new();
+    let input_items = match request
+        .get("input")
+        .ok_or_else(|| anyhow!("missing `input`"))?
+    {
+        Value::String(text) => {
+            if !text.trim().is_empty() {
+                push_anthropic_block(&mut messages, "user", json!({"type":"text","text": text}));
+            }
+            &[][..]
+        }
+        Value::Array(input_items) => input_items.as_slice(),
+        _ => return Err(anyhow!("`input` must be a string or array")),
+    };
+
+    for item in input_items {
+        let item_type = item.get("type").and_then(Value::as_str).unwrap_or_default();
+        match item_type {
+            "message" => {
+                let role = item.get("role").and_then(Value::as_str).unwrap_or("user");
+                let blocks = responses_content_to_anthropic_blocks(item.get("content"));
+                if matches!(role, "system" | "developer") {
+                    system_parts.extend(
+                        blocks
+                            .iter()
+                            .filter_map(|block| block.get("text").and_then(Value::as_str))
+                            .map(ToString::to_string),
+                    );
+                    continue;
+                }
+                let role = if role == "assistant" {
+                    "assistant"
+                } else {
+                    "user"
+                };
+                for block in blocks {
+                    push_anthropic_block(&mut messages, role, block);
+                }
+            }
+            "reasoning" => {
+                // Only signatures this bridge got from Anthropic are replayed.
+                let Some(signature) = item
+                    .get("encrypted_content")
+                    .and_then(Value::as_str)
+                    .and_then(|content| content.strip_prefix(ANTHROPIC_SIGNATURE_PREFIX))
+                    .filter(|signature| !signature.trim().is_empty())
+                else {
+                    continue;
+                };
+                let thinking = item
+                    .get("summary")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|part| part.get("text").and_then(Value::as_str))
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                push_anthropic_block(
+                    &mut messages,
+                    "assistant",
+                    json!({
+                        "type": "thinking",
+                        "thinking": thinking,
+                        "signature": signature,
+                    }),
+                );
+            }
+            "function_call" | "custom_tool_call" => {
+                let name = item.get("name").and_then(Value::as_str).unwrap_or_default();
+                if name.is_empty() {
+                    warn!("ignoring {item_type} item with empty name");
+                    continue;
+                }
+                let call_id = item
+                    .get("call_id")
+                    .and_then(Value::as_str)
+                    .filter(|v| !v.trim().is_empty())
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| format!("call_{}", Uuid::now_v7()));
+                let input = if item_type == "custom_tool_call" {
+                    json!({
+                        "input": item
+                            .get("input")
+                            .or_else(|| item.get("arguments"))
+                            .map(function_arguments_to_text)
+                            .unwrap_or_default(),
+                    })
+                } else {
+                    anthropic_tool_input_from_arguments(item.get("arguments"))
+                };
+                push_anthropic_block(
+                    &mut messages,
+                    "assistant",
+                    json!({
+                        "type": "tool_use",
+                        "id": call_id,
+                        "name": name,
+                        "input": input,
+                    }),
+                );
+            }
+            "function_call_output" | "custom_tool_call_output" => {
+                let call_id = item
+                    .get("call_id")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let output = item
+                    .get("output")
+                    .map(function_output_to_text)
+                    .unwrap_or_default();
+                push_anthropic_block(
+                    &mut messages,
+                    "user",
+                    json!({
+                        "type": "tool_result",
+                        "tool_use_id": call_id,
+                        "content": output,
+                    }),
+                );
+            }
+            _ => {
+                warn!("ignoring unsupported input item type for messages upstream: {item_type}");
+            }
+        }
+    }
+
+    let max_tokens = request
+        .get("max_output_tokens")
+        .and_then(Value::as_u64)
+        .unwrap_or(DEFAULT_ANTHROPIC_MAX_TOKENS);
+    let mut payload = json!({
+        "model": model,
+        "messages": messages,
+        "max_tokens": max_tokens,
+        "stream": stream,
+    });
+    let obj = payload
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("anthropic payload must be an object"))?;
+    if !system_parts.is_empty() {
+        obj.insert(
+            "system".to_string(),
+            Value::String(system_parts.join("\n\n")),
+        );
+    }
+
+    let tools = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .map(|tools| responses_tools_to_anthropic_tools(tools))
+        .unwrap_or_default();
+    if !tools.is_empty() {
+        let parallel_tool_calls = request
+            .get("parallel_tool_calls")
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+        if let Some(tool_choice) = anthropic_tool_choice(
+            request.get("tool_choice").unwrap_or(&Value::Null),
+            parallel_tool_calls,
+        ) {
+            obj.insert("tool_choice".to_string(), tool_choice);
+        }
+        obj.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    let effort = request
+        .get("reasoning")
+        .and_then(|reasoning| reasoning.get("effort"))
+        .and_then(Value::as_str);
+    insert_anthropic_thinking_or_sampling(obj, request, effort, max_tokens);
+
+    Ok(payload)
+}
+
+fn insert_anthropic_thinking_or_sampling(
+    obj: &mut serde_json::Map<String, Value>,
+    request: &Value,
+    effort: Option<&str>,
+    max_tokens: u64,
+) {
+    let thinking_budget = effort
+        .and_then(anthropic_thinking_budget_for_effort)
+        .map(|budget| budget.min(max_tokens.saturating_sub(1)))
+        .filter(|budget| *budget >= MIN_ANTHROPIC_THINKING_BUDGET);
+    if let Some(budget_tokens) = thinking_budget {
+        obj.insert(
+            "thinking".to_string(),
+            json!({"type": "enabled", "budget_tokens": budget_tokens}),
+        );
+    } else {
+        // Anthropic rejects sampling overrides while extended thinking is enabled.
+        for field in ["temperature", "top_p"] {
+            if let Some(value) = request.get(field).filter(|value| value.is_number()) {
+                obj.insert(field.to_string(), value.clone());
+            }
+        }
+    }
+}
+
+fn push_anthropic_block(messages: &mut Vec<Value>, role: &str, block: Value) {
+    if let Some(last) = messages.last_mut()
+        && last.get("role").and_then(Value::as_str) == Some(role)
+        && let Some(content) = last.get_mut("content").and_then(Value::as_array_mut)
+    {
+        content.push(block);
+        return;
+    }
+    messages.push(json!({
+        "role": role,
+        "content": [block],
+    }));
+}
+
+fn responses_content_to_anthropic_blocks(content: Option<&Value>) -> Vec<Value> {
+    match content {
+        Some(Value::String(text)) if !text.trim().is_empty() => {
+            vec![json!({"type":"text","text": text})]
+        }
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|item| {
+                let item_type = item.get("type").and_then(Value::as_str).unwrap_or_default();
+                if matches!(item_type, "input_text" | "output_text" | "text")
+                    && let Some(text) = item.get("text").and_then(Value::as_str)
+                {
+                    return (!text.is_empty()).then(|| json!({"type":"text","text": text}));
+                }
+                if item_type == "input_image"
+                    && let Some(block) =
+                        responses_input_image_to_chat_content_part(item).and_then(|part| {
+                            part.pointer("/image_url/url")
+                                .and_then(Value::as_str)
+                                .map(anthropic_image_block_from_url)
+                        })
+                {
+                    return Some(block);
+                }
+                responses_content_item_fallback_text(item)
+                    .map(|text| json!({"type":"text","text": text}))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn anthropic_image_block_from_url(url: &str) -> Value {
+    if let Some((media_type, data)) = url
+        .strip_prefix("data:")
+        .and_then(|rest| rest.split_once(";base64,"))
+    {
+        return json!({
+            "type": "image",
+            "source": {"type": "base64", "media_type": media_type, "data": data},
+        });
+    }
+    json!({
+        "type": "image",
+        "source": {"type": "url", "url": url},
+    })
+}
+
+fn anthropic_tool_input_from_arguments(arguments: Option<&Value>) -> Value {
+    let arguments = arguments
+        .map(function_arguments_to_json_repaired_text)
+        .unwrap_or_else(|| "{}".to_string());
+    match serde_json::from_str::<Value>(&arguments) {
+        Ok(input @ Value::Object(_)) => input,
+        _ => {
+            warn!("tool call arguments are not a JSON object; sending empty tool_use input");
+            json!({})
+        }
+    }
+}
+
+fn anthropic_tool(name: &str, description: &str, parameters: Value) -> Value {
+    let mut tool = json!({
+        "name": name,
+        "input_schema": normalize_anthropic_tool_parameters(name, parameters),
+    });
+    if !description.is_empty()
+        && let Some(obj) = tool.as_object_mut()
+    {
+        obj.insert(
+            "description".to_string(),
+            Value::String(description.to_string()),
+        );
+    }
+    tool
+}
+
+fn responses_tools_to_anthropic_tools(tools: &[Value]) -> Vec<Value> {
+    let mut anthropic_tools = Vec::new();
+    for tool in tools {
+        let tool_type = tool.get("type").and_then(Value::as_str).unwrap_or_default();
+        match tool_type {
+            "function" | "custom" => {
+                let Some(name) = function_tool_name(tool) else {
+                    continue;
+                };
+                let function = tool.get("function");
+                let description = function
+                    .and_then(|f| f.get("description"))
+                    .or_else(|| tool.get("description"))
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let parameters = function
+                    .and_then(|f| f.get("parameters"))
+                    .or_else(|| tool.get("parameters"))
+                    .cloned();
+                let parameters = if tool_type == "custom" {
+                    normalize_custom_tool_parameters(parameters)
+                } else {
+                    parameters.unwrap_or_else(default_function_tool_parameters)
+                };
+                anthropic_tools.push(anthropic_tool(&name, description, parameters));
+            }
+            "namespace" => {
+                for function in normalize_namespace_tool_to_chat_functions(tool) {
+                    let function = &function["function"];
+                    anthropic_tools.push(anthropic_tool(
+                        function["name"].as_str().unwrap_or_default(),
+                        function["description"].as_str().unwrap_or_default(),
+                        function["parameters"].clone(),
+                    ));
+                }
+            }
+            _ => warn!("dropping tool type unsupported by messages upstream: {tool_type}"),
+        }
+    }
+    anthropic_tools
+}
+
+fn anthropic_tool_choice(tool_choice: &Value, parallel_tool_calls: bool) -> Option<Value> {
+    let mut choice = match tool_choice {
+        Value::String(mode) => match mode.as_str() {
+            "required" => json!({"type": "any"}),
+            "none" => json!({"type": "none"}),
+            _ => json!({"type": "auto"}),
+        },
+        Value::Object(obj) => {
+            let name = obj
+                .get("function")
+                .and_then(|f| f.get("name"))
+                .or_else(|| obj.get("name"))
+                .and_then(Value::as_str);
+            match name {
+                Some(name) => json!({"type": "tool", "name": name}),
+                None => json!({"type": "auto"}),
+            }
+        }
+        _ => json!({"type": "auto"}),
+    };
+    if !parallel_tool_calls && choice["type"] != "none" {
+        choice["disable_parallel_tool_use"] = Value::Bool(true);
+    }
+    (choice != json!({"type": "auto"})).then_some(choice)
+}
+
+fn anthropic_thinking_budget_for_effort(effort: &str) -> Option<u64> {
+    match effort {
+        "minimal" | "low" => Some(MIN_ANTHROPIC_THINKING_BUDGET),
+        "medium" => Some(4096),
+        "high" => Some(16384),
+        _ => None,
+    }
+}
+
+pub(crate) fn anthropic_usage_to_responses_usage(usage: Option<&Value>) -> Option<Value> {
+    let usage = usage?;
+    let tokens = |key: &str| usage.get(key).and_then(Value::as_i64).unwrap_or(0);
+    let cached_tokens = tokens("cache_read_input_tokens");
+    let input_tokens =
+        tokens("input_tokens") + tokens("cache_creation_input_tokens") + cached_tokens;
+    let output_tokens = tokens("output_tokens");
+    Some(json!({
+        "input_tokens": input_tokens,
+        "input_tokens_details": {"cached_tokens": cached_tokens},
+        "output_tokens": output_tokens,
+        "output_tokens_details": null,
+        "total_tokens": input_tokens + output_tokens,
+    }))
+}
+
+const ANTHROPIC_SIGNATURE_PREFIX: &str = "anthropic-signature:";
+
+pub(crate) fn anthropic_thinking_to_reasoning_item(
+    id: &str,
+    thinking: &str,
+    signature: Option<&str>,
+) -> Value {
+    let mut item = json!({
+        "type": "reasoning",
+        "id": id,
+        "summary": [{
+            "type": "summary_text",
+            "text": thinking,
+        }],
+    });
+    if let Some(signature) = signature.filter(|signature| !signature.is_empty()) {
+        item["encrypted_content"] =
+            Value::String(format!("{ANTHROPIC_SIGNATURE_PREFIX}{signature}"));
+    }
+    item
+}
+
+pub(crate) fn anthropic_json_to_responses_json(
+    message: Value,
+    response_id: String,
+    tool_call_kinds_by_name: &HashMap<String, ResponsesToolCallKind>,
+) -> Value {
+    let mut output_items = Vec::new();
+    for (index, block) in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        match block
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+        {
+            "text" => {
+                let text = block
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                if !text.is_empty() {
+                    output_items.push(json!({
+                        "type": "message",
+                        "role": "assistant",
+                        "content": [{"type":"output_text","text": text}],
+                    }));
+                }
+            }
+            "thinking" => output_items.push(anthropic_thinking_to_reasoning_item(
+                &format!("rs_{response_id}_{index}"),
+                block
+                    .get("thinking")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                block.get("signature").and_then(Value::as_str),
+            )),
+            "tool_use" => {
+                let arguments = block
+                    .get("input")
+                    .map(Value::to_string)
+                    .unwrap_or_else(|| "{}".to_string());
+                output_items.push(responses_tool_call_item(
+                    block
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .unwrap_or("unknown_function"),
+                    &arguments,
+                    block.get("id").and_then(Value::as_str).unwrap_or_default(),
+                    tool_call_kinds_by_name,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    let status = match message.get("stop_reason").and_then(Value::as_str) {
+        Some("max_tokens") => "incomplete",
+        _ => "completed",
+    };
+    json!({
+        "id": response_id,
+        "object": "response",
+        "status": status,
+        "output": output_items,
+        "usage": anthropic_usage_to_responses_usage(message.get("usage")),
+    })
+}
+
+pub(crate) fn map_chat_to_anthropic_messages_request(
+    request: &Value,
+    stream: bool,
+) -> Result<Value> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("missing `model`"))?;
+    let chat_messages = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("`messages` must be an array"))?;
+
+    let mut system_parts = Vec::new();
+    let mut messages = Vec::new();
+    for message in chat_messages {
+        let role = message
+            .get("role")
+            .and_then(Value::as_str)
+            .unwrap_or("user");
+        match role {
+            "system" | "developer" => {
+                system_parts.extend(
+                    chat_content_to_anthropic_blocks(message.get("content"))
+                        .iter()
+                        .filter_map(|block| block.get("text").and_then(Value::as_str))
+                        .map(ToString::to_string),
+                );
+            }
+            "assistant" => {
+                for block in chat_content_to_anthropic_blocks(message.get("content")) {
+                    push_anthropic_block(&mut messages, "assistant", block);
+                }
+                for tool_call in message
+                    .get("tool_calls")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    let function = tool_call.get("function");
+                    let name = function
+                        .and_then(|f| f.get("name"))
+                        .and_then(Value::as_str)
+                        .unwrap_or_default();
+                    if name.is_empty() {
+                        warn!("ignoring assistant tool call with empty name");
+                        continue;
+                    }
+                    let id = tool_call
+                        .get("id")
+                        .and_then(Value::as_str)
+                        .filter(|v| !v.trim().is_empty())
+                        .map(ToString::to_string)
+                        .unwrap_or_else(|| format!("call_{}", Uuid::now_v7()));
+                    push_anthropic_block(
+                        &mut messages,
+                        "assistant",
+                        json!({
+                            "type": "tool_use",
+                            "id": id,
+                            "name": name,
+                            "input": anthropic_tool_input_from_arguments(
+                                function.and_then(|f| f.get("arguments")),
+                            ),
+                        }),
+                    );
+                }
+            }
+            "tool" | "function" => {
+                let tool_use_id = message
+                    .get("tool_call_id")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let output = message
+                    .get("content")
+                    .map(function_output_to_text)
+                    .unwrap_or_default();
+                push_anthropic_block(
+                    &mut messages,
+                    "user",
+                    json!({
+                        "type": "tool_result",
+                        "tool_use_id": tool_use_id,
+                        "content": output,
+                    }),
+                );
+            }
+            _ => {
+                for block in chat_content_to_anthropic_blocks(message.get("content")) {
+                    push_anthropic_block(&mut messages, "user", block);
+                }
+            }
+        }
+    }
+    if let Some(instruction) = response_format_system_instruction(request.get("response_format")) {
+        system_parts.push(instruction);
+    }
+
+    let max_tokens = request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .and_then(Value::as_u64)
+        .unwrap_or(DEFAULT_ANTHROPIC_MAX_TOKENS);
+    let mut payload = json!({
+        "model": model,
+        "messages": messages,
+        "max_tokens": max_tokens,
+        "stream": stream,
+    });
+    let obj = payload
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("anthropic payload must be an object"))?;
+    if !system_parts.is_empty() {
+        obj.insert(
+            "system".to_string(),
+            Value::String(system_parts.join("\n\n")),
+        );
+    }
+
+    let stop_sequences = match request.get("stop") {
+        Some(Value::String(stop)) => vec![Value::String(stop.clone())],
+        Some(Value::Array(stops)) => stops.iter().filter(|v| v.is_string()).cloned().collect(),
+        _ => Vec::new(),
+    };
+    if !stop_sequences.is_empty() {
+        obj.insert("stop_sequences".to_string(), Value::Array(stop_sequences));
+    }
+
+    let tools = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .map(|tools| responses_tools_to_anthropic_tools(tools))
+        .unwrap_or_default();
+    if !tools.is_empty() {
+        let parallel_tool_calls = request
+            .get("parallel_tool_calls")
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+        if let Some(tool_choice) = anthropic_tool_choice(
+            request.get("tool_choice").unwrap_or(&Value::Null),
+            parallel_tool_calls,
+        ) {
+            obj.insert("tool_choice".to_string(), tool_choice);
+        }
+        obj.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    let effort = request.get("reasoning_effort").and_then(Value::as_str);
+    insert_anthropic_thinking_or_sampling(obj, request, effort, max_tokens);
+
+    Ok(payload)
+}
+
+fn chat_content_to_anthropic_blocks(content: Option<&Value>) -> Vec<Value> {
+    match content {
+        Some(Value::String(text)) if !text.trim().is_empty() => {
+            vec![json!({"type":"text","text": text})]
+        }
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(
+                |part| match part.get("type").and_then(Value::as_str).unwrap_or_default() {
+                    "text" => part
+                        .get("text")
+                        .and_then(Value::as_str)
+                        .filter(|text| !text.is_empty())
+                        .map(|text| json!({"type":"text","text": text})),
+                    "image_url" => part
+                        .get("image_url")
+                        .and_then(|image| image.get("url").or(Some(image)))
+                        .and_then(Value::as_str)
+                        .map(anthropic_image_block_from_url),
+                    other => {
+                        warn!(
+                            "dropping chat content part unsupported by messages upstream: {other}"
+                        );
+                        None
+                    }
+                },
+            )
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn response_format_system_instruction(response_format: Option<&Value>) -> Option<String> {
+    let response_format = response_format?;
+    match response_format.get("type").and_then(Value::as_str)? {
+        "json_object" => Some("Respond with a single JSON object and no other text.".to_string()),
+        "json_schema" => {
+            let schema = response_format
+                .pointer("/json_schema/schema")
+                .cloned()
+                .unwrap_or_else(|| json!({}));
+            Some(format!(
+                "Respond with a single JSON object that matches this JSON schema and no other text:\n{schema}"
+            ))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn anthropic_stop_reason_to_chat_finish_reason(
+    stop_reason: Option<&str>,
+) -> &'static str {
+    match stop_reason {
+        Some("max_tokens") => "length",
+        Some("tool_use") => "tool_calls",
+        _ => "stop",
+    }
+}
+
+pub(crate) fn anthropic_usage_to_chat_usage(usage: Option<&Value>) -> Option<Value> {
+    let usage = anthropic_usage_to_responses_usage(usage)?;
+    Some(json!({
+        "prompt_tokens": usage["input_tokens"],
+        "completion_tokens": usage["output_tokens"],
+        "total_tokens": usage["total_tokens"],
+        "prompt_tokens_details": {"cached_tokens": usage["input_tokens_details"]["cached_tokens"]},
+    }))
+}
+
+pub(crate) fn anthropic_json_to_chat_json(message: Value, fallback_model: &str) -> Value {
+    let mut text_parts = Vec::new();
+    let mut reasoning_parts = Vec::new();
+    let mut tool_calls = Vec::new();
+    for block in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        match block
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+        {
+            "text" => text_parts.extend(block.get("text").and_then(Value::as_str)),
+            "thinking" => reasoning_parts.extend(block.get("thinking").and_then(Value::as_str)),
+            "tool_use" => tool_calls.push(json!({
+                "id": block.get("id").and_then(Value::as_str).unwrap_or_default(),
+                "type": "function",
+                "function": {
+                    "name": block.get("name").and_then(Value::as_str).unwrap_or("unknown_function"),
+                    "arguments": block
+                        .get("input")
+                        .map(Value::to_string)
+                        .unwrap_or_else(|| "{}".to_string()),
+                }
+            })),
+            _ => {}
+        }
+    }
+
+    let mut chat_message = json!({
+        "role": "assistant",
+        "content": text_parts.join(""),
+    });
+    if let Some(obj) = chat_message.as_object_mut() {
+        if !reasoning_parts.is_empty() {
+            obj.insert(
+                "reasoning_content".to_string(),
+                Value::String(reasoning_parts.join("\n\n")),
+            );
+        }
+        if !tool_calls.is_empty() {
+            obj.insert("tool_calls".to_string(), Value::Array(tool_calls));
+        }
+    }
+
+    json!({
+        "id": message.get("id").and_then(Value::as_str).map(ToString::to_string).unwrap_or_else(|| format!("chatcmpl_{}", Uuid::now_v7())),
+        "object": "chat.completion",
+        "created": 0,
+        "model": message.get("model").and_then(Value::as_str).unwrap_or(fallback_model),
+        "choices": [{
+            "index": 0,
+            "message": chat_message,
+            "finish_reason": anthropic_stop_reason_to_chat_finish_reason(
+                message.get("stop_reason").and_then(Value::as_str),
+            ),
+        }],
+        "usage": anthropic_usage_to_chat_usage(message.get("usage")),
+    })
+}
+
+// Gemini wants each `thoughtSignature` back, so it rides base64url-encoded in the tool call id.
+const GEMINI_THOUGHT_SIGNATURE_SEPARATOR: &str = "__thought__";
+
+pub(crate) fn gemini_tool_call_id(signature: Option<&str>) -> String {
+    let id = format!("call_{}", Uuid::now_v7().simple());
+    match signature.filter(|signature| !signature.is_empty()) {
+        Some(signature) => {
+            let signature = signature
+                .trim_end_matches('=')
+                .replace('+', "-")
+                .replace('/', "_");
+            format!("{id}{GEMINI_THOUGHT_SIGNATURE_SEPARATOR}{signature}")
+        }
+        None => id,
+    }
+}
+
+fn gemini_thought_signature_from_tool_call_id(id: &str) -> Option<String> {
+    let (_, signature) = id.split_once(GEMINI_THOUGHT_SIGNATURE_SEPARATOR)?;
+    let mut signature = signature.replace('-', "+").replace('_', "/");
+    while signature.len() % 4 != 0 {
+        signature.push('=');
+    }
+    Some(signature)
+}
+
+pub(crate) fn map_chat_to_gemini_request(request: &Value, stream: bool) -> Result<Value> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("missing `model`"))?;
+    let chat_messages = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("`messages` must be an array"))?;
+
+    let mut tool_names_by_id = HashMap::new();
+    let mut system_parts = Vec::new();
+    let mut contents = Vec::new();
+    for message in chat_messages {
+        let role = message
+            .get("role")
+            .and_then(Value::as_str)
+            .unwrap_or("user");
+        match role {
+            "system" | "developer" => {
+                system_parts.extend(gemini_parts_from_chat_content(message.get("content")));
+            }
+            "assistant" => {
+                for part in gemini_parts_from_chat_content(message.get("content")) {
+                    push_gemini_part(&mut contents, "model", part);
+                }
+                for tool_call in message
+                    .get("tool_calls")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    let function = tool_call.get("function");
+                    let name = function
+                        .and_then(|f| f.get("name"))
+                        .and_then(Value::as_str)
+                        .unwrap_or_default();
+                    if name.is_empty() {
+                        warn!("ignoring assistant tool call with empty name");
+                        continue;
+                    }
+                    let id = tool_call
+                        .get("id")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default();
+                    tool_names_by_id.insert(id.to_string(), name.to_string());
+                    let mut part = json!({
+                        "functionCall": {
+                            "name": name,
+                            "args": anthropic_tool_input_from_arguments(
+                                function.and_then(|f| f.get("arguments")),
+                            ),
+                        }
+                    });
+                    if let Some(signature) = gemini_thought_signature_from_tool_call_id(id) {
+                        part["thoughtSignature"] = Value::String(signature);
+                    }
+                    push_gemini_part(&mut contents, "model", part);
+                }
+            }
+            "tool" | "function" => {
+                let id = message
+                    .get("tool_call_id")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                let name = tool_names_by_id
+                    .get(id)
+                    .map(String::as_str)
+                    .or_else(|| message.get("name").and_then(Value::as_str))
+                    .unwrap_or("unknown_function");
+                let output = message
+                    .get("content")
+                    .map(function_output_to_text)
+                    .unwrap_or_default();
+                let response = match serde_json::from_str::<Value>(&output) {
+                    Ok(response @ Value::Object(_)) => response,
+                    _ => json!({"result": output}),
+                };
+                push_gemini_part(
+                    &mut contents,
+                    "user",
+                    json!({
+                        "functionResponse": {
+                            "name": name,
+                            "response": response,
+                        }
+                    }),
+                );
+            }
+            _ => {
+                for part in gemini_parts_from_chat_content(message.get("content")) {
+                    push_gemini_part(&mut contents, "user", part);
+                }
+            }
+        }
+    }
+
+    let mut payload = json!({
+        "model": model,
+        "stream": stream,
+        "contents": contents,
+    });
+    let obj = payload
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("gemini payload must be an object"))?;
+    if !system_parts.is_empty() {
+        obj.insert(
+            "systemInstruction".to_string(),
+            json!({"parts": system_parts}),
+        );
+    }
+
+    let function_declarations = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|tool| {
+            let name = function_tool_name(tool)?;
+            let function = tool.get("function").unwrap_or(tool);
+            let mut declaration = json!({
+                "name": name,
+                "parametersJsonSchema": function
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(default_function_tool_parameters),
+            });
+            if let Some(description) = function.get("description").and_then(Value::as_str) {
+                declaration["description"] = Value::String(description.to_string());
+            }
+            Some(declaration)
+        })
+        .collect::<Vec<_>>();
+    if !function_declarations.is_empty() {
+        if let Some(tool_config) = gemini_tool_config(request.get("tool_choice")) {
+            obj.insert("toolConfig".to_string(), tool_config);
+        }
+        obj.insert(
+            "tools".to_string(),
+            json!([{"functionDeclarations": function_declarations}]),
+        );
+    }
+
+    let mut generation_config = serde_json::Map::new();
+    if let Some(max_tokens) = request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .filter(|value| value.is_number())
+    {
+        generation_config.insert("maxOutputTokens".to_string(), max_tokens.clone());
+    }
+    for (field, gemini_field) in [("temperature", "temperature"), ("top_p", "topP")] {
+        if let Some(value) = request.get(field).filter(|value| value.is_number()) {
+            generation_config.insert(gemini_field.to_string(), value.clone());
+        }
+    }
+    match request.get("stop") {
+        Some(Value::String(stop)) => {
+            generation_config.insert("stopSequences".to_string(), json!([stop]));
+        }
+        Some(Value::Array(stops)) if !stops.is_empty() => {
+            generation_config.insert("stopSequences".to_string(), Value::Array(stops.clone()));
+        }
+        _ => {}
+    }
+    match request
+        .get("response_format")
+        .and_then(|format| format.get("type"))
+        .and_then(Value::as_str)
+    {
+        Some("json_object") => {
+            generation_config.insert("responseMimeType".to_string(), json!("application/json"));
+        }
+        Some("json_schema") => {
+            generation_config.insert("responseMimeType".to_string(), json!("application/json"));
+            if let Some(schema) = request.pointer("/response_format/json_schema/schema") {
+                generation_config.insert("responseJsonSchema".to_string(), schema.clone());
+            }
+        }
+        _ => {}
+    }
+    if let Some(thinking_budget) = request
+        .get("reasoning_effort")
+        .and_then(Value::as_str)
+        .and_then(gemini_thinking_budget_for_effort)
+    {
+        generation_config.insert(
+            "thinkingConfig".to_string(),
+            json!({"includeThoughts": true, "thinkingBudget": thinking_budget}),
+        );
+    }
+    if !generation_config.is_empty() {
+        obj.insert(
+            "generationConfig".to_string(),
+            Value::Object(generation_config),
+        );
+    }
+
+    Ok(payload)
+}
+
+fn push_gemini_part(contents: &mut Vec<Value>, role: &str, part: Value) {
+    if let Some(last) = contents.last_mut()
+        && last.get("role").and_then(Value::as_str) == Some(role)
+        && let Some(parts) = last.get_mut("parts").and_then(Value::as_array_mut)
+    {
+        parts.push(part);
+        return;
+    }
+    contents.push(json!({
+        "role": role,
+        "parts": [part],
+    }));
+}
+
+fn gemini_parts_from_chat_content(content: Option<&Value>) -> Vec<Value> {
+    chat_content_to_anthropic_blocks(content)
+        .into_iter()
+        .filter_map(|block| match block.get("type").and_then(Value::as_str) {
+            Some("text") => Some(json!({"text": block["text"]})),
+            Some("image") => match block.pointer("/source/type").and_then(Value::as_str) {
+                Some("base64") => Some(json!({
+                    "inlineData": {
+                        "mimeType": block["source"]["media_type"],
+                        "data": block["source"]["data"],
+                    }
+                })),
+                _ => Some(json!({"fileData": {"fileUri": block["source"]["url"]}})),
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+fn gemini_tool_config(tool_choice: Option<&Value>) -> Option<Value> {
+    let function_calling_config = match tool_choice? {
+        Value::String(mode) => match mode.as_str() {
+            "none" => json!({"mode": "NONE"}),
+            "required" => json!({"mode": "ANY"}),
+            _ => return None,
+        },
+        Value::Object(obj) => {
+            let name = obj
+                .get("function")
+                .and_then(|f| f.get("name"))
+                .or_else(|| obj.get("name"))
+                .and_then(Value::as_str)?;
+            json!({"mode": "ANY", "allowedFunctionNames": [name]})
+        }
+        _ => return None,
+    };
+    Some(json!({"functionCallingConfig": function_calling_config}))
+}
+
+fn gemini_thinking_budget_for_effort(effort: &str) -> Option<u64> {
+    match effort {
+        "minimal" | "low" => Some(1024),
+        "medium" => Some(8192),
+        "high" => Some(24576),
+        _ => None,
+    }
+}
+
+pub(crate) fn gemini_finish_reason_to_chat(
+    finish_reason: &str,
+    has_tool_calls: bool,
+) -> &'static str {
+    if has_tool_calls {
+        return "tool_calls";
+    }
+    match finish_reason {
+        "MAX_TOKENS" => "length",
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => "content_filter",
+        _ => "stop",
+    }
+}
+
+pub(crate) fn gemini_usage_to_chat_usage(usage: Option<&Value>) -> Option<Value> {
+    let usage = usage.filter(|usage| usage.is_object())?;
+    let tokens = |key: &str| usage.get(key).and_then(Value::as_i64).unwrap_or(0);
+    let prompt_tokens = tokens("promptTokenCount");
+    let completion_tokens = tokens("candidatesTokenCount") + tokens("thoughtsTokenCount");
+    Some(json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": usage
+            .get("totalTokenCount")
+            .and_then(Value::as_i64)
+            .unwrap_or(prompt_tokens + completion_tokens),
+        "prompt_tokens_details": {"cached_tokens": tokens("cachedContentTokenCount")},
+        "completion_tokens_details": {"reasoning_tokens": tokens("thoughtsTokenCount")},
+    }))
+}
+
+pub(crate) fn gemini_parts_to_chat_delta(parts: &[Value]) -> (String, String, Vec<Value>) {
+    let mut text = String::new();
+    let mut reasoning = String::new();
+    let mut tool_calls = Vec::new();
+    for part in parts {
+        if let Some(function_call) = part.get("functionCall") {
+            tool_calls.push(json!({
+                "id": gemini_tool_call_id(part.get("thoughtSignature").and_then(Value::as_str)),
+                "type": "function",
+                "function": {
+                    "name": function_call
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .unwrap_or("unknown_function"),
+                    "arguments": function_call
+                        .get("args")
+                        .map(Value::to_string)
+                        .unwrap_or_else(|| "{}".to_string()),
+                }
+            }));
+        } else if let Some(part_text) = part.get("text").and_then(Value::as_str) {
+            if part.get("thought").and_then(Value::as_bool) == Some(true) {
+                reasoning.push_str(part_text);
+            } else {
+                text.push_str(part_text);
+            }
+        }
+    }
+    (text, reasoning, tool_calls)
+}
+
+pub(crate) fn gemini_json_to_chat_json(response: Value, fallback_model: &str) -> Value {
+    let candidate = response
+        .get("candidates")
+        .and_then(Value::as_array)
+        .and_then(|candidates| candidates.first());
+    let parts = candidate
+        .and_then(|candidate| candidate.pointer("/content/parts"))
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let (text, reasoning, tool_calls) = gemini_parts_to_chat_delta(parts);
+    let finish_reason = gemini_finish_reason_to_chat(
+        candidate
+            .and_then(|candidate| candidate.get("finishReason"))
+            .and_then(Value::as_str)
+            .unwrap_or("STOP"),
+        !tool_calls.is_empty(),
+    );
+
+    let mut message = json!({
+        "role": "assistant",
+        "content": text,
+    });
+    if let Some(obj) = message.as_object_mut() {
+        if !reasoning.is_empty() {
+            obj.insert("reasoning_content".to_string(), Value::String(reasoning));
+        }
+        if !tool_calls.is_empty() {
+            obj.insert("tool_calls".to_string(), Value::Array(tool_calls));
+        }
+    }
+
+    json!({
+        "id": response.get("responseId").and_then(Value::as_str).map(ToString::to_string).unwrap_or_else(|| format!("chatcmpl_{}", Uuid::now_v7())),
+        "object": "chat.completion",
+        "created": 0,
+        "model": response.get("modelVersion").and_then(Value::as_str).unwrap_or(fallback_model),
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": finish_reason,
+        }],
+        "usage": gemini_usage_to_chat_usage(response.get("usageMetadata")),
+    })
+}
+
+pub(crate) fn map_chat_to_ollama_request(request: &Value, stream: bool) -> Result<Value> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("missing `model`"))?;
+    let chat_messages = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("`messages` must be an array"))?;
+
+    let mut tool_names_by_id = HashMap::new();
+    let mut messages = Vec::new();
+    for message in chat_messages {
+        let role = match message
+            .get("role")
+            .and_then(Value::as_str)
+            .unwrap_or("user")
+        {
+            "developer" => "system",
+            "function" => "tool",
+            role => role,
+        };
+        let (content, images) = ollama_content_from_chat_content(message.get("content"));
+        let mut ollama_message = json!({
+            "role": role,
+            "content": content,
+        });
+        if !images.is_empty() {
+            ollama_message["images"] = Value::Array(images);
+        }
+        match role {
+            "assistant" => {
+                if let Some(thinking) = message
+                    .get("reasoning_content")
+                    .and_then(Value::as_str)
+                    .filter(|thinking| !thinking.is_empty())
+                {
+                    ollama_message["thinking"] = Value::String(thinking.to_string());
+                }
+                let tool_calls = message
+                    .get("tool_calls")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|tool_call| {
+                        let function = tool_call.get("function")?;
+                        let name = function.get("name").and_then(Value::as_str)?;
+                        if let Some(id) = tool_call.get("id").and_then(Value::as_str) {
+                            tool_names_by_id.insert(id.to_string(), name.to_string());
+                        }
+                        Some(json!({
+                            "function": {
+                                "name": name,
+                                "arguments": anthropic_tool_input_from_arguments(
+                                    function.get("arguments"),
+                                ),
+                            }
+                        }))
+                    })
+                    .collect::<Vec<_>>();
+                if !tool_calls.is_empty() {
+                    ollama_message["tool_calls"] = Value::Array(tool_calls);
+                }
+            }
+            "tool" => {
+                ollama_message["content"] = Value::String(
+                    message
+                        .get("content")
+                        .map(function_output_to_text)
+                        .unwrap_or_default(),
+                );
+                if let Some(name) = message
+                    .get("tool_call_id")
+                    .and_then(Value::as_str)
+                    .and_then(|id| tool_names_by_id.get(id))
+                    .map(String::as_str)
+                    .or_else(|| message.get("name").and_then(Value::as_str))
+                {
+                    ollama_message["tool_name"] = Value::String(name.to_string());
+                }
+            }
+            _ => {}
+        }
+        messages.push(ollama_message);
+    }
+
+    let mut payload = json!({
+        "model": model,
+        "messages": messages,
+        "stream": stream,
+    });
+    let obj = payload
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("ollama payload must be an object"))?;
+
+    let tools = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|tool| function_tool_name(tool).is_some())
+        .cloned()
+        .collect::<Vec<_>>();
+    if !tools.is_empty() && request.get("tool_choice").and_then(Value::as_str) != Some("none") {
+        obj.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    match request
+        .get("response_format")
+        .and_then(|format| format.get("type"))
+        .and_then(Value::as_str)
+    {
+        Some("json_object") => {
+            obj.insert("format".to_string(), json!("json"));
+        }
+        Some("json_schema") => {
+            let schema = request
+                .pointer("/response_format/json_schema/schema")
+                .cloned()
+                .unwrap_or_else(|| json!("json"));
+            obj.insert("format".to_string(), schema);
+        }
+        _ => {}
+    }
+    if let Some(effort) = request.get("reasoning_effort").and_then(Value::as_str) {
+        obj.insert("think".to_string(), Value::Bool(effort != "none"));
+    }
+
+    let mut options = serde_json::Map::new();
+    if let Some(max_tokens) = request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .filter(|value| value.is_number())
+    {
+        options.insert("num_predict".to_string(), max_tokens.clone());
+    }
+    for field in [
+        "temperature",
+        "top_p",
+        "seed",
+        "presence_penalty",
+        "frequency_penalty",
+    ] {
+        if let Some(value) = request.get(field).filter(|value| value.is_number()) {
+            options.insert(field.to_string(), value.clone());
+        }
+    }
+    match request.get("stop") {
+        Some(Value::String(stop)) => {
+            options.insert("stop".to_string(), json!([stop]));
+        }
+        Some(Value::Array(stops)) if !stops.is_empty() => {
+            options.insert("stop".to_string(), Value::Array(stops.clone()));
+        }
+        _ => {}
+    }
+    if !options.is_empty() {
+        obj.insert("options".to_string(), Value::Object(options));
+    }
+
+    Ok(payload)
+}
+
+fn ollama_content_from_chat_content(content: Option<&Value>) -> (String, Vec<Value>) {
+    let mut text = Vec::new();
+    let mut images = Vec::new();
+    for block in chat_content_to_anthropic_blocks(content) {
+        match block.get("type").and_then(Value::as_str) {
+            Some("text") => {
+                if let Some(part) = block.get("text").and_then(Value::as_str) {
+                    text.push(part.to_string());
+                }
+            }
+            Some("image") => match block.pointer("/source/type").and_then(Value::as_str) {
+                Some("base64") => images.push(block["source"]["data"].clone()),
+                _ => warn!("dropping image url unsupported by ollama upstream"),
+            },
+            _ => {}
+        }
+    }
+    (text.join("\n"), images)
+}
+
+pub(crate) fn ollama_done_reason_to_chat(
+    done_reason: Option<&str>,
+    has_tool_calls: bool,
+) -> &'static str {
+    if has_tool_calls {
+        return "tool_calls";
+    }
+    match done_reason {
+        Some("length") => "length",
+        _ => "stop",
+    }
+}
+
+pub(crate) fn ollama_usage_to_chat_usage(response: &Value) -> Option<Value> {
+    if response.get("prompt_eval_count").is_none() && response.get("eval_count").is_none() {
+        return None;
+    }
+    let tokens = |key: &str| response.get(key).and_then(Value::as_i64).unwrap_or(0);
+    let prompt_tokens = tokens("prompt_eval_count");
+    let completion_tokens = tokens("eval_count");
+    Some(json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens,
+    }))
+}
+
+pub(crate) fn ollama_message_to_chat_delta(
+    message: Option<&Value>,
+) -> (String, String, Vec<Value>) {
+    let text = message
+        .and_then(|message| message.get("content"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let reasoning = message
+        .and_then(|message| message.get("thinking"))
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let tool_calls = message
+        .and_then(|message| message.get("tool_calls"))
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|tool_call| {
+            let function = tool_call.get("function");
+            let arguments = match function.and_then(|f| f.get("arguments")) {
+                Some(Value::String(arguments)) => arguments.clone(),
+                Some(arguments) => arguments.to_string(),
+                None => "{}".to_string(),
+            };
+            json!({
+                "id": tool_call
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| format!("call_{}", Uuid::now_v7().simple())),
+                "type": "function",
+                "function": {
+                    "name": function
+                        .and_then(|f| f.get("name"))
+                        .and_then(Value::as_str)
+                        .unwrap_or("unknown_function"),
+                    "arguments": arguments,
+                }
+            })
+        })
+        .collect();
+    (text, reasoning, tool_calls)
+}
+
+pub(crate) fn ollama_json_to_chat_json(response: Value, fallback_model: &str) -> Value {
+    let (text, reasoning, tool_calls) = ollama_message_to_chat_delta(response.get("message"));
+    let finish_reason = ollama_done_reason_to_chat(
+        response.get("done_reason").and_then(Value::as_str),
+        !tool_calls.is_empty(),
+    );
+
+    let mut message = json!({
+        "role": "assistant",
+        "content": text,
+    });
+    if let Some(obj) = message.as_object_mut() {
+        if !reasoning.is_empty() {
+            obj.insert("reasoning_content".to_string(), Value::String(reasoning));
+        }
+        if !tool_calls.is_empty() {
+            obj.insert("tool_calls".to_string(), Value::Array(tool_calls));
+        }
+    }
+
+    json!({
+        "id": format!("chatcmpl_{}", Uuid::now_v7()),
+        "object": "chat.completion",
+        "created": 0,
+        "model": response.get("model").and_then(Value::as_str).unwrap_or(fallback_model),
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": finish_reason,
+        }],
+        "usage": ollama_usage_to_chat_usage(&response),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
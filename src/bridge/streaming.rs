@@ -7,18 +7,28 @@ use std::collections::{BTreeMap, HashMap};
 use std::sync::atomic::{AtomicU64, Ordering};
 use tracing::warn;
 
+use crate::bridge::mapping::{
+    anthropic_stop_reason_to_chat_finish_reason, anthropic_thinking_to_reasoning_item,
+    anthropic_usage_to_chat_usage, anthropic_usage_to_responses_usage,
+    gemini_finish_reason_to_chat, gemini_parts_to_chat_delta, gemini_usage_to_chat_usage,
+    ollama_done_reason_to_chat, ollama_message_to_chat_delta, ollama_usage_to_chat_usage,
+};
 use crate::logging_utils::debug_large_log;
+use crate::session::SessionTurn;
+use crate::timeouts::UpstreamStreamError;
 use crate::{
-    ChatChunk, ResponsesToolCallKind, SseParser, StreamAccumulator, responses_tool_call_item,
+    ChatChunk, NdjsonParser, ResponsesToolCallKind, SseParser, StreamAccumulator,
+    responses_tool_call_item,
 };
 
-pub(crate) fn passthrough_responses_stream<S>(
+pub(crate) fn passthrough_responses_stream<S, E>(
     upstream_stream: S,
     router_name: String,
     verbose_logging: bool,
 ) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
 where
-    S: Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
 {
     stream! {
         let mut upstream_stream = Box::pin(upstream_stream);
@@ -37,13 +47,14 @@ where
                     yield Ok(chunk)
                 },
                 Err(err) => {
+                    let err: UpstreamStreamError = err.into();
                     yield Ok(sse_event(
                         "response.failed",
                         &json!({
                             "type": "response.failed",
                             "response": {
                                 "error": {
-                                    "code": "upstream_stream_error",
+                                    "code": err.code(),
                                     "message": err.to_string(),
                                 }
                             }
@@ -56,13 +67,14 @@ where
     }
 }
 
-pub(crate) fn passthrough_chat_stream<S>(
+pub(crate) fn passthrough_chat_stream<S, E>(
     upstream_stream: S,
     router_name: String,
     verbose_logging: bool,
 ) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
 where
-    S: Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
 {
     stream! {
         let mut upstream_stream = Box::pin(upstream_stream);
@@ -81,9 +93,10 @@ where
                     yield Ok(chunk)
                 },
                 Err(err) => {
+                    let err: UpstreamStreamError = err.into();
                     yield Ok(chat_sse_data(&json!({
                         "error": {
-                            "type": "upstream_stream_error",
+                            "type": err.code(),
                             "message": err.to_string(),
                         }
                     })));
@@ -95,13 +108,14 @@ where
     }
 }
 
-pub(crate) fn passthrough_messages_stream<S>(
+pub(crate) fn passthrough_messages_stream<S, E>(
     upstream_stream: S,
     router_name: String,
     verbose_logging: bool,
 ) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
 where
-    S: Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
 {
     stream! {
         let mut upstream_stream = Box::pin(upstream_stream);
@@ -120,12 +134,13 @@ where
                     yield Ok(chunk)
                 },
                 Err(err) => {
+                    let err: UpstreamStreamError = err.into();
                     yield Ok(anthropic_sse_event(
                         "error",
                         &json!({
                             "type": "error",
                             "error": {
-                                "type": "upstream_stream_error",
+                                "type": err.code(),
                                 "message": err.to_string(),
                             }
                         }),
@@ -137,14 +152,15 @@ where
     }
 }
 
-pub(crate) fn translate_responses_stream_to_chat<S>(
+pub(crate) fn translate_responses_stream_to_chat<S, E>(
     upstream_stream: S,
     router_name: String,
     verbose_logging: bool,
     model: String,
 ) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
 where
-    S: Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
 {
     stream! {
         let mut upstream_stream = Box::pin(upstream_stream);
@@ -155,9 +171,10 @@ where
             let chunk = match chunk_result {
                 Ok(chunk) => chunk,
                 Err(err) => {
+                    let err: UpstreamStreamError = err.into();
                     yield Ok(chat_sse_data(&json!({
                         "error": {
-                            "type": "upstream_stream_error",
+                            "type": err.code(),
                             "message": err.to_string(),
                         }
                     })));
@@ -205,7 +222,7 @@ where
     }
 }
 
-pub(crate) fn translate_chat_stream_to_anthropic<S>(
+pub(crate) fn translate_chat_stream_to_anthropic<S, E>(
     upstream_stream: S,
     router_name: String,
     verbose_logging: bool,
@@ -213,7 +230,8 @@ pub(crate) fn translate_chat_stream_to_anthropic<S>(
     input_tokens: i64,
 ) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
 where
-    S: Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
 {
     stream! {
         let mut upstream_stream = Box::pin(upstream_stream);
@@ -369,6 +387,7 @@ where
             let chunk = match chunk_result {
                 Ok(chunk) => chunk,
                 Err(err) => {
+                    let err: UpstreamStreamError = err.into();
                     yield Ok(anthropic_sse_event(
                         "error",
                         &json!({
@@ -454,16 +473,444 @@ where
     }
 }
 
-pub(crate) fn translate_chat_stream<S>(
+pub(crate) fn translate_responses_stream_to_anthropic<S, E>(
+    upstream_stream: S,
+    router_name: String,
+    verbose_logging: bool,
+    model: String,
+    input_tokens: i64,
+) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
+{
+    stream! {
+        let mut upstream_stream = Box::pin(upstream_stream);
+        let mut parser = SseParser::default();
+        let mut state = ResponsesToAnthropicStreamState::new(input_tokens);
+
+        yield Ok(anthropic_sse_event(
+            "message_start",
+            &json!({
+                "type": "message_start",
+                "message": {
+                    "id": format!("msg_{}", uuid::Uuid::now_v7()),
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [],
+                    "model": model,
+                    "usage": {
+                        "input_tokens": input_tokens,
+                        "output_tokens": 0,
+                    },
+                }
+            }),
+        ));
+
+        while let Some(chunk_result) = upstream_stream.next().await {
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    let err: UpstreamStreamError = err.into();
+                    yield Ok(anthropic_error_event(&err.to_string()));
+                    return;
+                }
+            };
+
+            if verbose_logging {
+                debug_large_log(
+                    &format!(
+                        "upstream response payload stream chunk (router={}, responses->anthropic)",
+                        router_name
+                    ),
+                    String::from_utf8_lossy(&chunk).as_ref(),
+                );
+            }
+
+            let text = String::from_utf8_lossy(&chunk);
+            for (event_name, data) in parser.feed_with_event_names(&text) {
+                for event in state.process_event(event_name.as_deref(), &data) {
+                    yield Ok(event);
+                }
+                if state.done {
+                    return;
+                }
+            }
+        }
+
+        if let Some(data) = parser.finish() {
+            for event in state.process_event(None, &data) {
+                yield Ok(event);
+            }
+        }
+
+        if !state.done {
+            yield Ok(anthropic_error_event(
+                "upstream stream ended before terminal marker",
+            ));
+        }
+    }
+}
+
+fn anthropic_error_event(message: &str) -> Bytes {
+    anthropic_sse_event(
+        "error",
+        &json!({
+            "type": "error",
+            "error": {
+                "type": "api_error",
+                "message": message,
+            }
+        }),
+    )
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum AnthropicOpenBlock {
+    Text(usize),
+    Thinking(usize),
+}
+
+struct ResponsesToAnthropicToolState {
+    block_index: usize,
+    custom: bool,
+    arguments: String,
+    argument_delta_seen: bool,
+    stopped: bool,
+}
+
+struct ResponsesToAnthropicStreamState {
+    input_tokens: i64,
+    next_index: usize,
+    open_block: Option<AnthropicOpenBlock>,
+    tools: HashMap<String, ResponsesToAnthropicToolState>,
+    tool_id_by_output_index: HashMap<u64, String>,
+    text_delta_seen: bool,
+    done: bool,
+}
+
+impl ResponsesToAnthropicStreamState {
+    fn new(input_tokens: i64) -> Self {
+        Self {
+            input_tokens,
+            next_index: 0,
+            open_block: None,
+            tools: HashMap::new(),
+            tool_id_by_output_index: HashMap::new(),
+            text_delta_seen: false,
+            done: false,
+        }
+    }
+
+    fn process_event(&mut self, event_name: Option<&str>, data: &str) -> Vec<Bytes> {
+        if data == "[DONE]" {
+            return Vec::new();
+        }
+        let Ok(payload) = serde_json::from_str::<Value>(data) else {
+            warn!("failed to decode upstream responses stream event");
+            return Vec::new();
+        };
+        let event_type = event_name
+            .filter(|value| !value.trim().is_empty())
+            .or_else(|| payload.get("type").and_then(Value::as_str))
+            .unwrap_or_default();
+        let delta = payload
+            .get("delta")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+
+        match event_type {
+            "response.output_text.delta" => {
+                self.text_delta_seen |= !delta.is_empty();
+                self.text_delta(delta)
+            }
+            "response.reasoning_summary_text.delta" | "response.reasoning_text.delta" => {
+                self.thinking_delta(delta)
+            }
+            "response.output_item.added" => self.process_output_item_added(&payload),
+            "response.function_call_arguments.delta" | "response.custom_tool_call_input.delta" => {
+                let Some(call_id) = self.tool_id_for_payload(&payload) else {
+                    return Vec::new();
+                };
+                let Some(tool) = self.tools.get_mut(&call_id) else {
+                    return Vec::new();
+                };
+                tool.argument_delta_seen = true;
+                if tool.custom {
+                    // tool_use input must be JSON, so custom tool text goes out as `{"input": ...}`.
+                    tool.arguments.push_str(delta);
+                    return Vec::new();
+                }
+                if delta.is_empty() {
+                    return Vec::new();
+                }
+                vec![anthropic_content_block_delta(
+                    tool.block_index,
+                    "input_json_delta",
+                    delta,
+                )]
+            }
+            "response.output_item.done" => self.process_output_item_done(&payload),
+            "response.completed" | "response.incomplete" => {
+                self.done = true;
+                let response = payload.get("response").unwrap_or(&payload);
+                let stop_reason = if !self.tools.is_empty() {
+                    "tool_use"
+                } else if event_type == "response.incomplete"
+                    || response.get("status").and_then(Value::as_str) == Some("incomplete")
+                {
+                    "max_tokens"
+                } else {
+                    "end_turn"
+                };
+                let usage = response.get("usage");
+                let tokens = |key: &str| usage.and_then(|u| u.get(key)).and_then(Value::as_i64);
+                let mut events = self.close_open_block();
+                let mut unfinished_tools = self
+                    .tools
+                    .values_mut()
+                    .filter(|tool| !tool.stopped)
+                    .map(|tool| {
+                        tool.stopped = true;
+                        tool.block_index
+                    })
+                    .collect::<Vec<_>>();
+                unfinished_tools.sort_unstable();
+                events.extend(
+                    unfinished_tools
+                        .into_iter()
+                        .map(anthropic_content_block_stop),
+                );
+                events.push(anthropic_sse_event(
+                    "message_delta",
+                    &json!({
+                        "type": "message_delta",
+                        "delta": {
+                            "stop_reason": stop_reason,
+                            "stop_sequence": null,
+                        },
+                        "usage": {
+                            "input_tokens": tokens("input_tokens").unwrap_or(self.input_tokens),
+                            "output_tokens": tokens("output_tokens").unwrap_or(0),
+                        },
+                    }),
+                ));
+                events.push(anthropic_sse_event(
+                    "message_stop",
+                    &json!({
+                        "type": "message_stop",
+                    }),
+                ));
+                events
+            }
+            "response.failed" | "error" => {
+                self.done = true;
+                let message = payload
+                    .pointer("/response/error/message")
+                    .or_else(|| payload.pointer("/error/message"))
+                    .or_else(|| payload.get("message"))
+                    .and_then(Value::as_str)
+                    .unwrap_or("upstream response failed");
+                vec![anthropic_error_event(message)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn text_delta(&mut self, delta: &str) -> Vec<Bytes> {
+        if delta.is_empty() {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        let index = match self.open_block {
+            Some(AnthropicOpenBlock::Text(index)) => index,
+            _ => {
+                events.extend(self.close_open_block());
+                let index = self.allocate_index();
+                self.open_block = Some(AnthropicOpenBlock::Text(index));
+                events.push(anthropic_content_block_start(
+                    index,
+                    "text",
+                    json!({"type":"text","text":""}),
+                ));
+                index
+            }
+        };
+        events.push(anthropic_content_block_delta(index, "text_delta", delta));
+        events
+    }
+
+    fn thinking_delta(&mut self, delta: &str) -> Vec<Bytes> {
+        if delta.is_empty() {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        let index = match self.open_block {
+            Some(AnthropicOpenBlock::Thinking(index)) => index,
+            _ => {
+                events.extend(self.close_open_block());
+                let index = self.allocate_index();
+                self.open_block = Some(AnthropicOpenBlock::Thinking(index));
+                events.push(anthropic_content_block_start(
+                    index,
+                    "thinking",
+                    json!({"type":"thinking","thinking":""}),
+                ));
+                index
+            }
+        };
+        events.push(anthropic_content_block_delta(
+            index,
+            "thinking_delta",
+            delta,
+        ));
+        events
+    }
+
+    fn process_output_item_added(&mut self, payload: &Value) -> Vec<Bytes> {
+        let Some(item) = payload
+            .get("item")
+            .filter(|item| is_responses_tool_item(item))
+        else {
+            return Vec::new();
+        };
+        let call_id = responses_tool_item_id(item);
+        if self.tools.contains_key(&call_id) {
+            return Vec::new();
+        }
+        let mut events = self.close_open_block();
+        let block_index = self.allocate_index();
+        if let Some(output_index) = payload.get("output_index").and_then(Value::as_u64) {
+            self.tool_id_by_output_index
+                .insert(output_index, call_id.clone());
+        }
+        self.tools.insert(
+            call_id.clone(),
+            ResponsesToAnthropicToolState {
+                block_index,
+                custom: item.get("type").and_then(Value::as_str) == Some("custom_tool_call"),
+                arguments: String::new(),
+                argument_delta_seen: false,
+                stopped: false,
+            },
+        );
+        events.push(anthropic_content_block_start(
+            block_index,
+            "tool_use",
+            json!({
+                "type": "tool_use",
+                "id": call_id,
+                "name": item.get("name").and_then(Value::as_str).unwrap_or("unknown_function"),
+                "input": {},
+            }),
+        ));
+        events
+    }
+
+    fn process_output_item_done(&mut self, payload: &Value) -> Vec<Bytes> {
+        let Some(item) = payload.get("item") else {
+            return Vec::new();
+        };
+        if item.get("type").and_then(Value::as_str) == Some("message") {
+            if self.text_delta_seen {
+                return Vec::new();
+            }
+            let text = item
+                .get("content")
+                .and_then(Value::as_array)
+                .map(|parts| {
+                    parts
+                        .iter()
+                        .filter_map(|part| part.get("text").and_then(Value::as_str))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })
+                .unwrap_or_default();
+            self.text_delta_seen = !text.is_empty();
+            return self.text_delta(&text);
+        }
+        if !is_responses_tool_item(item) {
+            return Vec::new();
+        }
+
+        let mut events = self.process_output_item_added(payload);
+        let call_id = self
+            .tool_id_for_payload(payload)
+            .unwrap_or_else(|| responses_tool_item_id(item));
+        let Some(tool) = self.tools.get_mut(&call_id).filter(|tool| !tool.stopped) else {
+            return events;
+        };
+        tool.stopped = true;
+        let raw = if tool.argument_delta_seen {
+            std::mem::take(&mut tool.arguments)
+        } else {
+            item.get("arguments")
+                .or_else(|| item.get("input"))
+                .map(crate::bridge::mapping::function_arguments_to_text)
+                .unwrap_or_default()
+        };
+        let arguments = if tool.custom {
+            json!({"input": raw}).to_string()
+        } else if tool.argument_delta_seen {
+            String::new()
+        } else {
+            raw
+        };
+        if !arguments.is_empty() {
+            events.push(anthropic_content_block_delta(
+                tool.block_index,
+                "input_json_delta",
+                &arguments,
+            ));
+        }
+        events.push(anthropic_content_block_stop(tool.block_index));
+        events
+    }
+
+    fn tool_id_for_payload(&self, payload: &Value) -> Option<String> {
+        payload
+            .get("item_id")
+            .or_else(|| payload.get("call_id"))
+            .or_else(|| payload.pointer("/item/call_id"))
+            .and_then(Value::as_str)
+            .filter(|id| self.tools.contains_key(*id))
+            .map(ToString::to_string)
+            .or_else(|| {
+                payload
+                    .get("output_index")
+                    .and_then(Value::as_u64)
+                    .and_then(|index| self.tool_id_by_output_index.get(&index).cloned())
+            })
+    }
+
+    fn close_open_block(&mut self) -> Vec<Bytes> {
+        match self.open_block.take() {
+            Some(AnthropicOpenBlock::Text(index) | AnthropicOpenBlock::Thinking(index)) => {
+                vec![anthropic_content_block_stop(index)]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn allocate_index(&mut self) -> usize {
+        let index = self.next_index;
+        self.next_index += 1;
+        index
+    }
+}
+
+pub(crate) fn translate_chat_stream<S, E>(
     upstream_stream: S,
     response_id: String,
     router_name: String,
     verbose_logging: bool,
     tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
     feature_flags: crate::FeatureFlags,
+    session_turn: Option<SessionTurn>,
 ) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
 where
-    S: Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
 {
     stream! {
         let mut upstream_stream = Box::pin(upstream_stream);
@@ -474,6 +921,7 @@ where
         let mut reasoning_item_added = false;
         let mut saw_done_marker = false;
         let mut saw_terminal_finish_reason = false;
+        let mut output_items = Vec::new();
 
         yield Ok(sse_event(
             "response.created",
@@ -500,6 +948,7 @@ where
             let chunk = match chunk_result {
                 Ok(chunk) => chunk,
                 Err(err) => {
+                    let err: UpstreamStreamError = err.into();
                     yield Ok(sse_event(
                         "response.failed",
                         &json!({
@@ -507,7 +956,7 @@ where
                             "response": {
                                 "id": response_id.clone(),
                                 "error": {
-                                    "code": "upstream_stream_error",
+                                    "code": err.code(),
                                     "message": err.to_string(),
                                 }
                             }
@@ -655,11 +1104,11 @@ where
                                                 if let Some(name) = function.name {
                                                     entry.name = Some(name);
                                                 }
-                                                if let Some(arguments) = function.arguments {
-                                                    if !arguments.is_empty() {
-                                                        entry.arguments.push_str(&arguments);
-                                                        delta_arguments = Some(arguments);
-                                                    }
+                                                if let Some(arguments) = function.arguments
+                                                    && !arguments.is_empty()
+                                                {
+                                                    entry.arguments.push_str(&arguments);
+                                                    delta_arguments = Some(arguments);
                                                 }
                                             }
 
@@ -759,22 +1208,24 @@ where
                 ));
             }
 
+            let item = json!({
+                "type": "message",
+                "role": "assistant",
+                "content": [
+                    {
+                        "type": "output_text",
+                        "text": acc.assistant_text,
+                    }
+                ]
+            });
             yield Ok(sse_event(
                 "response.output_item.done",
                 &json!({
                     "type": "response.output_item.done",
-                    "item": {
-                        "type": "message",
-                        "role": "assistant",
-                        "content": [
-                            {
-                                "type": "output_text",
-                                "text": acc.assistant_text,
-                            }
-                        ]
-                    }
+                    "item": item.clone(),
                 }),
             ));
+            output_items.push(item);
         }
 
         if feature_flags.enable_reasoning_stream_events && !acc.reasoning_text.is_empty() {
@@ -788,21 +1239,23 @@ where
                     "text": acc.reasoning_text,
                 }),
             ));
+            let item = json!({
+                "type": "reasoning",
+                "id": reasoning_item_id(&response_id),
+                "summary": [{
+                    "type": "summary_text",
+                    "text": acc.reasoning_text,
+                }]
+            });
             yield Ok(sse_event(
                 "response.output_item.done",
                 &json!({
                     "type": "response.output_item.done",
                     "output_index": 0,
-                    "item": {
-                        "type": "reasoning",
-                        "id": reasoning_item_id(&response_id),
-                        "summary": [{
-                            "type": "summary_text",
-                            "text": acc.reasoning_text,
-                        }]
-                    }
+                    "item": item.clone(),
                 }),
             ));
+            output_items.push(item);
         }
 
         for (index, tool_call) in acc.tool_calls {
@@ -848,9 +1301,10 @@ where
                 &json!({
                     "type": "response.output_item.done",
                     "output_index": tool_output_index(index),
-                    "item": item_for_done,
+                    "item": item_for_done.clone(),
                 }),
             ));
+            output_items.push(item_for_done);
         }
 
         if !saw_done_marker && !saw_terminal_finish_reason {
@@ -880,6 +1334,13 @@ where
             })
         });
 
+        // Stored before `response.completed` so an immediate follow-up finds it.
+        if let Some(session_turn) = session_turn {
+            session_turn
+                .complete(&output_items, usage_json.as_ref())
+                .await;
+        }
+
         yield Ok(sse_event(
             "response.completed",
             &json!({
@@ -893,16 +1354,1137 @@ where
     }
 }
 
-fn deterministic_tool_call_id(response_id: &str, index: usize) -> String {
-    format!("call_{}_{}", response_id, index)
-}
-
-fn reasoning_item_id(response_id: &str) -> String {
-    format!("rs_{}", response_id)
-}
-
-fn tool_output_index(index: usize) -> usize {
-    index + 1
+pub(crate) fn translate_anthropic_stream_to_responses<S, E>(
+    upstream_stream: S,
+    response_id: String,
+    router_name: String,
+    verbose_logging: bool,
+    tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
+    feature_flags: crate::FeatureFlags,
+    mut session_turn: Option<SessionTurn>,
+) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
+{
+    stream! {
+        let mut upstream_stream = Box::pin(upstream_stream);
+        let mut parser = SseParser::default();
+        let mut state = AnthropicToResponsesStreamState::new(
+            response_id.clone(),
+            tool_call_kinds_by_name,
+            feature_flags,
+        );
+
+        yield Ok(sse_event(
+            "response.created",
+            &json!({
+                "type": "response.created",
+                "response": {
+                    "id": response_id.clone(),
+                }
+            }),
+        ));
+        if feature_flags.enable_extended_stream_events {
+            yield Ok(sse_event(
+                "response.in_progress",
+                &json!({
+                    "type": "response.in_progress",
+                    "response": {
+                        "id": response_id.clone(),
+                    }
+                }),
+            ));
+        }
+
+        while let Some(chunk_result) = upstream_stream.next().await {
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    let err: UpstreamStreamError = err.into();
+                    yield Ok(state.failed_event(err.code(), &err.to_string()));
+                    return;
+                }
+            };
+
+            if verbose_logging {
+                debug_large_log(
+                    &format!(
+                        "upstream response payload stream chunk (router={}, messages->responses)",
+                        router_name
+                    ),
+                    String::from_utf8_lossy(&chunk).as_ref(),
+                );
+            }
+
+            let text = String::from_utf8_lossy(&chunk);
+            for data in parser.feed(&text) {
+                let events = state.process_event(&data);
+                if state.completed
+                    && let Some(session_turn) = session_turn.take()
+                {
+                    state.store_turn(session_turn).await;
+                }
+                for event in events {
+                    yield Ok(event);
+                }
+                if state.done {
+                    return;
+                }
+            }
+        }
+
+        if let Some(data) = parser.finish() {
+            let events = state.process_event(&data);
+            if state.completed
+                && let Some(session_turn) = session_turn.take()
+            {
+                state.store_turn(session_turn).await;
+            }
+            for event in events {
+                yield Ok(event);
+            }
+        }
+
+        if !state.done {
+            yield Ok(state.failed_event(
+                "upstream_stream_incomplete",
+                "upstream stream ended before terminal marker",
+            ));
+        }
+    }
+}
+
+enum AnthropicBlockState {
+    Text {
+        item_id: String,
+        text: String,
+    },
+    Thinking {
+        item_id: String,
+        thinking: String,
+        signature: String,
+    },
+    ToolUse {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+}
+
+struct AnthropicToResponsesStreamState {
+    response_id: String,
+    tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
+    feature_flags: crate::FeatureFlags,
+    blocks: BTreeMap<usize, AnthropicBlockState>,
+    output: Vec<Value>,
+    usage: serde_json::Map<String, Value>,
+    stop_reason: Option<String>,
+    completed: bool,
+    done: bool,
+}
+
+impl AnthropicToResponsesStreamState {
+    fn new(
+        response_id: String,
+        tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
+        feature_flags: crate::FeatureFlags,
+    ) -> Self {
+        Self {
+            response_id,
+            tool_call_kinds_by_name,
+            feature_flags,
+            blocks: BTreeMap::new(),
+            output: Vec::new(),
+            usage: serde_json::Map::new(),
+            stop_reason: None,
+            completed: false,
+            done: false,
+        }
+    }
+
+    fn process_event(&mut self, data: &str) -> Vec<Bytes> {
+        let Ok(payload) = serde_json::from_str::<Value>(data) else {
+            warn!("failed to decode upstream messages stream event");
+            return Vec::new();
+        };
+        let index = payload
+            .get("index")
+            .and_then(Value::as_u64)
+            .map(|index| index as usize)
+            .unwrap_or_default();
+
+        match payload
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+        {
+            "message_start" => {
+                self.merge_usage(payload.pointer("/message/usage"));
+                Vec::new()
+            }
+            "content_block_start" => {
+                self.start_block(index, payload.get("content_block").unwrap_or(&Value::Null))
+            }
+            "content_block_delta" => {
+                self.apply_delta(index, payload.get("delta").unwrap_or(&Value::Null))
+            }
+            "content_block_stop" => self.stop_block(index),
+            "message_delta" => {
+                if let Some(stop_reason) = payload
+                    .pointer("/delta/stop_reason")
+                    .and_then(Value::as_str)
+                {
+                    self.stop_reason = Some(stop_reason.to_string());
+                }
+                self.merge_usage(payload.get("usage"));
+                Vec::new()
+            }
+            "message_stop" => {
+                let mut events = self.stop_open_blocks();
+                events.push(self.completed_event());
+                self.completed = true;
+                self.done = true;
+                events
+            }
+            "error" => {
+                let code = payload
+                    .pointer("/error/type")
+                    .and_then(Value::as_str)
+                    .unwrap_or("upstream_error");
+                let message = payload
+                    .pointer("/error/message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("upstream stream error");
+                self.done = true;
+                vec![self.failed_event(code, message)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn start_block(&mut self, index: usize, block: &Value) -> Vec<Bytes> {
+        let (state, item) = match block
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+        {
+            "text" => {
+                let item_id = format!("msg_{}_{index}", self.response_id);
+                let item = json!({
+                    "type": "message",
+                    "id": item_id,
+                    "role": "assistant",
+                    "content": [],
+                });
+                (
+                    AnthropicBlockState::Text {
+                        item_id,
+                        text: String::new(),
+                    },
+                    item,
+                )
+            }
+            "thinking" => {
+                let item_id = format!("rs_{}_{index}", self.response_id);
+                let item = anthropic_thinking_to_reasoning_item(&item_id, "", None);
+                (
+                    AnthropicBlockState::Thinking {
+                        item_id,
+                        thinking: String::new(),
+                        signature: String::new(),
+                    },
+                    item,
+                )
+            }
+            "tool_use" => {
+                let call_id = block
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string)
+                    .unwrap_or_else(|| deterministic_tool_call_id(&self.response_id, index));
+                let name = block
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unknown_function")
+                    .to_string();
+                let item =
+                    responses_tool_call_item(&name, "", &call_id, &self.tool_call_kinds_by_name);
+                (
+                    AnthropicBlockState::ToolUse {
+                        call_id,
+                        name,
+                        arguments: String::new(),
+                    },
+                    item,
+                )
+            }
+            _ => return Vec::new(),
+        };
+
+        let is_thinking = matches!(state, AnthropicBlockState::Thinking { .. });
+        self.blocks.insert(index, state);
+        if is_thinking && !self.feature_flags.enable_reasoning_stream_events {
+            return Vec::new();
+        }
+        let mut events = vec![sse_event(
+            "response.output_item.added",
+            &json!({
+                "type": "response.output_item.added",
+                "output_index": index,
+                "item": item,
+            }),
+        )];
+        if let Some(AnthropicBlockState::Text { item_id, .. }) = self.blocks.get(&index)
+            && self.feature_flags.enable_extended_stream_events
+        {
+            events.push(sse_event(
+                "response.content_part.added",
+                &json!({
+                    "type": "response.content_part.added",
+                    "item_id": item_id,
+                    "output_index": index,
+                    "content_index": 0,
+                    "part": {
+                        "type": "output_text",
+                        "text": "",
+                    }
+                }),
+            ));
+        }
+        events
+    }
+
+    fn apply_delta(&mut self, index: usize, delta: &Value) -> Vec<Bytes> {
+        let feature_flags = self.feature_flags;
+        let Some(state) = self.blocks.get_mut(&index) else {
+            return Vec::new();
+        };
+        let delta_type = delta
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        match (state, delta_type) {
+            (AnthropicBlockState::Text { item_id, text }, "text_delta") => {
+                let delta = delta
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                text.push_str(delta);
+                vec![sse_event(
+                    "response.output_text.delta",
+                    &json!({
+                        "type": "response.output_text.delta",
+                        "item_id": item_id,
+                        "output_index": index,
+                        "content_index": 0,
+                        "delta": delta,
+                    }),
+                )]
+            }
+            (
+                AnthropicBlockState::Thinking {
+                    item_id, thinking, ..
+                },
+                "thinking_delta",
+            ) => {
+                let delta = delta
+                    .get("thinking")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                thinking.push_str(delta);
+                if !feature_flags.enable_reasoning_stream_events {
+                    return Vec::new();
+                }
+                vec![sse_event(
+                    "response.reasoning_summary_text.delta",
+                    &json!({
+                        "type": "response.reasoning_summary_text.delta",
+                        "item_id": item_id,
+                        "output_index": index,
+                        "summary_index": 0,
+                        "delta": delta,
+                    }),
+                )]
+            }
+            (AnthropicBlockState::Thinking { signature, .. }, "signature_delta") => {
+                signature.push_str(
+                    delta
+                        .get("signature")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default(),
+                );
+                Vec::new()
+            }
+            (
+                AnthropicBlockState::ToolUse {
+                    call_id, arguments, ..
+                },
+                "input_json_delta",
+            ) => {
+                let delta = delta
+                    .get("partial_json")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                arguments.push_str(delta);
+                if delta.is_empty() || !feature_flags.enable_tool_argument_stream_events {
+                    return Vec::new();
+                }
+                vec![sse_event(
+                    "response.function_call_arguments.delta",
+                    &json!({
+                        "type": "response.function_call_arguments.delta",
+                        "item_id": call_id,
+                        "output_index": index,
+                        "delta": delta,
+                    }),
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn stop_block(&mut self, index: usize) -> Vec<Bytes> {
+        let Some(state) = self.blocks.remove(&index) else {
+            return Vec::new();
+        };
+        let mut events = Vec::new();
+        let item = match state {
+            AnthropicBlockState::Text { item_id, text } => {
+                if self.feature_flags.enable_extended_stream_events {
+                    events.push(sse_event(
+                        "response.output_text.done",
+                        &json!({
+                            "type": "response.output_text.done",
+                            "item_id": item_id,
+                            "output_index": index,
+                            "content_index": 0,
+                            "text": text,
+                        }),
+                    ));
+                    events.push(sse_event(
+                        "response.content_part.done",
+                        &json!({
+                            "type": "response.content_part.done",
+                            "item_id": item_id,
+                            "output_index": index,
+                            "content_index": 0,
+                            "part": {
+                                "type": "output_text",
+                                "text": text,
+                            }
+                        }),
+                    ));
+                }
+                json!({
+                    "type": "message",
+                    "id": item_id,
+                    "role": "assistant",
+                    "content": [{
+                        "type": "output_text",
+                        "text": text,
+                    }]
+                })
+            }
+            AnthropicBlockState::Thinking {
+                item_id,
+                thinking,
+                signature,
+            } => {
+                if !self.feature_flags.enable_reasoning_stream_events {
+                    return Vec::new();
+                }
+                events.push(sse_event(
+                    "response.reasoning_summary_text.done",
+                    &json!({
+                        "type": "response.reasoning_summary_text.done",
+                        "item_id": item_id,
+                        "output_index": index,
+                        "summary_index": 0,
+                        "text": thinking,
+                    }),
+                ));
+                anthropic_thinking_to_reasoning_item(&item_id, &thinking, Some(&signature))
+            }
+            AnthropicBlockState::ToolUse {
+                call_id,
+                name,
+                arguments,
+            } => {
+                let arguments = if arguments.trim().is_empty() {
+                    "{}".to_string()
+                } else {
+                    arguments
+                };
+                if self.feature_flags.enable_tool_argument_stream_events {
+                    events.push(sse_event(
+                        "response.function_call_arguments.done",
+                        &json!({
+                            "type": "response.function_call_arguments.done",
+                            "item_id": call_id,
+                            "output_index": index,
+                            "arguments": arguments,
+                        }),
+                    ));
+                }
+                responses_tool_call_item(&name, &arguments, &call_id, &self.tool_call_kinds_by_name)
+            }
+        };
+        self.output.push(item.clone());
+        events.push(sse_event(
+            "response.output_item.done",
+            &json!({
+                "type": "response.output_item.done",
+                "output_index": index,
+                "item": item,
+            }),
+        ));
+        events
+    }
+
+    fn stop_open_blocks(&mut self) -> Vec<Bytes> {
+        let indexes = self.blocks.keys().copied().collect::<Vec<_>>();
+        indexes
+            .into_iter()
+            .flat_map(|index| self.stop_block(index))
+            .collect()
+    }
+
+    fn merge_usage(&mut self, usage: Option<&Value>) {
+        if let Some(usage) = usage.and_then(Value::as_object) {
+            for (key, value) in usage {
+                if !value.is_null() {
+                    self.usage.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    fn responses_usage(&self) -> Option<Value> {
+        anthropic_usage_to_responses_usage(Some(&Value::Object(self.usage.clone())))
+    }
+
+    async fn store_turn(&self, session_turn: SessionTurn) {
+        session_turn
+            .complete(&self.output, self.responses_usage().as_ref())
+            .await;
+    }
+
+    fn completed_event(&self) -> Bytes {
+        let usage = self.responses_usage();
+        if self.stop_reason.as_deref() == Some("max_tokens") {
+            return sse_event(
+                "response.incomplete",
+                &json!({
+                    "type": "response.incomplete",
+                    "response": {
+                        "id": self.response_id,
+                        "status": "incomplete",
+                        "incomplete_details": {"reason": "max_output_tokens"},
+                        "output": self.output,
+                        "usage": usage,
+                    }
+                }),
+            );
+        }
+        sse_event(
+            "response.completed",
+            &json!({
+                "type": "response.completed",
+                "response": {
+                    "id": self.response_id,
+                    "status": "completed",
+                    "output": self.output,
+                    "usage": usage,
+                }
+            }),
+        )
+    }
+
+    fn failed_event(&self, code: &str, message: &str) -> Bytes {
+        sse_event(
+            "response.failed",
+            &json!({
+                "type": "response.failed",
+                "response": {
+                    "id": self.response_id,
+                    "error": {
+                        "code": code,
+                        "message": message,
+                    }
+                }
+            }),
+        )
+    }
+}
+
+pub(crate) fn translate_anthropic_stream_to_chat<S, E>(
+    upstream_stream: S,
+    router_name: String,
+    verbose_logging: bool,
+    model: String,
+) -> impl Stream<Item = Result<Bytes, std::convert::Infallible>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
+{
+    stream! {
+        let mut upstream_stream = Box::pin(upstream_stream);
+        let mut parser = SseParser::default();
+        let mut state = AnthropicToChatStreamState::new(model);
+
+        while let Some(chunk_result) = upstream_stream.next().await {
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    let err: UpstreamStreamError = err.into();
+                    yield Ok(chat_sse_data(&json!({
+                        "error": {
+                            "type": err.code(),
+                            "message": err.to_string(),
+                        }
+                    })));
+                    yield Ok(Bytes::from("data: [DONE]\n\n"));
+                    return;
+                }
+            };
+
+            if verbose_logging {
+                debug_large_log(
+                    &format!(
+                        "upstream response payload stream chunk (router={}, messages->chat)",
+                        router_name
+                    ),
+                    String::from_utf8_lossy(&chunk).as_ref(),
+                );
+            }
+
+            let text = String::from_utf8_lossy(&chunk);
+            for data in parser.feed(&text) {
+                for event in state.process_event(&data) {
+                    yield Ok(event);
+                }
+                if state.done {
+                    return;
+                }
+            }
+        }
+
+        if let Some(data) = parser.finish() {
+            for event in state.process_event(&data) {
+                yield Ok(event);
+            }
+        }
+
+        if !state.done {
+            yield Ok(chat_sse_data(&json!({
+                "error": {
+                    "type": "upstream_stream_incomplete",
+                    "message": "upstream stream ended before terminal marker",
+                }
+            })));
+            yield Ok(Bytes::from("data: [DONE]\n\n"));
+        }
+    }
+}
+
+struct AnthropicToChatStreamState {
+    id: String,
+    model: String,
+    tool_index_by_block: HashMap<usize, usize>,
+    usage: serde_json::Map<String, Value>,
+    stop_reason: Option<String>,
+    done: bool,
+}
+
+impl AnthropicToChatStreamState {
+    fn new(model: String) -> Self {
+        Self {
+            id: format!("chatcmpl_{}", uuid::Uuid::now_v7()),
+            model,
+            tool_index_by_block: HashMap::new(),
+            usage: serde_json::Map::new(),
+            stop_reason: None,
+            done: false,
+        }
+    }
+
+    fn process_event(&mut self, data: &str) -> Vec<Bytes> {
+        let Ok(payload) = serde_json::from_str::<Value>(data) else {
+            warn!("failed to decode upstream messages stream event");
+            return Vec::new();
+        };
+        let index = payload
+            .get("index")
+            .and_then(Value::as_u64)
+            .map(|index| index as usize)
+            .unwrap_or_default();
+
+        match payload
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+        {
+            "message_start" => {
+                let message = payload.get("message");
+                if let Some(id) = message.and_then(|m| m.get("id")).and_then(Value::as_str) {
+                    self.id = id.to_string();
+                }
+                self.merge_usage(message.and_then(|m| m.get("usage")));
+                vec![self.chat_chunk(json!({"role": "assistant", "content": ""}), None, None)]
+            }
+            "content_block_start" => {
+                let block = payload.get("content_block").unwrap_or(&Value::Null);
+                if block.get("type").and_then(Value::as_str) != Some("tool_use") {
+                    return Vec::new();
+                }
+                let tool_index = self.tool_index_by_block.len();
+                self.tool_index_by_block.insert(index, tool_index);
+                vec![self.chat_chunk(
+                    json!({
+                        "tool_calls": [{
+                            "index": tool_index,
+                            "id": block.get("id").and_then(Value::as_str).unwrap_or_default(),
+                            "type": "function",
+                            "function": {
+                                "name": block.get("name").and_then(Value::as_str).unwrap_or("unknown_function"),
+                                "arguments": "",
+                            }
+                        }]
+                    }),
+                    None,
+                    None,
+                )]
+            }
+            "content_block_delta" => {
+                let delta = payload.get("delta").unwrap_or(&Value::Null);
+                let text = |key: &str| {
+                    delta
+                        .get(key)
+                        .and_then(Value::as_str)
+                        .filter(|text| !text.is_empty())
+                };
+                let chat_delta = match delta
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                {
+                    "text_delta" => text("text").map(|text| json!({"content": text})),
+                    "thinking_delta" => {
+                        text("thinking").map(|text| json!({"reasoning_content": text}))
+                    }
+                    "input_json_delta" => text("partial_json").and_then(|arguments| {
+                        let tool_index = self.tool_index_by_block.get(&index)?;
+                        Some(json!({
+                            "tool_calls": [{
+                                "index": tool_index,
+                                "function": {
+                                    "arguments": arguments,
+                                }
+                            }]
+                        }))
+                    }),
+                    _ => None,
+                };
+                chat_delta
+                    .map(|delta| vec![self.chat_chunk(delta, None, None)])
+                    .unwrap_or_default()
+            }
+            "message_delta" => {
+                if let Some(stop_reason) = payload
+                    .pointer("/delta/stop_reason")
+                    .and_then(Value::as_str)
+                {
+                    self.stop_reason = Some(stop_reason.to_string());
+                }
+                self.merge_usage(payload.get("usage"));
+                Vec::new()
+            }
+            "message_stop" => {
+                self.done = true;
+                let finish_reason =
+                    anthropic_stop_reason_to_chat_finish_reason(self.stop_reason.as_deref());
+                let usage = anthropic_usage_to_chat_usage(Some(&Value::Object(self.usage.clone())));
+                vec![
+                    self.chat_chunk(json!({}), Some(finish_reason), usage),
+                    Bytes::from("data: [DONE]\n\n"),
+                ]
+            }
+            "error" => {
+                self.done = true;
+                vec![
+                    chat_sse_data(&json!({
+                        "error": {
+                            "type": payload
+                                .pointer("/error/type")
+                                .and_then(Value::as_str)
+                                .unwrap_or("upstream_error"),
+                            "message": payload
+                                .pointer("/error/message")
+                                .and_then(Value::as_str)
+                                .unwrap_or("upstream stream error"),
+                        }
+                    })),
+                    Bytes::from("data: [DONE]\n\n"),
+                ]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn merge_usage(&mut self, usage: Option<&Value>) {
+        if let Some(usage) = usage.and_then(Value::as_object) {
+            for (key, value) in usage {
+                if !value.is_null() {
+                    self.usage.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    fn chat_chunk(&self, delta: Value, finish_reason: Option<&str>, usage: Option<Value>) -> Bytes {
+        let mut chunk = json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "delta": delta,
+                "finish_reason": finish_reason,
+            }],
+        });
+        if let Some(usage) = usage
+            && let Some(obj) = chunk.as_object_mut()
+        {
+            obj.insert("usage".to_string(), usage);
+        }
+        chat_sse_data(&chunk)
+    }
+}
+
+pub(crate) fn translate_gemini_stream_to_chat<S, E>(
+    upstream_stream: S,
+    router_name: String,
+    verbose_logging: bool,
+    model: String,
+) -> impl Stream<Item = Result<Bytes, UpstreamStreamError>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
+{
+    stream! {
+        let mut upstream_stream = Box::pin(upstream_stream);
+        let mut parser = SseParser::default();
+        let mut state = GeminiToChatStreamState::new(model);
+
+        while let Some(chunk_result) = upstream_stream.next().await {
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    yield Err(err.into());
+                    return;
+                }
+            };
+
+            if verbose_logging {
+                debug_large_log(
+                    &format!(
+                        "upstream response payload stream chunk (router={}, gemini->chat)",
+                        router_name
+                    ),
+                    String::from_utf8_lossy(&chunk).as_ref(),
+                );
+            }
+
+            let text = String::from_utf8_lossy(&chunk);
+            for data in parser.feed(&text) {
+                for event in state.process_event(&data) {
+                    yield Ok(event);
+                }
+            }
+        }
+
+        if let Some(data) = parser.finish() {
+            for event in state.process_event(&data) {
+                yield Ok(event);
+            }
+        }
+
+        // Gemini has no terminal marker; only a finish reason marks a complete stream.
+        match state.finish_reason {
+            Some(finish_reason) => {
+                yield Ok(state.chat_chunk(json!({}), Some(finish_reason), state.usage.clone()));
+                yield Ok(Bytes::from("data: [DONE]\n\n"));
+            }
+            None => {
+                yield Ok(chat_sse_data(&json!({
+                    "error": {
+                        "type": "upstream_stream_incomplete",
+                        "message": "upstream stream ended before terminal marker",
+                    }
+                })));
+                yield Ok(Bytes::from("data: [DONE]\n\n"));
+            }
+        }
+    }
+}
+
+struct GeminiToChatStreamState {
+    id: String,
+    model: String,
+    next_tool_index: usize,
+    finish_reason: Option<&'static str>,
+    usage: Option<Value>,
+}
+
+impl GeminiToChatStreamState {
+    fn new(model: String) -> Self {
+        Self {
+            id: format!("chatcmpl_{}", uuid::Uuid::now_v7()),
+            model,
+            next_tool_index: 0,
+            finish_reason: None,
+            usage: None,
+        }
+    }
+
+    fn process_event(&mut self, data: &str) -> Vec<Bytes> {
+        let Ok(payload) = serde_json::from_str::<Value>(data) else {
+            warn!("failed to decode upstream gemini stream chunk");
+            return Vec::new();
+        };
+        if let Some(usage) = gemini_usage_to_chat_usage(payload.get("usageMetadata")) {
+            self.usage = Some(usage);
+        }
+        let Some(candidate) = payload
+            .get("candidates")
+            .and_then(Value::as_array)
+            .and_then(|candidates| candidates.first())
+        else {
+            return Vec::new();
+        };
+
+        let parts = candidate
+            .pointer("/content/parts")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let (text, reasoning, tool_calls) = gemini_parts_to_chat_delta(parts);
+        let mut events = Vec::new();
+        if !reasoning.is_empty() {
+            events.push(self.chat_chunk(json!({"reasoning_content": reasoning}), None, None));
+        }
+        if !text.is_empty() {
+            events.push(self.chat_chunk(json!({"content": text}), None, None));
+        }
+        if !tool_calls.is_empty() {
+            let tool_calls = tool_calls
+                .into_iter()
+                .map(|mut tool_call| {
+                    tool_call["index"] = json!(self.next_tool_index);
+                    self.next_tool_index += 1;
+                    tool_call
+                })
+                .collect::<Vec<_>>();
+            events.push(self.chat_chunk(json!({"tool_calls": tool_calls}), None, None));
+        }
+        if let Some(finish_reason) = candidate.get("finishReason").and_then(Value::as_str) {
+            self.finish_reason = Some(gemini_finish_reason_to_chat(
+                finish_reason,
+                self.next_tool_index > 0,
+            ));
+        }
+        events
+    }
+
+    fn chat_chunk(&self, delta: Value, finish_reason: Option<&str>, usage: Option<Value>) -> Bytes {
+        let mut chunk = json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "delta": delta,
+                "finish_reason": finish_reason,
+            }],
+        });
+        if let Some(usage) = usage
+            && let Some(obj) = chunk.as_object_mut()
+        {
+            obj.insert("usage".to_string(), usage);
+        }
+        chat_sse_data(&chunk)
+    }
+}
+
+pub(crate) fn translate_ollama_stream_to_chat<S, E>(
+    upstream_stream: S,
+    router_name: String,
+    verbose_logging: bool,
+    model: String,
+) -> impl Stream<Item = Result<Bytes, UpstreamStreamError>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
+{
+    stream! {
+        let mut upstream_stream = Box::pin(upstream_stream);
+        let mut parser = NdjsonParser::default();
+        let mut state = OllamaToChatStreamState::new(model);
+
+        while let Some(chunk_result) = upstream_stream.next().await {
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    yield Err(err.into());
+                    return;
+                }
+            };
+
+            if verbose_logging {
+                debug_large_log(
+                    &format!(
+                        "upstream response payload stream chunk (router={}, ollama->chat)",
+                        router_name
+                    ),
+                    String::from_utf8_lossy(&chunk).as_ref(),
+                );
+            }
+
+            let text = String::from_utf8_lossy(&chunk);
+            for line in parser.feed(&text) {
+                for event in state.process_line(&line) {
+                    yield Ok(event);
+                }
+            }
+            if state.finished {
+                return;
+            }
+        }
+
+        if let Some(line) = parser.finish() {
+            for event in state.process_line(&line) {
+                yield Ok(event);
+            }
+        }
+        if !state.finished {
+            yield Ok(chat_sse_data(&json!({
+                "error": {
+                    "type": "upstream_stream_incomplete",
+                    "message": "upstream stream ended before terminal marker",
+                }
+            })));
+            yield Ok(Bytes::from("data: [DONE]\n\n"));
+        }
+    }
+}
+
+struct OllamaToChatStreamState {
+    id: String,
+    model: String,
+    next_tool_index: usize,
+    finished: bool,
+}
+
+impl OllamaToChatStreamState {
+    fn new(model: String) -> Self {
+        Self {
+            id: format!("chatcmpl_{}", uuid::Uuid::now_v7()),
+            model,
+            next_tool_index: 0,
+            finished: false,
+        }
+    }
+
+    fn process_line(&mut self, line: &str) -> Vec<Bytes> {
+        if self.finished {
+            return Vec::new();
+        }
+        let Ok(payload) = serde_json::from_str::<Value>(line) else {
+            warn!("failed to decode upstream ollama stream line");
+            return Vec::new();
+        };
+        if let Some(message) = payload.get("error") {
+            self.finished = true;
+            return vec![
+                chat_sse_data(&json!({
+                    "error": {
+                        "type": "upstream_error",
+                        "message": message.as_str().map_or_else(|| message.to_string(), ToString::to_string),
+                    }
+                })),
+                Bytes::from("data: [DONE]\n\n"),
+            ];
+        }
+
+        let (text, reasoning, tool_calls) = ollama_message_to_chat_delta(payload.get("message"));
+        let mut events = Vec::new();
+        if !reasoning.is_empty() {
+            events.push(self.chat_chunk(json!({"reasoning_content": reasoning}), None, None));
+        }
+        if !text.is_empty() {
+            events.push(self.chat_chunk(json!({"content": text}), None, None));
+        }
+        if !tool_calls.is_empty() {
+            let tool_calls = tool_calls
+                .into_iter()
+                .map(|mut tool_call| {
+                    tool_call["index"] = json!(self.next_tool_index);
+                    self.next_tool_index += 1;
+                    tool_call
+                })
+                .collect::<Vec<_>>();
+            events.push(self.chat_chunk(json!({"tool_calls": tool_calls}), None, None));
+        }
+        if payload.get("done").and_then(Value::as_bool) == Some(true) {
+            self.finished = true;
+            let finish_reason = ollama_done_reason_to_chat(
+                payload.get("done_reason").and_then(Value::as_str),
+                self.next_tool_index > 0,
+            );
+            events.push(self.chat_chunk(
+                json!({}),
+                Some(finish_reason),
+                ollama_usage_to_chat_usage(&payload),
+            ));
+            events.push(Bytes::from("data: [DONE]\n\n"));
+        }
+        events
+    }
+
+    fn chat_chunk(&self, delta: Value, finish_reason: Option<&str>, usage: Option<Value>) -> Bytes {
+        let mut chunk = json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": 0,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "delta": delta,
+                "finish_reason": finish_reason,
+            }],
+        });
+        if let Some(usage) = usage
+            && let Some(obj) = chunk.as_object_mut()
+        {
+            obj.insert("usage".to_string(), usage);
+        }
+        chat_sse_data(&chunk)
+    }
+}
+
+fn deterministic_tool_call_id(response_id: &str, index: usize) -> String {
+    format!("call_{}_{}", response_id, index)
+}
+
+fn reasoning_item_id(response_id: &str) -> String {
+    format!("rs_{}", response_id)
+}
+
+fn tool_output_index(index: usize) -> usize {
+    index + 1
 }
 
 pub(crate) fn sse_event(event_name: &str, payload: &Value) -> Bytes {
@@ -1388,6 +2970,26 @@ fn emit_thinking_segment(
     ));
 }
 
+impl NdjsonParser {
+    pub(crate) fn feed(&mut self, chunk: &str) -> Vec<String> {
+        self.buffer.push_str(chunk);
+        let mut lines = Vec::new();
+        while let Some(pos) = self.buffer.find('\n') {
+            let line = self.buffer[..pos].trim().to_string();
+            self.buffer.drain(..=pos);
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.buffer).trim().to_string();
+        (!line.is_empty()).then_some(line)
+    }
+}
+
 impl SseParser {
     pub(crate) fn feed(&mut self, chunk: &str) -> Vec<String> {
         self.feed_with_event_names(chunk)
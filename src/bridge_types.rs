@@ -102,3 +102,8 @@ pub(crate) struct SseParser {
     pub(crate) current_event_name: Option<String>,
     pub(crate) current_data_lines: Vec<String>,
 }
+
+#[derive(Debug, Default)]
+pub(crate) struct NdjsonParser {
+    pub(crate) buffer: String,
+}
@@ -0,0 +1,247 @@
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use async_stream::stream;
+use axum::body::Bytes;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use futures::Stream;
+use futures::StreamExt;
+use serde_json::Value;
+use serde_json::json;
+use std::fs::{self};
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::bridge::mapping::responses_tool_call_kind_by_name;
+use crate::bridge::streaming::passthrough_chat_stream;
+use crate::bridge::streaming::translate_chat_stream;
+use crate::bridge::streaming::translate_chat_stream_to_anthropic;
+use crate::bridge::streaming::translate_gemini_stream_to_chat;
+use crate::bridge::streaming::translate_ollama_stream_to_chat;
+use crate::logging_utils::headers_for_logging;
+use crate::model::FeatureFlags;
+use crate::model::FeatureFlagsConfig;
+use crate::model::IncomingApi;
+use crate::model::WireApi;
+use crate::timeouts::UpstreamStreamError;
+
+pub(crate) const UPSTREAM_STREAM_FILE: &str = "upstream_response.sse";
+pub(crate) const CLIENT_STREAM_FILE: &str = "client_response.sse";
+const EXCHANGE_FILE: &str = "exchange.json";
+const INCOMING_BODY_FILE: &str = "incoming_body.json";
+
+pub(crate) struct Capture {
+    dir: PathBuf,
+}
+
+impl Capture {
+    pub(crate) async fn start(root: &Path, router_name: &str) -> Option<Arc<Self>> {
+        let router_name = router_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        let dir = root.join(format!("{}-{router_name}", Uuid::now_v7()));
+        match tokio::fs::create_dir_all(&dir).await {
+            Ok(()) => Some(Arc::new(Self { dir })),
+            Err(err) => {
+                warn!(
+                    "capture disabled for request: dir={}, error={err}",
+                    dir.display()
+                );
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn record_incoming(
+        &self,
+        incoming_route: &str,
+        headers: &HeaderMap,
+        body: &str,
+    ) {
+        self.write_json(
+            "incoming_headers.json",
+            &json!({
+                "incoming_route": incoming_route,
+                "headers": headers_for_logging(headers),
+            }),
+        )
+        .await;
+        self.write_bytes(INCOMING_BODY_FILE, body.as_bytes()).await;
+    }
+
+    pub(crate) async fn record_upstream_request(
+        &self,
+        exchange: Value,
+        upstream_request_headers: &Value,
+        upstream_payload: &Value,
+    ) {
+        self.write_json(EXCHANGE_FILE, &exchange).await;
+        self.write_json("upstream_request_headers.json", upstream_request_headers)
+            .await;
+        self.write_json("upstream_payload.json", upstream_payload)
+            .await;
+    }
+
+    pub(crate) async fn record_upstream_error(
+        &self,
+        status: StatusCode,
+        headers: &Value,
+        body: &str,
+    ) {
+        self.write_json(
+            "upstream_error.json",
+            &json!({
+                "status": status.as_u16(),
+                "headers": headers,
+                "body": body,
+            }),
+        )
+        .await;
+    }
+
+    pub(crate) async fn write_json(&self, file: &str, value: &Value) {
+        match serde_json::to_vec_pretty(value) {
+            Ok(data) => self.write_bytes(file, &data).await,
+            Err(err) => warn!("capture write failed: file={file}, error={err}"),
+        }
+    }
+
+    async fn write_bytes(&self, file: &str, data: &[u8]) {
+        let path = self.dir.join(file);
+        if let Err(err) = tokio::fs::write(&path, data).await {
+            warn!("capture write failed: path={}, error={err}", path.display());
+        }
+    }
+
+    pub(crate) fn tee<S, E>(
+        &self,
+        file: &str,
+        stream: S,
+    ) -> impl Stream<Item = Result<Bytes, E>> + use<S, E>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+    {
+        let path = self.dir.join(file);
+        stream! {
+            let mut sink = match tokio::fs::File::create(&path).await {
+                Ok(sink) => Some(sink),
+                Err(err) => {
+                    warn!("capture write failed: path={}, error={err}", path.display());
+                    None
+                }
+            };
+            for await chunk in stream {
+                if let (Ok(bytes), Some(file)) = (&chunk, sink.as_mut())
+                    && let Err(err) = file.write_all(bytes).await
+                {
+                    warn!("capture write failed: path={}, error={err}", path.display());
+                    sink = None;
+                }
+                yield chunk;
+            }
+            if let Some(file) = sink.as_mut()
+                && let Err(err) = file.flush().await
+            {
+                warn!("capture write failed: path={}, error={err}", path.display());
+            }
+        }
+    }
+}
+
+pub(crate) async fn replay_capture(dir: &Path, out: &mut impl Write) -> Result<()> {
+    let exchange_path = dir.join(EXCHANGE_FILE);
+    let exchange: Value = serde_json::from_slice(
+        &fs::read(&exchange_path)
+            .with_context(|| format!("reading {}", exchange_path.display()))?,
+    )
+    .with_context(|| format!("parsing {}", exchange_path.display()))?;
+    let field = |name: &str| exchange.get(name).cloned().unwrap_or(Value::Null);
+    let incoming_api = serde_json::from_value::<IncomingApi>(field("incoming_api"))
+        .context("exchange.json: invalid `incoming_api`")?;
+    let upstream_wire = serde_json::from_value::<WireApi>(field("upstream_wire"))
+        .context("exchange.json: invalid `upstream_wire`")?;
+    let features = serde_json::from_value::<Option<FeatureFlagsConfig>>(field("features"))
+        .context("exchange.json: invalid `features`")?;
+    let feature_flags = FeatureFlags::default().with_overrides(features.as_ref());
+    let router_name = field("router").as_str().unwrap_or("replay").to_string();
+    let model = field("upstream_model")
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let response_id = field("response_id")
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("resp_bridge_{}", Uuid::now_v7()));
+    let input_tokens = field("anthropic_input_tokens").as_i64().unwrap_or(0);
+
+    let upstream_path = dir.join(UPSTREAM_STREAM_FILE);
+    if !upstream_path.exists() {
+        return Err(anyhow!(
+            "{} not found; only streaming exchanges can be replayed",
+            upstream_path.display()
+        ));
+    }
+    let raw =
+        fs::read(&upstream_path).with_context(|| format!("reading {}", upstream_path.display()))?;
+    let upstream = futures::stream::iter([Ok::<_, UpstreamStreamError>(Bytes::from(raw))]);
+    let chat_stream = match upstream_wire {
+        WireApi::Chat => upstream.boxed(),
+        WireApi::Ollama => {
+            translate_ollama_stream_to_chat(upstream, router_name.clone(), false, model.clone())
+                .boxed()
+        }
+        WireApi::Gemini => {
+            translate_gemini_stream_to_chat(upstream, router_name.clone(), false, model.clone())
+                .boxed()
+        }
+        WireApi::Responses | WireApi::Messages => {
+            return Err(anyhow!(
+                "replay only translates chat-shaped upstream streams; `{}` upstreams are passed through or translated elsewhere",
+                upstream_wire.as_str()
+            ));
+        }
+    };
+
+    let mut translated = match incoming_api {
+        IncomingApi::Anthropic => {
+            translate_chat_stream_to_anthropic(chat_stream, router_name, false, model, input_tokens)
+                .boxed()
+        }
+        IncomingApi::Chat => passthrough_chat_stream(chat_stream, router_name, false).boxed(),
+        IncomingApi::Responses => {
+            let tool_call_kinds_by_name = fs::read(dir.join(INCOMING_BODY_FILE))
+                .ok()
+                .and_then(|body| serde_json::from_slice::<Value>(&body).ok())
+                .map(|request| responses_tool_call_kind_by_name(&request))
+                .unwrap_or_default();
+            translate_chat_stream(
+                chat_stream,
+                response_id,
+                router_name,
+                false,
+                tool_call_kinds_by_name,
+                feature_flags,
+                None,
+            )
+            .boxed()
+        }
+    };
+    while let Some(Ok(chunk)) = translated.next().await {
+        out.write_all(&chunk)?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,339 @@
+use anyhow::Result;
+use anyhow::anyhow;
+use axum::http::HeaderMap;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use crate::config::Args;
+use crate::config::RouterConfig;
+use crate::config::resolve_config_path;
+use crate::credentials::ApiKey;
+use crate::credentials::ApiKeySource;
+use crate::logging_utils::upstream_headers_for_logging;
+use crate::model::IncomingApi;
+use crate::model::WireApi;
+use crate::routing::RouteTarget;
+use crate::routing::RouterManager;
+use crate::routing::describe_incoming_url;
+use crate::routing::normalize_incoming_url_to_path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Problem {
+    pub(crate) severity: Severity,
+    pub(crate) router: Option<String>,
+    pub(crate) message: String,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct CheckReport {
+    pub(crate) routes: String,
+    pub(crate) problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    fn push(&mut self, severity: Severity, router: Option<&str>, message: String) {
+        self.problems.push(Problem {
+            severity,
+            router: router.map(str::to_string),
+            message,
+        });
+    }
+
+    pub(crate) fn error_count(&self) -> usize {
+        self.problems
+            .iter()
+            .filter(|problem| problem.severity == Severity::Error)
+            .count()
+    }
+
+    fn render(&self) -> String {
+        let mut out = self.routes.clone();
+        for problem in &self.problems {
+            let severity = match problem.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            match problem.router.as_deref() {
+                Some(router) => {
+                    let _ = writeln!(out, "{severity}: [routers.{router}] {}", problem.message);
+                }
+                None => {
+                    let _ = writeln!(out, "{severity}: {}", problem.message);
+                }
+            }
+        }
+        let warnings = self.problems.len() - self.error_count();
+        let _ = writeln!(
+            out,
+            "{} error(s), {warnings} warning(s)",
+            self.error_count()
+        );
+        out
+    }
+}
+
+pub(crate) fn run_check(args: &Args) -> Result<()> {
+    let config_path = resolve_config_path(args.config.clone())?;
+    if !config_path.exists() {
+        return Err(anyhow!(
+            "config file {} does not exist",
+            config_path.display()
+        ));
+    }
+    let (config, routers) = crate::read_runtime_config(args, &config_path)?;
+    let default_api_key = ApiKey::from_source(&ApiKeySource::Env(config.api_key_env.clone()));
+    let report = check_routers(&routers, &default_api_key, |routers| {
+        crate::build_router_manager(&config, routers)
+    });
+
+    println!("config: {}", config_path.display());
+    print!("{}", report.render());
+    match report.error_count() {
+        0 => Ok(()),
+        errors => Err(anyhow!("{} has {errors} error(s)", config_path.display())),
+    }
+}
+
+pub(crate) fn check_routers(
+    routers: &BTreeMap<String, RouterConfig>,
+    default_api_key: &ApiKey,
+    build: impl Fn(BTreeMap<String, RouterConfig>) -> Result<RouterManager>,
+) -> CheckReport {
+    let mut report = CheckReport::default();
+    if routers.is_empty() {
+        report.push(
+            Severity::Error,
+            None,
+            "no [routers.*] defined; at least one router with an incoming_url is required"
+                .to_string(),
+        );
+        return report;
+    }
+
+    let mut valid = BTreeMap::new();
+    let mut claimed_routes = BTreeMap::<String, String>::new();
+    for (name, router) in routers {
+        if let Err(err) = build(BTreeMap::from([(name.clone(), router.clone())])) {
+            // Router errors already lead with `[routers.<name>]`.
+            let message = format!("{err:#}");
+            let message = message
+                .strip_prefix(&format!("[routers.{name}] "))
+                .unwrap_or(&message);
+            report.push(Severity::Error, Some(name), message.to_string());
+            continue;
+        }
+        if let Some(route) = router
+            .incoming_url
+            .as_deref()
+            .and_then(|url| describe_incoming_url(url).ok())
+        {
+            if let Some(existing) = claimed_routes.get(&route) {
+                report.push(
+                    Severity::Error,
+                    Some(name),
+                    format!("incoming_url route `{route}` is already used by [routers.{existing}]"),
+                );
+                continue;
+            }
+            claimed_routes.insert(route, name.clone());
+        }
+        valid.insert(name.clone(), router.clone());
+    }
+    if valid.is_empty() {
+        return report;
+    }
+
+    let manager = match build(valid.clone()) {
+        Ok(manager) => manager,
+        Err(err) => {
+            report.push(Severity::Error, None, format!("{err:#}"));
+            return report;
+        }
+    };
+    if manager.get_listen_addrs().is_empty() {
+        report.push(
+            Severity::Error,
+            None,
+            "no listenable incoming_url; use an absolute URL like `http://127.0.0.1:8787/v1/responses`"
+                .to_string(),
+        );
+    }
+    for (name, reason) in manager.get_api_key_failures(default_api_key) {
+        report.push(
+            Severity::Warning,
+            Some(&name),
+            format!("no upstream API key, requests will be rejected: {reason}"),
+        );
+    }
+    for snapshot in manager.get_router_delta_log_snapshots() {
+        if let Some(host) = snapshot.non_local_incoming_host
+            && snapshot.inbound_auth.is_none()
+        {
+            report.push(
+                Severity::Warning,
+                Some(&snapshot.name),
+                format!("incoming_url host `{host}` is not loopback and has no inbound_auth"),
+            );
+        }
+    }
+
+    for (name, router) in &valid {
+        let target = match manager.resolve_target_for_router_name(name) {
+            Ok(target) => target,
+            Err(err) => {
+                report.push(Severity::Error, Some(name), format!("{err:#}"));
+                continue;
+            }
+        };
+        let incoming_url = router.incoming_url.as_deref().unwrap_or_default();
+        let incoming_api = normalize_incoming_url_to_path(incoming_url)
+            .ok()
+            .and_then(|path| incoming_api_for_path(&path));
+        describe_target(&mut report.routes, &target, incoming_url, incoming_api);
+        check_target(&mut report, router, &target, incoming_api);
+    }
+    report
+}
+
+fn incoming_api_for_path(path: &str) -> Option<IncomingApi> {
+    if path.ends_with("/v1/responses") {
+        Some(IncomingApi::Responses)
+    } else if path.ends_with("/v1/chat/completions") {
+        Some(IncomingApi::Chat)
+    } else if path.ends_with("/v1/messages") {
+        Some(IncomingApi::Anthropic)
+    } else {
+        None
+    }
+}
+
+fn incoming_api_name(incoming_api: Option<IncomingApi>) -> &'static str {
+    match incoming_api {
+        Some(IncomingApi::Responses) => "responses",
+        Some(IncomingApi::Chat) => "chat",
+        Some(IncomingApi::Anthropic) => "anthropic",
+        None => "inferred per request",
+    }
+}
+
+fn describe_target(
+    out: &mut String,
+    target: &RouteTarget,
+    incoming_url: &str,
+    incoming_api: Option<IncomingApi>,
+) {
+    let _ = writeln!(out, "[routers.{}]", target.router_name);
+    let _ = writeln!(
+        out,
+        "  incoming_url = {incoming_url} ({})",
+        incoming_api_name(incoming_api)
+    );
+    for (index, candidate) in std::iter::once(target).chain(&target.fallbacks).enumerate() {
+        let label = if index == 0 {
+            "upstream_url".to_string()
+        } else {
+            format!("upstreams[{}]", index - 1)
+        };
+        let _ = writeln!(
+            out,
+            "  {label} = {} ({})",
+            candidate.upstream_url,
+            candidate.upstream_wire.as_str()
+        );
+        let models = [
+            ("upstream_model", &candidate.upstream_model),
+            ("upstream_model_opus", &candidate.upstream_model_opus),
+            ("upstream_model_sonnet", &candidate.upstream_model_sonnet),
+            ("upstream_model_haiku", &candidate.upstream_model_haiku),
+        ]
+        .into_iter()
+        .filter_map(|(key, model)| model.as_ref().map(|model| format!("{key}={model}")))
+        .collect::<Vec<_>>();
+        if !models.is_empty() {
+            let _ = writeln!(out, "    models: {}", models.join(", "));
+        }
+        let headers = upstream_headers_for_logging(
+            &HeaderMap::new(),
+            &candidate.auth_scheme,
+            "api-key",
+            candidate.upstream_wire,
+            &candidate.upstream_http_headers,
+            &[],
+        );
+        let _ = writeln!(out, "    headers: {headers}");
+    }
+    let mut drop_tool_types = target.drop_tool_types.iter().collect::<Vec<_>>();
+    drop_tool_types.sort();
+    let mut drop_request_fields = target.drop_request_fields.iter().collect::<Vec<_>>();
+    drop_request_fields.sort();
+    let _ = writeln!(
+        out,
+        "  forward_incoming_headers = {:?}",
+        target.forward_incoming_headers
+    );
+    let _ = writeln!(out, "  drop_tool_types = {drop_tool_types:?}");
+    let _ = writeln!(out, "  drop_request_fields = {drop_request_fields:?}");
+    let _ = writeln!(
+        out,
+        "  features = {}",
+        serde_json::to_value(target.feature_flags).unwrap_or_default()
+    );
+}
+
+fn check_target(
+    report: &mut CheckReport,
+    router: &RouterConfig,
+    target: &RouteTarget,
+    incoming_api: Option<IncomingApi>,
+) {
+    let name = Some(target.router_name.as_str());
+    let candidates = std::iter::once(target)
+        .chain(&target.fallbacks)
+        .collect::<Vec<_>>();
+    if let Some(incoming_api) = incoming_api {
+        if target.feature_flags.enable_responses_websocket && incoming_api != IncomingApi::Responses
+        {
+            report.push(
+                Severity::Warning,
+                name,
+                format!(
+                    "enable_responses_websocket has no effect on a {} incoming_url",
+                    incoming_api_name(Some(incoming_api))
+                ),
+            );
+        }
+        if router.tokenizer.is_some() && incoming_api != IncomingApi::Anthropic {
+            report.push(
+                Severity::Warning,
+                name,
+                "tokenizer only counts Anthropic requests, but incoming_url is not /v1/messages"
+                    .to_string(),
+            );
+        }
+    }
+    let has_wire = |wire: WireApi| {
+        candidates
+            .iter()
+            .any(|candidate| candidate.upstream_wire == wire)
+    };
+    if router.ollama.is_some() && !has_wire(WireApi::Ollama) {
+        report.push(
+            Severity::Warning,
+            name,
+            "[ollama] is set but no upstream uses the ollama wire".to_string(),
+        );
+    }
+    if router.message_roles.is_some() && !has_wire(WireApi::Chat) {
+        report.push(
+            Severity::Warning,
+            name,
+            "[message_roles] is set but no upstream uses the chat wire".to_string(),
+        );
+    }
+}
@@ -4,17 +4,27 @@ use anyhow::anyhow;
 use axum::http::HeaderName;
 use axum::http::HeaderValue;
 use clap::Parser;
+use clap::Subcommand;
 use serde::Deserialize;
 use std::collections::BTreeMap;
 use std::path::Path;
 use std::path::PathBuf;
 use tracing::info;
 
+use crate::balancer::LoadBalancingConfig;
+use crate::inbound_auth::InboundAuthConfig;
+use crate::message_roles::MessageRolesConfig;
+use crate::model::AuthScheme;
 use crate::model::DEFAULT_FORWARDED_UPSTREAM_HEADERS;
 use crate::model::FeatureFlags;
 use crate::model::FeatureFlagsConfig;
 use crate::model::UpstreamHeader;
 use crate::model::WireApi;
+use crate::retry::RetryConfig;
+use crate::session::SessionConfig;
+use crate::session::SessionPolicy;
+use crate::timeouts::TimeoutConfig;
+use crate::tokenizer::TokenizerConfig;
 
 #[derive(Debug, Clone, Parser)]
 #[command(
@@ -25,6 +35,7 @@ use crate::model::WireApi;
 pub(crate) struct Args {
     #[arg(
         long,
+        global = true,
         value_name = "FILE",
         help = "config file path (default: ~/.config/codex-chat-bridge/conf.toml)"
     )]
@@ -65,6 +76,13 @@ pub(crate) struct Args {
     #[arg(long, help = "enable verbose bridge logs (request/response payloads)")]
     pub(crate) verbose_logging: bool,
 
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "write each request/response exchange to a directory under DIR"
+    )]
+    pub(crate) capture_dir: Option<PathBuf>,
+
     #[arg(
         long = "drop-tool-type",
         value_name = "TYPE",
@@ -83,6 +101,49 @@ pub(crate) struct Args {
 
     #[arg(long, help = "list available routers from config file and exit")]
     pub(crate) list_routers: bool,
+
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub(crate) enum Command {
+    #[command(
+        about = "translate a captured upstream stream again and print the client-facing stream"
+    )]
+    Replay {
+        #[arg(
+            value_name = "DIR",
+            help = "capture directory written by --capture-dir"
+        )]
+        dir: PathBuf,
+    },
+    #[command(
+        about = "validate the config file and print every router's effective upstream target"
+    )]
+    Check,
+    #[command(
+        about = "ask for a provider, model and port, then write bridge routers, a Codex profile and a Claude Code env snippet"
+    )]
+    Init(InitArgs),
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub(crate) struct InitArgs {
+    #[arg(
+        long,
+        help = "upstream provider: openrouter, openai, anthropic, gemini or ollama"
+    )]
+    pub(crate) provider: Option<String>,
+    #[arg(long, help = "upstream model id sent for every request")]
+    pub(crate) model: Option<String>,
+    #[arg(long, help = "local port the bridge listens on")]
+    pub(crate) port: Option<u16>,
+    #[arg(
+        long,
+        help = "overwrite an existing Codex profile and Claude Code snippet"
+    )]
+    pub(crate) force: bool,
 }
 
 #[derive(Debug, Clone, Default, Deserialize)]
@@ -96,9 +157,11 @@ pub(crate) struct FileConfig {
     pub(crate) server_info: Option<PathBuf>,
     pub(crate) http_shutdown: Option<bool>,
     pub(crate) verbose_logging: Option<bool>,
+    pub(crate) capture_dir: Option<PathBuf>,
     pub(crate) drop_tool_types: Option<Vec<String>>,
     pub(crate) drop_request_fields: Option<Vec<String>>,
     pub(crate) features: Option<FeatureFlagsConfig>,
+    pub(crate) sessions: Option<SessionConfig>,
     pub(crate) routers: Option<BTreeMap<String, RouterConfig>>,
 }
 
@@ -120,6 +183,54 @@ pub(crate) struct RouterConfig {
     pub(crate) incoming_url: Option<String>,
     pub(crate) anthropic_preserve_thinking: Option<bool>,
     pub(crate) anthropic_enable_openrouter_reasoning: Option<bool>,
+    pub(crate) api_key_env: Option<String>,
+    pub(crate) api_key_file: Option<PathBuf>,
+    pub(crate) api_key_command: Option<String>,
+    pub(crate) auth_scheme: Option<AuthScheme>,
+    pub(crate) retry: Option<RetryConfig>,
+    pub(crate) upstreams: Option<Vec<UpstreamConfig>>,
+    pub(crate) weight: Option<u32>,
+    pub(crate) load_balancing: Option<LoadBalancingConfig>,
+    pub(crate) timeouts: Option<TimeoutConfig>,
+    pub(crate) inbound_auth: Option<InboundAuthConfig>,
+    pub(crate) ollama: Option<OllamaConfig>,
+    pub(crate) message_roles: Option<MessageRolesConfig>,
+    pub(crate) models: Option<Vec<String>>,
+    pub(crate) merge_upstream_models: Option<bool>,
+    pub(crate) tokenizer: Option<TokenizerConfig>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct UpstreamConfig {
+    #[serde(alias = "url")]
+    pub(crate) upstream_url: String,
+    #[serde(alias = "wire")]
+    pub(crate) upstream_wire: Option<WireApi>,
+    #[serde(alias = "model")]
+    pub(crate) upstream_model: Option<String>,
+    pub(crate) upstream_model_opus: Option<String>,
+    pub(crate) upstream_model_sonnet: Option<String>,
+    pub(crate) upstream_model_haiku: Option<String>,
+    #[serde(alias = "http_headers")]
+    pub(crate) upstream_http_headers: Option<BTreeMap<String, String>>,
+    pub(crate) api_key_env: Option<String>,
+    pub(crate) api_key_file: Option<PathBuf>,
+    pub(crate) api_key_command: Option<String>,
+    pub(crate) auth_scheme: Option<AuthScheme>,
+    pub(crate) weight: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub(crate) struct OllamaConfig {
+    pub(crate) options: Option<serde_json::Map<String, serde_json::Value>>,
+    pub(crate) keep_alive: Option<serde_json::Value>,
+    pub(crate) think: Option<serde_json::Value>,
+}
+
+impl OllamaConfig {
+    pub(crate) fn is_enabled(&self) -> bool {
+        *self != Self::default()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -132,23 +243,27 @@ pub(crate) struct ResolvedConfig {
     pub(crate) server_info: Option<PathBuf>,
     pub(crate) http_shutdown: bool,
     pub(crate) verbose_logging: bool,
+    pub(crate) capture_dir: Option<PathBuf>,
     pub(crate) drop_tool_types: Vec<String>,
     pub(crate) drop_request_fields: Vec<String>,
     pub(crate) feature_flags: FeatureFlags,
+    pub(crate) sessions: SessionPolicy,
 }
 
 pub(crate) const DEFAULT_CONFIG_TEMPLATE: &str = r#"# codex-chat-bridge runtime configuration
 #
 # Priority: CLI flags > config file > built-in defaults
+# Validate edits with `codex-chat-bridge check`.
 
 # upstream_url = "https://api.openai.com/v1/chat/completions"
-# upstream_wire = "chat" # chat | responses | messages
+# upstream_wire = "chat" # chat | responses | messages | gemini | ollama
 # upstream_http_headers = { "openai-organization" = "org_123", "x-custom-header" = "value" }
 # forward_incoming_headers = ["x-codex-turn-state"]
 # api_key_env = "OPENAI_API_KEY"
 # server_info = "/tmp/codex-chat-bridge-info.json"
 # http_shutdown = false
 # verbose_logging = false
+# capture_dir = "/tmp/codex-chat-bridge-captures" # one directory per request: headers, payloads and raw/translated streams (secrets redacted)
 # drop_tool_types = ["web_search", "web_search_preview"]
 # drop_request_fields = ["prompt_cache_key"]
 #
@@ -159,7 +274,14 @@ pub(crate) const DEFAULT_CONFIG_TEMPLATE: &str = r#"# codex-chat-bridge runtime
 # enable_reasoning_stream_events = true
 # enable_provider_specific_fields = true
 # enable_extended_input_types = true
+# enable_responses_websocket = false # accept Codex Responses WebSocket connections on incoming_url
 # tool_transform_mode = "legacy_convert" # passthrough | legacy_convert
+#
+# [sessions] # history kept for previous_response_id; restart to apply changes
+# backend = "memory" # memory | jsonl
+# path = "/path/to/sessions.jsonl" # required for jsonl; replayed on startup so chains survive restarts
+# capacity = 1024 # newest sessions kept; older ones are evicted
+# ttl_secs = 86400 # optional, sessions older than this are forgotten
 
 # [routers.default]
 # upstream_url = "https://api.openai.com/v1/chat/completions"
@@ -175,6 +297,51 @@ pub(crate) const DEFAULT_CONFIG_TEMPLATE: &str = r#"# codex-chat-bridge runtime
 # incoming_url = "http://<host>:<port>/v1/messages"
 # anthropic_preserve_thinking = true # optional, also copies assistant thinking blocks into chat message content
 # anthropic_enable_openrouter_reasoning = true # optional, injects reasoning.enabled=true for Anthropic requests; ignored on Claude parity routes
+# api_key_env = "OPENROUTER_API_KEY" # optional, falls back to the global api_key_env
+# api_key_file = "/path/to/api-key" # optional, reads the key from a file (use only one key source)
+# api_key_command = "pass show openrouter" # optional, uses the command's stdout as the key
+# models = ["gpt-5"] # optional, listed by GET <router base>/v1/models with the upstream_model* overrides
+# merge_upstream_models = false # optional, also lists what the upstream's own model listing returns
+# auth_scheme = "bearer" # optional, bearer | x-api-key | none | <header name>; defaults to x-api-key for messages, x-goog-api-key for gemini, none for ollama, bearer otherwise
+# [routers.default.retry] # optional, retries only before any response bytes reach the client
+# max_attempts = 3
+# initial_backoff_ms = 500 # doubles per attempt, with jitter, up to max_backoff_ms
+# max_backoff_ms = 8000 # also caps Retry-After / retry-after-ms hints
+# jitter = true
+# respect_retry_after = true
+# retryable_status_codes = [408, 429, 500, 502, 503, 504]
+# retryable_error_codes = ["rate_limit_exceeded"] # normalized upstream error codes
+# [[routers.default.upstreams]] # optional, fallbacks tried in order on transport errors or retryable statuses
+# upstream_url = "https://api.openai.com/v1/responses" # wire is inferred from the URL or set with upstream_wire
+# upstream_model = "gpt-4.1" # optional, model overrides default to the router's; headers/key/auth_scheme only on the same host
+# api_key_env = "OPENAI_API_KEY"
+# weight = 1 # optional, share of traffic for the weighted strategy (the router's own weight applies to upstream_url)
+# [routers.default.load_balancing] # optional, spreads requests across upstream_url and upstreams instead of strict failover order
+# strategy = "round_robin" # round_robin | weighted | least_in_flight | failover
+# eject_after_failures = 3 # consecutive retryable failures before an upstream is skipped
+# cooldown_ms = 30000
+# sticky_sessions = true # keeps previous_response_id follow-ups on the upstream that served the turn
+# [routers.default.timeouts] # optional, per upstream; unset timeouts never fire
+# connect_ms = 10000
+# first_byte_ms = 60000 # until upstream response headers arrive
+# total_ms = 600000 # whole exchange, including retries and the streamed body
+# stream_idle_ms = 120000 # max gap between body chunks; ends streams with response.failed / Anthropic error
+# [routers.default.inbound_auth] # optional, clients must send one of these tokens; needed before binding incoming_url beyond loopback
+# bearer_tokens = ["team-token"] # Authorization: Bearer <token> (Codex, Chat clients)
+# x_api_key_tokens = ["team-token"] # x-api-key: <token> (Claude Code)
+# tokens_file = "/path/to/tokens" # one token per line, accepted in either header; re-read on reload
+# [routers.default.ollama] # optional, only used with upstream_wire = "ollama"
+# options = { num_ctx = 32768, temperature = 0.2 } # overrides options mapped from the request
+# keep_alive = "30m"
+# think = true # or "low" | "medium" | "high" for models that take a level
+# [routers.default.message_roles] # optional, chat upstreams only; how tool and system messages are sent
+# tool = "user" # user (rewrite to user, drop tool_call_id) | native (keep tool role) | tagged (user + <tool_result tool_call_id="..."> text)
+# system = "merge" # merge (one leading system message) | preserve (keep positions, developer becomes system)
+# models = { "qwen3-coder" = { tool = "native", system = "preserve" } } # per upstream model id
+# [routers.default.tokenizer] # optional, counts Anthropic input tokens for count_tokens and message_start
+# kind = "heuristic" # heuristic (chars/4) | bpe | upstream
+# path = "/path/to/o200k_base.tiktoken" # bpe: tiktoken rank file, one "<base64 token> <rank>" per line
+# url = "https://api.anthropic.com/v1/messages/count_tokens" # upstream: falls back to chars/4 on failure
 # [routers.default.features]
 # enable_reasoning_stream_events = false
 # tool_transform_mode = "legacy_convert"
@@ -236,6 +403,7 @@ pub(crate) fn resolve_config(
 ) -> Result<ResolvedConfig> {
     let file_config = file_config.unwrap_or_default();
     let feature_flags = FeatureFlags::default().with_overrides(file_config.features.as_ref());
+    let sessions = SessionPolicy::from_config(file_config.sessions.as_ref())?;
     let mut drop_tool_types = file_config.drop_tool_types.unwrap_or_default();
     drop_tool_types.extend(args.drop_tool_types);
     drop_tool_types.retain(|v| !v.trim().is_empty());
@@ -289,9 +457,11 @@ pub(crate) fn resolve_config(
         server_info: args.server_info.or(file_config.server_info),
         http_shutdown: args.http_shutdown || file_config.http_shutdown.unwrap_or(false),
         verbose_logging: args.verbose_logging || file_config.verbose_logging.unwrap_or(false),
+        capture_dir: args.capture_dir.or(file_config.capture_dir),
         drop_tool_types,
         drop_request_fields,
         feature_flags,
+        sessions,
     })
 }
 
@@ -374,6 +544,11 @@ fn default_upstream_url(upstream_wire: WireApi) -> String {
         WireApi::Chat => "https://api.openai.com/v1/chat/completions".to_string(),
         WireApi::Responses => "https://api.openai.com/v1/responses".to_string(),
         WireApi::Messages => "https://api.anthropic.com/v1/messages".to_string(),
+        WireApi::Gemini => {
+            "https://generativelanguage.googleapis.com/v1beta/models/{model}:generateContent"
+                .to_string()
+        }
+        WireApi::Ollama => "http://127.0.0.1:11434/api/chat".to_string(),
     }
 }
 
@@ -395,6 +570,12 @@ fn infer_upstream_wire_from_url(raw: &str) -> Option<WireApi> {
         Some(WireApi::Responses)
     } else if normalized.ends_with("/v1/messages") {
         Some(WireApi::Messages)
+    } else if normalized.ends_with("/api/chat") {
+        Some(WireApi::Ollama)
+    } else if normalized.ends_with(":generateContent")
+        || normalized.ends_with(":streamGenerateContent")
+    {
+        Some(WireApi::Gemini)
     } else {
         None
     }
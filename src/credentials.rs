@@ -0,0 +1,157 @@
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::config::RouterConfig;
+use crate::config::UpstreamConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ApiKeySource {
+    Env(String),
+    File(PathBuf),
+    Command(String),
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum ApiKey {
+    Resolved(String),
+    Unavailable(String),
+}
+
+impl std::fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Resolved(_) => f.write_str("Resolved(<redacted>)"),
+            Self::Unavailable(reason) => f.debug_tuple("Unavailable").field(reason).finish(),
+        }
+    }
+}
+
+impl ApiKeySource {
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Self::Env(name) => format!("env:{name}"),
+            Self::File(path) => format!("file:{}", path.display()),
+            Self::Command(_) => "command".to_string(),
+        }
+    }
+}
+
+impl ApiKey {
+    pub(crate) fn from_source(source: &ApiKeySource) -> Self {
+        match resolve_api_key(source) {
+            Ok(value) => Self::Resolved(value),
+            Err(err) => Self::Unavailable(format!("{err:#}")),
+        }
+    }
+
+    pub(crate) fn value(&self) -> std::result::Result<&str, &str> {
+        match self {
+            Self::Resolved(value) => Ok(value),
+            Self::Unavailable(reason) => Err(reason),
+        }
+    }
+}
+
+pub(crate) fn router_api_key_source(
+    router_name: &str,
+    router: &RouterConfig,
+) -> Result<Option<ApiKeySource>> {
+    api_key_source(
+        &format!("[routers.{router_name}]"),
+        router.api_key_env.as_deref(),
+        router.api_key_file.as_deref(),
+        router.api_key_command.as_deref(),
+    )
+}
+
+pub(crate) fn upstream_api_key_source(
+    context: &str,
+    upstream: &UpstreamConfig,
+) -> Result<Option<ApiKeySource>> {
+    api_key_source(
+        context,
+        upstream.api_key_env.as_deref(),
+        upstream.api_key_file.as_deref(),
+        upstream.api_key_command.as_deref(),
+    )
+}
+
+fn api_key_source(
+    context: &str,
+    api_key_env: Option<&str>,
+    api_key_file: Option<&Path>,
+    api_key_command: Option<&str>,
+) -> Result<Option<ApiKeySource>> {
+    let mut sources = Vec::new();
+    if let Some(name) = api_key_env.map(str::trim) {
+        if name.is_empty() {
+            return Err(anyhow!("{context} api_key_env must not be empty"));
+        }
+        sources.push(ApiKeySource::Env(name.to_string()));
+    }
+    if let Some(path) = api_key_file {
+        if path.as_os_str().is_empty() {
+            return Err(anyhow!("{context} api_key_file must not be empty"));
+        }
+        sources.push(ApiKeySource::File(path.to_path_buf()));
+    }
+    if let Some(command) = api_key_command.map(str::trim) {
+        if command.is_empty() {
+            return Err(anyhow!("{context} api_key_command must not be empty"));
+        }
+        sources.push(ApiKeySource::Command(command.to_string()));
+    }
+
+    if sources.len() > 1 {
+        return Err(anyhow!(
+            "{context} accepts only one of api_key_env, api_key_file, api_key_command"
+        ));
+    }
+    Ok(sources.pop())
+}
+
+pub(crate) fn resolve_api_key(source: &ApiKeySource) -> Result<String> {
+    let raw = match source {
+        ApiKeySource::Env(name) => {
+            std::env::var(name).map_err(|_| anyhow!("missing or empty env var: {name}"))?
+        }
+        ApiKeySource::File(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("reading api key file {}", path.display()))?,
+        ApiKeySource::Command(command) => {
+            let output = shell_command(command)
+                .output()
+                .with_context(|| format!("running api_key_command `{command}`"))?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "api_key_command `{command}` exited with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            String::from_utf8(output.stdout)
+                .with_context(|| format!("api_key_command `{command}` printed non-utf8 output"))?
+        }
+    };
+
+    let value = raw.trim();
+    if value.is_empty() {
+        return Err(anyhow!("api key from {} is empty", source.describe()));
+    }
+    Ok(value.to_string())
+}
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
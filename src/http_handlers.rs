@@ -1,29 +1,51 @@
+use axum::Extension;
 use axum::Router;
+use axum::extract::ConnectInfo;
 use axum::extract::Path as AxumPath;
+use axum::extract::RawQuery;
 use axum::extract::State;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
 use axum::http::HeaderMap;
 use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::routing::get;
 use axum::routing::post;
 use serde_json::json;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use crate::model::IncomingApi;
+use crate::models::handle_models;
+use crate::models::parse_models_path;
 use crate::response_utils::json_success_response;
 use crate::routing::normalize_request_path;
 use crate::state::AppState;
+use crate::stored_responses::StoredResponseResource;
+use crate::stored_responses::handle_stored_response;
+use crate::stored_responses::parse_stored_response_path;
+use crate::websocket::handle_responses_websocket;
 
 pub(crate) fn build_app(state: Arc<AppState>) -> Router {
     Router::new()
         .route("/v1/messages", post(handle_anthropic_messages))
-        .route("/v1/responses", post(handle_responses))
+        .route(
+            "/v1/responses",
+            get(handle_responses_websocket_root).post(handle_responses),
+        )
         .route("/v1/chat/completions", post(handle_chat_completions))
         .route("/healthz", get(healthz))
         .route("/shutdown", get(shutdown))
         .route("/routers", get(list_routers))
-        .route("/{*incoming_path}", post(handle_routed_incoming))
+        .route("/metrics", get(metrics))
+        .route(
+            "/{*incoming_path}",
+            get(handle_routed_get)
+                .post(handle_routed_incoming)
+                .delete(handle_routed_delete),
+        )
         .with_state(state)
 }
 
@@ -53,6 +75,22 @@ async fn list_routers(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     }))
 }
 
+async fn metrics(
+    State(state): State<Arc<AppState>>,
+    peer: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Response {
+    // Metrics reveal router names and traffic, so only local clients may read them.
+    if !peer.is_some_and(|Extension(ConnectInfo(addr))| addr.ip().to_canonical().is_loopback()) {
+        return (StatusCode::NOT_FOUND, "not found").into_response();
+    }
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+        .into_response()
+}
+
 async fn handle_responses(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
@@ -104,6 +142,20 @@ async fn handle_routed_incoming(
     headers: HeaderMap,
     body: String,
 ) -> Response {
+    if let Some((base, response_id, StoredResponseResource::Cancel)) =
+        parse_stored_response_path(&incoming_path)
+    {
+        return handle_stored_response(
+            state,
+            headers,
+            &base,
+            &response_id,
+            StoredResponseResource::Cancel,
+            false,
+            None,
+        )
+        .await;
+    }
     crate::handle_incoming(
         state,
         headers,
@@ -113,3 +165,69 @@ async fn handle_routed_incoming(
     )
     .await
 }
+
+async fn handle_responses_websocket_root(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    handle_responses_websocket(state, headers, "/v1/responses".to_string(), ws).await
+}
+
+async fn handle_routed_get(
+    State(state): State<Arc<AppState>>,
+    AxumPath(incoming_path): AxumPath<String>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Response {
+    if let Ok(ws) = ws {
+        return handle_responses_websocket(
+            state,
+            headers,
+            normalize_request_path(&incoming_path),
+            ws,
+        )
+        .await;
+    }
+    if let Some((base, model_id)) = parse_models_path(&incoming_path) {
+        return handle_models(state, headers, &base, model_id.as_deref()).await;
+    }
+    match parse_stored_response_path(&incoming_path) {
+        Some((base, response_id, resource)) if resource != StoredResponseResource::Cancel => {
+            handle_stored_response(
+                state,
+                headers,
+                &base,
+                &response_id,
+                resource,
+                false,
+                query.as_deref(),
+            )
+            .await
+        }
+        _ => (StatusCode::NOT_FOUND, "not found").into_response(),
+    }
+}
+
+async fn handle_routed_delete(
+    State(state): State<Arc<AppState>>,
+    AxumPath(incoming_path): AxumPath<String>,
+    headers: HeaderMap,
+) -> Response {
+    match parse_stored_response_path(&incoming_path) {
+        Some((base, response_id, StoredResponseResource::Response)) => {
+            handle_stored_response(
+                state,
+                headers,
+                &base,
+                &response_id,
+                StoredResponseResource::Response,
+                true,
+                None,
+            )
+            .await
+        }
+        _ => (StatusCode::NOT_FOUND, "not found").into_response(),
+    }
+}
@@ -0,0 +1,129 @@
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use axum::http::HeaderMap;
+use axum::http::header::AUTHORIZATION;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct InboundAuthConfig {
+    pub(crate) bearer_tokens: Option<Vec<String>>,
+    pub(crate) x_api_key_tokens: Option<Vec<String>>,
+    pub(crate) tokens_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InboundAuth {
+    bearer_tokens: Vec<String>,
+    x_api_key_tokens: Vec<String>,
+}
+
+impl InboundAuth {
+    pub(crate) fn from_config(
+        config: Option<&InboundAuthConfig>,
+        context: &str,
+    ) -> Result<Option<Self>> {
+        let Some(config) = config else {
+            return Ok(None);
+        };
+        let mut bearer_tokens = normalize_tokens(
+            config.bearer_tokens.iter().flatten(),
+            context,
+            "bearer_tokens",
+        )?;
+        let mut x_api_key_tokens = normalize_tokens(
+            config.x_api_key_tokens.iter().flatten(),
+            context,
+            "x_api_key_tokens",
+        )?;
+        if let Some(path) = config.tokens_file.as_ref() {
+            let raw = std::fs::read_to_string(path).with_context(|| {
+                format!(
+                    "{context} reading inbound_auth.tokens_file {}",
+                    path.display()
+                )
+            })?;
+            let file_tokens = raw
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            bearer_tokens.extend(file_tokens.iter().cloned());
+            x_api_key_tokens.extend(file_tokens);
+        }
+
+        if bearer_tokens.is_empty() && x_api_key_tokens.is_empty() {
+            return Err(anyhow!(
+                "{context} inbound_auth must configure at least one token"
+            ));
+        }
+        Ok(Some(Self {
+            bearer_tokens,
+            x_api_key_tokens,
+        }))
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        format!(
+            "bearer_tokens:{},x_api_key_tokens:{}",
+            self.bearer_tokens.len(),
+            self.x_api_key_tokens.len()
+        )
+    }
+
+    pub(crate) fn authorizes(&self, headers: &HeaderMap) -> bool {
+        let bearer = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                value
+                    .split_once(' ')
+                    .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+                    .map(|(_, token)| token.trim())
+            });
+        let x_api_key = headers
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim);
+
+        bearer.is_some_and(|token| contains_token(&self.bearer_tokens, token))
+            || x_api_key.is_some_and(|token| contains_token(&self.x_api_key_tokens, token))
+    }
+}
+
+fn normalize_tokens<'a>(
+    tokens: impl Iterator<Item = &'a String>,
+    context: &str,
+    field: &str,
+) -> Result<Vec<String>> {
+    tokens
+        .map(|token| {
+            let token = token.trim();
+            if token.is_empty() {
+                return Err(anyhow!(
+                    "{context} inbound_auth.{field} must not contain empty tokens"
+                ));
+            }
+            Ok(token.to_string())
+        })
+        .collect()
+}
+
+// Checks every token without short-circuiting, so timing reveals nothing.
+fn contains_token(tokens: &[String], candidate: &str) -> bool {
+    tokens.iter().fold(false, |found, token| {
+        found | constant_time_eq(token.as_bytes(), candidate.as_bytes())
+    })
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter()
+        .zip(right)
+        .fold(0u8, |diff, (left, right)| diff | (left ^ right))
+        == 0
+}
@@ -0,0 +1,411 @@
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::check::check_routers;
+use crate::config::Args;
+use crate::config::DEFAULT_CONFIG_TEMPLATE;
+use crate::config::FileConfig;
+use crate::config::InitArgs;
+use crate::config::RouterConfig;
+use crate::config::ensure_default_config_file;
+use crate::config::resolve_config;
+use crate::config::resolve_config_path;
+use crate::credentials::ApiKey;
+use crate::model::WireApi;
+use crate::routing::describe_incoming_url;
+
+const CODEX_ROUTER: &str = "chat_bridge";
+const CLAUDE_ROUTER: &str = "claude_code";
+const CODEX_PROFILE: &str = "chat-bridge";
+const DEFAULT_PORT: u16 = 8787;
+
+struct Provider {
+    name: &'static str,
+    upstream_url: &'static str,
+    upstream_wire: WireApi,
+    api_key_env: Option<&'static str>,
+    default_model: &'static str,
+}
+
+const PROVIDERS: &[Provider] = &[
+    Provider {
+        name: "openrouter",
+        upstream_url: "https://openrouter.ai/api/v1/chat/completions",
+        upstream_wire: WireApi::Chat,
+        api_key_env: Some("OPENROUTER_API_KEY"),
+        default_model: "openai/gpt-oss-120b",
+    },
+    Provider {
+        name: "openai",
+        upstream_url: "https://api.openai.com/v1/chat/completions",
+        upstream_wire: WireApi::Chat,
+        api_key_env: Some("OPENAI_API_KEY"),
+        default_model: "gpt-4.1",
+    },
+    Provider {
+        name: "anthropic",
+        upstream_url: "https://api.anthropic.com/v1/messages",
+        upstream_wire: WireApi::Messages,
+        api_key_env: Some("ANTHROPIC_API_KEY"),
+        default_model: "claude-sonnet-4-5",
+    },
+    Provider {
+        name: "gemini",
+        upstream_url: "https://generativelanguage.googleapis.com/v1beta/models/{model}:generateContent",
+        upstream_wire: WireApi::Gemini,
+        api_key_env: Some("GEMINI_API_KEY"),
+        default_model: "gemini-2.5-pro",
+    },
+    Provider {
+        name: "ollama",
+        upstream_url: "http://127.0.0.1:11434/api/chat",
+        upstream_wire: WireApi::Ollama,
+        api_key_env: None,
+        default_model: "qwen3-coder:30b",
+    },
+];
+
+pub(crate) struct InitPaths {
+    pub(crate) bridge_config: PathBuf,
+    pub(crate) codex_profile: PathBuf,
+    pub(crate) claude_env: PathBuf,
+}
+
+impl InitPaths {
+    fn resolve(args: &Args) -> Result<Self> {
+        let bridge_config = resolve_config_path(args.config.clone())?;
+        let codex_home = match std::env::var_os("CODEX_HOME") {
+            Some(home) => PathBuf::from(home),
+            None => PathBuf::from(
+                std::env::var_os("HOME")
+                    .ok_or_else(|| anyhow!("HOME environment variable is not set"))?,
+            )
+            .join(".codex"),
+        };
+        let claude_env = bridge_config
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join("claude-code.env");
+        Ok(Self {
+            bridge_config,
+            codex_profile: codex_home.join(format!("{CODEX_PROFILE}.config.toml")),
+            claude_env,
+        })
+    }
+}
+
+struct ClientUrls {
+    codex_base_url: String,
+    claude_base_url: String,
+}
+
+impl ClientUrls {
+    fn new(port: u16) -> Self {
+        Self {
+            codex_base_url: format!("http://127.0.0.1:{port}/chat-bridge/v1"),
+            claude_base_url: format!("http://127.0.0.1:{port}/claude"),
+        }
+    }
+
+    fn codex_incoming_url(&self) -> String {
+        format!("{}/responses", self.codex_base_url)
+    }
+
+    fn claude_incoming_url(&self) -> String {
+        format!("{}/v1/messages", self.claude_base_url)
+    }
+}
+
+pub(crate) fn run_init(args: &Args, init: &InitArgs) -> Result<()> {
+    let paths = InitPaths::resolve(args)?;
+    init_with(
+        args,
+        init,
+        &paths,
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout().lock(),
+    )
+}
+
+pub(crate) fn init_with(
+    args: &Args,
+    init: &InitArgs,
+    paths: &InitPaths,
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> Result<()> {
+    let provider = match init.provider.as_deref() {
+        Some(name) => find_provider(name)?,
+        None => loop {
+            let names = PROVIDERS
+                .iter()
+                .map(|provider| provider.name)
+                .collect::<Vec<_>>()
+                .join("/");
+            let answer = ask(
+                input,
+                out,
+                &format!("Provider ({names})"),
+                PROVIDERS[0].name,
+            )?;
+            match find_provider(&answer) {
+                Ok(provider) => break provider,
+                Err(err) => writeln!(out, "{err}")?,
+            }
+        },
+    };
+    let model = match init.model.clone() {
+        Some(model) => model,
+        None => ask(input, out, "Upstream model", provider.default_model)?,
+    };
+    if model.trim().is_empty() {
+        return Err(anyhow!("model must not be empty"));
+    }
+    let port = match init.port {
+        Some(port) => port,
+        None => loop {
+            let answer = ask(input, out, "Bridge port", &DEFAULT_PORT.to_string())?;
+            match answer.parse::<u16>() {
+                Ok(port) if port != 0 => break port,
+                _ => writeln!(out, "port must be a number from 1 to 65535")?,
+            }
+        },
+    };
+    if port == 0 {
+        return Err(anyhow!("port must be a number from 1 to 65535"));
+    }
+
+    let urls = ClientUrls::new(port);
+    let routers_block = routers_block(provider, &model, &urls);
+    validate_routers(args, &paths.bridge_config, &routers_block)?;
+    for path in [&paths.codex_profile, &paths.claude_env] {
+        if path.exists() && !init.force {
+            return Err(anyhow!(
+                "{} already exists; rerun with --force to overwrite it",
+                path.display()
+            ));
+        }
+    }
+
+    ensure_default_config_file(&paths.bridge_config)?;
+    OpenOptions::new()
+        .append(true)
+        .open(&paths.bridge_config)
+        .and_then(|mut file| file.write_all(routers_block.as_bytes()))
+        .with_context(|| format!("appending routers to {}", paths.bridge_config.display()))?;
+    write_file(
+        &paths.codex_profile,
+        &codex_profile(provider, &model, &urls),
+    )?;
+    write_file(
+        &paths.claude_env,
+        &claude_env(provider, &model, &urls, &paths.claude_env),
+    )?;
+
+    let key_export = provider
+        .api_key_env
+        .map(|env| format!("export {env}=...\n  "))
+        .unwrap_or_default();
+    writeln!(
+        out,
+        "\nAdded [routers.{CODEX_ROUTER}] and [routers.{CLAUDE_ROUTER}] to {}\nWrote Codex profile {}\nWrote Claude Code environment {}\n\nStart the bridge:\n  {key_export}codex-chat-bridge --config {}\nCodex:\n  codex --profile {CODEX_PROFILE}\nClaude Code:\n  source {} && claude",
+        paths.bridge_config.display(),
+        paths.codex_profile.display(),
+        paths.claude_env.display(),
+        paths.bridge_config.display(),
+        paths.claude_env.display(),
+    )?;
+    Ok(())
+}
+
+fn find_provider(name: &str) -> Result<&'static Provider> {
+    PROVIDERS
+        .iter()
+        .find(|provider| provider.name.eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| {
+            anyhow!(
+                "unknown provider `{name}`; expected one of {}",
+                PROVIDERS
+                    .iter()
+                    .map(|provider| provider.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+fn ask(
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+    question: &str,
+    default: &str,
+) -> Result<String> {
+    write!(out, "{question} [{default}]: ")?;
+    out.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer.to_string()
+    })
+}
+
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+fn shell_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn routers_block(provider: &Provider, model: &str, urls: &ClientUrls) -> String {
+    let router = |name: &str, incoming_url: &str, client_note: &str| {
+        let mut block = format!(
+            "\n# Written by `codex-chat-bridge init`; {client_note}.\n[routers.{name}]\nincoming_url = {}\nupstream_url = {}\nupstream_wire = \"{}\"\nupstream_model = {}\n",
+            toml_string(incoming_url),
+            toml_string(provider.upstream_url),
+            provider.upstream_wire.as_str(),
+            toml_string(model),
+        );
+        if let Some(env) = provider.api_key_env {
+            block.push_str(&format!("api_key_env = \"{env}\"\n"));
+        }
+        block
+    };
+    let mut codex = router(
+        CODEX_ROUTER,
+        &urls.codex_incoming_url(),
+        &format!(
+            "Codex profile `{CODEX_PROFILE}` uses base_url = \"{}\"",
+            urls.codex_base_url
+        ),
+    );
+    if provider.upstream_wire == WireApi::Chat {
+        codex.push_str("drop_request_fields = [\"prompt_cache_key\"]\n");
+    }
+    if !provider.upstream_wire.keeps_responses_history() {
+        codex.push_str("features = { enable_previous_response_id = false }\n");
+    }
+    let mut claude = router(
+        CLAUDE_ROUTER,
+        &urls.claude_incoming_url(),
+        &format!(
+            "Claude Code uses ANTHROPIC_BASE_URL={}",
+            urls.claude_base_url
+        ),
+    );
+    if provider.upstream_wire == WireApi::Messages {
+        claude.push_str(
+            "forward_incoming_headers = [\"x-request-id\", \"anthropic-version\", \"anthropic-beta\"]\n",
+        );
+    }
+    codex + &claude
+}
+
+fn validate_routers(args: &Args, bridge_config: &Path, routers_block: &str) -> Result<()> {
+    let existing = if bridge_config.exists() {
+        std::fs::read_to_string(bridge_config)
+            .with_context(|| format!("reading {}", bridge_config.display()))?
+    } else {
+        DEFAULT_CONFIG_TEMPLATE.to_string()
+    };
+    let existing_config: FileConfig = toml::from_str(&existing)
+        .with_context(|| format!("parsing {}", bridge_config.display()))?;
+    let existing_routers = existing_config.routers.unwrap_or_default();
+    for name in [CODEX_ROUTER, CLAUDE_ROUTER] {
+        if existing_routers.contains_key(name) {
+            return Err(anyhow!(
+                "{} already defines [routers.{name}]; remove it to run init again",
+                bridge_config.display()
+            ));
+        }
+    }
+    let new_config: FileConfig = toml::from_str(&format!("{existing}\n{routers_block}"))
+        .context("parsing the generated routers")?;
+    let new_routers = new_config
+        .routers
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(name, _)| name == CODEX_ROUTER || name == CLAUDE_ROUTER)
+        .collect::<BTreeMap<String, RouterConfig>>();
+
+    for (name, router) in existing_routers {
+        let Some(route) = router
+            .incoming_url
+            .as_deref()
+            .and_then(|url| describe_incoming_url(url).ok())
+        else {
+            continue;
+        };
+        for (new_name, new_router) in &new_routers {
+            if new_router
+                .incoming_url
+                .as_deref()
+                .and_then(|url| describe_incoming_url(url).ok())
+                .is_some_and(|new_route| new_route == route)
+            {
+                return Err(anyhow!(
+                    "[routers.{new_name}] would claim `{route}`, which [routers.{name}] in {} already uses; pick another port",
+                    bridge_config.display()
+                ));
+            }
+        }
+    }
+
+    let resolved = resolve_config(args.clone(), Some(new_config))?;
+    let report = check_routers(&new_routers, &ApiKey::Resolved(String::new()), |routers| {
+        crate::build_router_manager(&resolved, routers)
+    });
+    if report.error_count() > 0 {
+        return Err(anyhow!(
+            "generated routers are invalid: {:?}",
+            report.problems
+        ));
+    }
+    Ok(())
+}
+
+fn codex_profile(provider: &Provider, model: &str, urls: &ClientUrls) -> String {
+    let env_key = provider
+        .api_key_env
+        .map(|env| format!("env_key = \"{env}\"\n"))
+        .unwrap_or_default();
+    format!(
+        "# Written by `codex-chat-bridge init`; run with `codex --profile {CODEX_PROFILE}`.\n# base_url stops at /v1: Codex appends /responses, matching incoming_url = \"{}\".\nmodel = {}\nmodel_provider = \"{CODEX_PROFILE}\"\n\n[model_providers.{CODEX_PROFILE}]\nname = \"codex-chat-bridge\"\nbase_url = {}\n{env_key}wire_api = \"responses\"\nrequires_openai_auth = false\nrequest_max_retries = 3\nstream_max_retries = 3\nstream_idle_timeout_ms = 300000\n",
+        urls.codex_incoming_url(),
+        toml_string(model),
+        toml_string(&urls.codex_base_url),
+    )
+}
+
+fn claude_env(provider: &Provider, model: &str, urls: &ClientUrls, path: &Path) -> String {
+    let upstream_key = provider
+        .api_key_env
+        .map(|env| format!("the bridge sends {env} upstream"))
+        .unwrap_or_else(|| "the upstream needs no key".to_string());
+    format!(
+        "# Written by `codex-chat-bridge init`; run `source {}` before `claude`.\n# Claude Code appends /v1/messages, matching incoming_url = \"{}\".\nexport ANTHROPIC_BASE_URL={}\nexport ANTHROPIC_AUTH_TOKEN='codex-chat-bridge' # placeholder; {upstream_key}\nexport ANTHROPIC_MODEL={}\n",
+        path.display(),
+        urls.claude_incoming_url(),
+        shell_string(&urls.claude_base_url),
+        shell_string(model),
+    )
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("creating directory {}", parent.display()))?;
+    }
+    std::fs::write(path, contents).with_context(|| format!("writing {}", path.display()))
+}
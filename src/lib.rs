@@ -12,7 +12,15 @@ use axum::http::header::CONTENT_TYPE;
 use axum::http::header::HOST;
 use axum::response::IntoResponse;
 use axum::response::Response;
+use background::BackgroundResponses;
+use background::start_background_response;
+use balancer::LoadBalancer;
 use clap::Parser;
+use futures::StreamExt;
+use futures::future::Either;
+use percent_encoding::AsciiSet;
+use percent_encoding::NON_ALPHANUMERIC;
+use percent_encoding::utf8_percent_encode;
 use reqwest::Client;
 use serde::Serialize;
 use serde_json::Value;
@@ -23,32 +31,72 @@ use std::fs::File;
 use std::fs::{self};
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use timeouts::UpstreamStreamError;
+use timeouts::collect_body;
+use timeouts::with_stream_timeouts;
+use tokenizer::count_anthropic_input_tokens;
+#[cfg(test)]
+use tokenizer::estimate_anthropic_count_tokens;
 use tokio::sync::RwLock;
+use tokio::time::Instant;
 use tracing::debug;
 use tracing::info;
 use tracing::warn;
 use uuid::Uuid;
 
+mod background;
+mod balancer;
 mod bridge;
 mod bridge_types;
+mod capture;
+mod check;
 mod config;
+mod credentials;
 mod http_handlers;
+mod inbound_auth;
+mod init;
 mod logging_utils;
+mod message_roles;
+mod metrics;
 mod model;
+mod models;
 mod pipeline;
+mod reload;
 mod response_utils;
+mod retry;
 mod routing;
 mod session;
 mod state;
+mod stored_responses;
+mod timeouts;
+mod tokenizer;
+mod websocket;
 use bridge::mapping::*;
 use bridge::streaming::*;
 use bridge_types::*;
+use capture::CLIENT_STREAM_FILE;
+use capture::Capture;
+use capture::UPSTREAM_STREAM_FILE;
+use capture::replay_capture;
+use check::run_check;
 use config::*;
+use credentials::ApiKey;
+use credentials::ApiKeySource;
 use http_handlers::build_app;
+use init::run_init;
 use logging_utils::*;
+use message_roles::apply_message_role_policy;
+use metrics::Metrics;
+use metrics::RequestMetricsGuard;
+use metrics::observe_stream_metrics;
+use metrics::usage_tokens;
 use model::*;
 use pipeline::*;
+use reload::ListenerSet;
+use reload::spawn_reload_triggers;
 use response_utils::*;
 use routing::*;
 use session::*;
@@ -80,7 +128,14 @@ fn init_tracing(verbose_logging: bool) {
 fn load_runtime_config(args: &Args) -> Result<(ResolvedConfig, BTreeMap<String, RouterConfig>)> {
     let config_path = resolve_config_path(args.config.clone())?;
     ensure_default_config_file(&config_path)?;
-    let file_config = load_file_config(&config_path)?;
+    read_runtime_config(args, &config_path)
+}
+
+fn read_runtime_config(
+    args: &Args,
+    config_path: &Path,
+) -> Result<(ResolvedConfig, BTreeMap<String, RouterConfig>)> {
+    let file_config = load_file_config(config_path)?;
     let config = resolve_config(args.clone(), file_config.clone())?;
     let routers = file_config
         .as_ref()
@@ -141,129 +196,392 @@ fn log_runtime_startup(
         router_defaults.drop_request_fields
     );
     info!(
-        "runtime config: api_key_env={}, server_info={:?}, http_shutdown={}, verbose_logging={}, feature_flags={:?}",
+        "runtime config: api_key_env={}, server_info={:?}, http_shutdown={}, verbose_logging={}, capture_dir={:?}, feature_flags={:?}, sessions={:?}",
         config.api_key_env,
         config.server_info,
         config.http_shutdown,
         config.verbose_logging,
-        config.feature_flags
+        config.capture_dir,
+        config.feature_flags,
+        config.sessions
     );
     for snapshot in router_manager.get_router_delta_log_snapshots() {
-        let RouterDeltaLogSnapshot {
-            name,
-            active,
-            incoming_url,
-            non_local_incoming_host,
-            upstream_wire,
-            override_upstream_url,
-            override_upstream_wire,
-            override_upstream_model,
-            override_upstream_model_opus,
-            override_upstream_model_sonnet,
-            override_upstream_model_haiku,
-            override_upstream_http_headers,
-            override_forward_incoming_headers,
-            override_drop_tool_types,
-            override_drop_request_fields,
-            override_anthropic_preserve_thinking,
-            override_anthropic_enable_openrouter_reasoning,
-        } = snapshot;
-
-        let mut overrides = Vec::new();
-        if let Some(v) = override_upstream_url {
-            overrides.push(format!("upstream_url={v}"));
-        }
-        if let Some(v) = override_upstream_wire {
-            overrides.push(format!("upstream_wire={v:?}"));
-        }
-        if let Some(v) = override_upstream_model {
-            overrides.push(format!("upstream_model={v}"));
-        }
-        if let Some(v) = override_upstream_model_opus {
-            overrides.push(format!("upstream_model_opus={v}"));
-        }
-        if let Some(v) = override_upstream_model_sonnet {
-            overrides.push(format!("upstream_model_sonnet={v}"));
-        }
-        if let Some(v) = override_upstream_model_haiku {
-            overrides.push(format!("upstream_model_haiku={v}"));
-        }
-        if let Some(v) = override_upstream_http_headers {
-            overrides.push(format!("upstream_http_headers={v:?}"));
-        }
-        if let Some(v) = override_forward_incoming_headers {
-            overrides.push(format!("forward_incoming_headers={v:?}"));
+        info!("router: {}", describe_router_delta(&snapshot));
+        if let Some(host) = snapshot.non_local_incoming_host
+            && snapshot.inbound_auth.is_none()
+        {
+            warn!(
+                "router `{}` incoming_url host `{}` is not loopback/local. codex-chat-bridge is typically intended for local-only use; consider binding to localhost/127.0.0.1, adding [routers.{}.inbound_auth], or adding network controls.",
+                snapshot.name, host, snapshot.name
+            );
         }
-        if let Some(v) = override_drop_tool_types {
-            overrides.push(format!("drop_tool_types={v:?}"));
+    }
+}
+
+fn describe_router_delta(snapshot: &RouterDeltaLogSnapshot) -> String {
+    let RouterDeltaLogSnapshot {
+        name,
+        active,
+        incoming_url,
+        non_local_incoming_host: _,
+        upstream_wire,
+        override_upstream_url,
+        override_upstream_wire,
+        override_upstream_model,
+        override_upstream_model_opus,
+        override_upstream_model_sonnet,
+        override_upstream_model_haiku,
+        override_upstream_http_headers,
+        override_forward_incoming_headers,
+        override_drop_tool_types,
+        override_drop_request_fields,
+        override_anthropic_preserve_thinking,
+        override_anthropic_enable_openrouter_reasoning,
+        override_api_key_source,
+        override_auth_scheme,
+        override_retry,
+        fallback_upstreams,
+        override_load_balancing,
+        override_timeouts,
+        override_ollama,
+        override_message_roles,
+        override_models,
+        override_merge_upstream_models,
+        inbound_auth,
+        tokenizer,
+    } = snapshot;
+
+    let mut overrides = Vec::new();
+    if let Some(v) = override_upstream_url {
+        overrides.push(format!("upstream_url={v}"));
+    }
+    if let Some(v) = override_upstream_wire {
+        overrides.push(format!("upstream_wire={v:?}"));
+    }
+    if let Some(v) = override_upstream_model {
+        overrides.push(format!("upstream_model={v}"));
+    }
+    if let Some(v) = override_upstream_model_opus {
+        overrides.push(format!("upstream_model_opus={v}"));
+    }
+    if let Some(v) = override_upstream_model_sonnet {
+        overrides.push(format!("upstream_model_sonnet={v}"));
+    }
+    if let Some(v) = override_upstream_model_haiku {
+        overrides.push(format!("upstream_model_haiku={v}"));
+    }
+    if let Some(v) = override_upstream_http_headers {
+        overrides.push(format!("upstream_http_headers={v:?}"));
+    }
+    if let Some(v) = override_forward_incoming_headers {
+        overrides.push(format!("forward_incoming_headers={v:?}"));
+    }
+    if let Some(v) = override_drop_tool_types {
+        overrides.push(format!("drop_tool_types={v:?}"));
+    }
+    if let Some(v) = override_drop_request_fields {
+        overrides.push(format!("drop_request_fields={v:?}"));
+    }
+    if *override_anthropic_preserve_thinking == Some(true) {
+        overrides.push("anthropic_preserve_thinking=true".to_string());
+    }
+    if *override_anthropic_enable_openrouter_reasoning == Some(true) {
+        overrides.push("anthropic_enable_openrouter_reasoning=true".to_string());
+    }
+    if let Some(v) = override_api_key_source {
+        overrides.push(format!("api_key={v}"));
+    }
+    if let Some(v) = override_auth_scheme {
+        overrides.push(format!("auth_scheme={}", v.as_str()));
+    }
+    if let Some(v) = override_retry {
+        overrides.push(format!(
+            "retry=max_attempts:{},backoff_ms:{}..{},status:{:?},codes:{:?}",
+            v.max_attempts,
+            v.initial_backoff.as_millis(),
+            v.max_backoff.as_millis(),
+            v.retryable_status_codes,
+            v.retryable_error_codes
+        ));
+    }
+    if !fallback_upstreams.is_empty() {
+        let upstreams = fallback_upstreams
+            .iter()
+            .map(|(url, wire)| format!("{url}({wire:?})"))
+            .collect::<Vec<_>>();
+        overrides.push(format!("upstreams={upstreams:?}"));
+    }
+    if let Some(v) = override_load_balancing {
+        overrides.push(format!(
+            "load_balancing=strategy:{:?},eject_after_failures:{},cooldown_ms:{},sticky_sessions:{}",
+            v.strategy,
+            v.eject_after_failures,
+            v.cooldown.as_millis(),
+            v.sticky_sessions
+        ));
+    }
+    if let Some(v) = override_timeouts {
+        let millis = |value: Option<Duration>| {
+            value.map_or_else(|| "none".to_string(), |value| value.as_millis().to_string())
+        };
+        overrides.push(format!(
+            "timeouts=connect_ms:{},first_byte_ms:{},total_ms:{},stream_idle_ms:{}",
+            millis(v.connect),
+            millis(v.first_byte),
+            millis(v.total),
+            millis(v.stream_idle)
+        ));
+    }
+    if let Some(v) = override_ollama {
+        overrides.push(format!(
+            "ollama=options:{},keep_alive:{},think:{}",
+            v.options.as_ref().map_or_else(
+                || "none".to_string(),
+                |options| Value::Object(options.clone()).to_string()
+            ),
+            v.keep_alive
+                .as_ref()
+                .map_or_else(|| "none".to_string(), Value::to_string),
+            v.think
+                .as_ref()
+                .map_or_else(|| "none".to_string(), Value::to_string)
+        ));
+    }
+    if let Some(v) = override_message_roles {
+        overrides.push(format!(
+            "message_roles=tool:{:?},system:{:?},models:{:?}",
+            v.tool,
+            v.system,
+            v.models.keys().collect::<Vec<_>>()
+        ));
+    }
+    if let Some(v) = override_models {
+        overrides.push(format!("models={v:?}"));
+    }
+    if *override_merge_upstream_models == Some(true) {
+        overrides.push("merge_upstream_models=true".to_string());
+    }
+    if let Some(v) = inbound_auth {
+        overrides.push(format!("inbound_auth={v}"));
+    }
+    if let Some(v) = tokenizer {
+        overrides.push(format!("tokenizer={v}"));
+    }
+    let override_summary = if overrides.is_empty() {
+        "none".to_string()
+    } else {
+        overrides.join(", ")
+    };
+
+    format!(
+        "name={}, active={}, incoming_url={:?}, upstream_wire={:?}, overrides={}",
+        name, active, incoming_url, upstream_wire, override_summary
+    )
+}
+
+fn check_router_api_keys(router_manager: &RouterManager, default_api_key: &ApiKey) -> Result<()> {
+    let failures = router_manager.get_api_key_failures(default_api_key);
+    if failures.len() == router_manager.get_router_names().len() {
+        if let Err(reason) = default_api_key.value() {
+            return Err(anyhow!("{reason}"));
         }
-        if let Some(v) = override_drop_request_fields {
-            overrides.push(format!("drop_request_fields={v:?}"));
+        if let Some((name, reason)) = failures.first() {
+            return Err(anyhow!("no upstream API key for router `{name}`: {reason}"));
         }
-        if override_anthropic_preserve_thinking == Some(true) {
-            overrides.push("anthropic_preserve_thinking=true".to_string());
+    }
+    for (name, reason) in failures {
+        warn!(
+            "router `{}` has no upstream API key and will reject requests: {}",
+            name, reason
+        );
+    }
+    Ok(())
+}
+
+fn require_listen_addrs(router_manager: &RouterManager) -> Result<Vec<String>> {
+    let listen_addrs = router_manager.get_listen_addrs();
+    if listen_addrs.is_empty() {
+        return Err(anyhow!(
+            "no listenable incoming_url found. configure at least one absolute URL like `http://<host>:<port>/<path>` in [routers.*].incoming_url"
+        ));
+    }
+    Ok(listen_addrs)
+}
+
+fn load_reloaded_router_manager(
+    args: &Args,
+    config_path: &Path,
+    default_api_key: &ApiKey,
+) -> Result<(ResolvedConfig, RouterManager)> {
+    if !config_path.exists() {
+        return Err(anyhow!(
+            "config file {} no longer exists",
+            config_path.display()
+        ));
+    }
+    let (config, routers) = read_runtime_config(args, config_path)?;
+    let router_manager = build_router_manager(&config, routers)?;
+    require_listen_addrs(&router_manager)?;
+    check_router_api_keys(&router_manager, default_api_key)?;
+    Ok((config, router_manager))
+}
+
+fn router_reload_changes(previous: &RouterManager, current: &RouterManager) -> Vec<String> {
+    let mut changes = Vec::new();
+    if previous.get_default_log_snapshot() != current.get_default_log_snapshot() {
+        changes.push(format!(
+            "router defaults changed: {:?}",
+            current.get_default_log_snapshot()
+        ));
+    }
+
+    let previous_snapshots = previous
+        .get_router_delta_log_snapshots()
+        .into_iter()
+        .map(|snapshot| (snapshot.name.clone(), snapshot))
+        .collect::<BTreeMap<_, _>>();
+    let current_snapshots = current
+        .get_router_delta_log_snapshots()
+        .into_iter()
+        .map(|snapshot| (snapshot.name.clone(), snapshot))
+        .collect::<BTreeMap<_, _>>();
+    for (name, snapshot) in &current_snapshots {
+        match previous_snapshots.get(name) {
+            None => changes.push(format!("router added: {}", describe_router_delta(snapshot))),
+            Some(previous_snapshot) if previous_snapshot != snapshot => changes.push(format!(
+                "router changed: {} (was: {})",
+                describe_router_delta(snapshot),
+                describe_router_delta(previous_snapshot)
+            )),
+            Some(_) => {}
         }
-        if override_anthropic_enable_openrouter_reasoning == Some(true) {
-            overrides.push("anthropic_enable_openrouter_reasoning=true".to_string());
+    }
+    for name in previous_snapshots.keys() {
+        if !current_snapshots.contains_key(name) {
+            changes.push(format!("router removed: name={name}"));
         }
-        let override_summary = if overrides.is_empty() {
-            "none".to_string()
-        } else {
-            overrides.join(", ")
-        };
+    }
+    changes
+}
 
-        info!(
-            "router: name={}, active={}, incoming_url={:?}, upstream_wire={:?}, overrides={}",
-            name, active, incoming_url, upstream_wire, override_summary
+fn warn_restart_only_changes(previous: &ResolvedConfig, current: &ResolvedConfig) {
+    let mut fields = Vec::new();
+    if previous.api_key_env != current.api_key_env {
+        fields.push("api_key_env");
+    }
+    if previous.server_info != current.server_info {
+        fields.push("server_info");
+    }
+    if previous.http_shutdown != current.http_shutdown {
+        fields.push("http_shutdown");
+    }
+    if previous.verbose_logging != current.verbose_logging {
+        fields.push("verbose_logging");
+    }
+    if previous.capture_dir != current.capture_dir {
+        fields.push("capture_dir");
+    }
+    if previous.sessions != current.sessions {
+        fields.push("sessions");
+    }
+    if !fields.is_empty() {
+        warn!(
+            "config reload: {} changed but only takes effect after a restart",
+            fields.join(", ")
         );
-        if let Some(host) = non_local_incoming_host {
-            warn!(
-                "router `{}` incoming_url host `{}` is not loopback/local. codex-chat-bridge is typically intended for local-only use; consider binding to localhost/127.0.0.1 or adding network controls.",
-                name, host
-            );
-        }
     }
 }
 
+async fn reload_runtime_config(
+    args: &Args,
+    config_path: &Path,
+    state: &AppState,
+    listeners: &mut ListenerSet,
+    current: &ResolvedConfig,
+    trigger: &str,
+) -> Option<ResolvedConfig> {
+    info!(
+        "config reload triggered by {}: {}",
+        trigger,
+        config_path.display()
+    );
+    let (config, mut router_manager) =
+        match load_reloaded_router_manager(args, config_path, &state.api_key) {
+            Ok(reloaded) => reloaded,
+            Err(err) => {
+                warn!("config reload rejected; keeping the running config: {err:#}");
+                return None;
+            }
+        };
+    let listen_addrs = router_manager.get_listen_addrs();
+    if let Err(err) = listeners.start_missing(&listen_addrs).await {
+        warn!("config reload rejected; keeping the running config: {err:#}");
+        return None;
+    }
+
+    let mut routers = state.routers.write().await;
+    let changes = router_reload_changes(&routers, &router_manager);
+    router_manager.reuse_load_balancer(routers.load_balancer());
+    *routers = router_manager;
+    drop(routers);
+    if changes.is_empty() {
+        info!("config reload: no router changes");
+    }
+    for change in changes {
+        info!("config reload: {}", change);
+    }
+    listeners.drain_removed(&listen_addrs);
+    if let Err(err) = listeners.write_server_info() {
+        warn!("config reload: failed to update server info: {err:#}");
+    }
+    warn_restart_only_changes(current, &config);
+    Some(config)
+}
+
 async fn run_server(
     app: Router,
+    state: Arc<AppState>,
+    args: &Args,
+    config_path: PathBuf,
+    mut config: ResolvedConfig,
     listen_addrs: &[String],
-    server_info: Option<&Path>,
 ) -> Result<()> {
-    let mut bound_ports = Vec::new();
-    let mut join_set = tokio::task::JoinSet::new();
-    for bind_addr in listen_addrs {
-        let listener = tokio::net::TcpListener::bind(bind_addr)
-            .await
-            .with_context(|| format!("binding {bind_addr}"))?;
-        let local_addr = listener.local_addr().context("reading local_addr")?;
-        bound_ports.push(local_addr.port());
-        info!("codex-chat-bridge listening on {}", local_addr);
-
-        let app_clone = app.clone();
-        let local_addr_for_task = local_addr;
-        join_set.spawn(async move {
-            axum::serve(listener, app_clone)
+    let mut listeners = ListenerSet::new(app, config.server_info.clone());
+    listeners.start_missing(listen_addrs).await?;
+    listeners.write_server_info()?;
+
+    let mut reload_triggers = spawn_reload_triggers(config_path.clone());
+    loop {
+        tokio::select! {
+            result = listeners.wait_for_unexpected_exit() => return result,
+            Some(trigger) = reload_triggers.recv() => {
+                if let Some(reloaded) = reload_runtime_config(
+                    args,
+                    &config_path,
+                    &state,
+                    &mut listeners,
+                    &config,
+                    trigger,
+                )
                 .await
-                .with_context(|| format!("serving axum app on {}", local_addr_for_task))
-        });
-    }
-
-    if let Some(path) = server_info {
-        write_server_info(path, &bound_ports)?;
-    }
-
-    match join_set.join_next().await {
-        Some(result) => {
-            result.context("listener task failed to join")??;
-            Err(anyhow!("listener exited unexpectedly"))
+                {
+                    config = reloaded;
+                }
+            }
         }
-        None => Err(anyhow!("no listener task started")),
     }
 }
 
 pub async fn run() -> Result<()> {
     let args = Args::parse();
+    match &args.command {
+        Some(Command::Replay { dir }) => {
+            return replay_capture(dir, &mut std::io::stdout().lock()).await;
+        }
+        Some(Command::Check) => return run_check(&args),
+        Some(Command::Init(init)) => return run_init(&args, init),
+        None => {}
+    }
     let (config, routers) = load_runtime_config(&args)?;
     init_tracing(config.verbose_logging);
 
@@ -271,23 +589,17 @@ pub async fn run() -> Result<()> {
         return Ok(());
     }
 
-    let api_key = std::env::var(&config.api_key_env)
-        .ok()
-        .filter(|v| !v.trim().is_empty())
-        .ok_or_else(|| anyhow!("missing or empty env var: {}", config.api_key_env))?;
+    let api_key = ApiKey::from_source(&ApiKeySource::Env(config.api_key_env.clone()));
 
     let client = Client::builder()
         .build()
         .context("building reqwest client")?;
 
     let router_manager = build_router_manager(&config, routers)?;
-    let listen_addrs = router_manager.get_listen_addrs();
-    if listen_addrs.is_empty() {
-        return Err(anyhow!(
-            "no listenable incoming_url found. configure at least one absolute URL like `http://<host>:<port>/<path>` in [routers.*].incoming_url"
-        ));
-    }
+    let listen_addrs = require_listen_addrs(&router_manager)?;
     log_runtime_startup(&config, &router_manager, &listen_addrs);
+    check_router_api_keys(&router_manager, &api_key)?;
+    let sessions = SessionStore::open(&config.sessions)?;
 
     let state = Arc::new(AppState {
         client,
@@ -295,11 +607,15 @@ pub async fn run() -> Result<()> {
         http_shutdown: config.http_shutdown,
         verbose_logging: config.verbose_logging,
         routers: Arc::new(RwLock::new(router_manager)),
-        sessions: Arc::new(RwLock::new(SessionStore::default())),
+        sessions: Arc::new(RwLock::new(sessions)),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: config.capture_dir.clone(),
     });
 
     let app = build_app(state.clone());
-    run_server(app, &listen_addrs, config.server_info.as_deref()).await
+    let config_path = resolve_config_path(args.config.clone())?;
+    run_server(app, state, &args, config_path, config, &listen_addrs).await
 }
 
 fn write_server_info(path: &Path, ports: &[u16]) -> Result<()> {
@@ -460,117 +776,20 @@ fn anthropic_request_enables_thinking(request: &Value) -> bool {
     }
 }
 
+fn wants_stream_for_request(
+    incoming_api: IncomingApi,
+    incoming_path: Option<&str>,
+    request: &Value,
+) -> bool {
+    !is_anthropic_count_tokens_path(incoming_path) && stream_flag_for_request(incoming_api, request)
+}
+
 fn is_anthropic_count_tokens_path(incoming_path: Option<&str>) -> bool {
     incoming_path
         .map(normalize_request_path)
         .is_some_and(|path| path.ends_with("/v1/messages/count_tokens"))
 }
 
-fn estimate_anthropic_count_tokens(request: &Value) -> i64 {
-    let mut total = 0usize;
-
-    total += count_anthropic_token_chars(request.get("system"));
-
-    if let Some(messages) = request.get("messages").and_then(Value::as_array) {
-        for message in messages {
-            total += count_anthropic_message_token_chars(message);
-        }
-    }
-
-    if let Some(tools) = request.get("tools").and_then(Value::as_array) {
-        for tool in tools {
-            total += count_anthropic_token_chars(tool.get("name"));
-            total += count_anthropic_token_chars(tool.get("description"));
-            total += count_anthropic_token_chars(tool.get("input_schema"));
-        }
-    }
-
-    let tokens = total / 4;
-    if tokens == 0 && request.get("messages").is_some() {
-        1
-    } else {
-        tokens as i64
-    }
-}
-
-fn count_anthropic_message_token_chars(message: &Value) -> usize {
-    let mut total = 0usize;
-    total += count_anthropic_token_chars(message.get("role"));
-    total += count_anthropic_visible_content_chars(message.get("content"));
-    total += count_anthropic_token_chars(message.get("name"));
-    total += count_anthropic_token_chars(message.get("tool_use_id"));
-    total += count_anthropic_token_chars(message.get("tool_call_id"));
-
-    total
-}
-
-fn count_anthropic_visible_content_chars(value: Option<&Value>) -> usize {
-    let Some(value) = value else {
-        return 0;
-    };
-
-    match value {
-        Value::Array(items) => items.iter().map(count_anthropic_content_block_chars).sum(),
-        Value::Object(_) => count_anthropic_content_block_chars(value),
-        _ => count_anthropic_token_chars(Some(value)),
-    }
-}
-
-fn count_anthropic_content_block_chars(block: &Value) -> usize {
-    let Some(obj) = block.as_object() else {
-        return count_anthropic_token_chars(Some(block));
-    };
-
-    let block_type = obj
-        .get("type")
-        .and_then(Value::as_str)
-        .unwrap_or_default()
-        .to_ascii_lowercase();
-
-    if matches!(
-        block_type.as_str(),
-        "thinking" | "redacted_thinking" | "signature"
-    ) {
-        return 0;
-    }
-
-    let mut total = 0usize;
-    if !block_type.is_empty() {
-        total += block_type.len();
-    }
-
-    total += count_anthropic_token_chars(obj.get("text"));
-    total += count_anthropic_token_chars(obj.get("content"));
-    total += count_anthropic_token_chars(obj.get("input"));
-    total += count_anthropic_token_chars(obj.get("name"));
-    total += count_anthropic_token_chars(obj.get("id"));
-    total += count_anthropic_token_chars(obj.get("tool_use_id"));
-    total += count_anthropic_token_chars(obj.get("data"));
-
-    total
-}
-
-fn count_anthropic_token_chars(value: Option<&Value>) -> usize {
-    let Some(value) = value else {
-        return 0;
-    };
-
-    match value {
-        Value::Null => 0,
-        Value::Bool(v) => {
-            if *v {
-                4
-            } else {
-                5
-            }
-        }
-        Value::Number(n) => n.to_string().len(),
-        Value::String(s) => s.len(),
-        Value::Array(items) => items.iter().map(count_anthropic_content_block_chars).sum(),
-        Value::Object(_) => serde_json::to_string(value).map(|s| s.len()).unwrap_or(0),
-    }
-}
-
 async fn resolve_route_target(
     state: &Arc<AppState>,
     headers: &HeaderMap,
@@ -613,6 +832,7 @@ async fn resolve_route_target(
     Ok(route_target)
 }
 
+#[allow(clippy::result_large_err)]
 fn parse_and_prepare_request(
     body: &str,
     incoming_api_hint: Option<IncomingApi>,
@@ -660,34 +880,13 @@ fn parse_and_prepare_request(
 
     let incoming_api =
         infer_incoming_api_from_hint_or_path(incoming_api_hint, incoming_path, &request_value);
-    let wants_stream = if is_anthropic_count_tokens_path(incoming_path) {
-        false
-    } else {
-        stream_flag_for_request(incoming_api, &request_value)
-    };
+    let wants_stream = wants_stream_for_request(incoming_api, incoming_path, &request_value);
     apply_request_filters(
         incoming_api,
         &mut request_value,
         &route_target.drop_tool_types,
         &route_target.drop_request_fields,
     );
-    if let Err(err) = validate_capability_gate(
-        incoming_api,
-        route_target.upstream_wire,
-        route_target.feature_flags.enable_extended_input_types,
-        &request_value,
-    ) {
-        warn!(
-            "request capability gate rejected: router={}, incoming_api={:?}, upstream_wire={:?}, error={}",
-            route_target.router_name, incoming_api, route_target.upstream_wire, err
-        );
-        return Err(error_response_for_api(
-            incoming_api,
-            wants_stream,
-            "unsupported_feature",
-            &err.to_string(),
-        ));
-    }
     let tool_call_kinds_by_name = if incoming_api == IncomingApi::Responses {
         responses_tool_call_kind_by_name(&request_value)
     } else {
@@ -708,170 +907,170 @@ async fn build_upstream_payload_with_session(
     incoming_api: IncomingApi,
     route_target: &RouteTarget,
     wants_stream: bool,
-) -> std::result::Result<(String, Value), Response> {
-    let response_id = format!("resp_bridge_{}", Uuid::now_v7());
-    let mut upstream_payload = match build_upstream_payload(
+    response_id: &str,
+) -> std::result::Result<(Value, Option<SessionTurn>), Response> {
+    let invalid_request = |stage: &str, err: anyhow::Error| {
+        warn!(
+            "{stage} failed: router={}, incoming_api={:?}, upstream_wire={:?}, response_id={}, error={}",
+            route_target.router_name, incoming_api, route_target.upstream_wire, response_id, err
+        );
+        error_response_for_api(
+            incoming_api,
+            wants_stream,
+            "invalid_request",
+            &err.to_string(),
+        )
+    };
+    let should_store_previous_response_messages =
+        route_target.feature_flags.enable_previous_response_id
+            && incoming_api == IncomingApi::Responses
+            && route_target.upstream_wire.keeps_responses_history();
+    let mapped_wire = if should_store_previous_response_messages {
+        WireApi::Chat
+    } else {
+        route_target.upstream_wire
+    };
+    let mut upstream_payload = build_upstream_payload(
         request_value,
         incoming_api,
-        route_target.upstream_wire,
+        mapped_wire,
         wants_stream,
         route_target.feature_flags.enable_extended_input_types,
         route_target.feature_flags.tool_transform_mode,
         route_target.anthropic_preserve_thinking,
-    ) {
-        Ok(v) => v,
-        Err(err) => {
-            warn!(
-                "request mapping failed: router={}, incoming_api={:?}, upstream_wire={:?}, error={}",
-                route_target.router_name, incoming_api, route_target.upstream_wire, err
-            );
-            return Err(error_response_for_api(
-                incoming_api,
-                wants_stream,
-                "invalid_request",
-                &err.to_string(),
-            ));
+    )
+    .map_err(|err| invalid_request("request mapping", err))?;
+
+    let mut session_messages = None;
+    if should_store_previous_response_messages {
+        let previous_messages = {
+            let sessions = state.sessions.read().await;
+            resolve_previous_messages_for_request(request_value, &sessions)
+                .map_err(|err| invalid_request("previous response lookup", err))?
+        };
+        let mut native_request = None;
+        if let Some(messages) = previous_messages {
+            if route_target.upstream_wire == WireApi::Messages {
+                native_request = Some(
+                    prepend_previous_input(request_value, &messages)
+                        .map_err(|err| invalid_request("previous response merge", err))?,
+                );
+            }
+            merge_previous_messages(&mut upstream_payload, messages)
+                .map_err(|err| invalid_request("previous response merge", err))?;
         }
-    };
+        if mapped_wire != route_target.upstream_wire {
+            session_messages = upstream_payload.get("messages").cloned();
+            upstream_payload = if route_target.upstream_wire == WireApi::Messages {
+                build_upstream_payload(
+                    native_request.as_ref().unwrap_or(request_value),
+                    incoming_api,
+                    route_target.upstream_wire,
+                    wants_stream,
+                    route_target.feature_flags.enable_extended_input_types,
+                    route_target.feature_flags.tool_transform_mode,
+                    route_target.anthropic_preserve_thinking,
+                )
+            } else {
+                map_chat_to_native_request(
+                    &upstream_payload,
+                    route_target.upstream_wire,
+                    wants_stream,
+                )
+            }
+            .map_err(|err| invalid_request("request mapping", err))?;
+        }
+    }
+
     apply_upstream_model_override(&mut upstream_payload, route_target);
+    if route_target.upstream_wire == WireApi::Ollama {
+        apply_ollama_overrides(&mut upstream_payload, &route_target.ollama);
+    }
     if incoming_api == IncomingApi::Anthropic && route_target.upstream_wire != WireApi::Messages {
         strip_anthropic_reasoning_fields(&mut upstream_payload);
     }
-    if route_target.upstream_wire != WireApi::Messages
-        && route_target.anthropic_enable_openrouter_reasoning
+    if matches!(
+        route_target.upstream_wire,
+        WireApi::Chat | WireApi::Responses
+    ) && route_target.anthropic_enable_openrouter_reasoning
         && anthropic_request_enables_thinking(request_value)
     {
         inject_openrouter_reasoning(&mut upstream_payload);
     }
 
-    let should_store_previous_response_messages =
-        route_target.feature_flags.enable_previous_response_id
-            && incoming_api == IncomingApi::Responses
-            && route_target.upstream_wire == WireApi::Chat;
-    if should_store_previous_response_messages {
-        let previous_messages = {
-            let sessions = state.sessions.read().await;
-            match resolve_previous_messages_for_request(request_value, &sessions) {
-                Ok(v) => v,
-                Err(err) => {
-                    warn!(
-                        "previous response lookup failed: router={}, response_id={}, error={}",
-                        route_target.router_name, response_id, err
-                    );
-                    return Err(error_response_for_api(
-                        incoming_api,
-                        wants_stream,
-                        "invalid_request",
-                        &err.to_string(),
-                    ));
-                }
-            }
-        };
-        if let Some(messages) = previous_messages
-            && let Err(err) = merge_previous_messages(&mut upstream_payload, messages)
-        {
-            warn!(
-                "previous response merge failed: router={}, response_id={}, error={}",
-                route_target.router_name, response_id, err
-            );
-            return Err(error_response_for_api(
-                incoming_api,
-                wants_stream,
-                "invalid_request",
-                &err.to_string(),
-            ));
-        }
-    }
-
     if route_target.upstream_wire == WireApi::Chat {
-        normalize_unsupported_chat_message_roles(&mut upstream_payload);
+        apply_message_role_policy(&mut upstream_payload, &route_target.message_roles);
     }
 
-    if should_store_previous_response_messages {
-        let chat_messages = upstream_payload
-            .get("messages")
+    let session_turn = should_store_previous_response_messages.then(|| {
+        let messages = session_messages
+            .as_ref()
+            .or_else(|| upstream_payload.get("messages"))
             .and_then(Value::as_array)
             .cloned()
             .unwrap_or_default();
-        let mut sessions = state.sessions.write().await;
-        sessions.insert_messages(response_id.clone(), chat_messages);
-    }
-
-    Ok((response_id, upstream_payload))
-}
-
-fn normalize_unsupported_chat_message_roles(payload: &mut Value) {
-    let Some(messages) = payload.get_mut("messages").and_then(Value::as_array_mut) else {
-        return;
-    };
-
-    let original_messages = std::mem::take(messages);
-    let mut system_contents = Vec::new();
-    let mut normalized_messages = Vec::new();
-
-    for mut message in original_messages {
-        let role = message
-            .get("role")
-            .and_then(Value::as_str)
-            .unwrap_or_default()
-            .to_string();
-
-        match role.as_str() {
-            "developer" | "system" => {
-                if let Some(content) = chat_message_content_to_system_text(&message) {
-                    system_contents.push(content);
-                }
-            }
-            "tool" | "function" => {
-                let Some(obj) = message.as_object_mut() else {
-                    normalized_messages.push(message);
-                    continue;
-                };
-                obj.insert("role".to_string(), Value::String("user".to_string()));
-                obj.remove("tool_call_id");
-                obj.remove("name");
-                normalized_messages.push(message);
-            }
-            _ => normalized_messages.push(message),
-        }
-    }
-
-    if !system_contents.is_empty() {
-        messages.push(serde_json::json!({
-            "role": "system",
-            "content": system_contents.join("\n\n"),
-        }));
-    }
-    messages.extend(normalized_messages);
-}
+        SessionTurn::new(
+            state.sessions.clone(),
+            route_target.router_name.clone(),
+            response_id.to_string(),
+            upstream_payload
+                .get("model")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+            messages,
+        )
+    });
 
-fn chat_message_content_to_system_text(message: &Value) -> Option<String> {
-    let content = message.get("content")?;
-    let text = match content {
-        Value::String(text) => text.clone(),
-        Value::Null => return None,
-        other => other.to_string(),
-    };
-    if text.trim().is_empty() {
-        None
-    } else {
-        Some(text)
-    }
+    Ok((upstream_payload, session_turn))
 }
 
 fn build_upstream_request(
     state: &Arc<AppState>,
     route_target: &RouteTarget,
+    api_key: &str,
     headers: &HeaderMap,
     upstream_payload: &Value,
     incoming_path: Option<&str>,
 ) -> reqwest::RequestBuilder {
+    let merged_headers = upstream_request_headers(route_target, api_key, headers);
+    let (upstream_url, gemini_body) = if route_target.upstream_wire == WireApi::Gemini {
+        let (upstream_url, body) =
+            gemini_upstream_request(&route_target.upstream_url, upstream_payload);
+        (upstream_url, Some(body))
+    } else {
+        (upstream_url_for_request(route_target, incoming_path), None)
+    };
+    route_target
+        .client
+        .as_ref()
+        .unwrap_or(&state.client)
+        .post(upstream_url)
+        .header(CONTENT_TYPE, "application/json")
+        .json(gemini_body.as_ref().unwrap_or(upstream_payload))
+        .headers(merged_headers)
+}
+
+pub(crate) fn upstream_request_headers(
+    route_target: &RouteTarget,
+    api_key: &str,
+    headers: &HeaderMap,
+) -> HeaderMap {
     let mut merged_headers = HeaderMap::new();
+    if let Some(header_name) = route_target.auth_scheme.header_name()
+        && let (Ok(name), Ok(mut value)) = (
+            HeaderName::from_bytes(header_name.as_bytes()),
+            HeaderValue::from_str(&route_target.auth_scheme.header_value(api_key)),
+        )
+    {
+        value.set_sensitive(true);
+        merged_headers.insert(name, value);
+    }
     for header_name in &route_target.forward_incoming_headers {
-        if let Some(value) = headers.get(header_name) {
-            if let Ok(name) = HeaderName::from_bytes(header_name.as_bytes()) {
-                merged_headers.insert(name, value.clone());
-            }
+        if let Some(value) = headers.get(header_name)
+            && let Ok(name) = HeaderName::from_bytes(header_name.as_bytes())
+        {
+            merged_headers.insert(name, value.clone());
         }
     }
     for header in &route_target.upstream_http_headers {
@@ -882,19 +1081,121 @@ fn build_upstream_request(
             merged_headers.insert(name, value);
         }
     }
+    if route_target.upstream_wire == WireApi::Messages
+        && !merged_headers.contains_key("anthropic-version")
+    {
+        merged_headers.insert(
+            HeaderName::from_static("anthropic-version"),
+            HeaderValue::from_static(DEFAULT_ANTHROPIC_VERSION),
+        );
+    }
+    merged_headers
+}
 
-    let upstream_url = upstream_url_for_request(route_target, incoming_path);
-    let mut upstream_request = state
-        .client
-        .post(upstream_url)
-        .bearer_auth(&state.api_key)
-        .header(CONTENT_TYPE, "application/json")
-        .json(upstream_payload);
+enum UpstreamSendError {
+    Transport(reqwest::Error),
+    Timeout(String),
+    Status {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: String,
+    },
+}
 
-    if !merged_headers.is_empty() {
-        upstream_request = upstream_request.headers(merged_headers);
+async fn send_upstream_request(
+    state: &Arc<AppState>,
+    route_target: &RouteTarget,
+    api_key: &str,
+    headers: &HeaderMap,
+    upstream_payload: &Value,
+    incoming_path: Option<&str>,
+) -> std::result::Result<(reqwest::Response, Option<Instant>), UpstreamSendError> {
+    let retry = &route_target.retry;
+    let deadline = route_target.timeouts.deadline_from(Instant::now());
+    let out_of_time =
+        |delay: Duration| deadline.is_some_and(|deadline| Instant::now() + delay >= deadline);
+    let mut attempt = 1;
+    loop {
+        let send = build_upstream_request(
+            state,
+            route_target,
+            api_key,
+            headers,
+            upstream_payload,
+            incoming_path,
+        )
+        .send();
+        let result = match route_target.timeouts.first_byte_wait(deadline) {
+            Some((wait, message)) => match tokio::time::timeout(wait, send).await {
+                Ok(result) => result.map_err(UpstreamStreamError::Transport),
+                Err(_) => Err(UpstreamStreamError::Timeout(message)),
+            },
+            None => send.await.map_err(UpstreamStreamError::Transport),
+        };
+        match &result {
+            Ok(response) => state
+                .metrics
+                .record_upstream_status(&route_target.router_name, response.status().as_u16()),
+            Err(UpstreamStreamError::Transport(_)) => state
+                .metrics
+                .record_upstream_error(&route_target.router_name, "upstream_transport_error"),
+            Err(err) => state
+                .metrics
+                .record_upstream_error(&route_target.router_name, err.code()),
+        }
+
+        let (reason, delay) = match result {
+            Ok(response) if response.status().is_success() => return Ok((response, deadline)),
+            Ok(response) => {
+                let status = response.status();
+                let response_headers = response.headers().clone();
+                let body = response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "<failed to read error body>".to_string());
+                let code = normalize_upstream_error_payload(status, &body).code;
+                state
+                    .metrics
+                    .record_upstream_error(&route_target.router_name, &code);
+                let delay = retry.delay_for_attempt(attempt, Some(&response_headers));
+                if attempt >= retry.max_attempts
+                    || !retry.is_retryable_failure(status, &code)
+                    || out_of_time(delay)
+                {
+                    return Err(UpstreamSendError::Status {
+                        status,
+                        headers: response_headers,
+                        body,
+                    });
+                }
+                (format!("status={status}, code={code}"), delay)
+            }
+            Err(err) => {
+                let delay = retry.delay_for_attempt(attempt, None);
+                if attempt >= retry.max_attempts || out_of_time(delay) {
+                    return Err(match err {
+                        UpstreamStreamError::Transport(err) => UpstreamSendError::Transport(err),
+                        UpstreamStreamError::Timeout(message) => {
+                            UpstreamSendError::Timeout(message)
+                        }
+                    });
+                }
+                (format!("transport error: {err}"), delay)
+            }
+        };
+
+        warn!(
+            "retrying upstream request: router={}, upstream_url={}, attempt={}/{}, delay_ms={}, reason={}",
+            route_target.router_name,
+            route_target.upstream_url,
+            attempt + 1,
+            retry.max_attempts,
+            delay.as_millis(),
+            reason
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
     }
-    upstream_request
 }
 
 fn upstream_url_for_request(route_target: &RouteTarget, incoming_path: Option<&str>) -> String {
@@ -908,6 +1209,55 @@ fn upstream_url_for_request(route_target: &RouteTarget, incoming_path: Option<&s
     route_target.upstream_url.clone()
 }
 
+const GEMINI_MODEL_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn gemini_upstream_request(upstream_url: &str, payload: &Value) -> (String, Value) {
+    let model = payload
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let model = model.strip_prefix("models/").unwrap_or(model);
+    let model = utf8_percent_encode(model, GEMINI_MODEL_SEGMENT).to_string();
+    let stream = payload
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    let mut url = upstream_url.replace("{model}", &model);
+    url = if stream {
+        url.replace(":generateContent", ":streamGenerateContent")
+    } else {
+        url.replace(":streamGenerateContent", ":generateContent")
+    };
+    if let Ok(mut parsed) = reqwest::Url::parse(&url) {
+        let query = parsed
+            .query_pairs()
+            .filter(|(key, _)| key != "alt")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+        parsed.query_pairs_mut().clear().extend_pairs(query);
+        if stream {
+            parsed.query_pairs_mut().append_pair("alt", "sse");
+        }
+        if parsed.query() == Some("") {
+            parsed.set_query(None);
+        }
+        url = parsed.to_string();
+    }
+
+    let mut body = payload.clone();
+    if let Some(obj) = body.as_object_mut() {
+        obj.remove("model");
+        obj.remove("stream");
+    }
+    (url, body)
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn finalize_upstream_response(
     upstream_response: reqwest::Response,
     route_target: &RouteTarget,
@@ -922,6 +1272,10 @@ async fn finalize_upstream_response(
     response_id: String,
     tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
     verbose_logging: bool,
+    body_deadline: Option<Instant>,
+    metrics: &Arc<Metrics>,
+    session_turn: Option<SessionTurn>,
+    capture: Option<&Capture>,
 ) -> Response {
     if !upstream_response.status().is_success() {
         let status = upstream_response.status();
@@ -930,84 +1284,106 @@ async fn finalize_upstream_response(
             .text()
             .await
             .unwrap_or_else(|_| "<failed to read error body>".to_string());
-        warn_llm_error_exchange(LlmErrorExchangeLog {
-            route_target,
-            incoming_api,
-            incoming_headers,
-            incoming_body,
-            upstream_request_headers,
-            upstream_payload,
-            upstream_response_status: status,
-            upstream_response_headers: &upstream_response_headers,
-            upstream_response_body: &upstream_response_body,
-        });
-        let normalized = normalize_upstream_error_payload(status, &upstream_response_body);
-        warn!(
-            "upstream error: router={}, incoming_api={:?}, upstream_wire={:?}, status={}, code={}, message={}",
-            route_target.router_name,
-            incoming_api,
-            route_target.upstream_wire,
-            status,
-            normalized.code,
-            normalized.message
-        );
-        return error_response_for_api(
-            incoming_api,
+        if let Some(capture) = capture {
+            capture
+                .record_upstream_error(status, &upstream_response_headers, &upstream_response_body)
+                .await;
+        }
+        return upstream_error_response(
+            LlmErrorExchangeLog {
+                route_target,
+                incoming_api,
+                incoming_headers,
+                incoming_body,
+                upstream_request_headers,
+                upstream_payload,
+                upstream_response_status: status,
+                upstream_response_headers: &upstream_response_headers,
+                upstream_response_body: &upstream_response_body,
+            },
             wants_stream,
-            &normalized.code,
-            &normalized.message,
         );
     }
 
+    let upstream_body = with_stream_timeouts(
+        upstream_response.bytes_stream(),
+        route_target.timeouts.clone(),
+        body_deadline,
+    );
+    let upstream_body = match capture {
+        Some(capture) => Either::Left(capture.tee(
+            if wants_stream {
+                UPSTREAM_STREAM_FILE
+            } else {
+                "upstream_response.json"
+            },
+            upstream_body,
+        )),
+        None => Either::Right(upstream_body),
+    };
     if wants_stream {
-        if incoming_api == IncomingApi::Anthropic
-            && route_target.upstream_wire == WireApi::Responses
-        {
-            return error_response_for_api(
-                incoming_api,
-                true,
-                "unsupported_feature",
-                "anthropic `/v1/messages` streaming currently requires `upstream_wire = \"chat\"` or `\"messages\"`",
-            );
-        }
+        let upstream_body = if route_target.upstream_wire == WireApi::Ollama {
+            Either::Left(translate_ollama_stream_to_chat(
+                upstream_body,
+                route_target.router_name.clone(),
+                verbose_logging,
+                upstream_model.clone(),
+            ))
+        } else {
+            Either::Right(upstream_body)
+        };
+        let upstream_body = observe_stream_metrics(
+            upstream_body,
+            metrics.clone(),
+            route_target.router_name.clone(),
+        );
         let body = match route_target.upstream_wire {
-            WireApi::Chat => {
+            WireApi::Chat | WireApi::Ollama => chat_stream_body(
+                upstream_body,
+                route_target,
+                incoming_api,
+                verbose_logging,
+                upstream_model,
+                anthropic_input_tokens,
+                response_id,
+                tool_call_kinds_by_name,
+                session_turn,
+            ),
+            WireApi::Gemini => chat_stream_body(
+                translate_gemini_stream_to_chat(
+                    upstream_body,
+                    route_target.router_name.clone(),
+                    verbose_logging,
+                    upstream_model.clone(),
+                ),
+                route_target,
+                incoming_api,
+                verbose_logging,
+                upstream_model,
+                anthropic_input_tokens,
+                response_id,
+                tool_call_kinds_by_name,
+                session_turn,
+            ),
+            WireApi::Responses => {
                 if incoming_api == IncomingApi::Anthropic {
-                    Body::from_stream(translate_chat_stream_to_anthropic(
-                        upstream_response.bytes_stream(),
+                    Body::from_stream(translate_responses_stream_to_anthropic(
+                        upstream_body,
                         route_target.router_name.clone(),
                         verbose_logging,
                         upstream_model.clone(),
                         anthropic_input_tokens,
                     ))
                 } else if incoming_api == IncomingApi::Chat {
-                    Body::from_stream(passthrough_chat_stream(
-                        upstream_response.bytes_stream(),
-                        route_target.router_name.clone(),
-                        verbose_logging,
-                    ))
-                } else {
-                    Body::from_stream(translate_chat_stream(
-                        upstream_response.bytes_stream(),
-                        response_id,
-                        route_target.router_name.clone(),
-                        verbose_logging,
-                        tool_call_kinds_by_name,
-                        route_target.feature_flags,
-                    ))
-                }
-            }
-            WireApi::Responses => {
-                if incoming_api == IncomingApi::Chat {
                     Body::from_stream(translate_responses_stream_to_chat(
-                        upstream_response.bytes_stream(),
+                        upstream_body,
                         route_target.router_name.clone(),
                         verbose_logging,
                         upstream_model.clone(),
                     ))
                 } else {
                     Body::from_stream(passthrough_responses_stream(
-                        upstream_response.bytes_stream(),
+                        upstream_body,
                         route_target.router_name.clone(),
                         verbose_logging,
                     ))
@@ -1016,20 +1392,36 @@ async fn finalize_upstream_response(
             WireApi::Messages => {
                 if incoming_api == IncomingApi::Anthropic {
                     Body::from_stream(passthrough_messages_stream(
-                        upstream_response.bytes_stream(),
+                        upstream_body,
                         route_target.router_name.clone(),
                         verbose_logging,
                     ))
+                } else if incoming_api == IncomingApi::Responses {
+                    Body::from_stream(translate_anthropic_stream_to_responses(
+                        upstream_body,
+                        response_id,
+                        route_target.router_name.clone(),
+                        verbose_logging,
+                        tool_call_kinds_by_name,
+                        route_target.feature_flags,
+                        session_turn,
+                    ))
                 } else {
-                    return error_response_for_api(
-                        incoming_api,
-                        true,
-                        "unsupported_feature",
-                        "`upstream_wire = \"messages\"` only supports Anthropic `/v1/messages` clients",
-                    );
+                    Body::from_stream(translate_anthropic_stream_to_chat(
+                        upstream_body,
+                        route_target.router_name.clone(),
+                        verbose_logging,
+                        upstream_model.clone(),
+                    ))
                 }
             }
         };
+        let body = match capture {
+            Some(capture) => {
+                Body::from_stream(capture.tee(CLIENT_STREAM_FILE, body.into_data_stream()))
+            }
+            None => body,
+        };
 
         return (
             StatusCode::OK,
@@ -1046,7 +1438,19 @@ async fn finalize_upstream_response(
             .into_response();
     }
 
-    let upstream_json = match upstream_response.json::<Value>().await {
+    let upstream_body = match collect_body(upstream_body).await {
+        Ok(body) => body,
+        Err(err) => {
+            metrics.record_upstream_error(&route_target.router_name, err.code());
+            return error_response_for_api(
+                incoming_api,
+                false,
+                err.code(),
+                &format!("failed to read upstream response: {err}"),
+            );
+        }
+    };
+    let upstream_json = match serde_json::from_slice::<Value>(&upstream_body) {
         Ok(v) => v,
         Err(err) => {
             return error_response_for_api(
@@ -1066,21 +1470,46 @@ async fn finalize_upstream_response(
             &upstream_json.to_string(),
         );
     }
+    if let Some(usage) = usage_tokens(&upstream_json) {
+        metrics.record_usage(&route_target.router_name, usage);
+    }
 
     let response_json = match route_target.upstream_wire {
         WireApi::Chat => {
-            if incoming_api == IncomingApi::Anthropic {
-                chat_json_to_anthropic_json(upstream_json, &upstream_model)
-            } else if incoming_api == IncomingApi::Chat {
-                upstream_json
-            } else {
-                chat_json_to_responses_json(
-                    upstream_json,
-                    response_id,
-                    &tool_call_kinds_by_name,
-                    route_target.feature_flags.enable_provider_specific_fields,
-                )
-            }
+            chat_json_for_incoming_api(
+                upstream_json,
+                route_target,
+                incoming_api,
+                &upstream_model,
+                response_id,
+                &tool_call_kinds_by_name,
+                session_turn,
+            )
+            .await
+        }
+        WireApi::Gemini => {
+            chat_json_for_incoming_api(
+                gemini_json_to_chat_json(upstream_json, &upstream_model),
+                route_target,
+                incoming_api,
+                &upstream_model,
+                response_id,
+                &tool_call_kinds_by_name,
+                session_turn,
+            )
+            .await
+        }
+        WireApi::Ollama => {
+            chat_json_for_incoming_api(
+                ollama_json_to_chat_json(upstream_json, &upstream_model),
+                route_target,
+                incoming_api,
+                &upstream_model,
+                response_id,
+                &tool_call_kinds_by_name,
+                session_turn,
+            )
+            .await
         }
         WireApi::Responses => {
             if incoming_api == IncomingApi::Anthropic {
@@ -1094,20 +1523,112 @@ async fn finalize_upstream_response(
         WireApi::Messages => {
             if incoming_api == IncomingApi::Anthropic {
                 upstream_json
-            } else {
-                return error_response_for_api(
-                    incoming_api,
-                    false,
-                    "unsupported_feature",
-                    "`upstream_wire = \"messages\"` only supports Anthropic `/v1/messages` clients",
+            } else if incoming_api == IncomingApi::Responses {
+                let response_json = anthropic_json_to_responses_json(
+                    upstream_json,
+                    response_id,
+                    &tool_call_kinds_by_name,
                 );
+                if let Some(session_turn) = session_turn {
+                    let output = response_json
+                        .get("output")
+                        .and_then(Value::as_array)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default();
+                    session_turn
+                        .complete(output, response_json.get("usage"))
+                        .await;
+                }
+                response_json
+            } else {
+                anthropic_json_to_chat_json(upstream_json, &upstream_model)
             }
         }
     };
+    if let Some(capture) = capture {
+        capture
+            .write_json("client_response.json", &response_json)
+            .await;
+    }
 
     json_success_response(response_json)
 }
 
+#[allow(clippy::too_many_arguments)]
+fn chat_stream_body<S, E>(
+    chat_stream: S,
+    route_target: &RouteTarget,
+    incoming_api: IncomingApi,
+    verbose_logging: bool,
+    upstream_model: String,
+    anthropic_input_tokens: i64,
+    response_id: String,
+    tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
+    session_turn: Option<SessionTurn>,
+) -> Body
+where
+    S: futures::Stream<Item = std::result::Result<axum::body::Bytes, E>> + Send + 'static,
+    E: Into<UpstreamStreamError> + Send + 'static,
+{
+    match incoming_api {
+        IncomingApi::Anthropic => Body::from_stream(translate_chat_stream_to_anthropic(
+            chat_stream,
+            route_target.router_name.clone(),
+            verbose_logging,
+            upstream_model,
+            anthropic_input_tokens,
+        )),
+        IncomingApi::Chat => Body::from_stream(passthrough_chat_stream(
+            chat_stream,
+            route_target.router_name.clone(),
+            verbose_logging,
+        )),
+        IncomingApi::Responses => Body::from_stream(translate_chat_stream(
+            chat_stream,
+            response_id,
+            route_target.router_name.clone(),
+            verbose_logging,
+            tool_call_kinds_by_name,
+            route_target.feature_flags,
+            session_turn,
+        )),
+    }
+}
+
+async fn chat_json_for_incoming_api(
+    chat_json: Value,
+    route_target: &RouteTarget,
+    incoming_api: IncomingApi,
+    upstream_model: &str,
+    response_id: String,
+    tool_call_kinds_by_name: &HashMap<String, ResponsesToolCallKind>,
+    session_turn: Option<SessionTurn>,
+) -> Value {
+    match incoming_api {
+        IncomingApi::Anthropic => chat_json_to_anthropic_json(chat_json, upstream_model),
+        IncomingApi::Chat => chat_json,
+        IncomingApi::Responses => {
+            let response_json = chat_json_to_responses_json(
+                chat_json,
+                response_id,
+                tool_call_kinds_by_name,
+                route_target.feature_flags.enable_provider_specific_fields,
+            );
+            if let Some(session_turn) = session_turn {
+                let output = response_json
+                    .get("output")
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                session_turn
+                    .complete(output, response_json.get("usage"))
+                    .await;
+            }
+            response_json
+        }
+    }
+}
+
 struct LlmErrorExchangeLog<'a> {
     route_target: &'a RouteTarget,
     incoming_api: IncomingApi,
@@ -1120,6 +1641,29 @@ struct LlmErrorExchangeLog<'a> {
     upstream_response_body: &'a str,
 }
 
+fn upstream_error_response(log: LlmErrorExchangeLog<'_>, wants_stream: bool) -> Response {
+    let route_target = log.route_target;
+    let incoming_api = log.incoming_api;
+    let status = log.upstream_response_status;
+    let normalized = normalize_upstream_error_payload(status, log.upstream_response_body);
+    warn_llm_error_exchange(log);
+    warn!(
+        "upstream error: router={}, incoming_api={:?}, upstream_wire={:?}, status={}, code={}, message={}",
+        route_target.router_name,
+        incoming_api,
+        route_target.upstream_wire,
+        status,
+        normalized.code,
+        normalized.message
+    );
+    error_response_for_api(
+        incoming_api,
+        wants_stream,
+        &normalized.code,
+        &normalized.message,
+    )
+}
+
 fn warn_llm_error_exchange(log: LlmErrorExchangeLog<'_>) {
     warn!(
         "llm error incoming request headers: router={}, incoming_api={:?}, upstream_wire={:?}, headers={}",
@@ -1206,6 +1750,16 @@ pub(crate) async fn handle_incoming(
             Err(response) => return response,
         };
 
+    if let Some(inbound_auth) = route_target.inbound_auth.as_ref()
+        && !inbound_auth.authorizes(&headers)
+    {
+        warn!(
+            "inbound auth rejected: router={}, incoming_route={}, host={:?}",
+            route_target.router_name, incoming_route, host_header
+        );
+        return inbound_auth_error_response(incoming_api_hint, incoming_path.as_deref(), &body);
+    }
+
     debug!(
         "request routed: router={}, incoming_route={}, upstream_url={}, upstream_wire={:?}",
         route_target.router_name,
@@ -1214,6 +1768,16 @@ pub(crate) async fn handle_incoming(
         route_target.upstream_wire
     );
 
+    let capture = match state.capture_dir.as_deref() {
+        Some(root) => Capture::start(root, &route_target.router_name).await,
+        None => None,
+    };
+    if let Some(capture) = capture.as_deref() {
+        capture
+            .record_incoming(&incoming_route, &headers, &body)
+            .await;
+    }
+
     if verbose_logging {
         debug!(
             "incoming headers (router={}): {}",
@@ -1240,15 +1804,22 @@ pub(crate) async fn handle_incoming(
             Ok(v) => v,
             Err(response) => return response,
         };
+    let request_metrics = state.metrics.start_request(
+        &route_target.router_name,
+        incoming_api,
+        route_target.upstream_wire,
+        wants_stream,
+    );
 
     if incoming_api == IncomingApi::Anthropic
         && is_anthropic_count_tokens_path(incoming_path.as_deref())
-        && route_target.upstream_wire != WireApi::Messages
+        && (route_target.upstream_wire != WireApi::Messages || route_target.tokenizer.is_some())
     {
-        let input_tokens = estimate_anthropic_count_tokens(&request_value);
+        let input_tokens =
+            count_anthropic_input_tokens(&state, &route_target, &headers, &request_value).await;
         if verbose_logging {
             debug!(
-                "anthropic count_tokens handled locally (router={}, estimated_input_tokens={})",
+                "anthropic count_tokens handled locally (router={}, input_tokens={})",
                 route_target.router_name, input_tokens
             );
         }
@@ -1257,139 +1828,438 @@ pub(crate) async fn handle_incoming(
         }));
     }
 
-    let (response_id, upstream_payload) = match build_upstream_payload_with_session(
+    if incoming_api == IncomingApi::Responses
+        && route_target.upstream_wire == WireApi::Chat
+        && request_value.get("background").and_then(Value::as_bool) == Some(true)
+    {
+        if wants_stream {
+            return error_response_for_api(
+                incoming_api,
+                wants_stream,
+                "unsupported_feature",
+                "`background` responses cannot be streamed; poll `GET /v1/responses/{id}` instead",
+            );
+        }
+        return start_background_response(
+            state,
+            headers,
+            body,
+            incoming_path,
+            incoming_route,
+            route_target,
+            request_value,
+            tool_call_kinds_by_name,
+            request_metrics,
+            capture,
+        )
+        .await;
+    }
+
+    forward_to_upstream_candidates(
         &state,
-        &request_value,
+        &headers,
+        &body,
         incoming_api,
+        incoming_path.as_deref(),
+        &incoming_route,
         &route_target,
+        &request_value,
         wants_stream,
+        format!("resp_bridge_{}", Uuid::now_v7()),
+        tool_call_kinds_by_name,
+        request_metrics,
+        capture.as_deref(),
+        true,
     )
     .await
-    {
-        Ok(v) => v,
-        Err(response) => return response,
-    };
+}
 
-    let upstream_request_headers = upstream_headers_for_logging(
-        &headers,
-        &state.api_key,
-        &route_target.upstream_http_headers,
-        &route_target.forward_incoming_headers,
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn forward_to_upstream_candidates(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    body: &str,
+    incoming_api: IncomingApi,
+    incoming_path: Option<&str>,
+    incoming_route: &str,
+    route_target: &RouteTarget,
+    request_value: &Value,
+    wants_stream: bool,
+    response_id: String,
+    tool_call_kinds_by_name: HashMap<String, ResponsesToolCallKind>,
+    mut request_metrics: RequestMetricsGuard,
+    capture: Option<&Capture>,
+    store_turn: bool,
+) -> Response {
+    let verbose_logging = state.verbose_logging;
+    let anthropic_input_tokens = if incoming_api == IncomingApi::Anthropic {
+        count_anthropic_input_tokens(state, route_target, headers, request_value).await
+    } else {
+        0
+    };
+    let balancer = state.routers.read().await.load_balancer();
+    let candidates = balancer.order_candidates(
+        route_target,
+        previous_response_id_for_request(request_value),
     );
+    for (candidate_index, candidate) in candidates.iter().enumerate() {
+        let has_fallback = candidate_index + 1 < candidates.len();
+        request_metrics.set_upstream_wire(candidate.upstream_wire);
+        let (failure, fail_over) = 'attempt: {
+            if let Err(err) = validate_capability_gate(
+                incoming_api,
+                candidate.upstream_wire,
+                candidate.feature_flags.enable_extended_input_types,
+                request_value,
+            ) {
+                warn!(
+                    "request capability gate rejected: router={}, incoming_api={:?}, upstream_wire={:?}, error={}",
+                    candidate.router_name, incoming_api, candidate.upstream_wire, err
+                );
+                break 'attempt (
+                    error_response_for_api(
+                        incoming_api,
+                        wants_stream,
+                        "unsupported_feature",
+                        &err.to_string(),
+                    ),
+                    true,
+                );
+            }
 
-    if verbose_logging {
-        if let Some(messages) =
-            upstream_messages_for_logging(route_target.upstream_wire, &upstream_payload)
-        {
-            debug_large_log(
-                &format!(
-                    "upstream messages (router={}, {:?}->{:?})",
-                    route_target.router_name, incoming_api, route_target.upstream_wire
-                ),
-                &messages.to_string(),
+            let api_key = match candidate.api_key.as_ref().unwrap_or(&state.api_key).value() {
+                Ok(api_key) => api_key.to_string(),
+                Err(_) if !candidate.auth_scheme.requires_api_key() => String::new(),
+                Err(reason) => {
+                    break 'attempt (
+                        error_response_for_api(
+                            incoming_api,
+                            wants_stream,
+                            "upstream_auth_error",
+                            &format!(
+                                "no upstream API key for router `{}`: {reason}",
+                                candidate.router_name
+                            ),
+                        ),
+                        true,
+                    );
+                }
+            };
+
+            let (upstream_payload, session_turn) = match build_upstream_payload_with_session(
+                state,
+                request_value,
+                incoming_api,
+                candidate,
+                wants_stream,
+                &response_id,
+            )
+            .await
+            {
+                Ok((payload, session_turn)) => (payload, session_turn.filter(|_| store_turn)),
+                Err(response) => return response,
+            };
+
+            let upstream_request_headers = upstream_headers_for_logging(
+                headers,
+                &candidate.auth_scheme,
+                &api_key,
+                candidate.upstream_wire,
+                &candidate.upstream_http_headers,
+                &candidate.forward_incoming_headers,
             );
+
+            if verbose_logging {
+                log_upstream_request(
+                    candidate,
+                    incoming_api,
+                    &upstream_request_headers,
+                    &upstream_payload,
+                );
+            }
+
+            let upstream_model = upstream_payload_model(&upstream_payload);
+            if let Some(capture) = capture {
+                capture
+                    .record_upstream_request(
+                        serde_json::json!({
+                            "router": candidate.router_name,
+                            "incoming_route": incoming_route,
+                            "incoming_api": incoming_api,
+                            "stream": wants_stream,
+                            "upstream_url": candidate.upstream_url,
+                            "upstream_wire": candidate.upstream_wire,
+                            "upstream_model": upstream_model,
+                            "response_id": response_id,
+                            "anthropic_input_tokens": anthropic_input_tokens,
+                            "features": candidate.feature_flags,
+                        }),
+                        &upstream_request_headers,
+                        &upstream_payload,
+                    )
+                    .await;
+            }
+            let in_flight_guard = candidate
+                .load_balancing
+                .as_ref()
+                .map(|_| balancer.start_request(&candidate.router_name, &candidate.upstream_url));
+            let (upstream_response, body_deadline) = match send_upstream_request(
+                state,
+                candidate,
+                &api_key,
+                headers,
+                &upstream_payload,
+                incoming_path,
+            )
+            .await
+            {
+                Ok(response) => {
+                    state.metrics.observe_time_to_first_byte(
+                        &candidate.router_name,
+                        request_metrics.elapsed(),
+                    );
+                    response
+                }
+                Err(UpstreamSendError::Timeout(message)) => {
+                    warn!(
+                        "upstream timed out: router={}, incoming_route={}, upstream_url={}, error={}",
+                        candidate.router_name, incoming_route, candidate.upstream_url, message
+                    );
+                    record_upstream_failure(&balancer, candidate);
+                    break 'attempt (
+                        error_response_for_api(
+                            incoming_api,
+                            wants_stream,
+                            "upstream_timeout",
+                            &message,
+                        ),
+                        true,
+                    );
+                }
+                Err(UpstreamSendError::Transport(err)) => {
+                    warn!(
+                        "upstream transport failed: router={}, incoming_route={}, upstream_url={}, error={}",
+                        candidate.router_name, incoming_route, candidate.upstream_url, err
+                    );
+                    record_upstream_failure(&balancer, candidate);
+                    break 'attempt (
+                        error_response_for_api(
+                            incoming_api,
+                            wants_stream,
+                            "upstream_transport_error",
+                            &format!("failed to call upstream endpoint: {err}"),
+                        ),
+                        true,
+                    );
+                }
+                Err(UpstreamSendError::Status {
+                    status,
+                    headers: upstream_response_headers,
+                    body: upstream_response_body,
+                }) => {
+                    let upstream_response_headers = headers_for_logging(&upstream_response_headers);
+                    if let Some(capture) = capture {
+                        capture
+                            .record_upstream_error(
+                                status,
+                                &upstream_response_headers,
+                                &upstream_response_body,
+                            )
+                            .await;
+                    }
+                    let code =
+                        normalize_upstream_error_payload(status, &upstream_response_body).code;
+                    let retryable = candidate.retry.is_retryable_failure(status, &code);
+                    if retryable {
+                        record_upstream_failure(&balancer, candidate);
+                    }
+                    break 'attempt (
+                        upstream_error_response(
+                            LlmErrorExchangeLog {
+                                route_target: candidate,
+                                incoming_api,
+                                incoming_headers: headers,
+                                incoming_body: body,
+                                upstream_request_headers: &upstream_request_headers,
+                                upstream_payload: &upstream_payload,
+                                upstream_response_status: status,
+                                upstream_response_headers: &upstream_response_headers,
+                                upstream_response_body: &upstream_response_body,
+                            },
+                            wants_stream,
+                        ),
+                        retryable,
+                    );
+                }
+            };
+
+            if let Some(policy) = candidate.load_balancing.as_ref() {
+                balancer.record_success(&candidate.router_name, &candidate.upstream_url);
+                if policy.sticky_sessions {
+                    balancer.remember_response(
+                        &response_id,
+                        &candidate.router_name,
+                        &candidate.upstream_url,
+                    );
+                }
+            }
+
+            if verbose_logging {
+                debug!(
+                    "upstream response status (router={}): {} {}",
+                    candidate.router_name,
+                    upstream_response.status().as_u16(),
+                    upstream_response.status()
+                );
+                debug!(
+                    "upstream response headers (router={}, {:?}<-{:?}): {}",
+                    candidate.router_name,
+                    incoming_api,
+                    candidate.upstream_wire,
+                    headers_for_logging(upstream_response.headers())
+                );
+            }
+
+            let response = finalize_upstream_response(
+                upstream_response,
+                candidate,
+                incoming_api,
+                headers,
+                body,
+                &upstream_request_headers,
+                &upstream_payload,
+                wants_stream,
+                upstream_model,
+                anthropic_input_tokens,
+                response_id,
+                tool_call_kinds_by_name,
+                verbose_logging,
+                body_deadline,
+                &state.metrics,
+                session_turn,
+                capture,
+            )
+            .await;
+            if !wants_stream {
+                return response;
+            }
+            // Held until the client finishes reading the stream.
+            return response.map(|body| {
+                Body::from_stream(body.into_data_stream().map(move |chunk| {
+                    let _ = (&in_flight_guard, &request_metrics);
+                    chunk
+                }))
+            });
+        };
+
+        if !(fail_over && has_fallback) {
+            return failure;
         }
+        let next = &candidates[candidate_index + 1];
+        warn!(
+            "upstream failover: router={}, failed_upstream_url={}, next_upstream_url={}, next_upstream_wire={:?}",
+            candidate.router_name, candidate.upstream_url, next.upstream_url, next.upstream_wire
+        );
+    }
 
-        debug!(
-            "upstream headers (router={}, {:?}->{:?}): {}",
+    unreachable!("the last upstream candidate always returns a response")
+}
+
+pub(crate) fn inbound_auth_error_response(
+    incoming_api_hint: Option<IncomingApi>,
+    incoming_path: Option<&str>,
+    body: &str,
+) -> Response {
+    let request_value = serde_json::from_str::<Value>(body).unwrap_or(Value::Null);
+    let incoming_api =
+        infer_incoming_api_from_hint_or_path(incoming_api_hint, incoming_path, &request_value);
+    let code = if incoming_api == IncomingApi::Anthropic {
+        "authentication_error"
+    } else {
+        "invalid_api_key"
+    };
+    error_response_for_api(
+        incoming_api,
+        wants_stream_for_request(incoming_api, incoming_path, &request_value),
+        code,
+        "missing or invalid bridge API key for this router",
+    )
+}
+
+fn record_upstream_failure(balancer: &LoadBalancer, route_target: &RouteTarget) {
+    let Some(policy) = route_target.load_balancing.as_ref() else {
+        return;
+    };
+    if balancer.record_failure(
+        &route_target.router_name,
+        &route_target.upstream_url,
+        policy,
+    ) {
+        warn!(
+            "upstream ejected: router={}, upstream_url={}, cooldown_ms={}",
             route_target.router_name,
-            incoming_api,
-            route_target.upstream_wire,
-            upstream_request_headers
+            route_target.upstream_url,
+            policy.cooldown.as_millis()
         );
+    }
+}
 
+fn log_upstream_request(
+    route_target: &RouteTarget,
+    incoming_api: IncomingApi,
+    upstream_request_headers: &Value,
+    upstream_payload: &Value,
+) {
+    if let Some(messages) =
+        upstream_messages_for_logging(route_target.upstream_wire, upstream_payload)
+    {
         debug_large_log(
             &format!(
-                "upstream payload (router={}, {:?}->{:?})",
+                "upstream messages (router={}, {:?}->{:?})",
                 route_target.router_name, incoming_api, route_target.upstream_wire
             ),
-            &upstream_payload.to_string(),
-        );
-        debug!(
-            "upstream tool types (router={}, {:?}->{:?}): {}",
-            route_target.router_name,
-            incoming_api,
-            route_target.upstream_wire,
-            tool_types_for_logging(&upstream_payload)
-        );
-        debug!(
-            "upstream tool definitions (router={}, {:?}->{:?}): {}",
-            route_target.router_name,
-            incoming_api,
-            route_target.upstream_wire,
-            tool_definitions_for_logging(&upstream_payload)
-        );
-        debug!(
-            "upstream request fields (router={}, {:?}->{:?}): {}",
-            route_target.router_name,
-            incoming_api,
-            route_target.upstream_wire,
-            request_fields_for_logging(&upstream_payload)
+            &messages.to_string(),
         );
     }
 
-    let upstream_request = build_upstream_request(
-        &state,
-        &route_target,
-        &headers,
-        &upstream_payload,
-        incoming_path.as_deref(),
+    debug!(
+        "upstream headers (router={}, {:?}->{:?}): {}",
+        route_target.router_name,
+        incoming_api,
+        route_target.upstream_wire,
+        upstream_request_headers
     );
-    let upstream_model = upstream_payload_model(&upstream_payload);
-    let anthropic_input_tokens = if incoming_api == IncomingApi::Anthropic {
-        estimate_anthropic_count_tokens(&request_value)
-    } else {
-        0
-    };
-    let upstream_response = match upstream_request.send().await {
-        Ok(response) => response,
-        Err(err) => {
-            warn!(
-                "upstream transport failed: router={}, incoming_route={}, upstream_url={}, error={}",
-                route_target.router_name, incoming_route, route_target.upstream_url, err
-            );
-            return error_response_for_api(
-                incoming_api,
-                wants_stream,
-                "upstream_transport_error",
-                &format!("failed to call upstream endpoint: {err}"),
-            );
-        }
-    };
 
-    if verbose_logging {
-        debug!(
-            "upstream response status (router={}): {} {}",
-            route_target.router_name,
-            upstream_response.status().as_u16(),
-            upstream_response.status()
-        );
-        debug!(
-            "upstream response headers (router={}, {:?}<-{:?}): {}",
-            route_target.router_name,
-            incoming_api,
-            route_target.upstream_wire,
-            headers_for_logging(upstream_response.headers())
-        );
-    }
-
-    finalize_upstream_response(
-        upstream_response,
-        &route_target,
+    debug_large_log(
+        &format!(
+            "upstream payload (router={}, {:?}->{:?})",
+            route_target.router_name, incoming_api, route_target.upstream_wire
+        ),
+        &upstream_payload.to_string(),
+    );
+    debug!(
+        "upstream tool types (router={}, {:?}->{:?}): {}",
+        route_target.router_name,
         incoming_api,
-        &headers,
-        &body,
-        &upstream_request_headers,
-        &upstream_payload,
-        wants_stream,
-        upstream_model,
-        anthropic_input_tokens,
-        response_id,
-        tool_call_kinds_by_name,
-        verbose_logging,
-    )
-    .await
+        route_target.upstream_wire,
+        tool_types_for_logging(upstream_payload)
+    );
+    debug!(
+        "upstream tool definitions (router={}, {:?}->{:?}): {}",
+        route_target.router_name,
+        incoming_api,
+        route_target.upstream_wire,
+        tool_definitions_for_logging(upstream_payload)
+    );
+    debug!(
+        "upstream request fields (router={}, {:?}->{:?}): {}",
+        route_target.router_name,
+        incoming_api,
+        route_target.upstream_wire,
+        request_fields_for_logging(upstream_payload)
+    );
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests;
@@ -3,6 +3,8 @@ use axum::http::header::CONTENT_TYPE;
 use serde_json::Value;
 use tracing::{debug, warn};
 
+use crate::model::AuthScheme;
+use crate::model::DEFAULT_ANTHROPIC_VERSION;
 use crate::model::UpstreamHeader;
 use crate::model::WireApi;
 
@@ -86,8 +88,9 @@ pub(crate) fn upstream_messages_for_logging(
     payload: &Value,
 ) -> Option<Value> {
     match upstream_wire {
-        WireApi::Chat => payload.get("messages").cloned(),
+        WireApi::Chat | WireApi::Ollama => payload.get("messages").cloned(),
         WireApi::Messages => payload.get("messages").cloned(),
+        WireApi::Gemini => payload.get("contents").cloned(),
         WireApi::Responses => payload.get("input").and_then(Value::as_array).map(|items| {
             let messages = items
                 .iter()
@@ -202,15 +205,19 @@ fn tool_definition_for_logging(tool: &Value) -> Value {
 
 pub(crate) fn upstream_headers_for_logging(
     headers: &HeaderMap,
+    auth_scheme: &AuthScheme,
     api_key: &str,
+    upstream_wire: WireApi,
     upstream_http_headers: &[UpstreamHeader],
     forwarded_headers: &[String],
 ) -> Value {
     let mut out = serde_json::Map::new();
-    out.insert(
-        "authorization".to_string(),
-        Value::String(format!("Bearer {}", redact_for_logging(api_key))),
-    );
+    if let Some(header_name) = auth_scheme.header_name() {
+        out.insert(
+            header_name.to_string(),
+            Value::String(auth_scheme.header_value(redact_for_logging(api_key))),
+        );
+    }
     out.insert(
         CONTENT_TYPE.as_str().to_string(),
         Value::String("application/json".to_string()),
@@ -234,6 +241,12 @@ pub(crate) fn upstream_headers_for_logging(
         };
         out.insert(header_name, Value::String(header_value));
     }
+    if upstream_wire == WireApi::Messages && !out.contains_key("anthropic-version") {
+        out.insert(
+            "anthropic-version".to_string(),
+            Value::String(DEFAULT_ANTHROPIC_VERSION.to_string()),
+        );
+    }
 
     Value::Object(out)
 }
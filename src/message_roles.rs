@@ -0,0 +1,154 @@
+use serde::Deserialize;
+use serde_json::Value;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ToolRoleMode {
+    #[default]
+    User,
+    Native,
+    Tagged,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SystemRoleMode {
+    #[default]
+    Merge,
+    Preserve,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub(crate) struct MessageRoleModes {
+    pub(crate) tool: Option<ToolRoleMode>,
+    pub(crate) system: Option<SystemRoleMode>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub(crate) struct MessageRolesConfig {
+    pub(crate) tool: Option<ToolRoleMode>,
+    pub(crate) system: Option<SystemRoleMode>,
+    #[serde(default)]
+    pub(crate) models: BTreeMap<String, MessageRoleModes>,
+}
+
+impl MessageRolesConfig {
+    pub(crate) fn is_enabled(&self) -> bool {
+        *self != Self::default()
+    }
+
+    pub(crate) fn modes_for_model(&self, model: &str) -> (ToolRoleMode, SystemRoleMode) {
+        let model_modes = self.models.get(model);
+        (
+            model_modes
+                .and_then(|modes| modes.tool)
+                .or(self.tool)
+                .unwrap_or_default(),
+            model_modes
+                .and_then(|modes| modes.system)
+                .or(self.system)
+                .unwrap_or_default(),
+        )
+    }
+}
+
+pub(crate) fn apply_message_role_policy(payload: &mut Value, config: &MessageRolesConfig) {
+    let model = payload
+        .get("model")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let (tool_mode, system_mode) = config.modes_for_model(model);
+    let Some(messages) = payload.get_mut("messages").and_then(Value::as_array_mut) else {
+        return;
+    };
+
+    let original_messages = std::mem::take(messages);
+    let mut system_contents = Vec::new();
+    let mut normalized_messages = Vec::new();
+
+    for mut message in original_messages {
+        let role = message
+            .get("role")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+
+        match role.as_str() {
+            "developer" | "system" if system_mode == SystemRoleMode::Merge => {
+                if let Some(content) = chat_message_content_to_system_text(&message) {
+                    system_contents.push(content);
+                }
+            }
+            "developer" => {
+                if let Some(obj) = message.as_object_mut() {
+                    obj.insert("role".to_string(), Value::String("system".to_string()));
+                }
+                normalized_messages.push(message);
+            }
+            "tool" | "function" if tool_mode != ToolRoleMode::Native => {
+                let Some(obj) = message.as_object_mut() else {
+                    normalized_messages.push(message);
+                    continue;
+                };
+                if tool_mode == ToolRoleMode::Tagged {
+                    let content = tagged_tool_result(obj);
+                    obj.insert("content".to_string(), Value::String(content));
+                }
+                obj.insert("role".to_string(), Value::String("user".to_string()));
+                obj.remove("tool_call_id");
+                obj.remove("name");
+                normalized_messages.push(message);
+            }
+            _ => normalized_messages.push(message),
+        }
+    }
+
+    if !system_contents.is_empty() {
+        messages.push(json!({
+            "role": "system",
+            "content": system_contents.join("\n\n"),
+        }));
+    }
+    messages.extend(normalized_messages);
+}
+
+fn tagged_tool_result(message: &serde_json::Map<String, Value>) -> String {
+    let attribute = match (
+        message.get("tool_call_id").and_then(Value::as_str),
+        message.get("name").and_then(Value::as_str),
+    ) {
+        (Some(id), _) => format!(" tool_call_id=\"{id}\""),
+        (None, Some(name)) => format!(" name=\"{name}\""),
+        (None, None) => String::new(),
+    };
+    let text = match message.get("content") {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .map(|part| match part.get("text").and_then(Value::as_str) {
+                Some(text) => text.to_string(),
+                None => part.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Some(Value::Null) | None => String::new(),
+        Some(other) => other.to_string(),
+    };
+    format!("<tool_result{attribute}>\n{text}\n</tool_result>")
+}
+
+fn chat_message_content_to_system_text(message: &Value) -> Option<String> {
+    let content = message.get("content")?;
+    let text = match content {
+        Value::String(text) => text.clone(),
+        Value::Null => return None,
+        other => other.to_string(),
+    };
+    if text.trim().is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
@@ -0,0 +1,430 @@
+use async_stream::stream;
+use axum::body::Bytes;
+use futures::Stream;
+use futures::StreamExt;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::bridge_types::SseParser;
+use crate::model::IncomingApi;
+use crate::model::WireApi;
+use crate::timeouts::UpstreamStreamError;
+
+const DURATION_BUCKETS_SECONDS: [f64; 12] = [
+    0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    requests: BTreeMap<(String, &'static str, &'static str), u64>,
+    upstream_responses: BTreeMap<(String, u16), u64>,
+    upstream_errors: BTreeMap<(String, String), u64>,
+    time_to_first_byte: BTreeMap<String, Histogram>,
+    request_duration: BTreeMap<String, Histogram>,
+    prompt_tokens: BTreeMap<String, u64>,
+    completion_tokens: BTreeMap<String, u64>,
+    in_flight_streams: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS_SECONDS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        for (bucket, upper_bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS_SECONDS) {
+            if seconds <= upper_bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+pub(crate) struct RequestMetricsGuard {
+    metrics: Arc<Metrics>,
+    router_name: String,
+    incoming_api: IncomingApi,
+    upstream_wire: WireApi,
+    started_at: Instant,
+    stream: bool,
+}
+
+impl RequestMetricsGuard {
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub(crate) fn set_upstream_wire(&mut self, upstream_wire: WireApi) {
+        self.upstream_wire = upstream_wire;
+    }
+}
+
+impl Drop for RequestMetricsGuard {
+    fn drop(&mut self) {
+        let mut state = self.metrics.lock();
+        *state
+            .requests
+            .entry((
+                self.router_name.clone(),
+                self.incoming_api.as_str(),
+                self.upstream_wire.as_str(),
+            ))
+            .or_default() += 1;
+        state
+            .request_duration
+            .entry(self.router_name.clone())
+            .or_default()
+            .observe(self.started_at.elapsed());
+        if self.stream
+            && let Some(in_flight) = state.in_flight_streams.get_mut(&self.router_name)
+        {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+    }
+}
+
+impl Metrics {
+    fn lock(&self) -> std::sync::MutexGuard<'_, MetricsState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn start_request(
+        self: &Arc<Self>,
+        router_name: &str,
+        incoming_api: IncomingApi,
+        upstream_wire: WireApi,
+        stream: bool,
+    ) -> RequestMetricsGuard {
+        if stream {
+            *self
+                .lock()
+                .in_flight_streams
+                .entry(router_name.to_string())
+                .or_default() += 1;
+        }
+        RequestMetricsGuard {
+            metrics: self.clone(),
+            router_name: router_name.to_string(),
+            incoming_api,
+            upstream_wire,
+            started_at: Instant::now(),
+            stream,
+        }
+    }
+
+    pub(crate) fn record_upstream_status(&self, router_name: &str, status: u16) {
+        *self
+            .lock()
+            .upstream_responses
+            .entry((router_name.to_string(), status))
+            .or_default() += 1;
+    }
+
+    pub(crate) fn record_upstream_error(&self, router_name: &str, code: &str) {
+        *self
+            .lock()
+            .upstream_errors
+            .entry((router_name.to_string(), code.to_string()))
+            .or_default() += 1;
+    }
+
+    pub(crate) fn observe_time_to_first_byte(&self, router_name: &str, elapsed: Duration) {
+        self.lock()
+            .time_to_first_byte
+            .entry(router_name.to_string())
+            .or_default()
+            .observe(elapsed);
+    }
+
+    pub(crate) fn record_usage(&self, router_name: &str, usage: UsageTokens) {
+        let mut state = self.lock();
+        *state
+            .prompt_tokens
+            .entry(router_name.to_string())
+            .or_default() += usage.prompt_tokens;
+        *state
+            .completion_tokens
+            .entry(router_name.to_string())
+            .or_default() += usage.completion_tokens;
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let state = self.lock();
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "codex_chat_bridge_requests_total",
+            "counter",
+            "Finished requests by router, incoming API and the wire of the upstream that served them.",
+        );
+        for ((router, incoming_api, upstream_wire), value) in &state.requests {
+            write_sample(
+                &mut out,
+                "codex_chat_bridge_requests_total",
+                &[
+                    ("router", router),
+                    ("incoming_api", incoming_api),
+                    ("upstream_wire", upstream_wire),
+                ],
+                *value,
+            );
+        }
+
+        write_header(
+            &mut out,
+            "codex_chat_bridge_upstream_responses_total",
+            "counter",
+            "Upstream HTTP responses by status code.",
+        );
+        for ((router, status), value) in &state.upstream_responses {
+            write_sample(
+                &mut out,
+                "codex_chat_bridge_upstream_responses_total",
+                &[("router", router), ("status", &status.to_string())],
+                *value,
+            );
+        }
+
+        write_header(
+            &mut out,
+            "codex_chat_bridge_upstream_errors_total",
+            "counter",
+            "Upstream failures by normalized error code.",
+        );
+        for ((router, code), value) in &state.upstream_errors {
+            write_sample(
+                &mut out,
+                "codex_chat_bridge_upstream_errors_total",
+                &[("router", router), ("code", code)],
+                *value,
+            );
+        }
+
+        write_histogram(
+            &mut out,
+            "codex_chat_bridge_upstream_time_to_first_byte_seconds",
+            "Time from receiving a request until upstream response headers arrive.",
+            &state.time_to_first_byte,
+        );
+        write_histogram(
+            &mut out,
+            "codex_chat_bridge_request_duration_seconds",
+            "Time from receiving a request until its response body is finished.",
+            &state.request_duration,
+        );
+
+        for (name, help, values) in [
+            (
+                "codex_chat_bridge_prompt_tokens_total",
+                "Prompt tokens reported by upstream usage.",
+                &state.prompt_tokens,
+            ),
+            (
+                "codex_chat_bridge_completion_tokens_total",
+                "Completion tokens reported by upstream usage.",
+                &state.completion_tokens,
+            ),
+        ] {
+            write_header(&mut out, name, "counter", help);
+            for (router, value) in values {
+                write_sample(&mut out, name, &[("router", router)], *value);
+            }
+        }
+
+        write_header(
+            &mut out,
+            "codex_chat_bridge_in_flight_streams",
+            "gauge",
+            "Streaming requests that have not finished yet.",
+        );
+        for (router, value) in &state.in_flight_streams {
+            write_sample(
+                &mut out,
+                "codex_chat_bridge_in_flight_streams",
+                &[("router", router)],
+                *value,
+            );
+        }
+
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct UsageTokens {
+    pub(crate) prompt_tokens: u64,
+    pub(crate) completion_tokens: u64,
+}
+
+pub(crate) fn usage_tokens(payload: &Value) -> Option<UsageTokens> {
+    if payload.get("prompt_eval_count").is_some() || payload.get("eval_count").is_some() {
+        let tokens = |key: &str| payload.get(key).and_then(Value::as_u64).unwrap_or(0);
+        return Some(UsageTokens {
+            prompt_tokens: tokens("prompt_eval_count"),
+            completion_tokens: tokens("eval_count"),
+        });
+    }
+    if let Some(usage) = payload
+        .get("usageMetadata")
+        .filter(|usage| usage.is_object())
+    {
+        let tokens = |key: &str| usage.get(key).and_then(Value::as_u64).unwrap_or(0);
+        return Some(UsageTokens {
+            prompt_tokens: tokens("promptTokenCount"),
+            completion_tokens: tokens("candidatesTokenCount") + tokens("thoughtsTokenCount"),
+        });
+    }
+    let usage = [
+        payload.get("usage"),
+        payload.get("response").and_then(|r| r.get("usage")),
+        payload.get("message").and_then(|m| m.get("usage")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|usage| usage.is_object())?;
+    let tokens = |keys: [&str; 2]| {
+        keys.iter()
+            .find_map(|key| usage.get(*key).and_then(Value::as_u64))
+            .unwrap_or(0)
+    };
+    Some(UsageTokens {
+        prompt_tokens: tokens(["prompt_tokens", "input_tokens"]),
+        completion_tokens: tokens(["completion_tokens", "output_tokens"]),
+    })
+}
+
+pub(crate) fn observe_stream_metrics<S>(
+    upstream_stream: S,
+    metrics: Arc<Metrics>,
+    router_name: String,
+) -> impl Stream<Item = Result<Bytes, UpstreamStreamError>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, UpstreamStreamError>> + Send + 'static,
+{
+    stream! {
+        let mut upstream_stream = Box::pin(upstream_stream);
+        let mut parser = SseParser::default();
+        let mut tally = UsageTally {
+            metrics: metrics.clone(),
+            router_name: router_name.clone(),
+            usage: None,
+        };
+        while let Some(chunk) = upstream_stream.next().await {
+            match &chunk {
+                Ok(bytes) => {
+                    for data in parser.feed(&String::from_utf8_lossy(bytes)) {
+                        if let Some(usage) = serde_json::from_str::<Value>(&data)
+                            .ok()
+                            .as_ref()
+                            .and_then(usage_tokens)
+                        {
+                            tally.observe(usage);
+                        }
+                    }
+                }
+                Err(err) => metrics.record_upstream_error(&router_name, err.code()),
+            }
+            yield chunk;
+        }
+    }
+}
+
+struct UsageTally {
+    metrics: Arc<Metrics>,
+    router_name: String,
+    usage: Option<UsageTokens>,
+}
+
+impl UsageTally {
+    // Usage can repeat or be split across events; keep the largest value per field.
+    fn observe(&mut self, usage: UsageTokens) {
+        let current = self.usage.get_or_insert_default();
+        current.prompt_tokens = current.prompt_tokens.max(usage.prompt_tokens);
+        current.completion_tokens = current.completion_tokens.max(usage.completion_tokens);
+    }
+}
+
+impl Drop for UsageTally {
+    fn drop(&mut self) {
+        if let Some(usage) = self.usage {
+            self.metrics.record_usage(&self.router_name, usage);
+        }
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn write_sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl ToString) {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| format!("{key}=\"{}\"", escape_label_value(value)))
+        .collect::<Vec<_>>()
+        .join(",");
+    let _ = writeln!(out, "{name}{{{labels}}} {}", value.to_string());
+}
+
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histograms: &BTreeMap<String, Histogram>,
+) {
+    write_header(out, name, "histogram", help);
+    for (router, histogram) in histograms {
+        for (upper_bound, count) in DURATION_BUCKETS_SECONDS.iter().zip(histogram.buckets) {
+            write_sample(
+                out,
+                &format!("{name}_bucket"),
+                &[("router", router), ("le", &upper_bound.to_string())],
+                count,
+            );
+        }
+        write_sample(
+            out,
+            &format!("{name}_bucket"),
+            &[("router", router), ("le", "+Inf")],
+            histogram.count,
+        );
+        write_sample(
+            out,
+            &format!("{name}_sum"),
+            &[("router", router)],
+            histogram.sum,
+        );
+        write_sample(
+            out,
+            &format!("{name}_count"),
+            &[("router", router)],
+            histogram.count,
+        );
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,3 +1,4 @@
+use axum::http::HeaderName;
 use clap::ValueEnum;
 use serde::Deserialize;
 use serde::Serialize;
@@ -8,6 +9,8 @@ pub(crate) enum WireApi {
     Chat,
     Responses,
     Messages,
+    Gemini,
+    Ollama,
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
@@ -17,19 +20,123 @@ pub(crate) enum ToolTransformMode {
     LegacyConvert,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub(crate) enum IncomingApi {
     Responses,
     Chat,
     Anthropic,
 }
 
+impl WireApi {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Chat => "chat",
+            Self::Responses => "responses",
+            Self::Messages => "messages",
+            Self::Gemini => "gemini",
+            Self::Ollama => "ollama",
+        }
+    }
+
+    pub(crate) fn keeps_responses_history(self) -> bool {
+        matches!(
+            self,
+            Self::Chat | Self::Messages | Self::Gemini | Self::Ollama
+        )
+    }
+}
+
+impl IncomingApi {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Responses => "responses",
+            Self::Chat => "chat",
+            Self::Anthropic => "anthropic",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) enum AuthScheme {
+    Bearer,
+    XApiKey,
+    Header(String),
+    None,
+}
+
+impl AuthScheme {
+    pub(crate) fn default_for_wire(wire: WireApi) -> Self {
+        match wire {
+            WireApi::Messages => Self::XApiKey,
+            WireApi::Gemini => Self::Header("x-goog-api-key".to_string()),
+            // A local Ollama needs no key; hosted Ollama can set `auth_scheme = "bearer"`.
+            WireApi::Ollama => Self::None,
+            WireApi::Chat | WireApi::Responses => Self::Bearer,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Self::Bearer => "bearer",
+            Self::XApiKey => "x-api-key",
+            Self::Header(name) => name,
+            Self::None => "none",
+        }
+    }
+
+    pub(crate) fn requires_api_key(&self) -> bool {
+        *self != Self::None
+    }
+
+    pub(crate) fn header_name(&self) -> Option<&str> {
+        match self {
+            Self::Bearer => Some("authorization"),
+            Self::XApiKey => Some("x-api-key"),
+            Self::Header(name) => Some(name),
+            Self::None => None,
+        }
+    }
+
+    pub(crate) fn header_value(&self, api_key: &str) -> String {
+        match self {
+            Self::Bearer => format!("Bearer {api_key}"),
+            _ => api_key.to_string(),
+        }
+    }
+}
+
+impl TryFrom<String> for AuthScheme {
+    type Error = String;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        let normalized = raw.trim().to_ascii_lowercase();
+        match normalized.as_str() {
+            "" => Err("auth_scheme must not be empty".to_string()),
+            "bearer" => Ok(Self::Bearer),
+            "x-api-key" => Ok(Self::XApiKey),
+            "none" => Ok(Self::None),
+            _ => HeaderName::from_bytes(normalized.as_bytes())
+                .map(|_| Self::Header(normalized.clone()))
+                .map_err(|err| {
+                    format!(
+                        "invalid auth_scheme `{}`: expected bearer, x-api-key, none, or a header name ({err})",
+                        raw.trim()
+                    )
+                }),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct UpstreamHeader {
     pub(crate) name: String,
     pub(crate) value: String,
 }
 
+pub(crate) const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
 pub(crate) const DEFAULT_FORWARDED_UPSTREAM_HEADERS: [&str; 6] = [
     "openai-organization",
     "openai-project",
@@ -47,10 +154,11 @@ pub(crate) struct FeatureFlagsConfig {
     pub(crate) enable_reasoning_stream_events: Option<bool>,
     pub(crate) enable_provider_specific_fields: Option<bool>,
     pub(crate) enable_extended_input_types: Option<bool>,
+    pub(crate) enable_responses_websocket: Option<bool>,
     pub(crate) tool_transform_mode: Option<ToolTransformMode>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub(crate) struct FeatureFlags {
     pub(crate) enable_previous_response_id: bool,
     pub(crate) enable_tool_argument_stream_events: bool,
@@ -58,6 +166,7 @@ pub(crate) struct FeatureFlags {
     pub(crate) enable_reasoning_stream_events: bool,
     pub(crate) enable_provider_specific_fields: bool,
     pub(crate) enable_extended_input_types: bool,
+    pub(crate) enable_responses_websocket: bool,
     pub(crate) tool_transform_mode: ToolTransformMode,
 }
 
@@ -70,6 +179,7 @@ impl Default for FeatureFlags {
             enable_reasoning_stream_events: true,
             enable_provider_specific_fields: true,
             enable_extended_input_types: true,
+            enable_responses_websocket: false,
             tool_transform_mode: ToolTransformMode::LegacyConvert,
         }
     }
@@ -98,6 +208,9 @@ impl FeatureFlags {
         if let Some(v) = overrides.enable_extended_input_types {
             self.enable_extended_input_types = v;
         }
+        if let Some(v) = overrides.enable_responses_websocket {
+            self.enable_responses_websocket = v;
+        }
         if let Some(v) = overrides.tool_transform_mode {
             self.tool_transform_mode = v;
         }
@@ -0,0 +1,271 @@
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::http::header::HOST;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use serde_json::Value;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+use tracing::warn;
+
+use crate::model::IncomingApi;
+use crate::model::WireApi;
+use crate::response_utils::error_response_for_api;
+use crate::response_utils::json_success_response;
+use crate::routing::RouteTarget;
+use crate::routing::normalize_request_path;
+use crate::state::AppState;
+
+const UPSTREAM_MODELS_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) fn parse_models_path(path: &str) -> Option<(String, Option<String>)> {
+    let normalized = normalize_request_path(path);
+    let (base, rest) = normalized.split_once("/v1/models")?;
+    if rest.is_empty() {
+        return Some((base.to_string(), None));
+    }
+    let id = rest.strip_prefix('/')?;
+    Some((base.to_string(), Some(id.to_string())))
+}
+
+pub(crate) async fn handle_models(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    base: &str,
+    model_id: Option<&str>,
+) -> Response {
+    let host_header = headers.get(HOST).and_then(|h| h.to_str().ok());
+    let mut candidates = vec![
+        (IncomingApi::Responses, format!("{base}/v1/responses")),
+        (IncomingApi::Chat, format!("{base}/v1/chat/completions")),
+        (IncomingApi::Anthropic, format!("{base}/v1/messages")),
+    ];
+    if headers.contains_key("anthropic-version") {
+        candidates.rotate_right(1);
+    }
+
+    let resolved = {
+        let routers = state.routers.read().await;
+        candidates.into_iter().find_map(|(incoming_api, path)| {
+            match routers.get_target_for_incoming_route(&path, host_header) {
+                Ok(Some(target)) => Some((incoming_api, path, target)),
+                Ok(None) => None,
+                Err(err) => {
+                    warn!("model listing route resolution failed: path={path}, error={err}");
+                    None
+                }
+            }
+        })
+    };
+    let Some((incoming_api, incoming_path, route_target)) = resolved else {
+        warn!("router miss for model listing: host={host_header:?}, base={base}");
+        return (StatusCode::NOT_FOUND, "not found").into_response();
+    };
+
+    if let Some(inbound_auth) = route_target.inbound_auth.as_ref()
+        && !inbound_auth.authorizes(&headers)
+    {
+        warn!(
+            "inbound auth rejected: router={}, incoming_route={}/v1/models, host={:?}",
+            route_target.router_name, base, host_header
+        );
+        return crate::inbound_auth_error_response(Some(incoming_api), Some(&incoming_path), "");
+    }
+
+    let mut models = configured_models(&route_target);
+    if route_target.merge_upstream_models {
+        for model in fetch_upstream_models(&state, &route_target, &headers).await {
+            if !models.contains(&model) {
+                models.push(model);
+            }
+        }
+    }
+    debug!(
+        "model listing served: router={}, incoming_api={:?}, models={:?}",
+        route_target.router_name, incoming_api, models
+    );
+
+    match model_id {
+        None => json_success_response(models_list_json(
+            incoming_api,
+            &route_target.router_name,
+            &models,
+        )),
+        Some(id) if models.iter().any(|model| model == id) => {
+            json_success_response(model_json(incoming_api, &route_target.router_name, id))
+        }
+        Some(id) => {
+            let code = if incoming_api == IncomingApi::Anthropic {
+                "not_found_error"
+            } else {
+                "model_not_found"
+            };
+            let mut response = error_response_for_api(
+                incoming_api,
+                false,
+                code,
+                &format!(
+                    "model `{id}` is not served by router `{}`",
+                    route_target.router_name
+                ),
+            );
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    }
+}
+
+pub(crate) fn configured_models(route_target: &RouteTarget) -> Vec<String> {
+    let mut models = route_target.models.clone();
+    for model in [
+        &route_target.upstream_model,
+        &route_target.upstream_model_opus,
+        &route_target.upstream_model_sonnet,
+        &route_target.upstream_model_haiku,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if !models.contains(model) {
+            models.push(model.clone());
+        }
+    }
+    models
+}
+
+pub(crate) fn models_list_json(incoming_api: IncomingApi, owner: &str, models: &[String]) -> Value {
+    let data = models
+        .iter()
+        .map(|model| model_json(incoming_api, owner, model))
+        .collect::<Vec<_>>();
+    if incoming_api == IncomingApi::Anthropic {
+        json!({
+            "data": data,
+            "has_more": false,
+            "first_id": models.first(),
+            "last_id": models.last(),
+        })
+    } else {
+        json!({
+            "object": "list",
+            "data": data,
+        })
+    }
+}
+
+fn model_json(incoming_api: IncomingApi, owner: &str, model: &str) -> Value {
+    if incoming_api == IncomingApi::Anthropic {
+        json!({
+            "type": "model",
+            "id": model,
+            "display_name": model,
+            "created_at": "1970-01-01T00:00:00Z",
+        })
+    } else {
+        json!({
+            "id": model,
+            "object": "model",
+            "created": 0,
+            "owned_by": owner,
+        })
+    }
+}
+
+pub(crate) fn upstream_models_url(upstream_url: &str, upstream_wire: WireApi) -> Option<String> {
+    let trimmed = upstream_url.trim().trim_end_matches('/');
+    let (without_query, _) = trimmed.split_once('?').unwrap_or((trimmed, ""));
+    match upstream_wire {
+        WireApi::Chat | WireApi::Responses | WireApi::Messages => {
+            let base = ["/chat/completions", "/responses", "/messages"]
+                .iter()
+                .find_map(|suffix| without_query.strip_suffix(suffix))?;
+            Some(format!("{base}/models"))
+        }
+        WireApi::Gemini => {
+            let (base, _) = without_query.split_once("/models/")?;
+            Some(format!("{base}/models"))
+        }
+        WireApi::Ollama => without_query
+            .strip_suffix("/api/chat")
+            .map(|base| format!("{base}/api/tags")),
+    }
+}
+
+pub(crate) fn upstream_model_ids(listing: &Value) -> Vec<String> {
+    let from_data = listing
+        .get("data")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|model| model.get("id").and_then(Value::as_str));
+    let from_models = listing
+        .get("models")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|model| {
+            model
+                .get("name")
+                .or_else(|| model.get("model"))
+                .and_then(Value::as_str)
+        })
+        .map(|name| name.strip_prefix("models/").unwrap_or(name));
+    from_data
+        .chain(from_models)
+        .map(ToString::to_string)
+        .collect()
+}
+
+async fn fetch_upstream_models(
+    state: &Arc<AppState>,
+    route_target: &RouteTarget,
+    headers: &HeaderMap,
+) -> Vec<String> {
+    let router_name = &route_target.router_name;
+    let Some(url) = upstream_models_url(&route_target.upstream_url, route_target.upstream_wire)
+    else {
+        warn!("router `{router_name}` has no upstream model listing to merge");
+        return Vec::new();
+    };
+    let api_key = match route_target
+        .api_key
+        .as_ref()
+        .unwrap_or(&state.api_key)
+        .value()
+    {
+        Ok(api_key) => api_key.to_string(),
+        Err(_) if !route_target.auth_scheme.requires_api_key() => String::new(),
+        Err(reason) => {
+            warn!("skipping upstream model listing for router `{router_name}`: {reason}");
+            return Vec::new();
+        }
+    };
+
+    let response = route_target
+        .client
+        .as_ref()
+        .unwrap_or(&state.client)
+        .get(&url)
+        .headers(crate::upstream_request_headers(
+            route_target,
+            &api_key,
+            headers,
+        ))
+        .timeout(UPSTREAM_MODELS_TIMEOUT)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+    let listing = match response {
+        Ok(response) => response.json::<Value>().await,
+        Err(err) => Err(err),
+    };
+    match listing {
+        Ok(listing) => upstream_model_ids(&listing),
+        Err(err) => {
+            warn!("upstream model listing failed: router={router_name}, url={url}, error={err}");
+            Vec::new()
+        }
+    }
+}
@@ -4,8 +4,13 @@ use serde_json::Value;
 use std::collections::HashSet;
 
 use crate::bridge::mapping::map_anthropic_messages_to_chat_request;
+use crate::bridge::mapping::map_chat_to_anthropic_messages_request;
+use crate::bridge::mapping::map_chat_to_gemini_request;
+use crate::bridge::mapping::map_chat_to_ollama_request;
 use crate::bridge::mapping::map_chat_to_responses_request;
+use crate::bridge::mapping::map_responses_to_anthropic_messages_request;
 use crate::bridge::mapping::map_responses_to_chat_request_with_stream;
+use crate::config::OllamaConfig;
 use crate::model::IncomingApi;
 use crate::model::ToolTransformMode;
 use crate::model::WireApi;
@@ -76,7 +81,12 @@ pub(crate) fn validate_capability_gate(
     enable_extended_input_types: bool,
     request: &Value,
 ) -> Result<()> {
-    if incoming_api != IncomingApi::Responses || upstream_wire != WireApi::Chat {
+    if incoming_api != IncomingApi::Responses
+        || !matches!(
+            upstream_wire,
+            WireApi::Chat | WireApi::Gemini | WireApi::Ollama
+        )
+    {
         return Ok(());
     }
 
@@ -145,16 +155,12 @@ pub(crate) fn build_upstream_payload(
             .chat_request
         }
         (IncomingApi::Responses, WireApi::Messages) => {
-            return Err(anyhow!(
-                "responses->messages bridge is not supported; use `upstream_wire = \"chat\"` or `\"responses\"`"
-            ));
+            map_responses_to_anthropic_messages_request(request, stream)?
         }
         (IncomingApi::Chat, WireApi::Chat) => request.clone(),
         (IncomingApi::Chat, WireApi::Responses) => map_chat_to_responses_request(request, stream)?,
         (IncomingApi::Chat, WireApi::Messages) => {
-            return Err(anyhow!(
-                "chat->messages bridge is not supported; use `upstream_wire = \"chat\"` or `\"responses\"`"
-            ));
+            map_chat_to_anthropic_messages_request(request, stream)?
         }
         (IncomingApi::Anthropic, WireApi::Chat) => {
             map_anthropic_messages_to_chat_request(request, anthropic_preserve_thinking)?
@@ -165,16 +171,69 @@ pub(crate) fn build_upstream_payload(
             map_chat_to_responses_request(&chat_request?, stream)?
         }
         (IncomingApi::Anthropic, WireApi::Messages) => request.clone(),
+        (_, WireApi::Gemini | WireApi::Ollama) => {
+            let chat_request = match incoming_api {
+                IncomingApi::Responses => {
+                    map_responses_to_chat_request_with_stream(
+                        request,
+                        &HashSet::new(),
+                        stream,
+                        enable_extended_input_types,
+                        tool_transform_mode,
+                    )?
+                    .chat_request
+                }
+                IncomingApi::Chat => request.clone(),
+                IncomingApi::Anthropic => {
+                    map_anthropic_messages_to_chat_request(request, anthropic_preserve_thinking)?
+                }
+            };
+            map_chat_to_native_request(&chat_request, upstream_wire, stream)?
+        }
     };
     if incoming_api == IncomingApi::Anthropic && upstream_wire != WireApi::Messages {
         strip_anthropic_reasoning_fields(&mut payload);
     }
-    if upstream_wire != WireApi::Messages {
+    if !matches!(upstream_wire, WireApi::Messages | WireApi::Gemini) {
         set_stream_flag(&mut payload, stream);
     }
     Ok(payload)
 }
 
+pub(crate) fn map_chat_to_native_request(
+    chat_request: &Value,
+    upstream_wire: WireApi,
+    stream: bool,
+) -> Result<Value> {
+    if upstream_wire == WireApi::Gemini {
+        map_chat_to_gemini_request(chat_request, stream)
+    } else {
+        let mut payload = map_chat_to_ollama_request(chat_request, stream)?;
+        set_stream_flag(&mut payload, stream);
+        Ok(payload)
+    }
+}
+
+pub(crate) fn apply_ollama_overrides(payload: &mut Value, ollama: &OllamaConfig) {
+    let Some(obj) = payload.as_object_mut() else {
+        return;
+    };
+    if let Some(router_options) = ollama.options.as_ref().filter(|o| !o.is_empty()) {
+        let options = obj
+            .entry("options".to_string())
+            .or_insert_with(|| Value::Object(serde_json::Map::new()));
+        if let Some(options) = options.as_object_mut() {
+            options.extend(router_options.clone());
+        }
+    }
+    if let Some(keep_alive) = &ollama.keep_alive {
+        obj.insert("keep_alive".to_string(), keep_alive.clone());
+    }
+    if let Some(think) = &ollama.think {
+        obj.insert("think".to_string(), think.clone());
+    }
+}
+
 fn set_stream_flag(payload: &mut Value, stream: bool) {
     if let Some(obj) = payload.as_object_mut() {
         obj.insert("stream".to_string(), Value::Bool(stream));
@@ -0,0 +1,172 @@
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use axum::Router;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tracing::info;
+use tracing::warn;
+
+use crate::write_server_info;
+
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub(crate) struct ListenerSet {
+    app: Router,
+    server_info: Option<PathBuf>,
+    running: BTreeMap<String, RunningListener>,
+    tasks: JoinSet<(String, Result<()>)>,
+}
+
+struct RunningListener {
+    port: u16,
+    shutdown: oneshot::Sender<()>,
+}
+
+impl ListenerSet {
+    pub(crate) fn new(app: Router, server_info: Option<PathBuf>) -> Self {
+        Self {
+            app,
+            server_info,
+            running: BTreeMap::new(),
+            tasks: JoinSet::new(),
+        }
+    }
+
+    pub(crate) async fn start_missing(&mut self, listen_addrs: &[String]) -> Result<()> {
+        let mut bound = Vec::new();
+        for bind_addr in listen_addrs {
+            if self.running.contains_key(bind_addr) {
+                continue;
+            }
+            let listener = TcpListener::bind(bind_addr)
+                .await
+                .with_context(|| format!("binding {bind_addr}"))?;
+            bound.push((bind_addr.clone(), listener));
+        }
+
+        for (bind_addr, listener) in bound {
+            let local_addr = listener.local_addr().context("reading local_addr")?;
+            info!("codex-chat-bridge listening on {}", local_addr);
+
+            let (shutdown, shutdown_rx) = oneshot::channel();
+            let app = self.app.clone();
+            let task_addr = bind_addr.clone();
+            self.tasks.spawn(async move {
+                let result = axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(async {
+                    let _ = shutdown_rx.await;
+                })
+                .await
+                .with_context(|| format!("serving axum app on {local_addr}"));
+                (task_addr, result)
+            });
+            self.running.insert(
+                bind_addr,
+                RunningListener {
+                    port: local_addr.port(),
+                    shutdown,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    pub(crate) fn drain_removed(&mut self, listen_addrs: &[String]) {
+        let removed = self
+            .running
+            .keys()
+            .filter(|bind_addr| !listen_addrs.contains(bind_addr))
+            .cloned()
+            .collect::<Vec<_>>();
+        for bind_addr in removed {
+            if let Some(listener) = self.running.remove(&bind_addr) {
+                info!("draining listener on {}", bind_addr);
+                let _ = listener.shutdown.send(());
+            }
+        }
+    }
+
+    pub(crate) fn ports(&self) -> Vec<u16> {
+        self.running
+            .values()
+            .map(|listener| listener.port)
+            .collect()
+    }
+
+    pub(crate) fn write_server_info(&self) -> Result<()> {
+        match self.server_info.as_deref() {
+            Some(path) => write_server_info(path, &self.ports()),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) async fn wait_for_unexpected_exit(&mut self) -> Result<()> {
+        while let Some(joined) = self.tasks.join_next().await {
+            let (bind_addr, result) = joined.context("listener task failed to join")?;
+            if self.running.contains_key(&bind_addr) {
+                result?;
+                return Err(anyhow!("listener exited unexpectedly"));
+            }
+            match result {
+                Ok(()) => info!("listener on {} drained", bind_addr),
+                Err(err) => warn!("drained listener on {} failed: {err:#}", bind_addr),
+            }
+        }
+        Err(anyhow!("no listener task started"))
+    }
+}
+
+pub(crate) fn spawn_reload_triggers(config_path: PathBuf) -> mpsc::Receiver<&'static str> {
+    let (tx, rx) = mpsc::channel(4);
+
+    let file_tx = tx.clone();
+    tokio::spawn(async move {
+        let mut last_seen = tokio::fs::read(&config_path).await.ok();
+        let mut interval = tokio::time::interval(CONFIG_POLL_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let current = tokio::fs::read(&config_path).await.ok();
+            if current.is_none() || current == last_seen {
+                continue;
+            }
+            last_seen = current;
+            if file_tx.send("config file change").await.is_err() {
+                return;
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::SignalKind;
+        use tokio::signal::unix::signal;
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(err) => {
+                warn!("failed to install SIGHUP handler; reload on SIGHUP disabled: {err}");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            if tx.send("SIGHUP").await.is_err() {
+                return;
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    drop(tx);
+
+    rx
+}
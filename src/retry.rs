@@ -0,0 +1,180 @@
+use anyhow::Result;
+use anyhow::anyhow;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+use std::time::SystemTime;
+
+pub(crate) const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 3;
+pub(crate) const DEFAULT_RETRY_INITIAL_BACKOFF_MS: u64 = 500;
+pub(crate) const DEFAULT_RETRY_MAX_BACKOFF_MS: u64 = 8_000;
+pub(crate) const DEFAULT_RETRYABLE_STATUS_CODES: [u16; 6] = [408, 429, 500, 502, 503, 504];
+pub(crate) const DEFAULT_RETRYABLE_ERROR_CODES: [&str; 1] = ["rate_limit_exceeded"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct RetryConfig {
+    pub(crate) max_attempts: Option<u32>,
+    pub(crate) initial_backoff_ms: Option<u64>,
+    pub(crate) max_backoff_ms: Option<u64>,
+    pub(crate) jitter: Option<bool>,
+    pub(crate) respect_retry_after: Option<bool>,
+    pub(crate) retryable_status_codes: Option<Vec<u16>>,
+    pub(crate) retryable_error_codes: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RetryPolicy {
+    pub(crate) max_attempts: u32,
+    pub(crate) initial_backoff: Duration,
+    pub(crate) max_backoff: Duration,
+    pub(crate) jitter: bool,
+    pub(crate) respect_retry_after: bool,
+    pub(crate) retryable_status_codes: Vec<u16>,
+    pub(crate) retryable_error_codes: Vec<String>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(DEFAULT_RETRY_INITIAL_BACKOFF_MS),
+            max_backoff: Duration::from_millis(DEFAULT_RETRY_MAX_BACKOFF_MS),
+            jitter: true,
+            respect_retry_after: true,
+            retryable_status_codes: DEFAULT_RETRYABLE_STATUS_CODES.to_vec(),
+            retryable_error_codes: DEFAULT_RETRYABLE_ERROR_CODES
+                .iter()
+                .map(|code| code.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn from_config(config: Option<&RetryConfig>, context: &str) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+        let defaults = Self::default();
+
+        let max_attempts = config.max_attempts.unwrap_or(DEFAULT_RETRY_MAX_ATTEMPTS);
+        if max_attempts == 0 {
+            return Err(anyhow!("{context} retry.max_attempts must be at least 1"));
+        }
+        let initial_backoff = config
+            .initial_backoff_ms
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.initial_backoff);
+        let max_backoff = config
+            .max_backoff_ms
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.max_backoff);
+        if max_backoff < initial_backoff {
+            return Err(anyhow!(
+                "{context} retry.max_backoff_ms must not be less than retry.initial_backoff_ms"
+            ));
+        }
+        let retryable_status_codes = match config.retryable_status_codes.clone() {
+            Some(codes) => {
+                if let Some(code) = codes
+                    .iter()
+                    .find(|code| StatusCode::from_u16(**code).is_err())
+                {
+                    return Err(anyhow!(
+                        "{context} retry.retryable_status_codes contains invalid status `{code}`"
+                    ));
+                }
+                codes
+            }
+            None => defaults.retryable_status_codes,
+        };
+        let retryable_error_codes = config
+            .retryable_error_codes
+            .as_ref()
+            .map(|codes| {
+                codes
+                    .iter()
+                    .map(|code| code.trim().to_string())
+                    .filter(|code| !code.is_empty())
+                    .collect()
+            })
+            .unwrap_or(defaults.retryable_error_codes);
+
+        Ok(Self {
+            max_attempts,
+            initial_backoff,
+            max_backoff,
+            jitter: config.jitter.unwrap_or(defaults.jitter),
+            respect_retry_after: config
+                .respect_retry_after
+                .unwrap_or(defaults.respect_retry_after),
+            retryable_status_codes,
+            retryable_error_codes,
+        })
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_attempts > 1
+    }
+
+    pub(crate) fn is_retryable_failure(&self, status: StatusCode, error_code: &str) -> bool {
+        self.retryable_status_codes.contains(&status.as_u16())
+            || self
+                .retryable_error_codes
+                .iter()
+                .any(|code| code == error_code)
+    }
+
+    pub(crate) fn delay_for_attempt(
+        &self,
+        attempt: u32,
+        response_headers: Option<&HeaderMap>,
+    ) -> Duration {
+        if self.respect_retry_after
+            && let Some(retry_after) = response_headers.and_then(retry_after_from_headers)
+        {
+            return retry_after.min(self.max_backoff);
+        }
+
+        let exponent = attempt.saturating_sub(1).min(16);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1u32 << exponent)
+            .min(self.max_backoff);
+        if !self.jitter || backoff.is_zero() {
+            return backoff;
+        }
+
+        let half = backoff / 2;
+        let jitter_range = (backoff - half).as_millis() as u64;
+        half + Duration::from_millis(random_u64() % (jitter_range + 1))
+    }
+}
+
+pub(crate) fn retry_after_from_headers(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(millis) = headers
+        .get("retry-after-ms")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .filter(|millis| millis.is_finite() && *millis >= 0.0)
+    {
+        return Some(Duration::from_millis(millis as u64));
+    }
+
+    let retry_after = headers.get("retry-after")?.to_str().ok()?.trim();
+    if let Ok(seconds) = retry_after.parse::<f64>() {
+        return (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds));
+    }
+    let date = httpdate::parse_http_date(retry_after).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+fn random_u64() -> u64 {
+    RandomState::new().hash_one(SystemTime::now())
+}
@@ -2,19 +2,35 @@ use anyhow::Context;
 use anyhow::Result;
 use anyhow::anyhow;
 use axum::http::uri::Authority;
+use reqwest::Client;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::net::IpAddr;
+use std::sync::Arc;
 
+use crate::balancer::LoadBalancer;
+use crate::balancer::LoadBalancingPolicy;
+use crate::config::OllamaConfig;
 use crate::config::RouterConfig;
+use crate::config::UpstreamConfig;
 use crate::config::resolve_upstream_wire;
 use crate::config::upsert_upstream_http_header;
 use crate::config::validate_forward_incoming_header;
+use crate::credentials::ApiKey;
+use crate::credentials::router_api_key_source;
+use crate::credentials::upstream_api_key_source;
+use crate::inbound_auth::InboundAuth;
+use crate::message_roles::MessageRolesConfig;
+use crate::model::AuthScheme;
 use crate::model::DEFAULT_FORWARDED_UPSTREAM_HEADERS;
 use crate::model::FeatureFlags;
 use crate::model::UpstreamHeader;
 use crate::model::WireApi;
+use crate::retry::RetryPolicy;
+use crate::timeouts::TimeoutPolicy;
+use crate::tokenizer::Tokenizer;
 
 #[derive(Clone)]
 pub(crate) struct RouterManager {
@@ -26,6 +42,12 @@ pub(crate) struct RouterManager {
     default_drop_tool_types: HashSet<String>,
     default_drop_request_fields: HashSet<String>,
     default_feature_flags: FeatureFlags,
+    router_api_keys: BTreeMap<String, ApiKey>,
+    upstream_api_keys: BTreeMap<(String, usize), ApiKey>,
+    router_clients: BTreeMap<String, Client>,
+    inbound_auth: BTreeMap<String, Arc<InboundAuth>>,
+    tokenizers: BTreeMap<String, Arc<Tokenizer>>,
+    balancer: Arc<LoadBalancer>,
     incoming_route_to_router: BTreeMap<IncomingRouteKey, String>,
     listen_addrs: BTreeSet<String>,
 }
@@ -46,9 +68,23 @@ pub(crate) struct RouteTarget {
     pub(crate) feature_flags: FeatureFlags,
     pub(crate) anthropic_preserve_thinking: bool,
     pub(crate) anthropic_enable_openrouter_reasoning: bool,
+    pub(crate) api_key: Option<ApiKey>,
+    pub(crate) auth_scheme: AuthScheme,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) fallbacks: Vec<RouteTarget>,
+    pub(crate) weight: u32,
+    pub(crate) load_balancing: Option<LoadBalancingPolicy>,
+    pub(crate) timeouts: TimeoutPolicy,
+    pub(crate) client: Option<Client>,
+    pub(crate) inbound_auth: Option<Arc<InboundAuth>>,
+    pub(crate) ollama: OllamaConfig,
+    pub(crate) message_roles: MessageRolesConfig,
+    pub(crate) models: Vec<String>,
+    pub(crate) merge_upstream_models: bool,
+    pub(crate) tokenizer: Option<Arc<Tokenizer>>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) struct RouterDefaultsLogSnapshot {
     pub(crate) upstream_url: String,
     pub(crate) upstream_wire: WireApi,
@@ -58,7 +94,7 @@ pub(crate) struct RouterDefaultsLogSnapshot {
     pub(crate) drop_request_fields: Vec<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) struct RouterDeltaLogSnapshot {
     pub(crate) name: String,
     pub(crate) active: bool,
@@ -77,6 +113,18 @@ pub(crate) struct RouterDeltaLogSnapshot {
     pub(crate) override_drop_request_fields: Option<Vec<String>>,
     pub(crate) override_anthropic_preserve_thinking: Option<bool>,
     pub(crate) override_anthropic_enable_openrouter_reasoning: Option<bool>,
+    pub(crate) override_api_key_source: Option<String>,
+    pub(crate) override_auth_scheme: Option<AuthScheme>,
+    pub(crate) override_retry: Option<RetryPolicy>,
+    pub(crate) fallback_upstreams: Vec<(String, WireApi)>,
+    pub(crate) override_load_balancing: Option<LoadBalancingPolicy>,
+    pub(crate) override_timeouts: Option<TimeoutPolicy>,
+    pub(crate) override_ollama: Option<OllamaConfig>,
+    pub(crate) override_message_roles: Option<MessageRolesConfig>,
+    pub(crate) override_models: Option<Vec<String>>,
+    pub(crate) override_merge_upstream_models: Option<bool>,
+    pub(crate) inbound_auth: Option<String>,
+    pub(crate) tokenizer: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -93,6 +141,7 @@ struct ParsedIncomingUrl {
 }
 
 impl RouterManager {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         routers: BTreeMap<String, RouterConfig>,
         default_upstream_url: String,
@@ -114,6 +163,11 @@ impl RouterManager {
 
         let mut incoming_route_to_router = BTreeMap::new();
         let mut listen_addrs = BTreeSet::new();
+        let mut router_api_keys = BTreeMap::new();
+        let mut upstream_api_keys = BTreeMap::new();
+        let mut router_clients = BTreeMap::new();
+        let mut inbound_auth = BTreeMap::new();
+        let mut tokenizers = BTreeMap::new();
         for (router_name, router_config) in &routers {
             let incoming_url = router_config.incoming_url.as_ref().ok_or_else(|| {
                 anyhow!(
@@ -124,12 +178,28 @@ impl RouterManager {
                 .upstream_url
                 .as_deref()
                 .unwrap_or(&default_upstream_url);
-            resolve_upstream_wire(
+            let router_upstream_wire = resolve_upstream_wire(
                 Some(effective_router_upstream_url),
                 router_config.upstream_wire,
                 default_upstream_wire,
                 &format!("[routers.{router_name}]"),
             )?;
+            for (index, upstream) in router_config.upstreams.iter().flatten().enumerate() {
+                let context = format!("[routers.{router_name}].upstreams[{index}]");
+                if upstream.upstream_url.trim().is_empty() {
+                    return Err(anyhow!("{context} upstream_url must not be empty"));
+                }
+                resolve_upstream_wire(
+                    Some(&upstream.upstream_url),
+                    upstream.upstream_wire,
+                    router_upstream_wire,
+                    &context,
+                )?;
+                if let Some(source) = upstream_api_key_source(&context, upstream)? {
+                    upstream_api_keys
+                        .insert((router_name.clone(), index), ApiKey::from_source(&source));
+                }
+            }
             let parsed = parse_incoming_url(incoming_url).with_context(|| {
                 format!(
                     "invalid incoming_url for [routers.{router_name}] => {}",
@@ -150,6 +220,36 @@ impl RouterManager {
                     router_name
                 ));
             }
+            RetryPolicy::from_config(
+                router_config.retry.as_ref(),
+                &format!("[routers.{router_name}]"),
+            )?;
+            LoadBalancingPolicy::from_config(
+                router_config.load_balancing.as_ref(),
+                &format!("[routers.{router_name}]"),
+            )?;
+            let timeouts = TimeoutPolicy::from_config(
+                router_config.timeouts.as_ref(),
+                &format!("[routers.{router_name}]"),
+            )?;
+            if let Some(client) = timeouts.build_client()? {
+                router_clients.insert(router_name.clone(), client);
+            }
+            if let Some(auth) = InboundAuth::from_config(
+                router_config.inbound_auth.as_ref(),
+                &format!("[routers.{router_name}]"),
+            )? {
+                inbound_auth.insert(router_name.clone(), Arc::new(auth));
+            }
+            if let Some(tokenizer) = Tokenizer::from_config(
+                router_config.tokenizer.as_ref(),
+                &format!("[routers.{router_name}]"),
+            )? {
+                tokenizers.insert(router_name.clone(), Arc::new(tokenizer));
+            }
+            if let Some(source) = router_api_key_source(router_name, router_config)? {
+                router_api_keys.insert(router_name.clone(), ApiKey::from_source(&source));
+            }
         }
 
         Ok(Self {
@@ -161,15 +261,68 @@ impl RouterManager {
             default_drop_tool_types: default_drop_tool_types.into_iter().collect(),
             default_drop_request_fields: default_drop_request_fields.into_iter().collect(),
             default_feature_flags,
+            router_api_keys,
+            upstream_api_keys,
+            router_clients,
+            inbound_auth,
+            tokenizers,
+            balancer: Arc::new(LoadBalancer::default()),
             incoming_route_to_router,
             listen_addrs,
         })
     }
 
+    pub(crate) fn load_balancer(&self) -> Arc<LoadBalancer> {
+        self.balancer.clone()
+    }
+
+    pub(crate) fn reuse_load_balancer(&mut self, balancer: Arc<LoadBalancer>) {
+        let endpoints = self
+            .routers
+            .keys()
+            .filter_map(|name| {
+                let target = self.resolve_target_for_router_name(name).ok()?;
+                let urls = std::iter::once(&target)
+                    .chain(&target.fallbacks)
+                    .map(|candidate| candidate.upstream_url.clone())
+                    .collect::<HashSet<_>>();
+                Some((name.clone(), urls))
+            })
+            .collect::<HashMap<_, _>>();
+        balancer.retain_endpoints(&endpoints);
+        self.balancer = balancer;
+    }
+
     pub(crate) fn get_router_names(&self) -> Vec<String> {
         self.routers.keys().cloned().collect()
     }
 
+    pub(crate) fn get_api_key_failures(&self, default_api_key: &ApiKey) -> Vec<(String, String)> {
+        self.routers
+            .keys()
+            .filter_map(|name| {
+                let target = self.resolve_target_for_router_name(name).ok()?;
+                let mut reasons = Vec::new();
+                for candidate in std::iter::once(&target).chain(&target.fallbacks) {
+                    if !candidate.auth_scheme.requires_api_key() {
+                        return None;
+                    }
+                    match candidate
+                        .api_key
+                        .as_ref()
+                        .unwrap_or(default_api_key)
+                        .value()
+                    {
+                        Ok(_) => return None,
+                        Err(reason) => reasons.push(reason.to_string()),
+                    }
+                }
+                reasons.dedup();
+                Some((name.clone(), reasons.join("; ")))
+            })
+            .collect()
+    }
+
     pub(crate) fn get_default_log_snapshot(&self) -> RouterDefaultsLogSnapshot {
         let mut drop_tool_types = self
             .default_drop_tool_types
@@ -321,6 +474,62 @@ impl RouterManager {
                 override_anthropic_enable_openrouter_reasoning: router_cfg
                     .anthropic_enable_openrouter_reasoning
                     .filter(|enabled| *enabled),
+                override_api_key_source: router_api_key_source(name, router_cfg)
+                    .ok()
+                    .flatten()
+                    .map(|source| source.describe()),
+                override_auth_scheme: router_cfg.auth_scheme.clone().filter(|scheme| {
+                    *scheme != AuthScheme::default_for_wire(resolved_upstream_wire)
+                }),
+                override_retry: RetryPolicy::from_config(
+                    router_cfg.retry.as_ref(),
+                    &format!("[routers.{name}]"),
+                )
+                .ok()
+                .filter(RetryPolicy::is_enabled),
+                fallback_upstreams: router_cfg
+                    .upstreams
+                    .iter()
+                    .flatten()
+                    .map(|upstream| {
+                        let wire = resolve_upstream_wire(
+                            Some(&upstream.upstream_url),
+                            upstream.upstream_wire,
+                            resolved_upstream_wire,
+                            &format!("[routers.{name}]"),
+                        )
+                        .unwrap_or(resolved_upstream_wire);
+                        (upstream.upstream_url.clone(), wire)
+                    })
+                    .collect(),
+                override_load_balancing: LoadBalancingPolicy::from_config(
+                    router_cfg.load_balancing.as_ref(),
+                    &format!("[routers.{name}]"),
+                )
+                .ok()
+                .flatten(),
+                override_timeouts: TimeoutPolicy::from_config(
+                    router_cfg.timeouts.as_ref(),
+                    &format!("[routers.{name}]"),
+                )
+                .ok()
+                .filter(TimeoutPolicy::is_enabled),
+                override_ollama: router_cfg.ollama.clone().filter(OllamaConfig::is_enabled),
+                override_message_roles: router_cfg
+                    .message_roles
+                    .clone()
+                    .filter(MessageRolesConfig::is_enabled),
+                override_models: router_cfg
+                    .models
+                    .as_deref()
+                    .map(normalize_router_models)
+                    .filter(|models| !models.is_empty()),
+                override_merge_upstream_models: router_cfg.merge_upstream_models,
+                inbound_auth: self.inbound_auth.get(name).map(|auth| auth.describe()),
+                tokenizer: self
+                    .tokenizers
+                    .get(name)
+                    .map(|tokenizer| tokenizer.describe()),
             });
         }
 
@@ -396,7 +605,7 @@ impl RouterManager {
         best_router_name
     }
 
-    fn resolve_target_for_router_name(&self, name: &str) -> Result<RouteTarget> {
+    pub(crate) fn resolve_target_for_router_name(&self, name: &str) -> Result<RouteTarget> {
         let router = if self.routers.is_empty() {
             None
         } else {
@@ -464,8 +673,20 @@ impl RouterManager {
         let feature_flags = self
             .default_feature_flags
             .with_overrides(router.and_then(|r| r.features.as_ref()));
+        let retry = RetryPolicy::from_config(
+            router.and_then(|r| r.retry.as_ref()),
+            &format!("router '{name}'"),
+        )?;
+        let load_balancing = LoadBalancingPolicy::from_config(
+            router.and_then(|r| r.load_balancing.as_ref()),
+            &format!("router '{name}'"),
+        )?;
+        let timeouts = TimeoutPolicy::from_config(
+            router.and_then(|r| r.timeouts.as_ref()),
+            &format!("router '{name}'"),
+        )?;
 
-        Ok(RouteTarget {
+        let mut target = RouteTarget {
             router_name: name.to_string(),
             upstream_url,
             upstream_wire,
@@ -484,7 +705,109 @@ impl RouterManager {
             anthropic_enable_openrouter_reasoning: router
                 .and_then(|r| r.anthropic_enable_openrouter_reasoning)
                 .unwrap_or(false),
-        })
+            api_key: self.router_api_keys.get(name).cloned(),
+            auth_scheme: router
+                .and_then(|r| r.auth_scheme.clone())
+                .unwrap_or_else(|| AuthScheme::default_for_wire(upstream_wire)),
+            retry,
+            fallbacks: Vec::new(),
+            weight: router.and_then(|r| r.weight).unwrap_or(1),
+            load_balancing,
+            timeouts,
+            client: self.router_clients.get(name).cloned(),
+            inbound_auth: self.inbound_auth.get(name).cloned(),
+            ollama: router.and_then(|r| r.ollama.clone()).unwrap_or_default(),
+            message_roles: router
+                .and_then(|r| r.message_roles.clone())
+                .unwrap_or_default(),
+            models: router
+                .and_then(|r| r.models.as_deref())
+                .map(normalize_router_models)
+                .unwrap_or_default(),
+            merge_upstream_models: router
+                .and_then(|r| r.merge_upstream_models)
+                .unwrap_or(false),
+            tokenizer: self.tokenizers.get(name).cloned(),
+        };
+        if let Some(router) = router {
+            target.fallbacks = router
+                .upstreams
+                .iter()
+                .flatten()
+                .enumerate()
+                .map(|(index, upstream)| {
+                    self.resolve_fallback_target(&target, router, index, upstream)
+                })
+                .collect::<Result<Vec<_>>>()?;
+        }
+        Ok(target)
+    }
+
+    fn resolve_fallback_target(
+        &self,
+        primary: &RouteTarget,
+        router: &RouterConfig,
+        index: usize,
+        upstream: &UpstreamConfig,
+    ) -> Result<RouteTarget> {
+        let name = &primary.router_name;
+        let upstream_wire = resolve_upstream_wire(
+            Some(&upstream.upstream_url),
+            upstream.upstream_wire,
+            primary.upstream_wire,
+            &format!("router '{name}' upstreams[{index}]"),
+        )?;
+
+        // Credentials and headers only carry over to the primary's own host.
+        let same_host = upstream_host(&upstream.upstream_url)
+            .is_some_and(|host| upstream_host(&primary.upstream_url) == Some(host));
+        let mut target = primary.clone();
+        if !same_host {
+            target.upstream_http_headers = Vec::new();
+            target.api_key = Some(ApiKey::Unavailable(format!(
+                "router '{name}' upstreams[{index}] is on another host than upstream_url and sets no api_key_env, api_key_file or api_key_command"
+            )));
+        }
+        target.upstream_url = upstream.upstream_url.trim().to_string();
+        target.upstream_wire = upstream_wire;
+        target.weight = upstream.weight.unwrap_or(1);
+        for (slot, raw) in [
+            (&mut target.upstream_model, &upstream.upstream_model),
+            (
+                &mut target.upstream_model_opus,
+                &upstream.upstream_model_opus,
+            ),
+            (
+                &mut target.upstream_model_sonnet,
+                &upstream.upstream_model_sonnet,
+            ),
+            (
+                &mut target.upstream_model_haiku,
+                &upstream.upstream_model_haiku,
+            ),
+        ] {
+            if let Some(model) = normalize_optional_router_model(raw.as_deref()) {
+                *slot = Some(model);
+            }
+        }
+        for (name, value) in upstream.upstream_http_headers.iter().flatten() {
+            upsert_upstream_http_header(
+                &mut target.upstream_http_headers,
+                UpstreamHeader {
+                    name: name.clone(),
+                    value: value.clone(),
+                },
+            );
+        }
+        if let Some(api_key) = self.upstream_api_keys.get(&(name.clone(), index)) {
+            target.api_key = Some(api_key.clone());
+        }
+        target.auth_scheme = upstream
+            .auth_scheme
+            .clone()
+            .or_else(|| router.auth_scheme.clone().filter(|_| same_host))
+            .unwrap_or_else(|| AuthScheme::default_for_wire(upstream_wire));
+        Ok(target)
     }
 }
 
@@ -501,6 +824,13 @@ fn normalize_host_port(host: &str, port: u16) -> String {
     }
 }
 
+fn upstream_host(url: &str) -> Option<String> {
+    reqwest::Url::parse(url.trim())
+        .ok()?
+        .host_str()
+        .map(str::to_ascii_lowercase)
+}
+
 fn normalize_optional_router_model(raw: Option<&str>) -> Option<String> {
     raw.map(str::trim)
         .filter(|model| !model.is_empty())
@@ -513,6 +843,10 @@ fn normalize_host_header_to_authority(raw: &str) -> Option<String> {
     Some(normalize_host_port(authority.host(), port))
 }
 
+pub(crate) fn describe_incoming_url(raw: &str) -> Result<String> {
+    Ok(describe_route_key(&parse_incoming_url(raw)?.route_key))
+}
+
 fn describe_route_key(key: &IncomingRouteKey) -> String {
     match key.authority.as_deref() {
         Some(authority) => format!("http://{}{}", authority, key.path),
@@ -520,6 +854,17 @@ fn describe_route_key(key: &IncomingRouteKey) -> String {
     }
 }
 
+fn normalize_router_models(models: &[String]) -> Vec<String> {
+    let mut normalized = Vec::new();
+    for model in models {
+        let model = model.trim();
+        if !model.is_empty() && !normalized.iter().any(|m| m == model) {
+            normalized.push(model.to_string());
+        }
+    }
+    normalized
+}
+
 fn route_path_matches_prefix(request_path: &str, route_path: &str) -> bool {
     if request_path == route_path {
         return true;
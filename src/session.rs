@@ -1,48 +1,546 @@
+use anyhow::Context;
 use anyhow::Result;
 use anyhow::anyhow;
+use serde::Deserialize;
+use serde::Serialize;
 use serde_json::Value;
+use serde_json::json;
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio::sync::RwLock;
+use tracing::warn;
 
-#[derive(Debug)]
-pub(crate) struct SessionStore {
-    messages_by_response_id: HashMap<String, Vec<Value>>,
-    insertion_order: VecDeque<String>,
+use crate::bridge::mapping::assistant_chat_message_from_responses_output;
+use crate::bridge::mapping::chat_messages_to_responses_input;
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SessionBackendKind {
+    #[default]
+    Memory,
+    Jsonl,
 }
 
-impl Default for SessionStore {
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct SessionConfig {
+    pub(crate) backend: Option<SessionBackendKind>,
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) capacity: Option<usize>,
+    pub(crate) ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SessionPolicy {
+    pub(crate) backend: SessionBackendKind,
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) capacity: usize,
+    pub(crate) ttl: Option<Duration>,
+}
+
+impl Default for SessionPolicy {
     fn default() -> Self {
         Self {
-            messages_by_response_id: HashMap::new(),
-            insertion_order: VecDeque::new(),
+            backend: SessionBackendKind::Memory,
+            path: None,
+            capacity: DEFAULT_CAPACITY,
+            ttl: None,
+        }
+    }
+}
+
+impl SessionPolicy {
+    pub(crate) fn from_config(config: Option<&SessionConfig>) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+        let backend = config.backend.unwrap_or_default();
+        if backend == SessionBackendKind::Jsonl && config.path.is_none() {
+            return Err(anyhow!("[sessions] backend = \"jsonl\" requires `path`"));
+        }
+        let capacity = config.capacity.unwrap_or(DEFAULT_CAPACITY);
+        if capacity == 0 {
+            return Err(anyhow!("[sessions] capacity must be greater than 0"));
         }
+        let ttl = match config.ttl_secs {
+            Some(0) => return Err(anyhow!("[sessions] ttl_secs must be greater than 0")),
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => None,
+        };
+        Ok(Self {
+            backend,
+            path: config.path.clone(),
+            capacity,
+            ttl,
+        })
+    }
+}
+
+pub(crate) trait SessionBackend: Send + Sync {
+    fn get_session(&self, response_id: &str) -> Option<SessionRecord>;
+    fn store_session(&mut self, record: SessionRecord) -> Result<()>;
+    fn remove_session(&mut self, response_id: &str) -> Result<bool>;
+}
+
+pub(crate) struct SessionStore {
+    backend: Box<dyn SessionBackend>,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new(MemorySessionBackend::new(DEFAULT_CAPACITY, None))
     }
 }
 
 impl SessionStore {
-    const MAX_ENTRIES: usize = 1024;
+    pub(crate) fn new(backend: impl SessionBackend + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+        }
+    }
+
+    pub(crate) fn open(policy: &SessionPolicy) -> Result<Self> {
+        match (policy.backend, policy.path.as_deref()) {
+            (SessionBackendKind::Jsonl, Some(path)) => Ok(Self::new(JsonlSessionBackend::open(
+                path,
+                policy.capacity,
+                policy.ttl,
+            )?)),
+            _ => Ok(Self::new(MemorySessionBackend::new(
+                policy.capacity,
+                policy.ttl,
+            ))),
+        }
+    }
 
+    #[cfg(test)]
     pub(crate) fn get_messages(&self, response_id: &str) -> Option<Vec<Value>> {
-        self.messages_by_response_id.get(response_id).cloned()
+        self.backend
+            .get_session(response_id)
+            .map(|record| record.messages)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn insert_messages(
+        &mut self,
+        response_id: String,
+        messages: Vec<Value>,
+    ) -> Result<()> {
+        self.backend.store_session(SessionRecord {
+            router_name: String::new(),
+            response_id,
+            created_at_ms: now_ms(),
+            messages,
+            response: None,
+            deleted: false,
+        })
+    }
+
+    pub(crate) fn get_session(&self, response_id: &str) -> Option<SessionRecord> {
+        self.backend.get_session(response_id)
+    }
+
+    pub(crate) fn store_session(&mut self, record: SessionRecord) -> Result<()> {
+        self.backend.store_session(record)
+    }
+
+    pub(crate) fn remove_session(&mut self, response_id: &str) -> Result<bool> {
+        self.backend.remove_session(response_id)
+    }
+}
+
+pub(crate) struct SessionTurn {
+    sessions: Arc<RwLock<SessionStore>>,
+    router_name: String,
+    response_id: String,
+    model: String,
+    created_at_ms: u64,
+    messages: Vec<Value>,
+}
+
+impl SessionTurn {
+    pub(crate) fn new(
+        sessions: Arc<RwLock<SessionStore>>,
+        router_name: String,
+        response_id: String,
+        model: String,
+        messages: Vec<Value>,
+    ) -> Self {
+        Self {
+            sessions,
+            router_name,
+            response_id,
+            model,
+            created_at_ms: now_ms(),
+            messages,
+        }
+    }
+
+    pub(crate) fn created_at(&self) -> u64 {
+        self.created_at_ms / 1000
+    }
+
+    pub(crate) async fn complete(self, output: &[Value], usage: Option<&Value>) {
+        let response = json!({
+            "id": self.response_id,
+            "object": "response",
+            "created_at": self.created_at(),
+            "status": "completed",
+            "model": self.model,
+            "output": output,
+            "usage": usage,
+        });
+        self.complete_response(response).await;
+    }
+
+    pub(crate) async fn complete_response(mut self, response: Value) {
+        let output = response
+            .get("output")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        if let Some(message) = assistant_chat_message_from_responses_output(output) {
+            self.messages.push(message);
+        }
+        let messages = std::mem::take(&mut self.messages);
+        self.store(messages, response).await;
     }
 
-    pub(crate) fn insert_messages(&mut self, response_id: String, messages: Vec<Value>) {
-        if self.messages_by_response_id.contains_key(&response_id) {
+    pub(crate) async fn store_pending(&self, response: Value) {
+        self.store(self.messages.clone(), response).await;
+    }
+
+    async fn store(&self, messages: Vec<Value>, response: Value) {
+        let mut sessions = self.sessions.write().await;
+        if let Err(err) = sessions.store_session(SessionRecord {
+            router_name: self.router_name.clone(),
+            response_id: self.response_id.clone(),
+            created_at_ms: self.created_at_ms,
+            messages,
+            response: Some(response),
+            deleted: false,
+        }) {
+            warn!(
+                "session store write failed: response_id={}, error={err:#}",
+                self.response_id
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SessionRecord {
+    #[serde(default)]
+    pub(crate) router_name: String,
+    pub(crate) response_id: String,
+    pub(crate) created_at_ms: u64,
+    pub(crate) messages: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) response: Option<Value>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) deleted: bool,
+}
+
+impl SessionRecord {
+    pub(crate) fn status(&self) -> Option<&str> {
+        self.response
+            .as_ref()
+            .and_then(|response| response.get("status"))
+            .and_then(Value::as_str)
+    }
+
+    pub(crate) fn input_messages(&self) -> &[Value] {
+        let output = self
+            .response
+            .as_ref()
+            .and_then(|response| response.get("output"))
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        match assistant_chat_message_from_responses_output(output) {
+            Some(_) if !self.messages.is_empty() => &self.messages[..self.messages.len() - 1],
+            _ => &self.messages,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct MemorySessionBackend {
+    records: HashMap<String, SessionRecord>,
+    insertion_order: VecDeque<String>,
+    capacity: usize,
+    ttl: Option<Duration>,
+}
+
+impl MemorySessionBackend {
+    pub(crate) fn new(capacity: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            records: HashMap::new(),
+            insertion_order: VecDeque::new(),
+            capacity,
+            ttl,
+        }
+    }
+
+    fn is_expired(&self, record: &SessionRecord, now_ms: u64) -> bool {
+        self.ttl.is_some_and(|ttl| {
+            now_ms.saturating_sub(record.created_at_ms) >= ttl.as_millis() as u64
+        })
+    }
+
+    fn insert_record(&mut self, record: SessionRecord) {
+        let response_id = record.response_id.clone();
+        if self.records.insert(response_id.clone(), record).is_some() {
             self.insertion_order.retain(|id| id != &response_id);
         }
-        self.messages_by_response_id
-            .insert(response_id.clone(), messages);
         self.insertion_order.push_back(response_id);
+        self.evict(now_ms());
+    }
 
-        while self.messages_by_response_id.len() > Self::MAX_ENTRIES {
-            let Some(oldest_id) = self.insertion_order.pop_front() else {
+    fn evict(&mut self, now_ms: u64) {
+        while let Some(oldest_id) = self.insertion_order.front() {
+            let over_capacity = self.records.len() > self.capacity;
+            let expired = self
+                .records
+                .get(oldest_id)
+                .is_none_or(|record| self.is_expired(record, now_ms));
+            if !over_capacity && !expired {
                 break;
-            };
-            self.messages_by_response_id.remove(&oldest_id);
+            }
+            if let Some(oldest_id) = self.insertion_order.pop_front() {
+                self.records.remove(&oldest_id);
+            }
+        }
+    }
+
+    fn remove_record(&mut self, response_id: &str) -> bool {
+        let removed = self
+            .records
+            .remove(response_id)
+            .is_some_and(|record| !self.is_expired(&record, now_ms()));
+        self.insertion_order.retain(|id| id != response_id);
+        removed
+    }
+
+    fn records(&self) -> impl Iterator<Item = &SessionRecord> {
+        self.insertion_order
+            .iter()
+            .filter_map(|id| self.records.get(id))
+    }
+}
+
+impl SessionBackend for MemorySessionBackend {
+    fn get_session(&self, response_id: &str) -> Option<SessionRecord> {
+        self.records
+            .get(response_id)
+            .filter(|record| !self.is_expired(record, now_ms()))
+            .cloned()
+    }
+
+    fn store_session(&mut self, record: SessionRecord) -> Result<()> {
+        self.insert_record(record);
+        Ok(())
+    }
+
+    fn remove_session(&mut self, response_id: &str) -> Result<bool> {
+        Ok(self.remove_record(response_id))
+    }
+}
+
+pub(crate) struct JsonlSessionBackend {
+    memory: MemorySessionBackend,
+    writes: Option<mpsc::Sender<SessionRecord>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl JsonlSessionBackend {
+    pub(crate) fn open(path: &Path, capacity: usize, ttl: Option<Duration>) -> Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("creating session directory {}", parent.display()))?;
+        }
+        let mut memory = MemorySessionBackend::new(capacity, ttl);
+        let mut line_count = 0;
+        if path.exists() {
+            let file = File::open(path)
+                .with_context(|| format!("opening session file {}", path.display()))?;
+            for (index, line) in BufReader::new(file).lines().enumerate() {
+                let line =
+                    line.with_context(|| format!("reading session file {}", path.display()))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                line_count += 1;
+                match serde_json::from_str::<SessionRecord>(&line) {
+                    Ok(record) if record.deleted => {
+                        memory.remove_record(&record.response_id);
+                    }
+                    Ok(record) if memory.is_expired(&record, now_ms()) => {}
+                    Ok(record) => memory.insert_record(record),
+                    Err(err) => warn!(
+                        "skipping unreadable session record: path={}, line={}, error={}",
+                        path.display(),
+                        index + 1,
+                        err
+                    ),
+                }
+            }
         }
+        let mut writer = JsonlWriter {
+            path: path.to_path_buf(),
+            file: open_append(path)?,
+            line_count,
+            live: memory.clone(),
+        };
+        writer.compact_if_needed()?;
+
+        let (writes, pending) = mpsc::channel::<SessionRecord>();
+        let writer = std::thread::Builder::new()
+            .name("session-writer".to_string())
+            .spawn(move || {
+                for record in pending {
+                    if let Err(err) = writer.append(record) {
+                        warn!("session store write failed: {err:#}");
+                    }
+                }
+            })
+            .context("starting session writer thread")?;
+        Ok(Self {
+            memory,
+            writes: Some(writes),
+            writer: Some(writer),
+        })
+    }
+
+    fn append(&self, record: SessionRecord) -> Result<()> {
+        self.writes
+            .as_ref()
+            .and_then(|writes| writes.send(record).ok())
+            .ok_or_else(|| anyhow!("session writer thread has stopped"))
     }
 }
 
+impl Drop for JsonlSessionBackend {
+    fn drop(&mut self) {
+        self.writes.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl SessionBackend for JsonlSessionBackend {
+    fn get_session(&self, response_id: &str) -> Option<SessionRecord> {
+        self.memory.get_session(response_id)
+    }
+
+    fn store_session(&mut self, record: SessionRecord) -> Result<()> {
+        self.memory.insert_record(record.clone());
+        self.append(record)
+    }
+
+    fn remove_session(&mut self, response_id: &str) -> Result<bool> {
+        if !self.memory.remove_record(response_id) {
+            return Ok(false);
+        }
+        self.append(SessionRecord {
+            router_name: String::new(),
+            response_id: response_id.to_string(),
+            created_at_ms: now_ms(),
+            messages: Vec::new(),
+            response: None,
+            deleted: true,
+        })?;
+        Ok(true)
+    }
+}
+
+struct JsonlWriter {
+    path: PathBuf,
+    file: File,
+    line_count: usize,
+    live: MemorySessionBackend,
+}
+
+impl JsonlWriter {
+    fn append(&mut self, record: SessionRecord) -> Result<()> {
+        let line = serde_json::to_string(&record)?;
+        writeln!(self.file, "{line}")
+            .with_context(|| format!("appending to session file {}", self.path.display()))?;
+        self.line_count += 1;
+        if record.deleted {
+            self.live.remove_record(&record.response_id);
+        } else {
+            self.live.insert_record(record);
+        }
+        self.compact_if_needed()
+    }
+
+    fn compact_if_needed(&mut self) -> Result<()> {
+        if self.line_count <= self.live.capacity.saturating_mul(2) {
+            return Ok(());
+        }
+        let tmp_path = self.path.with_extension("jsonl.tmp");
+        let mut tmp = private_file_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .with_context(|| format!("creating {}", tmp_path.display()))?;
+        let mut line_count = 0;
+        for record in self.live.records() {
+            writeln!(tmp, "{}", serde_json::to_string(record)?)
+                .with_context(|| format!("writing {}", tmp_path.display()))?;
+            line_count += 1;
+        }
+        tmp.sync_all()
+            .with_context(|| format!("syncing {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("replacing session file {}", self.path.display()))?;
+        self.file = open_append(&self.path)?;
+        self.line_count = line_count;
+        Ok(())
+    }
+}
+
+fn private_file_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    private_file_options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening session file {}", path.display()))
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
 pub(crate) fn previous_response_id_for_request(request: &Value) -> Option<&str> {
     request
         .get("previous_response_id")
@@ -59,12 +557,20 @@ pub(crate) fn resolve_previous_messages_for_request(
         return Ok(None);
     };
 
-    let Some(messages) = sessions.get_messages(previous_response_id) else {
+    let Some(record) = sessions.get_session(previous_response_id) else {
         return Err(anyhow!(
             "unknown `previous_response_id`: {previous_response_id}"
         ));
     };
-    Ok(Some(messages))
+    // Background turns are stored before they finish; their history has no reply yet.
+    if let Some(status) = record.status()
+        && status != "completed"
+    {
+        return Err(anyhow!(
+            "`previous_response_id` {previous_response_id} has status `{status}`"
+        ));
+    }
+    Ok(Some(record.messages))
 }
 
 pub(crate) fn merge_previous_messages(
@@ -86,3 +592,20 @@ pub(crate) fn merge_previous_messages(
     *messages = previous_messages;
     Ok(())
 }
+
+pub(crate) fn prepend_previous_input(
+    request: &Value,
+    previous_messages: &[Value],
+) -> Result<Value> {
+    let mut input = chat_messages_to_responses_input(previous_messages);
+    match request.get("input") {
+        Some(Value::String(text)) => {
+            input.push(json!({"type": "message", "role": "user", "content": text}));
+        }
+        Some(Value::Array(items)) => input.extend(items.iter().cloned()),
+        _ => return Err(anyhow!("`input` must be a string or array")),
+    }
+    let mut request = request.clone();
+    request["input"] = Value::Array(input);
+    Ok(request)
+}
@@ -1,16 +1,23 @@
 use reqwest::Client;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::background::BackgroundResponses;
+use crate::credentials::ApiKey;
+use crate::metrics::Metrics;
 use crate::routing::RouterManager;
 use crate::session::SessionStore;
 
 #[derive(Clone)]
 pub(crate) struct AppState {
     pub(crate) client: Client,
-    pub(crate) api_key: String,
+    pub(crate) api_key: ApiKey,
     pub(crate) http_shutdown: bool,
     pub(crate) verbose_logging: bool,
     pub(crate) routers: Arc<RwLock<RouterManager>>,
     pub(crate) sessions: Arc<RwLock<SessionStore>>,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) background: Arc<BackgroundResponses>,
+    pub(crate) capture_dir: Option<PathBuf>,
 }
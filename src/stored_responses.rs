@@ -0,0 +1,248 @@
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::http::header::HOST;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use serde_json::Value;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::debug;
+use tracing::warn;
+
+use crate::background::update_pending_status;
+use crate::bridge::mapping::chat_messages_to_responses_input;
+use crate::model::IncomingApi;
+use crate::response_utils::error_response_for_api;
+use crate::response_utils::json_success_response;
+use crate::routing::RouteTarget;
+use crate::routing::normalize_request_path;
+use crate::session::SessionRecord;
+use crate::state::AppState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StoredResponseResource {
+    Response,
+    InputItems,
+    Cancel,
+}
+
+pub(crate) fn parse_stored_response_path(
+    path: &str,
+) -> Option<(String, String, StoredResponseResource)> {
+    let normalized = normalize_request_path(path);
+    let (base, rest) = normalized.split_once("/v1/responses/")?;
+    let (id, resource) = match rest.split_once('/') {
+        None => (rest, StoredResponseResource::Response),
+        Some((id, "input_items")) => (id, StoredResponseResource::InputItems),
+        Some((id, "cancel")) => (id, StoredResponseResource::Cancel),
+        Some(_) => return None,
+    };
+    if id.is_empty() {
+        return None;
+    }
+    Some((base.to_string(), id.to_string(), resource))
+}
+
+pub(crate) async fn handle_stored_response(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    base: &str,
+    response_id: &str,
+    resource: StoredResponseResource,
+    delete: bool,
+    query: Option<&str>,
+) -> Response {
+    let route_target = match resolve_responses_route(&state, &headers, base).await {
+        Ok(route_target) => route_target,
+        Err(response) => return response,
+    };
+    if !route_target.upstream_wire.keeps_responses_history() {
+        let mut response = error_response_for_api(
+            IncomingApi::Responses,
+            false,
+            "unsupported_feature",
+            &format!(
+                "router `{}` does not store responses locally; only chat, messages, gemini and ollama upstreams do",
+                route_target.router_name
+            ),
+        );
+        *response.status_mut() = StatusCode::BAD_REQUEST;
+        return response;
+    }
+
+    // Turns are only visible through the router that created them.
+    let owned = |record: &SessionRecord| record.router_name == route_target.router_name;
+    if delete {
+        let removed = {
+            let mut sessions = state.sessions.write().await;
+            match sessions.get_session(response_id) {
+                Some(record) if owned(&record) => sessions.remove_session(response_id),
+                _ => Ok(false),
+            }
+        };
+        return match removed {
+            Ok(true) => {
+                debug!(
+                    "stored response deleted: router={}, response_id={response_id}",
+                    route_target.router_name
+                );
+                json_success_response(json!({
+                    "id": response_id,
+                    "object": "response",
+                    "deleted": true,
+                }))
+            }
+            Ok(false) => response_not_found(response_id),
+            Err(err) => {
+                warn!("session store delete failed: response_id={response_id}, error={err:#}");
+                error_response_for_api(
+                    IncomingApi::Responses,
+                    false,
+                    "server_error",
+                    "failed to delete stored response",
+                )
+            }
+        };
+    }
+
+    let record = {
+        let sessions = state.sessions.read().await;
+        sessions.get_session(response_id)
+    };
+    let Some(record) = record.filter(owned) else {
+        return response_not_found(response_id);
+    };
+    match resource {
+        StoredResponseResource::Cancel => cancel_background_response(&state, record).await,
+        StoredResponseResource::Response => match record.response {
+            Some(response) => json_success_response(response),
+            None => response_not_found(response_id),
+        },
+        StoredResponseResource::InputItems => {
+            let ascending = query
+                .into_iter()
+                .flat_map(|query| query.split('&'))
+                .any(|pair| pair == "order=asc");
+            json_success_response(input_items_list_json(&record, ascending))
+        }
+    }
+}
+
+pub(crate) fn input_items_list_json(record: &SessionRecord, ascending: bool) -> Value {
+    let mut data = chat_messages_to_responses_input(record.input_messages())
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut item)| {
+            if let Some(object) = item.as_object_mut()
+                && !object.contains_key("id")
+            {
+                object.insert(
+                    "id".to_string(),
+                    Value::String(format!("{}_item_{index}", record.response_id)),
+                );
+            }
+            item
+        })
+        .collect::<Vec<_>>();
+    if !ascending {
+        data.reverse();
+    }
+    let first_id = data.first().and_then(|item| item.get("id")).cloned();
+    let last_id = data.last().and_then(|item| item.get("id")).cloned();
+    json!({
+        "object": "list",
+        "data": data,
+        "first_id": first_id,
+        "last_id": last_id,
+        "has_more": false,
+    })
+}
+
+async fn cancel_background_response(state: &AppState, record: SessionRecord) -> Response {
+    let response_id = record.response_id.as_str();
+    let is_background = record
+        .response
+        .as_ref()
+        .and_then(|response| response.get("background"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    if !is_background {
+        return cancel_rejected("only `background` responses can be cancelled");
+    }
+    if state.background.cancel(response_id) {
+        update_pending_status(state, response_id, "cancelled").await;
+        debug!("background response cancelled: response_id={response_id}");
+    }
+
+    let record = {
+        let sessions = state.sessions.read().await;
+        sessions.get_session(response_id)
+    };
+    match record {
+        Some(record) if record.status() == Some("cancelled") => {
+            json_success_response(record.response.unwrap_or_default())
+        }
+        Some(record) => cancel_rejected(&format!(
+            "cannot cancel a response with status `{}`",
+            record.status().unwrap_or_default()
+        )),
+        None => response_not_found(response_id),
+    }
+}
+
+fn cancel_rejected(message: &str) -> Response {
+    let mut response =
+        error_response_for_api(IncomingApi::Responses, false, "invalid_request", message);
+    *response.status_mut() = StatusCode::BAD_REQUEST;
+    response
+}
+
+async fn resolve_responses_route(
+    state: &Arc<AppState>,
+    headers: &HeaderMap,
+    base: &str,
+) -> Result<RouteTarget, Response> {
+    let host_header = headers.get(HOST).and_then(|h| h.to_str().ok());
+    let incoming_path = format!("{base}/v1/responses");
+    let resolved = {
+        let routers = state.routers.read().await;
+        routers.get_target_for_incoming_route(&incoming_path, host_header)
+    };
+    let route_target = match resolved {
+        Ok(Some(route_target)) => route_target,
+        Ok(None) => {
+            warn!("router miss for stored response: host={host_header:?}, base={base}");
+            return Err((StatusCode::NOT_FOUND, "not found").into_response());
+        }
+        Err(err) => {
+            warn!("stored response route resolution failed: path={incoming_path}, error={err}");
+            return Err((StatusCode::NOT_FOUND, "not found").into_response());
+        }
+    };
+
+    if let Some(inbound_auth) = route_target.inbound_auth.as_ref()
+        && !inbound_auth.authorizes(headers)
+    {
+        warn!(
+            "inbound auth rejected: router={}, incoming_route={incoming_path}/{{id}}, host={:?}",
+            route_target.router_name, host_header
+        );
+        return Err(crate::inbound_auth_error_response(
+            Some(IncomingApi::Responses),
+            Some(&incoming_path),
+            "",
+        ));
+    }
+    Ok(route_target)
+}
+
+fn response_not_found(response_id: &str) -> Response {
+    let mut response = error_response_for_api(
+        IncomingApi::Responses,
+        false,
+        "not_found",
+        &format!("no stored response with id `{response_id}`"),
+    );
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response
+}
@@ -1,8 +1,25 @@
 use super::*;
+use crate::balancer::{
+    LoadBalancer, LoadBalancingConfig, LoadBalancingPolicy, LoadBalancingStrategy,
+};
 use crate::bridge::apply_patch::normalize_apply_patch_input_with_repairs;
 use crate::bridge_types::ChatDelta;
+use crate::inbound_auth::{InboundAuth, InboundAuthConfig};
+use crate::message_roles::{
+    MessageRoleModes, MessageRolesConfig, SystemRoleMode, ToolRoleMode, apply_message_role_policy,
+};
+use crate::metrics::UsageTokens;
+use crate::models::{parse_models_path, upstream_model_ids, upstream_models_url};
+use crate::retry::RetryConfig;
+use crate::retry::RetryPolicy;
+use crate::retry::retry_after_from_headers;
+use crate::stored_responses::{StoredResponseResource, parse_stored_response_path};
+use crate::timeouts::{TimeoutConfig, TimeoutPolicy};
+use crate::tokenizer::{BpeVocab, Tokenizer, TokenizerConfig, TokenizerKind};
+use crate::websocket::websocket_request_body;
 use axum::Json;
 use axum::body::{Body, Bytes, to_bytes};
+use axum::extract::ConnectInfo;
 use axum::extract::State as AxumState;
 use axum::http::{HeaderMap, Request};
 use axum::routing::post;
@@ -10,7 +27,9 @@ use futures::StreamExt;
 use futures::stream;
 use serde_json::json;
 use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 use tower::ServiceExt;
 
 #[test]
@@ -1205,9 +1224,34 @@ fn build_upstream_payload_bypasses_anthropic_messages_without_mutation() {
 }
 
 #[test]
-fn build_upstream_payload_rejects_non_anthropic_messages_wire() {
-    let err = build_upstream_payload(
-        &json!({"model":"gpt-4.1","messages":[]}),
+fn build_upstream_payload_maps_chat_to_anthropic_messages() {
+    let input = json!({
+        "model": "claude-sonnet",
+        "max_completion_tokens": 1024,
+        "temperature": 0.3,
+        "stop": "END",
+        "tool_choice": {"type":"function","function":{"name":"get_weather"}},
+        "response_format": {"type":"json_schema","json_schema":{"name":"weather","schema":{"type":"object"}}},
+        "messages": [
+            {"role":"system","content":"be brief"},
+            {"role":"user","content":[
+                {"type":"text","text":"weather?"},
+                {"type":"image_url","image_url":{"url":"data:image/png;base64,AAAA"}}
+            ]},
+            {"role":"assistant","content":null,"tool_calls":[
+                {"id":"call_1","type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"seoul\"}"}}
+            ]},
+            {"role":"tool","tool_call_id":"call_1","content":"sunny"},
+            {"role":"user","content":"thanks"}
+        ],
+        "tools": [{
+            "type":"function",
+            "function":{"name":"get_weather","description":"look up weather","parameters":{"type":"object","properties":{"city":{"type":"string"}}}}
+        }]
+    });
+
+    let out = build_upstream_payload(
+        &input,
         IncomingApi::Chat,
         WireApi::Messages,
         false,
@@ -1215,756 +1259,1292 @@ fn build_upstream_payload_rejects_non_anthropic_messages_wire() {
         ToolTransformMode::LegacyConvert,
         false,
     )
-    .expect_err("must fail");
+    .expect("ok");
 
-    assert!(
-        err.to_string()
-            .contains("chat->messages bridge is not supported")
+    assert_eq!(out["model"], "claude-sonnet");
+    assert_eq!(out["stream"], false);
+    assert_eq!(out["max_tokens"], 1024);
+    assert_eq!(out["temperature"], 0.3);
+    assert_eq!(out["stop_sequences"], json!(["END"]));
+    let system = out["system"].as_str().expect("system");
+    assert!(system.starts_with("be brief\n\n"));
+    assert!(system.contains("{\"type\":\"object\"}"));
+    assert_eq!(
+        out["tool_choice"],
+        json!({"type":"tool","name":"get_weather"})
+    );
+    assert_eq!(out["tools"][0]["name"], "get_weather");
+    assert_eq!(out["tools"][0]["description"], "look up weather");
+    assert_eq!(
+        out["tools"][0]["input_schema"]["properties"]["city"]["type"],
+        "string"
+    );
+
+    assert_eq!(
+        out["messages"],
+        json!([
+            {"role":"user","content":[
+                {"type":"text","text":"weather?"},
+                {"type":"image","source":{"type":"base64","media_type":"image/png","data":"AAAA"}}
+            ]},
+            {"role":"assistant","content":[
+                {"type":"tool_use","id":"call_1","name":"get_weather","input":{"city":"seoul"}}
+            ]},
+            {"role":"user","content":[
+                {"type":"tool_result","tool_use_id":"call_1","content":"sunny"},
+                {"type":"text","text":"thanks"}
+            ]}
+        ])
     );
 }
 
 #[test]
-fn map_anthropic_messages_to_chat_request_normalizes_empty_additional_properties() {
-    let input = json!({
-        "model": "claude-sonnet",
-        "messages": [{"role":"user","content":[{"type":"text","text":"hi"}]}],
-        "tools": [
-            {
-                "name": "ExitPlanMode",
-                "description": "exit plan mode",
-                "input_schema": {
-                    "type": "object",
-                    "properties": {},
-                    "additionalProperties": {}
-                }
-            }
-        ]
-    });
-
-    let out = map_anthropic_messages_to_chat_request(&input, false).expect("ok");
-    let parameters = &out["tools"][0]["function"]["parameters"];
-    assert_eq!(parameters["required"], json!([]));
-    assert_eq!(parameters["additionalProperties"], false);
+fn resolve_upstream_wire_infers_gemini_from_generate_content_urls() {
+    for url in [
+        "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-pro:generateContent",
+        "https://generativelanguage.googleapis.com/v1beta/models/{model}:streamGenerateContent?alt=sse",
+    ] {
+        assert_eq!(
+            resolve_upstream_wire(Some(url), None, WireApi::Chat, "test").expect("wire"),
+            WireApi::Gemini
+        );
+    }
+    assert_eq!(
+        AuthScheme::default_for_wire(WireApi::Gemini),
+        AuthScheme::Header("x-goog-api-key".to_string())
+    );
 }
 
 #[test]
-fn map_anthropic_messages_to_chat_request_normalizes_null_required() {
-    let input = json!({
-        "model": "claude-sonnet",
-        "messages": [{"role":"user","content":[{"type":"text","text":"hi"}]}],
-        "tools": [
-            {
-                "name": "shell",
-                "description": "run shell",
-                "input_schema": {
-                    "type": "object",
-                    "properties": {},
-                    "required": null
-                }
-            }
-        ]
-    });
+fn gemini_upstream_request_moves_model_and_stream_into_url() {
+    let payload = json!({"model":"models/gemini-2.5-pro","stream":true,"contents":[]});
+    let (url, body) = gemini_upstream_request(
+        "https://example.com/v1beta/models/{model}:generateContent?key=abc",
+        &payload,
+    );
+    assert_eq!(
+        url,
+        "https://example.com/v1beta/models/gemini-2.5-pro:streamGenerateContent?key=abc&alt=sse"
+    );
+    assert_eq!(body, json!({"contents":[]}));
 
-    let out = map_anthropic_messages_to_chat_request(&input, false).expect("ok");
+    let (url, _) = gemini_upstream_request(
+        "https://example.com/v1beta/models/gemini-fixed:streamGenerateContent?alt=sse",
+        &json!({"model":"ignored","stream":false}),
+    );
     assert_eq!(
-        out["tools"][0]["function"]["parameters"]["required"],
-        json!([])
+        url,
+        "https://example.com/v1beta/models/gemini-fixed:generateContent"
+    );
+
+    let (url, _) = gemini_upstream_request(
+        "https://example.com/v1beta/models/{model}:generateContent",
+        &json!({"model":"tuned/my model?v=1#a","stream":false}),
+    );
+    assert_eq!(
+        url,
+        "https://example.com/v1beta/models/tuned%2Fmy%20model%3Fv%3D1%23a:generateContent"
     );
 }
 
 #[test]
-fn map_anthropic_messages_to_chat_request_preserves_thinking_in_content_when_enabled() {
+fn build_upstream_payload_maps_chat_to_gemini_contents() {
+    let signed_call_id = gemini_tool_call_id(Some("c2ln+/w="));
     let input = json!({
-        "model": "claude-sonnet",
+        "model": "gemini-2.5-pro",
+        "max_tokens": 512,
+        "temperature": 0.1,
+        "stop": ["END"],
+        "reasoning_effort": "medium",
+        "tool_choice": "required",
+        "response_format": {"type":"json_object"},
         "messages": [
-            {
-                "role": "assistant",
-                "content": [
-                    {"type":"thinking","thinking":"internal chain"},
-                    {"type":"text","text":"hello"}
-                ]
-            }
-        ]
+            {"role":"system","content":"be brief"},
+            {"role":"user","content":"weather?"},
+            {"role":"assistant","content":"checking","tool_calls":[
+                {"id": signed_call_id,"type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"seoul\"}"}}
+            ]},
+            {"role":"tool","tool_call_id": signed_call_id,"content":"sunny"}
+        ],
+        "tools": [{
+            "type":"function",
+            "function":{"name":"get_weather","description":"look up weather","parameters":{"type":"object","additionalProperties":false}}
+        }]
     });
 
-    let out = map_anthropic_messages_to_chat_request(&input, true).expect("ok");
-    let messages = out["messages"].as_array().expect("messages");
-    assert_eq!(messages[0]["reasoning_content"], "internal chain");
+    let out = build_upstream_payload(
+        &input,
+        IncomingApi::Chat,
+        WireApi::Gemini,
+        true,
+        true,
+        ToolTransformMode::LegacyConvert,
+        false,
+    )
+    .expect("ok");
+
+    assert_eq!(out["model"], "gemini-2.5-pro");
+    assert_eq!(out["stream"], true);
     assert_eq!(
-        messages[0]["content"],
-        "<thinking>\ninternal chain\n</thinking>\n\nhello"
+        out["systemInstruction"],
+        json!({"parts":[{"text":"be brief"}]})
+    );
+    assert_eq!(
+        out["contents"],
+        json!([
+            {"role":"user","parts":[{"text":"weather?"}]},
+            {"role":"model","parts":[
+                {"text":"checking"},
+                {"functionCall":{"name":"get_weather","args":{"city":"seoul"}},"thoughtSignature":"c2ln+/w="}
+            ]},
+            {"role":"user","parts":[
+                {"functionResponse":{"name":"get_weather","response":{"result":"sunny"}}}
+            ]}
+        ])
+    );
+    assert_eq!(
+        out["tools"][0]["functionDeclarations"][0],
+        json!({
+            "name":"get_weather",
+            "description":"look up weather",
+            "parametersJsonSchema":{"type":"object","additionalProperties":false}
+        })
+    );
+    assert_eq!(out["toolConfig"]["functionCallingConfig"]["mode"], "ANY");
+    assert_eq!(
+        out["generationConfig"],
+        json!({
+            "maxOutputTokens": 512,
+            "temperature": 0.1,
+            "stopSequences": ["END"],
+            "responseMimeType": "application/json",
+            "thinkingConfig": {"includeThoughts": true, "thinkingBudget": 8192}
+        })
     );
 }
 
 #[test]
-fn map_anthropic_messages_to_chat_request_preserves_thinking_without_text_for_tool_calls() {
-    let input = json!({
-        "model": "claude-sonnet",
-        "messages": [
-            {
-                "role": "assistant",
-                "content": [
-                    {"type":"thinking","thinking":"internal chain"},
-                    {"type":"tool_use","id":"tool_1","name":"shell","input":{"cmd":"pwd"}}
-                ]
+fn gemini_json_to_chat_json_maps_parts_signatures_and_usage() {
+    let out = gemini_json_to_chat_json(
+        json!({
+            "responseId": "gem_1",
+            "modelVersion": "gemini-2.5-pro",
+            "candidates": [{
+                "content": {"role":"model","parts":[
+                    {"text":"plan","thought":true},
+                    {"text":"calling"},
+                    {"functionCall":{"name":"get_weather","args":{"city":"seoul"}},"thoughtSignature":"c2ln+/w="}
+                ]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {
+                "promptTokenCount": 10,
+                "candidatesTokenCount": 4,
+                "thoughtsTokenCount": 6,
+                "totalTokenCount": 20
             }
-        ]
-    });
+        }),
+        "fallback",
+    );
 
-    let out = map_anthropic_messages_to_chat_request(&input, true).expect("ok");
-    let messages = out["messages"].as_array().expect("messages");
+    let message = &out["choices"][0]["message"];
+    assert_eq!(out["model"], "gemini-2.5-pro");
+    assert_eq!(message["content"], "calling");
+    assert_eq!(message["reasoning_content"], "plan");
+    assert_eq!(message["tool_calls"][0]["function"]["name"], "get_weather");
     assert_eq!(
-        messages[0]["content"],
-        "<thinking>\ninternal chain\n</thinking>"
+        message["tool_calls"][0]["function"]["arguments"],
+        "{\"city\":\"seoul\"}"
+    );
+    assert_eq!(out["choices"][0]["finish_reason"], "tool_calls");
+    assert_eq!(out["usage"]["prompt_tokens"], 10);
+    assert_eq!(out["usage"]["completion_tokens"], 10);
+    assert_eq!(out["usage"]["total_tokens"], 20);
+
+    // The signature survives a round trip through the tool call id.
+    let call_id = message["tool_calls"][0]["id"].as_str().expect("call id");
+    assert!(
+        call_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    );
+    let next_turn = map_chat_to_gemini_request(
+        &json!({
+            "model": "gemini-2.5-pro",
+            "messages": [{"role":"assistant","content":null,"tool_calls": message["tool_calls"]}]
+        }),
+        false,
+    )
+    .expect("ok");
+    assert_eq!(
+        next_turn["contents"][0]["parts"][0]["thoughtSignature"],
+        "c2ln+/w="
     );
-    assert_eq!(messages[0]["tool_calls"][0]["id"], "tool_1");
-    assert_eq!(messages[0]["tool_calls"][0]["function"]["name"], "shell");
 }
 
-#[test]
-fn chat_json_to_anthropic_json_maps_tool_calls() {
-    let input = json!({
-        "id": "chatcmpl_1",
-        "model": "gpt-4.1",
-        "choices": [{
-            "finish_reason": "tool_calls",
-            "message": {
-                "content": "hello",
-                "reasoning_content": "thinking",
-                "tool_calls": [{
-                    "id": "call_1",
-                    "function": {
-                        "name": "shell",
-                        "arguments": "{\"cmd\":\"pwd\"}"
-                    }
-                }]
-            }
-        }],
-        "usage": {
-            "prompt_tokens": 10,
-            "completion_tokens": 5
-        }
-    });
+#[tokio::test]
+async fn gemini_stream_to_chat_stream_emits_chunks_usage_and_done() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"plan\",\"thought\":true}]}}]}\n\n\
+         data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"text\":\"Hi\"}]}}]}\n\n\
+         data: {\"candidates\":[{\"content\":{\"role\":\"model\",\"parts\":[{\"functionCall\":{\"name\":\"shell\",\"args\":{\"cmd\":\"ls\"}},\"thoughtSignature\":\"c2ln\"}]},\"finishReason\":\"STOP\"}],\"usageMetadata\":{\"promptTokenCount\":3,\"candidatesTokenCount\":2,\"totalTokenCount\":5}}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_gemini_stream_to_chat(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "gemini-2.5-pro".to_string(),
+    ));
+    let mut payload = String::new();
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
 
-    let out = chat_json_to_anthropic_json(input, "claude-bridge");
-    assert_eq!(out["type"], "message");
-    assert_eq!(out["stop_reason"], "tool_use");
-    assert_eq!(out["content"][0]["type"], "thinking");
-    assert_eq!(out["content"][1]["type"], "text");
-    assert_eq!(out["content"][2]["type"], "tool_use");
-    assert_eq!(out["content"][2]["input"]["cmd"], "pwd");
+    assert!(payload.contains("\"reasoning_content\":\"plan\""));
+    assert!(payload.contains("\"content\":\"Hi\""));
+    assert!(payload.contains("\"name\":\"shell\""));
+    assert!(payload.contains("\"arguments\":\"{\\\"cmd\\\":\\\"ls\\\"}\""));
+    assert!(payload.contains("__thought__c2ln"));
+    assert!(payload.contains("\"finish_reason\":\"tool_calls\""));
+    assert!(payload.contains("\"prompt_tokens\":3"));
+    assert!(payload.ends_with("data: [DONE]\n\n"));
+
+    let cut_off = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"Hi\"}]}}]}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_gemini_stream_to_chat(
+        cut_off,
+        "test_router".to_string(),
+        false,
+        "gemini-2.5-pro".to_string(),
+    ));
+    let mut payload = String::new();
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+    assert!(payload.contains("\"type\":\"upstream_stream_incomplete\""));
 }
 
 #[test]
-fn chat_json_to_anthropic_json_keeps_think_tags_as_text() {
-    let input = json!({
-        "choices": [{
-            "message": {
-                "content": "before <think>internal</think> after"
-            }
-        }]
-    });
-
-    let out = chat_json_to_anthropic_json(input, "claude-bridge");
-    assert_eq!(out["content"][0]["type"], "text");
+fn resolve_upstream_wire_infers_ollama_from_api_chat_url() {
     assert_eq!(
-        out["content"][0]["text"],
-        "before <think>internal</think> after"
+        resolve_upstream_wire(
+            Some("http://127.0.0.1:11434/api/chat"),
+            None,
+            WireApi::Chat,
+            "test"
+        )
+        .expect("wire"),
+        WireApi::Ollama
+    );
+    assert_eq!(
+        AuthScheme::default_for_wire(WireApi::Ollama),
+        AuthScheme::None
     );
-    assert!(out.get("stop_sequence").is_none());
 }
 
 #[test]
-fn chat_json_to_anthropic_json_ignores_provider_specific_thinking_blocks() {
+fn build_upstream_payload_maps_chat_to_ollama_messages_and_options() {
     let input = json!({
-        "choices": [{
-            "message": {
-                "thinking_blocks": [
-                    {
-                        "type": "thinking",
-                        "thinking": "step one",
-                        "signature": "sig_1"
-                    },
-                    {
-                        "type": "redacted_thinking",
-                        "data": "redacted_payload"
-                    }
-                ]
-            }
-        }]
+        "model": "qwen3-coder:30b",
+        "max_tokens": 256,
+        "temperature": 0.7,
+        "stop": "END",
+        "reasoning_effort": "high",
+        "response_format": {"type":"json_schema","json_schema":{"name":"out","schema":{"type":"object"}}},
+        "messages": [
+            {"role":"developer","content":"be brief"},
+            {"role":"user","content":[
+                {"type":"text","text":"what is this?"},
+                {"type":"image_url","image_url":{"url":"data:image/png;base64,aGVsbG8="}}
+            ]},
+            {"role":"assistant","content":null,"reasoning_content":"look first","tool_calls":[
+                {"id":"call_1","type":"function","function":{"name":"shell","arguments":"{\"cmd\":\"ls\"}"}}
+            ]},
+            {"role":"tool","tool_call_id":"call_1","content":"a.txt"}
+        ],
+        "tools": [{"type":"function","function":{"name":"shell","parameters":{"type":"object"}}}]
     });
 
-    let out = chat_json_to_anthropic_json(input, "claude-bridge");
-    assert_eq!(out["content"].as_array().unwrap().len(), 0);
-    assert!(out.get("stop_reason").is_none());
-    assert!(out.get("stop_sequence").is_none());
-}
+    let mut out = build_upstream_payload(
+        &input,
+        IncomingApi::Chat,
+        WireApi::Ollama,
+        false,
+        true,
+        ToolTransformMode::LegacyConvert,
+        false,
+    )
+    .expect("ok");
 
-#[test]
-fn chat_json_to_anthropic_json_maps_unknown_finish_reason_to_stop_sequence() {
-    let input = json!({
-        "choices": [{
-            "finish_reason": "content_filter",
-            "message": {"content": "filtered"}
-        }]
-    });
-
-    let out = chat_json_to_anthropic_json(input, "claude-bridge");
-    assert_eq!(out["stop_reason"], "stop_sequence");
-    assert!(out.get("stop_sequence").is_none());
-}
-
-#[test]
-fn chat_json_to_anthropic_json_omits_empty_finish_reason() {
-    let input = json!({
-        "choices": [{
-            "finish_reason": "",
-            "message": {"content": "hello"}
-        }]
-    });
+    assert_eq!(out["stream"], false);
+    assert_eq!(out["think"], true);
+    assert_eq!(out["format"], json!({"type":"object"}));
+    assert_eq!(
+        out["messages"],
+        json!([
+            {"role":"system","content":"be brief"},
+            {"role":"user","content":"what is this?","images":["aGVsbG8="]},
+            {"role":"assistant","content":"","thinking":"look first","tool_calls":[
+                {"function":{"name":"shell","arguments":{"cmd":"ls"}}}
+            ]},
+            {"role":"tool","content":"a.txt","tool_name":"shell"}
+        ])
+    );
+    assert_eq!(out["tools"][0]["function"]["name"], "shell");
+    assert_eq!(
+        out["options"],
+        json!({"num_predict": 256, "temperature": 0.7, "stop": ["END"]})
+    );
 
-    let out = chat_json_to_anthropic_json(input, "claude-bridge");
-    assert!(out.get("stop_reason").is_none());
-    assert!(out.get("stop_sequence").is_none());
+    let ollama: OllamaConfig = toml::from_str(
+        r#"
+options = { num_ctx = 32768, temperature = 0.2 }
+keep_alive = "30m"
+"#,
+    )
+    .expect("ollama config");
+    apply_ollama_overrides(&mut out, &ollama);
+    assert_eq!(
+        out["options"],
+        json!({"num_predict": 256, "temperature": 0.2, "stop": ["END"], "num_ctx": 32768})
+    );
+    assert_eq!(out["keep_alive"], "30m");
+    assert_eq!(out["think"], true);
 }
 
 #[test]
-fn normalize_chat_tools_converts_custom_tool_to_function() {
-    let tools = vec![json!({
-        "type": "custom",
-        "name": "shell",
-        "description": "run shell",
-        "input_schema": {"type":"object","properties":{"cmd":{"type":"string"}}}
-    })];
-    let out = normalize_chat_tools(tools, &HashSet::new(), ToolTransformMode::LegacyConvert);
-    assert_eq!(
-        out[0],
+fn ollama_json_to_chat_json_maps_message_thinking_tool_calls_and_usage() {
+    let out = ollama_json_to_chat_json(
         json!({
-            "type": "function",
-            "function": {
-                "name": "shell",
-                "description": "run shell",
-                "parameters": {"type":"object","properties":{"cmd":{"type":"string"}},"required":[]}
-            }
-        })
+            "model": "qwen3-coder:30b",
+            "message": {
+                "role": "assistant",
+                "content": "",
+                "thinking": "need a listing",
+                "tool_calls": [{"function":{"name":"shell","arguments":{"cmd":"ls"}}}]
+            },
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 21,
+            "eval_count": 5
+        }),
+        "fallback",
     );
-}
 
-#[test]
-fn normalize_chat_tools_custom_without_schema_requires_input_string() {
-    let tools = vec![json!({
-        "type": "custom",
-        "name": "apply_patch",
-        "description": "patch files"
-    })];
-    let out = normalize_chat_tools(tools, &HashSet::new(), ToolTransformMode::LegacyConvert);
-    assert_eq!(out[0]["type"], "function");
-    assert_eq!(out[0]["function"]["parameters"]["type"], "object");
+    let message = &out["choices"][0]["message"];
+    assert_eq!(out["model"], "qwen3-coder:30b");
+    assert_eq!(message["reasoning_content"], "need a listing");
+    assert!(
+        message["tool_calls"][0]["id"]
+            .as_str()
+            .expect("id")
+            .starts_with("call_")
+    );
     assert_eq!(
-        out[0]["function"]["parameters"]["required"],
-        json!(["input"])
+        message["tool_calls"][0]["function"]["arguments"],
+        "{\"cmd\":\"ls\"}"
     );
+    assert_eq!(out["choices"][0]["finish_reason"], "tool_calls");
     assert_eq!(
-        out[0]["function"]["parameters"]["properties"]["input"]["type"],
-        "string"
+        out["usage"],
+        json!({"prompt_tokens": 21, "completion_tokens": 5, "total_tokens": 26})
     );
-}
-
-#[test]
-fn normalize_chat_tools_custom_string_schema_wraps_as_input_object() {
-    let tools = vec![json!({
-        "type": "custom",
-        "name": "apply_patch",
-        "input_schema": {"type":"string"}
-    })];
-    let out = normalize_chat_tools(tools, &HashSet::new(), ToolTransformMode::LegacyConvert);
-    assert_eq!(out[0]["type"], "function");
-    assert_eq!(out[0]["function"]["parameters"]["type"], "object");
     assert_eq!(
-        out[0]["function"]["parameters"]["properties"]["input"]["type"],
-        "string"
+        usage_tokens(&json!({"prompt_eval_count": 21, "eval_count": 5})),
+        Some(UsageTokens {
+            prompt_tokens: 21,
+            completion_tokens: 5
+        })
     );
 }
 
-#[test]
-fn normalize_tool_choice_preserves_wrapped_choice() {
-    let choice = json!({"type":"function", "function":{"name":"do_it"}});
-    assert_eq!(
-        normalize_tool_choice(choice.clone(), ToolTransformMode::LegacyConvert),
-        choice
-    );
+#[tokio::test]
+async fn ollama_ndjson_stream_to_chat_stream_handles_split_lines_and_done() {
+    let upstream = stream::iter(vec![
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"\",\"thinking\":\"hm\"},\"done\":false}\n{\"message\":{\"role\":\"assistant\",\"content\":\"He",
+        )),
+        Ok(Bytes::from(
+            "llo\"},\"done\":false}\n{\"message\":{\"role\":\"assistant\",\"content\":\"\"},\"done\":true,\"done_reason\":\"length\",\"prompt_eval_count\":7,\"eval_count\":3}\n",
+        )),
+    ]);
+    let mut output = Box::pin(translate_ollama_stream_to_chat(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "qwen3".to_string(),
+    ));
+    let mut payload = String::new();
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"reasoning_content\":\"hm\""));
+    assert!(payload.contains("\"content\":\"Hello\""));
+    assert!(payload.contains("\"finish_reason\":\"length\""));
+    assert!(payload.contains("\"prompt_tokens\":7"));
+    assert!(payload.ends_with("data: [DONE]\n\n"));
+    assert_eq!(payload.matches("[DONE]").count(), 1);
+
+    for (body, error_type) in [
+        (
+            "{\"error\":\"model 'qwen3' not found\"}\n",
+            "upstream_error",
+        ),
+        (
+            "{\"message\":{\"content\":\"Hi\"},\"done\":false}\n",
+            "upstream_stream_incomplete",
+        ),
+    ] {
+        let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(body))]);
+        let mut output = Box::pin(translate_ollama_stream_to_chat(
+            upstream,
+            "test_router".to_string(),
+            false,
+            "qwen3".to_string(),
+        ));
+        let mut payload = String::new();
+        while let Some(event) = output.next().await {
+            payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+        }
+        assert!(payload.contains(&format!("\"type\":\"{error_type}\"")));
+        assert!(payload.ends_with("data: [DONE]\n\n"));
+    }
 }
 
 #[test]
-fn normalize_tool_choice_converts_custom_name() {
-    let choice = json!({"type":"custom", "name":"shell"});
+fn anthropic_json_to_chat_json_maps_content_tool_calls_and_usage() {
+    let out = anthropic_json_to_chat_json(
+        json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet",
+            "content": [
+                {"type":"thinking","thinking":"plan","signature":"sig"},
+                {"type":"text","text":"checking"},
+                {"type":"tool_use","id":"toolu_1","name":"get_weather","input":{"city":"seoul"}}
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 7, "cache_read_input_tokens": 3, "output_tokens": 5}
+        }),
+        "fallback",
+    );
+
+    assert_eq!(out["object"], "chat.completion");
+    assert_eq!(out["model"], "claude-sonnet");
+    let message = &out["choices"][0]["message"];
+    assert_eq!(message["content"], "checking");
+    assert_eq!(message["reasoning_content"], "plan");
+    assert_eq!(message["tool_calls"][0]["id"], "toolu_1");
+    assert_eq!(message["tool_calls"][0]["function"]["name"], "get_weather");
     assert_eq!(
-        normalize_tool_choice(choice, ToolTransformMode::LegacyConvert),
-        json!({"type":"function","function":{"name":"shell"}})
+        message["tool_calls"][0]["function"]["arguments"],
+        "{\"city\":\"seoul\"}"
     );
+    assert_eq!(out["choices"][0]["finish_reason"], "tool_calls");
+    assert_eq!(out["usage"]["prompt_tokens"], 10);
+    assert_eq!(out["usage"]["completion_tokens"], 5);
+    assert_eq!(out["usage"]["total_tokens"], 15);
+    assert_eq!(out["usage"]["prompt_tokens_details"]["cached_tokens"], 3);
 }
 
-#[test]
-fn function_output_to_text_for_object_uses_json() {
-    let value = json!({"ok": true});
-    assert_eq!(function_output_to_text(&value), "{\"ok\":true}");
+#[tokio::test]
+async fn anthropic_stream_to_chat_stream_emits_chunks_usage_and_done() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "event: message_start\n\
+         data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":4,\"output_tokens\":1}}}\n\n\
+         event: content_block_start\n\
+         data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n\
+         event: content_block_stop\n\
+         data: {\"type\":\"content_block_stop\",\"index\":0}\n\n\
+         event: content_block_start\n\
+         data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"shell\",\"input\":{}}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"cmd\\\":\\\"ls\\\"}\"}}\n\n\
+         event: content_block_stop\n\
+         data: {\"type\":\"content_block_stop\",\"index\":1}\n\n\
+         event: message_delta\n\
+         data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":6}}\n\n\
+         event: message_stop\n\
+         data: {\"type\":\"message_stop\"}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_anthropic_stream_to_chat(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-sonnet".to_string(),
+    ));
+    let mut payload = String::new();
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(!payload.contains("event:"));
+    assert!(payload.contains("\"object\":\"chat.completion.chunk\""));
+    assert!(payload.contains("\"role\":\"assistant\""));
+    assert!(payload.contains("\"content\":\"Hi\""));
+    assert!(payload.contains("\"id\":\"toolu_1\""));
+    assert!(payload.contains("\"arguments\":\"{\\\"cmd\\\":\\\"ls\\\"}\""));
+    assert!(payload.contains("\"finish_reason\":\"tool_calls\""));
+    assert!(payload.contains("\"prompt_tokens\":4"));
+    assert!(payload.contains("\"completion_tokens\":6"));
+    assert!(payload.ends_with("data: [DONE]\n\n"));
 }
 
 #[test]
-fn map_includes_system_message_from_instructions() {
+fn build_upstream_payload_maps_responses_to_anthropic_messages() {
     let input = json!({
-        "model": "gpt-4.1",
-        "instructions": "sys",
-        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
-        "tools": []
+        "model": "claude-sonnet",
+        "instructions": "be brief",
+        "max_output_tokens": 2048,
+        "reasoning": {"effort": "medium"},
+        "temperature": 0.2,
+        "parallel_tool_calls": false,
+        "tool_choice": "required",
+        "input": [
+            {"type":"message","role":"developer","content":"use tools"},
+            {"type":"message","role":"user","content":[{"type":"input_text","text":"list files"}]},
+            {"type":"reasoning","summary":[{"type":"summary_text","text":"think"}],"encrypted_content":"anthropic-signature:sig_1"},
+            {"type":"reasoning","summary":[{"type":"summary_text","text":"foreign"}]},
+            {"type":"reasoning","summary":[{"type":"summary_text","text":"encrypted"}],"encrypted_content":"gAAAA-openai-blob"},
+            {"type":"function_call","call_id":"call_1","name":"shell","arguments":"{\"cmd\":\"ls\"}"},
+            {"type":"custom_tool_call","call_id":"call_2","name":"apply_patch","input":"*** Begin Patch"},
+            {"type":"function_call_output","call_id":"call_1","output":"a.txt"},
+            {"type":"custom_tool_call_output","call_id":"call_2","output":"ok"}
+        ],
+        "tools": [
+            {"type":"function","name":"shell","description":"run shell","parameters":{"type":"object","properties":{"cmd":{"type":"string"}}}},
+            {"type":"custom","name":"apply_patch","description":"patch files"},
+            {"type":"web_search"}
+        ]
     });
-    let req = map_responses_to_chat_request_with_stream(
+
+    let out = build_upstream_payload(
         &input,
-        &HashSet::new(),
+        IncomingApi::Responses,
+        WireApi::Messages,
         true,
         true,
         ToolTransformMode::LegacyConvert,
+        false,
     )
     .expect("ok");
-    let messages = req.chat_request["messages"].as_array().expect("array");
-    assert_eq!(messages[0]["role"], "system");
-}
 
-#[test]
-fn normalize_chat_tools_drops_configured_tool_types() {
-    let tools = vec![
-        json!({"type": "web_search_preview"}),
-        json!({"type": "function", "name": "f", "parameters": {"type":"object"}}),
-    ];
-    let mut drop = HashSet::new();
-    drop.insert("web_search_preview".to_string());
-    let out = normalize_chat_tools(tools, &drop, ToolTransformMode::LegacyConvert);
-    assert_eq!(out.len(), 1);
-    assert_eq!(out[0]["type"], "function");
-}
+    assert_eq!(out["system"], "be brief\n\nuse tools");
+    assert_eq!(out["stream"], true);
+    assert_eq!(out["max_tokens"], 2048);
+    assert_eq!(
+        out["thinking"],
+        json!({"type":"enabled","budget_tokens":2047})
+    );
+    assert!(out.get("temperature").is_none());
+    assert_eq!(
+        out["tool_choice"],
+        json!({"type":"any","disable_parallel_tool_use":true})
+    );
 
-#[test]
-fn normalize_chat_tools_passthrough_keeps_custom_tool() {
-    let tools = vec![json!({
-        "type": "custom",
-        "name": "apply_patch",
-        "description": "patch files",
-        "input_schema": {"type":"string"}
-    })];
-    let out = normalize_chat_tools(
-        tools.clone(),
-        &HashSet::new(),
-        ToolTransformMode::Passthrough,
+    let messages = out["messages"].as_array().expect("messages");
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(messages[0]["content"][0]["text"], "list files");
+    assert_eq!(messages[1]["role"], "assistant");
+    assert_eq!(
+        messages[1]["content"],
+        json!([
+            {"type":"thinking","thinking":"think","signature":"sig_1"},
+            {"type":"tool_use","id":"call_1","name":"shell","input":{"cmd":"ls"}},
+            {"type":"tool_use","id":"call_2","name":"apply_patch","input":{"input":"*** Begin Patch"}}
+        ])
     );
-    assert_eq!(out, tools);
+    assert_eq!(messages[2]["role"], "user");
+    assert_eq!(
+        messages[2]["content"],
+        json!([
+            {"type":"tool_result","tool_use_id":"call_1","content":"a.txt"},
+            {"type":"tool_result","tool_use_id":"call_2","content":"ok"}
+        ])
+    );
+
+    let tools = out["tools"].as_array().expect("tools");
+    assert_eq!(tools.len(), 2);
+    assert_eq!(tools[0]["name"], "shell");
+    assert_eq!(tools[0]["description"], "run shell");
+    assert_eq!(
+        tools[0]["input_schema"]["properties"]["cmd"]["type"],
+        "string"
+    );
+    assert_eq!(tools[1]["name"], "apply_patch");
+    assert_eq!(tools[1]["input_schema"]["type"], "object");
 }
 
 #[test]
-fn normalize_tool_choice_passthrough_keeps_custom_choice() {
-    let choice = json!({"type":"custom", "name":"apply_patch"});
+fn build_upstream_payload_keeps_sampling_fields_without_anthropic_thinking() {
+    let out = build_upstream_payload(
+        &json!({
+            "model": "claude-sonnet",
+            "input": "hello",
+            "temperature": 0.4,
+            "tool_choice": {"type":"function","name":"shell"},
+            "tools": [{"type":"function","name":"shell","parameters":{"type":"object"}}]
+        }),
+        IncomingApi::Responses,
+        WireApi::Messages,
+        false,
+        true,
+        ToolTransformMode::LegacyConvert,
+        false,
+    )
+    .expect("ok");
+
+    assert_eq!(out["stream"], false);
+    assert_eq!(out["max_tokens"], 8192);
+    assert_eq!(out["temperature"], 0.4);
+    assert!(out.get("thinking").is_none());
+    assert!(out.get("system").is_none());
+    assert_eq!(out["tool_choice"], json!({"type":"tool","name":"shell"}));
     assert_eq!(
-        normalize_tool_choice(choice.clone(), ToolTransformMode::Passthrough),
-        choice
+        out["messages"],
+        json!([{"role":"user","content":[{"type":"text","text":"hello"}]}])
     );
 }
 
 #[test]
-fn responses_tool_call_item_maps_custom_and_function_types() {
+fn anthropic_json_to_responses_json_maps_blocks_and_usage() {
     let mut kinds = HashMap::new();
-    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
-    kinds.insert("get_weather".to_string(), ResponsesToolCallKind::Function);
+    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
+    let out = anthropic_json_to_responses_json(
+        json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                {"type":"thinking","thinking":"plan","signature":"sig_1"},
+                {"type":"text","text":"done"},
+                {"type":"tool_use","id":"toolu_1","name":"shell","input":{"cmd":"ls"}},
+                {"type":"tool_use","id":"toolu_2","name":"apply_patch","input":{"input":"*** Begin Patch"}}
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 10, "cache_read_input_tokens": 4, "output_tokens": 6}
+        }),
+        "resp_1".to_string(),
+        &kinds,
+    );
 
-    let custom_item = responses_tool_call_item("shell", "ls -al", "call_custom_1", &kinds);
-    assert_eq!(custom_item["type"], "custom_tool_call");
-    assert_eq!(custom_item["call_id"], "call_custom_1");
-    assert_eq!(custom_item["input"], "ls -al");
+    assert_eq!(out["status"], "completed");
+    let output = out["output"].as_array().expect("output");
+    assert_eq!(output[0]["type"], "reasoning");
+    assert_eq!(output[0]["summary"][0]["text"], "plan");
+    assert_eq!(output[0]["encrypted_content"], "anthropic-signature:sig_1");
+    assert_eq!(output[1]["content"][0]["text"], "done");
+    assert_eq!(output[2]["type"], "function_call");
+    assert_eq!(output[2]["call_id"], "toolu_1");
+    assert_eq!(output[2]["arguments"], "{\"cmd\":\"ls\"}");
+    assert_eq!(output[3]["type"], "custom_tool_call");
+    assert_eq!(output[3]["input"], "*** Begin Patch");
+    assert_eq!(out["usage"]["input_tokens"], 14);
+    assert_eq!(out["usage"]["input_tokens_details"]["cached_tokens"], 4);
+    assert_eq!(out["usage"]["output_tokens"], 6);
+    assert_eq!(out["usage"]["total_tokens"], 20);
+}
 
-    let function_item =
-        responses_tool_call_item("get_weather", "{\"city\":\"seoul\"}", "call_fn_1", &kinds);
-    assert_eq!(function_item["type"], "function_call");
-    assert_eq!(function_item["call_id"], "call_fn_1");
-    assert_eq!(function_item["arguments"], "{\"city\":\"seoul\"}");
+#[tokio::test]
+async fn anthropic_stream_to_responses_stream_translates_blocks() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "event: message_start\n\
+         data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n\
+         event: content_block_start\n\
+         data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"thinking\",\"thinking\":\"\"}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"plan\"}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"signature_delta\",\"signature\":\"sig_1\"}}\n\n\
+         event: content_block_stop\n\
+         data: {\"type\":\"content_block_stop\",\"index\":0}\n\n\
+         event: content_block_start\n\
+         data: {\"type\":\"content_block_start\",\"index\":1,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n\
+         event: content_block_stop\n\
+         data: {\"type\":\"content_block_stop\",\"index\":1}\n\n\
+         event: content_block_start\n\
+         data: {\"type\":\"content_block_start\",\"index\":2,\"content_block\":{\"type\":\"tool_use\",\"id\":\"toolu_1\",\"name\":\"shell\",\"input\":{}}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":2,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"{\\\"cmd\\\":\"}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":2,\"delta\":{\"type\":\"input_json_delta\",\"partial_json\":\"\\\"ls\\\"}\"}}\n\n\
+         event: content_block_stop\n\
+         data: {\"type\":\"content_block_stop\",\"index\":2}\n\n\
+         event: message_delta\n\
+         data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"tool_use\"},\"usage\":{\"output_tokens\":9}}\n\n\
+         event: message_stop\n\
+         data: {\"type\":\"message_stop\"}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_anthropic_stream_to_responses(
+        upstream,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        HashMap::new(),
+        FeatureFlags::default(),
+        None,
+    ));
+    let mut payload = String::new();
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.starts_with("event: response.created\n"));
+    assert!(payload.contains("event: response.reasoning_summary_text.delta"));
+    assert!(payload.contains("\"encrypted_content\":\"anthropic-signature:sig_1\""));
+    assert!(payload.contains("\"type\":\"response.output_text.delta\""));
+    assert!(payload.contains("\"delta\":\"Hi\""));
+    assert!(payload.contains("event: response.function_call_arguments.delta"));
+    assert!(payload.contains("\"arguments\":\"{\\\"cmd\\\":\\\"ls\\\"}\""));
+    assert!(payload.contains("\"call_id\":\"toolu_1\""));
+    let completed = SseParser::default()
+        .feed(&payload)
+        .into_iter()
+        .filter_map(|data| serde_json::from_str::<Value>(&data).ok())
+        .find(|event| event["type"] == "response.completed")
+        .expect("response.completed event");
+    let output_types = completed["response"]["output"]
+        .as_array()
+        .expect("completed output")
+        .iter()
+        .map(|item| item["type"].as_str().unwrap_or_default())
+        .collect::<Vec<_>>();
+    assert_eq!(output_types, ["reasoning", "message", "function_call"]);
+    assert!(payload.contains("\"input_tokens\":12"));
+    assert!(payload.contains("\"output_tokens\":9"));
+    assert!(!payload.contains("response.failed"));
 }
 
 #[tokio::test]
-async fn stream_emits_output_item_added_before_text_delta() {
+async fn anthropic_stream_to_responses_stream_fails_when_upstream_stops_early() {
     let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n\
-             data: [DONE]\n\n",
+        "event: message_start\n\
+         data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\"}}\n\n\
+         event: content_block_start\n\
+         data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
     ))]);
-    let mut output = Box::pin(translate_chat_stream(
+    let mut output = Box::pin(translate_anthropic_stream_to_responses(
         upstream,
         "resp_1".to_string(),
         "test_router".to_string(),
         false,
         HashMap::new(),
         FeatureFlags::default(),
+        None,
     ));
     let mut payload = String::new();
-
     while let Some(event) = output.next().await {
         payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
     }
 
-    let added_idx = payload
-        .find("event: response.output_item.added")
-        .expect("added event");
-    let delta_idx = payload
-        .find("event: response.output_text.delta")
-        .expect("delta event");
-    assert!(added_idx < delta_idx);
-    assert!(payload.contains("event: response.in_progress"));
-    assert!(payload.contains("event: response.content_part.added"));
-    assert!(payload.contains("event: response.output_text.done"));
-    assert!(payload.contains("event: response.content_part.done"));
+    assert!(payload.contains("event: response.failed"));
+    assert!(payload.contains("\"code\":\"upstream_stream_incomplete\""));
+    assert!(!payload.contains("response.completed"));
 }
 
 #[tokio::test]
-async fn stream_maps_custom_tool_call_and_preserves_call_id() {
+async fn anthropic_stream_to_responses_stream_reports_max_tokens_as_incomplete() {
     let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_custom_1\",\"function\":{\"name\":\"shell\",\"arguments\":\"echo hello\"}}]}}]}\n\n\
-             data: [DONE]\n\n",
+        "event: message_start\n\
+         data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":5}}}\n\n\
+         event: content_block_start\n\
+         data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"cut\"}}\n\n\
+         event: message_delta\n\
+         data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"max_tokens\"},\"usage\":{\"output_tokens\":1}}\n\n\
+         event: message_stop\n\
+         data: {\"type\":\"message_stop\"}\n\n",
     ))]);
-    let mut kinds = HashMap::new();
-    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
-    let mut output = Box::pin(translate_chat_stream(
+    let mut output = Box::pin(translate_anthropic_stream_to_responses(
         upstream,
         "resp_1".to_string(),
         "test_router".to_string(),
         false,
-        kinds,
+        HashMap::new(),
         FeatureFlags::default(),
+        None,
     ));
     let mut payload = String::new();
-
     while let Some(event) = output.next().await {
         payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
     }
 
-    assert!(payload.contains("\"type\":\"custom_tool_call\""));
-    assert!(payload.contains("\"call_id\":\"call_custom_1\""));
-    assert!(payload.contains("\"input\":\"echo hello\""));
+    assert!(!payload.contains("response.completed"));
+    let incomplete = SseParser::default()
+        .feed(&payload)
+        .into_iter()
+        .filter_map(|data| serde_json::from_str::<Value>(&data).ok())
+        .find(|event| event["type"] == "response.incomplete")
+        .expect("response.incomplete event");
+    assert_eq!(incomplete["response"]["status"], "incomplete");
+    assert_eq!(
+        incomplete["response"]["incomplete_details"]["reason"],
+        "max_output_tokens"
+    );
+    assert_eq!(
+        incomplete["response"]["output"],
+        json!([{
+            "type": "message",
+            "id": "msg_resp_1_0",
+            "role": "assistant",
+            "content": [{"type": "output_text", "text": "cut"}]
+        }])
+    );
+}
+
+#[tokio::test]
+async fn anthropic_stream_to_responses_stores_only_completed_turns() {
+    let sessions = Arc::new(tokio::sync::RwLock::new(SessionStore::default()));
+    let session_turn = |response_id: &str| {
+        Some(SessionTurn::new(
+            sessions.clone(),
+            "default".to_string(),
+            response_id.to_string(),
+            "claude-test".to_string(),
+            vec![json!({"role":"user","content":"hi"})],
+        ))
+    };
+    let failed = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "event: error\n\
+         data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"busy\"}}\n\n",
+    ))]);
+    let payload = collect_stream_text(translate_anthropic_stream_to_responses(
+        failed,
+        "resp_failed".to_string(),
+        "test_router".to_string(),
+        false,
+        HashMap::new(),
+        FeatureFlags::default(),
+        session_turn("resp_failed"),
+    ))
+    .await;
+    assert!(payload.contains("event: response.failed"));
+    assert!(sessions.read().await.get_messages("resp_failed").is_none());
+
+    let completed = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "event: message_start\n\
+         data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":5}}}\n\n\
+         event: content_block_start\n\
+         data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n\
+         event: content_block_delta\n\
+         data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"hello\"}}\n\n\
+         event: content_block_stop\n\
+         data: {\"type\":\"content_block_stop\",\"index\":0}\n\n\
+         event: message_delta\n\
+         data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":1}}\n\n\
+         event: message_stop\n\
+         data: {\"type\":\"message_stop\"}\n\n",
+    ))]);
+    let payload = collect_stream_text(translate_anthropic_stream_to_responses(
+        completed,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        HashMap::new(),
+        FeatureFlags::default(),
+        session_turn("resp_1"),
+    ))
+    .await;
+    assert!(payload.contains("event: response.completed"));
+    let messages = sessions
+        .read()
+        .await
+        .get_messages("resp_1")
+        .expect("stored turn");
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1]["role"], "assistant");
+    assert_eq!(messages[1]["content"], "hello");
+    let record = sessions.read().await.get_session("resp_1").expect("record");
+    let response = record.response.as_ref().expect("stored response");
+    assert_eq!(response["usage"]["input_tokens"], 5);
 }
 
 #[test]
-fn chat_json_to_responses_json_maps_custom_tool_call_type() {
-    let chat = json!({
-        "choices": [{
-            "message": {
-                "tool_calls": [{
-                    "id": "call_custom_1",
-                    "function": {
-                        "name": "shell",
-                        "arguments": "echo hello"
-                    }
-                }]
+fn map_anthropic_messages_to_chat_request_normalizes_empty_additional_properties() {
+    let input = json!({
+        "model": "claude-sonnet",
+        "messages": [{"role":"user","content":[{"type":"text","text":"hi"}]}],
+        "tools": [
+            {
+                "name": "ExitPlanMode",
+                "description": "exit plan mode",
+                "input_schema": {
+                    "type": "object",
+                    "properties": {},
+                    "additionalProperties": {}
+                }
             }
-        }]
+        ]
     });
-    let mut kinds = HashMap::new();
-    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
-    let out = chat_json_to_responses_json(chat, "resp_1".to_string(), &kinds, false);
-    let output = out["output"].as_array().expect("output array");
-    assert_eq!(output[0]["type"], "custom_tool_call");
-    assert_eq!(output[0]["call_id"], "call_custom_1");
-    assert_eq!(output[0]["input"], "echo hello");
+
+    let out = map_anthropic_messages_to_chat_request(&input, false).expect("ok");
+    let parameters = &out["tools"][0]["function"]["parameters"];
+    assert_eq!(parameters["required"], json!([]));
+    assert_eq!(parameters["additionalProperties"], false);
 }
 
 #[test]
-fn chat_json_to_responses_json_repairs_invalid_function_tool_arguments() {
-    let chat = json!({
-        "choices": [{
-            "message": {
-                "tool_calls": [{
-                    "id": "call_1",
-                    "function": {
-                        "name": "exec_command",
-                        "arguments": "{"
-                    }
-                }]
+fn map_anthropic_messages_to_chat_request_normalizes_null_required() {
+    let input = json!({
+        "model": "claude-sonnet",
+        "messages": [{"role":"user","content":[{"type":"text","text":"hi"}]}],
+        "tools": [
+            {
+                "name": "shell",
+                "description": "run shell",
+                "input_schema": {
+                    "type": "object",
+                    "properties": {},
+                    "required": null
+                }
             }
-        }]
+        ]
     });
-    let mut kinds = HashMap::new();
-    kinds.insert("exec_command".to_string(), ResponsesToolCallKind::Function);
-    let out = chat_json_to_responses_json(chat, "resp_1".to_string(), &kinds, false);
-    let output = out["output"].as_array().expect("output array");
-    assert_eq!(output[0]["type"], "function_call");
-    assert_eq!(output[0]["call_id"], "call_1");
-    assert_eq!(output[0]["name"], "exec_command");
-    assert_eq!(output[0]["arguments"], "{}");
+
+    let out = map_anthropic_messages_to_chat_request(&input, false).expect("ok");
+    assert_eq!(
+        out["tools"][0]["function"]["parameters"]["required"],
+        json!([])
+    );
 }
 
 #[test]
-fn chat_json_to_responses_json_unwraps_custom_tool_call_json_string_arguments() {
-    let chat = json!({
-        "choices": [{
-            "message": {
-                "tool_calls": [{
-                    "id": "call_custom_2",
-                    "function": {
-                        "name": "apply_patch",
-                        "arguments": "\"*** Begin Patch\\n*** End Patch\""
-                    }
-                }]
+fn map_anthropic_messages_to_chat_request_preserves_thinking_in_content_when_enabled() {
+    let input = json!({
+        "model": "claude-sonnet",
+        "messages": [
+            {
+                "role": "assistant",
+                "content": [
+                    {"type":"thinking","thinking":"internal chain"},
+                    {"type":"text","text":"hello"}
+                ]
             }
-        }]
+        ]
     });
-    let mut kinds = HashMap::new();
-    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
-    let out = chat_json_to_responses_json(chat, "resp_2".to_string(), &kinds, false);
-    let output = out["output"].as_array().expect("output");
-    assert_eq!(output[0]["type"], "custom_tool_call");
-    assert_eq!(output[0]["input"], "*** Begin Patch\n*** End Patch");
+
+    let out = map_anthropic_messages_to_chat_request(&input, true).expect("ok");
+    let messages = out["messages"].as_array().expect("messages");
+    assert_eq!(messages[0]["reasoning_content"], "internal chain");
+    assert_eq!(
+        messages[0]["content"],
+        "<thinking>\ninternal chain\n</thinking>\n\nhello"
+    );
 }
 
 #[test]
-fn chat_json_to_responses_json_unwraps_custom_tool_call_input_field_arguments() {
-    let chat = json!({
-        "choices": [{
-            "message": {
-                "tool_calls": [{
-                    "id": "call_custom_3",
-                    "function": {
-                        "name": "apply_patch",
-                        "arguments": "{\"input\":\"*** Begin Patch\\n*** End Patch\"}"
-                    }
-                }]
+fn map_anthropic_messages_to_chat_request_preserves_thinking_without_text_for_tool_calls() {
+    let input = json!({
+        "model": "claude-sonnet",
+        "messages": [
+            {
+                "role": "assistant",
+                "content": [
+                    {"type":"thinking","thinking":"internal chain"},
+                    {"type":"tool_use","id":"tool_1","name":"shell","input":{"cmd":"pwd"}}
+                ]
             }
-        }]
+        ]
     });
-    let mut kinds = HashMap::new();
-    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
-    let out = chat_json_to_responses_json(chat, "resp_3".to_string(), &kinds, false);
-    let output = out["output"].as_array().expect("output");
-    assert_eq!(output[0]["type"], "custom_tool_call");
-    assert_eq!(output[0]["input"], "*** Begin Patch\n*** End Patch");
+
+    let out = map_anthropic_messages_to_chat_request(&input, true).expect("ok");
+    let messages = out["messages"].as_array().expect("messages");
+    assert_eq!(
+        messages[0]["content"],
+        "<thinking>\ninternal chain\n</thinking>"
+    );
+    assert_eq!(messages[0]["tool_calls"][0]["id"], "tool_1");
+    assert_eq!(messages[0]["tool_calls"][0]["function"]["name"], "shell");
 }
 
 #[test]
-fn chat_json_to_responses_json_unwraps_custom_tool_call_patch_field_arguments() {
-    let chat = json!({
+fn chat_json_to_anthropic_json_maps_tool_calls() {
+    let input = json!({
+        "id": "chatcmpl_1",
+        "model": "gpt-4.1",
         "choices": [{
+            "finish_reason": "tool_calls",
             "message": {
+                "content": "hello",
+                "reasoning_content": "thinking",
                 "tool_calls": [{
-                    "id": "call_custom_4",
+                    "id": "call_1",
                     "function": {
-                        "name": "apply_patch",
-                        "arguments": "{\"patch\":\"*** Begin Patch\\n*** End Patch\"}"
+                        "name": "shell",
+                        "arguments": "{\"cmd\":\"pwd\"}"
                     }
                 }]
             }
-        }]
+        }],
+        "usage": {
+            "prompt_tokens": 10,
+            "completion_tokens": 5
+        }
     });
-    let mut kinds = HashMap::new();
-    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
-    let out = chat_json_to_responses_json(chat, "resp_4".to_string(), &kinds, false);
-    let output = out["output"].as_array().expect("output");
-    assert_eq!(output[0]["type"], "custom_tool_call");
-    assert_eq!(output[0]["input"], "*** Begin Patch\n*** End Patch");
-}
-
-#[test]
-fn normalize_apply_patch_input_strips_code_fence_and_text() {
-    let raw = "Here is the patch:\n```patch\n*** Begin Patch\n*** Update File: a.txt\n@@\n-old   \n+new   \n*** End Patch\n```\n";
-    assert_eq!(
-        normalize_apply_patch_input_with_repairs(raw).normalized,
-        "*** Begin Patch\n*** Update File: a.txt\n@@\n-old\n+new\n*** End Patch"
-    );
-}
-
-#[test]
-fn normalize_apply_patch_input_repairs_add_file_lines_without_prefix() {
-    let raw = "*** Begin Patch\n*** Add File: hello.txt\nhello\n\nworld\n*** End Patch";
-    assert_eq!(
-        normalize_apply_patch_input_with_repairs(raw).normalized,
-        "*** Begin Patch\n*** Add File: hello.txt\n+hello\n+\n+world\n*** End Patch"
-    );
-}
-
-#[test]
-fn normalize_apply_patch_input_repairs_mixed_add_file_lines() {
-    let raw = "*** Begin Patch\n*** Add File: hello.txt\n+hello\n world\n*** Update File: a.txt\n@@\n-old\n+new\n*** End Patch";
-    assert_eq!(
-        normalize_apply_patch_input_with_repairs(raw).normalized,
-        "*** Begin Patch\n*** Add File: hello.txt\n+hello\n+ world\n*** Update File: a.txt\n@@\n-old\n+new\n*** End Patch"
-    );
-}
 
-#[test]
-fn normalize_apply_patch_input_reports_repairs() {
-    let raw = "*** Begin Patch\n*** Add File: hello.txt\nhello\n\n*** End Patch";
-    let normalized = normalize_apply_patch_input_with_repairs(raw);
-    assert_eq!(
-        normalized.normalized,
-        "*** Begin Patch\n*** Add File: hello.txt\n+hello\n+\n*** End Patch"
-    );
-    assert_eq!(
-        normalized.repairs,
-        vec![
-            "added missing '+' prefix for add-file content line (hello.txt:1)",
-            "added missing '+' prefix for blank add-file line (hello.txt:2)",
-        ]
-    );
+    let out = chat_json_to_anthropic_json(input, "claude-bridge");
+    assert_eq!(out["type"], "message");
+    assert_eq!(out["stop_reason"], "tool_use");
+    assert_eq!(out["content"][0]["type"], "thinking");
+    assert_eq!(out["content"][1]["type"], "text");
+    assert_eq!(out["content"][2]["type"], "tool_use");
+    assert_eq!(out["content"][2]["input"]["cmd"], "pwd");
 }
 
 #[test]
-fn chat_json_to_responses_json_normalizes_apply_patch_arguments() {
-    let chat = json!({
+fn chat_json_to_anthropic_json_keeps_think_tags_as_text() {
+    let input = json!({
         "choices": [{
             "message": {
-                "tool_calls": [{
-                    "id": "call_custom_5",
-                    "function": {
-                        "name": "apply_patch",
-                        "arguments": "{\"input\":\"Patch follows\\n```patch\\n*** Begin Patch\\n*** Update File: a.txt\\n@@\\n-old   \\n+new   \\n*** End Patch\\n```\"}"
-                    }
-                }]
+                "content": "before <think>internal</think> after"
             }
         }]
     });
-    let mut kinds = HashMap::new();
-    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
-    let out = chat_json_to_responses_json(chat, "resp_5".to_string(), &kinds, false);
-    let output = out["output"].as_array().expect("output");
+
+    let out = chat_json_to_anthropic_json(input, "claude-bridge");
+    assert_eq!(out["content"][0]["type"], "text");
     assert_eq!(
-        output[0]["input"],
-        "*** Begin Patch\n*** Update File: a.txt\n@@\n-old\n+new\n*** End Patch"
+        out["content"][0]["text"],
+        "before <think>internal</think> after"
     );
+    assert!(out.get("stop_sequence").is_none());
 }
 
 #[test]
-fn chat_json_to_responses_json_keeps_non_apply_patch_custom_input_unchanged() {
-    let chat = json!({
+fn chat_json_to_anthropic_json_ignores_provider_specific_thinking_blocks() {
+    let input = json!({
         "choices": [{
             "message": {
-                "tool_calls": [{
-                    "id": "call_custom_6",
-                    "function": {
-                        "name": "shell",
-                        "arguments": "{\"input\":\"```bash\\necho hello\\n```\"}"
+                "thinking_blocks": [
+                    {
+                        "type": "thinking",
+                        "thinking": "step one",
+                        "signature": "sig_1"
+                    },
+                    {
+                        "type": "redacted_thinking",
+                        "data": "redacted_payload"
                     }
-                }]
+                ]
             }
         }]
     });
-    let mut kinds = HashMap::new();
-    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
-    let out = chat_json_to_responses_json(chat, "resp_6".to_string(), &kinds, false);
-    let output = out["output"].as_array().expect("output");
-    assert_eq!(output[0]["input"], "```bash\necho hello\n```");
+
+    let out = chat_json_to_anthropic_json(input, "claude-bridge");
+    assert_eq!(out["content"].as_array().unwrap().len(), 0);
+    assert!(out.get("stop_reason").is_none());
+    assert!(out.get("stop_sequence").is_none());
 }
 
 #[test]
-fn chat_json_to_responses_json_maps_length_finish_reason_to_incomplete() {
-    let chat = json!({
+fn chat_json_to_anthropic_json_maps_unknown_finish_reason_to_stop_sequence() {
+    let input = json!({
         "choices": [{
-            "finish_reason": "length",
-            "message": {
-                "content": "partial output"
-            }
+            "finish_reason": "content_filter",
+            "message": {"content": "filtered"}
         }]
     });
-    let out = chat_json_to_responses_json(chat, "resp_2".to_string(), &HashMap::new(), false);
-    assert_eq!(out["status"], "incomplete");
+
+    let out = chat_json_to_anthropic_json(input, "claude-bridge");
+    assert_eq!(out["stop_reason"], "stop_sequence");
+    assert!(out.get("stop_sequence").is_none());
 }
 
 #[test]
-fn chat_json_to_responses_json_preserves_provider_specific_fields() {
-    let chat = json!({
-        "provider_specific_fields": {
-            "mcp_list_tools": [{"name":"shell"}]
-        },
+fn chat_json_to_anthropic_json_omits_empty_finish_reason() {
+    let input = json!({
         "choices": [{
-            "message": {
-                "tool_calls": [{
-                    "id": "call_fn_1",
-                    "provider_specific_fields": {"source":"provider"},
-                    "function": {
-                        "name": "get_weather",
-                        "arguments": "{\"city\":\"seoul\"}"
-                    }
-                }]
-            }
+            "finish_reason": "",
+            "message": {"content": "hello"}
         }]
     });
-    let out = chat_json_to_responses_json(chat, "resp_3".to_string(), &HashMap::new(), true);
+
+    let out = chat_json_to_anthropic_json(input, "claude-bridge");
+    assert!(out.get("stop_reason").is_none());
+    assert!(out.get("stop_sequence").is_none());
+}
+
+#[test]
+fn normalize_chat_tools_converts_custom_tool_to_function() {
+    let tools = vec![json!({
+        "type": "custom",
+        "name": "shell",
+        "description": "run shell",
+        "input_schema": {"type":"object","properties":{"cmd":{"type":"string"}}}
+    })];
+    let out = normalize_chat_tools(tools, &HashSet::new(), ToolTransformMode::LegacyConvert);
     assert_eq!(
-        out["provider_specific_fields"]["mcp_list_tools"][0]["name"],
-        "shell"
+        out[0],
+        json!({
+            "type": "function",
+            "function": {
+                "name": "shell",
+                "description": "run shell",
+                "parameters": {"type":"object","properties":{"cmd":{"type":"string"}},"required":[]}
+            }
+        })
+    );
+}
+
+#[test]
+fn normalize_chat_tools_custom_without_schema_requires_input_string() {
+    let tools = vec![json!({
+        "type": "custom",
+        "name": "apply_patch",
+        "description": "patch files"
+    })];
+    let out = normalize_chat_tools(tools, &HashSet::new(), ToolTransformMode::LegacyConvert);
+    assert_eq!(out[0]["type"], "function");
+    assert_eq!(out[0]["function"]["parameters"]["type"], "object");
+    assert_eq!(
+        out[0]["function"]["parameters"]["required"],
+        json!(["input"])
     );
     assert_eq!(
-        out["output"][0]["provider_specific_fields"]["source"],
-        "provider"
+        out[0]["function"]["parameters"]["properties"]["input"]["type"],
+        "string"
     );
 }
 
 #[test]
-fn responses_json_to_chat_json_maps_text_tool_calls_and_usage() {
-    let response = json!({
-        "id": "resp_1",
-        "model": "gpt-4.1",
-        "status": "completed",
-        "output": [
-            {
-                "type": "message",
-                "role": "assistant",
-                "content": [{"type":"output_text","text":"hello"}]
-            },
-            {
-                "type": "function_call",
-                "call_id": "call_1",
-                "name": "get_weather",
-                "arguments": "{\"city\":\"seoul\"}"
-            }
-        ],
-        "usage": {
-            "input_tokens": 3,
-            "output_tokens": 5,
-            "total_tokens": 8
-        }
-    });
-
-    let out = responses_json_to_chat_json(response, "fallback-model");
+fn normalize_chat_tools_custom_string_schema_wraps_as_input_object() {
+    let tools = vec![json!({
+        "type": "custom",
+        "name": "apply_patch",
+        "input_schema": {"type":"string"}
+    })];
+    let out = normalize_chat_tools(tools, &HashSet::new(), ToolTransformMode::LegacyConvert);
+    assert_eq!(out[0]["type"], "function");
+    assert_eq!(out[0]["function"]["parameters"]["type"], "object");
+    assert_eq!(
+        out[0]["function"]["parameters"]["properties"]["input"]["type"],
+        "string"
+    );
+}
 
-    assert_eq!(out["object"], "chat.completion");
-    assert_eq!(out["id"], "resp_1");
-    assert_eq!(out["model"], "gpt-4.1");
-    assert_eq!(out["choices"][0]["message"]["role"], "assistant");
-    assert_eq!(out["choices"][0]["message"]["content"], "hello");
-    assert_eq!(out["choices"][0]["finish_reason"], "tool_calls");
+#[test]
+fn normalize_tool_choice_preserves_wrapped_choice() {
+    let choice = json!({"type":"function", "function":{"name":"do_it"}});
     assert_eq!(
-        out["choices"][0]["message"]["tool_calls"][0]["function"]["name"],
-        "get_weather"
+        normalize_tool_choice(choice.clone(), ToolTransformMode::LegacyConvert),
+        choice
     );
+}
+
+#[test]
+fn normalize_tool_choice_converts_custom_name() {
+    let choice = json!({"type":"custom", "name":"shell"});
     assert_eq!(
-        out["choices"][0]["message"]["tool_calls"][0]["function"]["arguments"],
-        "{\"city\":\"seoul\"}"
+        normalize_tool_choice(choice, ToolTransformMode::LegacyConvert),
+        json!({"type":"function","function":{"name":"shell"}})
     );
-    assert_eq!(out["usage"]["prompt_tokens"], 3);
-    assert_eq!(out["usage"]["completion_tokens"], 5);
-    assert_eq!(out["usage"]["total_tokens"], 8);
 }
 
-#[tokio::test]
-async fn responses_stream_to_chat_stream_emits_data_only_chunks_and_done() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "event: response.output_text.delta\n\
-         data: {\"type\":\"response.output_text.delta\",\"delta\":\"Hi\"}\n\n\
-         event: response.output_item.added\n\
-         data: {\"type\":\"response.output_item.added\",\"output_index\":1,\"item\":{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell\",\"arguments\":\"\"}}\n\n\
-         event: response.function_call_arguments.delta\n\
-         data: {\"type\":\"response.function_call_arguments.delta\",\"item_id\":\"call_1\",\"output_index\":1,\"delta\":\"{\\\"cmd\\\":\"}\n\n\
-         event: response.function_call_arguments.delta\n\
-         data: {\"type\":\"response.function_call_arguments.delta\",\"item_id\":\"call_1\",\"output_index\":1,\"delta\":\"\\\"ls\\\"}\"}\n\n\
-         event: response.completed\n\
-         data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"status\":\"completed\",\"usage\":{\"input_tokens\":1,\"output_tokens\":2,\"total_tokens\":3}}}\n\n",
+#[test]
+fn function_output_to_text_for_object_uses_json() {
+    let value = json!({"ok": true});
+    assert_eq!(function_output_to_text(&value), "{\"ok\":true}");
+}
+
+#[test]
+fn map_includes_system_message_from_instructions() {
+    let input = json!({
+        "model": "gpt-4.1",
+        "instructions": "sys",
+        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
+        "tools": []
+    });
+    let req = map_responses_to_chat_request_with_stream(
+        &input,
+        &HashSet::new(),
+        true,
+        true,
+        ToolTransformMode::LegacyConvert,
+    )
+    .expect("ok");
+    let messages = req.chat_request["messages"].as_array().expect("array");
+    assert_eq!(messages[0]["role"], "system");
+}
+
+#[test]
+fn normalize_chat_tools_drops_configured_tool_types() {
+    let tools = vec![
+        json!({"type": "web_search_preview"}),
+        json!({"type": "function", "name": "f", "parameters": {"type":"object"}}),
+    ];
+    let mut drop = HashSet::new();
+    drop.insert("web_search_preview".to_string());
+    let out = normalize_chat_tools(tools, &drop, ToolTransformMode::LegacyConvert);
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["type"], "function");
+}
+
+#[test]
+fn normalize_chat_tools_passthrough_keeps_custom_tool() {
+    let tools = vec![json!({
+        "type": "custom",
+        "name": "apply_patch",
+        "description": "patch files",
+        "input_schema": {"type":"string"}
+    })];
+    let out = normalize_chat_tools(
+        tools.clone(),
+        &HashSet::new(),
+        ToolTransformMode::Passthrough,
+    );
+    assert_eq!(out, tools);
+}
+
+#[test]
+fn normalize_tool_choice_passthrough_keeps_custom_choice() {
+    let choice = json!({"type":"custom", "name":"apply_patch"});
+    assert_eq!(
+        normalize_tool_choice(choice.clone(), ToolTransformMode::Passthrough),
+        choice
+    );
+}
+
+#[test]
+fn responses_tool_call_item_maps_custom_and_function_types() {
+    let mut kinds = HashMap::new();
+    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
+    kinds.insert("get_weather".to_string(), ResponsesToolCallKind::Function);
+
+    let custom_item = responses_tool_call_item("shell", "ls -al", "call_custom_1", &kinds);
+    assert_eq!(custom_item["type"], "custom_tool_call");
+    assert_eq!(custom_item["call_id"], "call_custom_1");
+    assert_eq!(custom_item["input"], "ls -al");
+
+    let function_item =
+        responses_tool_call_item("get_weather", "{\"city\":\"seoul\"}", "call_fn_1", &kinds);
+    assert_eq!(function_item["type"], "function_call");
+    assert_eq!(function_item["call_id"], "call_fn_1");
+    assert_eq!(function_item["arguments"], "{\"city\":\"seoul\"}");
+}
+
+#[tokio::test]
+async fn stream_emits_output_item_added_before_text_delta() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n\
+             data: [DONE]\n\n",
     ))]);
-    let mut output = Box::pin(translate_responses_stream_to_chat(
+    let mut output = Box::pin(translate_chat_stream(
         upstream,
+        "resp_1".to_string(),
         "test_router".to_string(),
         false,
-        "gpt-4.1".to_string(),
+        HashMap::new(),
+        FeatureFlags::default(),
+        None,
     ));
     let mut payload = String::new();
 
@@ -1972,756 +2552,936 @@ async fn responses_stream_to_chat_stream_emits_data_only_chunks_and_done() {
         payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
     }
 
-    assert!(!payload.contains("event:"));
-    assert!(payload.contains("\"object\":\"chat.completion.chunk\""));
-    assert!(payload.contains("\"content\":\"Hi\""));
-    assert!(payload.contains("\"tool_calls\""));
-    assert!(payload.contains("\"arguments\":\"{\\\"cmd\\\":\""));
-    assert!(payload.contains("\"arguments\":\"\\\"ls\\\"}\""));
-    assert!(payload.contains("\"finish_reason\":\"tool_calls\""));
-    assert!(payload.contains("\"prompt_tokens\":1"));
-    assert!(payload.ends_with("data: [DONE]\n\n"));
+    let added_idx = payload
+        .find("event: response.output_item.added")
+        .expect("added event");
+    let delta_idx = payload
+        .find("event: response.output_text.delta")
+        .expect("delta event");
+    assert!(added_idx < delta_idx);
+    assert!(payload.contains("event: response.in_progress"));
+    assert!(payload.contains("event: response.content_part.added"));
+    assert!(payload.contains("event: response.output_text.done"));
+    assert!(payload.contains("event: response.content_part.done"));
 }
 
-#[test]
-fn resolve_config_prefers_cli_over_file_and_defaults() {
-    let args = Args {
-        config: None,
-        upstream_url: None,
-        upstream_wire: None,
-        upstream_http_headers: vec![
-            parse_upstream_http_header_arg("x-trace-id=cli").expect("valid header"),
-        ],
-        forward_incoming_headers: vec![],
-        api_key_env: Some("CLI_API_KEY".to_string()),
-        server_info: None,
-        http_shutdown: true,
-        verbose_logging: false,
-        drop_tool_types: vec![],
-        router: None,
-        list_routers: false,
-    };
-    let file = FileConfig {
-        upstream_url: Some("https://example.com/v1/chat/completions".to_string()),
-        upstream_wire: None,
-        upstream_http_headers: Some(BTreeMap::from([(
-            "x-trace-id".to_string(),
-            "file".to_string(),
-        )])),
-        forward_incoming_headers: None,
-        api_key_env: Some("FILE_API_KEY".to_string()),
-        server_info: Some(PathBuf::from("/tmp/server.json")),
-        http_shutdown: Some(false),
-        verbose_logging: Some(true),
-        drop_tool_types: None,
-        drop_request_fields: None,
-        features: None,
-        routers: None,
-    };
+#[tokio::test]
+async fn stream_maps_custom_tool_call_and_preserves_call_id() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_custom_1\",\"function\":{\"name\":\"shell\",\"arguments\":\"echo hello\"}}]}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut kinds = HashMap::new();
+    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
+    let mut output = Box::pin(translate_chat_stream(
+        upstream,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        kinds,
+        FeatureFlags::default(),
+        None,
+    ));
+    let mut payload = String::new();
 
-    let resolved = resolve_config(args, Some(file)).expect("ok");
-    assert_eq!(
-        resolved.upstream_url,
-        "https://example.com/v1/chat/completions"
-    );
-    assert_eq!(resolved.upstream_http_headers.len(), 1);
-    assert_eq!(resolved.upstream_http_headers[0].name, "x-trace-id");
-    assert_eq!(resolved.upstream_http_headers[0].value, "cli");
-    assert_eq!(resolved.api_key_env, "CLI_API_KEY");
-    assert_eq!(
-        resolved.server_info,
-        Some(PathBuf::from("/tmp/server.json"))
-    );
-    assert!(resolved.http_shutdown);
-    assert!(resolved.verbose_logging);
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"type\":\"custom_tool_call\""));
+    assert!(payload.contains("\"call_id\":\"call_custom_1\""));
+    assert!(payload.contains("\"input\":\"echo hello\""));
 }
 
-#[test]
-fn resolve_config_uses_defaults_when_missing() {
-    let args = Args {
-        config: None,
-        upstream_url: None,
-        upstream_wire: None,
-        upstream_http_headers: vec![],
-        forward_incoming_headers: vec![],
-        api_key_env: None,
-        server_info: None,
-        http_shutdown: false,
-        verbose_logging: false,
-        drop_tool_types: vec![],
-        router: None,
-        list_routers: false,
-    };
+#[tokio::test]
+async fn stream_records_completed_assistant_turn_in_session_store() {
+    let sessions = Arc::new(tokio::sync::RwLock::new(SessionStore::default()));
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"think\",\"content\":\"Checking\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"shell\",\"arguments\":\"{\\\"cmd\\\":\\\"ls\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let payload = collect_stream_text(translate_chat_stream(
+        upstream,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        HashMap::new(),
+        FeatureFlags::default(),
+        Some(SessionTurn::new(
+            sessions.clone(),
+            "default".to_string(),
+            "resp_1".to_string(),
+            "gpt-test".to_string(),
+            vec![json!({"role":"user","content":"list files"})],
+        )),
+    ))
+    .await;
+    assert!(payload.contains("event: response.completed"));
 
-    let resolved = resolve_config(args, None).expect("ok");
+    let messages = sessions
+        .read()
+        .await
+        .get_messages("resp_1")
+        .expect("stored turn");
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["content"], "list files");
+    assert_eq!(messages[1]["role"], "assistant");
+    assert_eq!(messages[1]["content"], "Checking");
+    assert_eq!(messages[1]["reasoning_content"], "think");
+    assert_eq!(messages[1]["tool_calls"][0]["id"], "call_1");
     assert_eq!(
-        resolved.upstream_url,
-        "https://api.openai.com/v1/chat/completions"
+        messages[1]["tool_calls"][0]["function"]["arguments"],
+        "{\"cmd\":\"ls\"}"
     );
-    assert_eq!(resolved.api_key_env, "OPENAI_API_KEY");
-    assert_eq!(resolved.server_info, None);
-    assert!(!resolved.http_shutdown);
-    assert!(!resolved.verbose_logging);
+
+    let record = sessions.read().await.get_session("resp_1").expect("record");
+    let response = record.response.as_ref().expect("stored response");
+    assert_eq!(response["id"], "resp_1");
+    assert_eq!(response["status"], "completed");
+    assert_eq!(response["model"], "gpt-test");
+    assert_eq!(response["output"][0]["type"], "message");
+    assert_eq!(record.input_messages().len(), 1);
 }
 
-#[test]
-fn resolve_config_path_prefers_cli_value() {
-    let path = resolve_config_path(Some(PathBuf::from("/tmp/custom.toml"))).expect("ok");
-    assert_eq!(path, PathBuf::from("/tmp/custom.toml"));
+#[tokio::test]
+async fn stream_does_not_record_failed_turn_in_session_store() {
+    let sessions = Arc::new(tokio::sync::RwLock::new(SessionStore::default()));
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"partial\"}}]}\n\n",
+    ))]);
+    let payload = collect_stream_text(translate_chat_stream(
+        upstream,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        HashMap::new(),
+        FeatureFlags::default(),
+        Some(SessionTurn::new(
+            sessions.clone(),
+            "default".to_string(),
+            "resp_1".to_string(),
+            "gpt-test".to_string(),
+            vec![json!({"role":"user","content":"hi"})],
+        )),
+    ))
+    .await;
+    assert!(payload.contains("event: response.failed"));
+    assert!(sessions.read().await.get_messages("resp_1").is_none());
 }
 
 #[test]
-fn resolve_config_defaults_upstream_url_by_wire() {
-    let args = Args {
-        config: None,
-        upstream_url: None,
-        upstream_wire: Some(WireApi::Responses),
-        upstream_http_headers: vec![],
-        forward_incoming_headers: vec![],
-        api_key_env: None,
-        server_info: None,
-        http_shutdown: false,
-        verbose_logging: false,
-        drop_tool_types: vec![],
-        router: None,
-        list_routers: false,
-    };
-
-    let resolved = resolve_config(args, None).expect("ok");
-    assert_eq!(resolved.upstream_wire, WireApi::Responses);
-    assert_eq!(resolved.upstream_url, "https://api.openai.com/v1/responses");
-}
-
-#[test]
-fn resolve_config_defaults_upstream_url_by_messages_wire() {
-    let args = Args {
-        config: None,
-        upstream_url: None,
-        upstream_wire: Some(WireApi::Messages),
-        upstream_http_headers: vec![],
-        forward_incoming_headers: vec![],
-        api_key_env: None,
-        server_info: None,
-        http_shutdown: false,
-        verbose_logging: false,
-        drop_tool_types: vec![],
-        router: None,
-        list_routers: false,
-    };
-
-    let resolved = resolve_config(args, None).expect("ok");
-    assert_eq!(resolved.upstream_wire, WireApi::Messages);
+fn assistant_chat_message_from_responses_output_rebuilds_text_and_tool_calls() {
+    let response = chat_json_to_responses_json(
+        json!({
+            "choices": [{
+                "finish_reason": "tool_calls",
+                "message": {
+                    "content": "Running it",
+                    "tool_calls": [{
+                        "id": "call_custom_1",
+                        "function": {"name": "shell", "arguments": "echo hello"}
+                    }]
+                }
+            }]
+        }),
+        "resp_1".to_string(),
+        &HashMap::from([("shell".to_string(), ResponsesToolCallKind::Custom)]),
+        false,
+    );
+    let message = assistant_chat_message_from_responses_output(
+        response["output"].as_array().expect("output"),
+    )
+    .expect("assistant message");
     assert_eq!(
-        resolved.upstream_url,
-        "https://api.anthropic.com/v1/messages"
+        message,
+        json!({
+            "role": "assistant",
+            "content": "Running it",
+            "tool_calls": [{
+                "id": "call_custom_1",
+                "type": "function",
+                "function": {"name": "shell", "arguments": "echo hello"}
+            }]
+        })
     );
+    assert!(assistant_chat_message_from_responses_output(&[]).is_none());
 }
 
 #[test]
-fn resolve_config_infers_upstream_wire_from_upstream_url() {
-    let args = Args {
-        config: None,
-        upstream_url: None,
-        upstream_wire: None,
-        upstream_http_headers: vec![],
-        forward_incoming_headers: vec![],
-        api_key_env: None,
-        server_info: None,
-        http_shutdown: false,
-        verbose_logging: false,
-        drop_tool_types: vec![],
-        router: None,
-        list_routers: false,
-    };
-    let file = FileConfig {
-        upstream_url: Some("https://api.openai.com/v1/responses".to_string()),
-        ..Default::default()
-    };
-
-    let resolved = resolve_config(args, Some(file)).expect("ok");
-    assert_eq!(resolved.upstream_wire, WireApi::Responses);
+fn chat_json_to_responses_json_maps_custom_tool_call_type() {
+    let chat = json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "id": "call_custom_1",
+                    "function": {
+                        "name": "shell",
+                        "arguments": "echo hello"
+                    }
+                }]
+            }
+        }]
+    });
+    let mut kinds = HashMap::new();
+    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
+    let out = chat_json_to_responses_json(chat, "resp_1".to_string(), &kinds, false);
+    let output = out["output"].as_array().expect("output array");
+    assert_eq!(output[0]["type"], "custom_tool_call");
+    assert_eq!(output[0]["call_id"], "call_custom_1");
+    assert_eq!(output[0]["input"], "echo hello");
 }
 
 #[test]
-fn resolve_config_infers_messages_upstream_wire_from_upstream_url() {
-    let args = Args {
-        config: None,
-        upstream_url: None,
-        upstream_wire: None,
-        upstream_http_headers: vec![],
-        forward_incoming_headers: vec![],
-        api_key_env: None,
-        server_info: None,
-        http_shutdown: false,
-        verbose_logging: false,
-        drop_tool_types: vec![],
-        router: None,
-        list_routers: false,
-    };
-    let file = FileConfig {
-        upstream_url: Some("https://api.anthropic.com/v1/messages".to_string()),
-        ..Default::default()
-    };
-
-    let resolved = resolve_config(args, Some(file)).expect("ok");
-    assert_eq!(resolved.upstream_wire, WireApi::Messages);
+fn chat_json_to_responses_json_repairs_invalid_function_tool_arguments() {
+    let chat = json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "id": "call_1",
+                    "function": {
+                        "name": "exec_command",
+                        "arguments": "{"
+                    }
+                }]
+            }
+        }]
+    });
+    let mut kinds = HashMap::new();
+    kinds.insert("exec_command".to_string(), ResponsesToolCallKind::Function);
+    let out = chat_json_to_responses_json(chat, "resp_1".to_string(), &kinds, false);
+    let output = out["output"].as_array().expect("output array");
+    assert_eq!(output[0]["type"], "function_call");
+    assert_eq!(output[0]["call_id"], "call_1");
+    assert_eq!(output[0]["name"], "exec_command");
+    assert_eq!(output[0]["arguments"], "{}");
 }
 
 #[test]
-fn resolve_config_rejects_mismatched_upstream_wire_and_url() {
-    let args = Args {
-        config: None,
-        upstream_url: None,
-        upstream_wire: Some(WireApi::Chat),
-        upstream_http_headers: vec![],
-        forward_incoming_headers: vec![],
-        api_key_env: None,
-        server_info: None,
-        http_shutdown: false,
-        verbose_logging: false,
-        drop_tool_types: vec![],
-        router: None,
-        list_routers: false,
-    };
-    let file = FileConfig {
-        upstream_url: Some("https://api.openai.com/v1/responses".to_string()),
-        ..Default::default()
-    };
-
-    let err = resolve_config(args, Some(file)).expect_err("must fail");
-    assert!(err.to_string().contains("configuration mismatch"));
+fn chat_json_to_responses_json_unwraps_custom_tool_call_json_string_arguments() {
+    let chat = json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "id": "call_custom_2",
+                    "function": {
+                        "name": "apply_patch",
+                        "arguments": "\"*** Begin Patch\\n*** End Patch\""
+                    }
+                }]
+            }
+        }]
+    });
+    let mut kinds = HashMap::new();
+    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
+    let out = chat_json_to_responses_json(chat, "resp_2".to_string(), &kinds, false);
+    let output = out["output"].as_array().expect("output");
+    assert_eq!(output[0]["type"], "custom_tool_call");
+    assert_eq!(output[0]["input"], "*** Begin Patch\n*** End Patch");
 }
 
 #[test]
-fn parse_upstream_http_header_arg_rejects_invalid_input() {
-    let err = parse_upstream_http_header_arg("not-valid").expect_err("must fail");
-    assert!(err.contains("NAME=VALUE"));
+fn chat_json_to_responses_json_unwraps_custom_tool_call_input_field_arguments() {
+    let chat = json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "id": "call_custom_3",
+                    "function": {
+                        "name": "apply_patch",
+                        "arguments": "{\"input\":\"*** Begin Patch\\n*** End Patch\"}"
+                    }
+                }]
+            }
+        }]
+    });
+    let mut kinds = HashMap::new();
+    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
+    let out = chat_json_to_responses_json(chat, "resp_3".to_string(), &kinds, false);
+    let output = out["output"].as_array().expect("output");
+    assert_eq!(output[0]["type"], "custom_tool_call");
+    assert_eq!(output[0]["input"], "*** Begin Patch\n*** End Patch");
 }
 
 #[test]
-fn file_config_accepts_http_headers_alias() {
-    let parsed: FileConfig = toml::from_str("http_headers = { \"x-test\" = \"1\" }").expect("ok");
-    let headers = parsed.upstream_http_headers.expect("headers");
-    assert_eq!(headers.get("x-test"), Some(&"1".to_string()));
+fn chat_json_to_responses_json_unwraps_custom_tool_call_patch_field_arguments() {
+    let chat = json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "id": "call_custom_4",
+                    "function": {
+                        "name": "apply_patch",
+                        "arguments": "{\"patch\":\"*** Begin Patch\\n*** End Patch\"}"
+                    }
+                }]
+            }
+        }]
+    });
+    let mut kinds = HashMap::new();
+    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
+    let out = chat_json_to_responses_json(chat, "resp_4".to_string(), &kinds, false);
+    let output = out["output"].as_array().expect("output");
+    assert_eq!(output[0]["type"], "custom_tool_call");
+    assert_eq!(output[0]["input"], "*** Begin Patch\n*** End Patch");
 }
 
 #[test]
-fn file_config_does_not_accept_profiles_alias_for_routers() {
-    let parsed: FileConfig =
-        toml::from_str("[profiles.gpt_oss]\nincoming_url = \"http://localhost:8080/gpt-oss\"")
-            .expect("ok");
-    assert!(parsed.routers.is_none());
+fn normalize_apply_patch_input_strips_code_fence_and_text() {
+    let raw = "Here is the patch:\n```patch\n*** Begin Patch\n*** Update File: a.txt\n@@\n-old   \n+new   \n*** End Patch\n```\n";
+    assert_eq!(
+        normalize_apply_patch_input_with_repairs(raw).normalized,
+        "*** Begin Patch\n*** Update File: a.txt\n@@\n-old\n+new\n*** End Patch"
+    );
 }
 
 #[test]
-fn normalize_incoming_url_to_path_supports_full_url_and_path() {
+fn normalize_apply_patch_input_repairs_add_file_lines_without_prefix() {
+    let raw = "*** Begin Patch\n*** Add File: hello.txt\nhello\n\nworld\n*** End Patch";
     assert_eq!(
-        normalize_incoming_url_to_path("http://localhost:8080/gpt-oss/").expect("normalized"),
-        "/gpt-oss"
+        normalize_apply_patch_input_with_repairs(raw).normalized,
+        "*** Begin Patch\n*** Add File: hello.txt\n+hello\n+\n+world\n*** End Patch"
     );
+}
+
+#[test]
+fn normalize_apply_patch_input_repairs_mixed_add_file_lines() {
+    let raw = "*** Begin Patch\n*** Add File: hello.txt\n+hello\n world\n*** Update File: a.txt\n@@\n-old\n+new\n*** End Patch";
     assert_eq!(
-        normalize_incoming_url_to_path("/gpt-oss/").expect("normalized"),
-        "/gpt-oss"
+        normalize_apply_patch_input_with_repairs(raw).normalized,
+        "*** Begin Patch\n*** Add File: hello.txt\n+hello\n+ world\n*** Update File: a.txt\n@@\n-old\n+new\n*** End Patch"
     );
 }
 
 #[test]
-fn router_manager_routes_by_incoming_url_path() {
-    let mut routers = BTreeMap::new();
-    routers.insert(
-        "gpt_oss".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://localhost:8080/gpt-oss".to_string()),
-            upstream_url: Some("http://upstream.local/v1/chat/completions".to_string()),
-            ..Default::default()
-        },
+fn normalize_apply_patch_input_reports_repairs() {
+    let raw = "*** Begin Patch\n*** Add File: hello.txt\nhello\n\n*** End Patch";
+    let normalized = normalize_apply_patch_input_with_repairs(raw);
+    assert_eq!(
+        normalized.normalized,
+        "*** Begin Patch\n*** Add File: hello.txt\n+hello\n+\n*** End Patch"
     );
-
-    let manager = RouterManager::new(
-        routers,
-        "https://api.openai.com/v1/chat/completions".to_string(),
-        WireApi::Chat,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        FeatureFlags::default(),
-    )
-    .expect("manager");
-    let target = manager
-        .get_target_for_incoming_route("/gpt-oss", Some("localhost:8080"))
-        .expect("route lookup")
-        .expect("target");
-
-    assert_eq!(target.router_name, "gpt_oss");
     assert_eq!(
-        target.upstream_url,
-        "http://upstream.local/v1/chat/completions"
+        normalized.repairs,
+        vec![
+            "added missing '+' prefix for add-file content line (hello.txt:1)",
+            "added missing '+' prefix for blank add-file line (hello.txt:2)",
+        ]
     );
 }
 
 #[test]
-fn router_manager_routes_prefix_paths_to_the_best_match() {
-    let mut routers = BTreeMap::new();
-    routers.insert(
-        "claude_root".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://localhost:8080/claude".to_string()),
-            upstream_url: Some("http://upstream.local/v1/chat/completions".to_string()),
-            ..Default::default()
-        },
-    );
-    routers.insert(
-        "claude_messages".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://localhost:8080/claude/v1/messages".to_string()),
-            upstream_url: Some("http://upstream.local/v1/chat/completions".to_string()),
-            ..Default::default()
-        },
+fn chat_json_to_responses_json_normalizes_apply_patch_arguments() {
+    let chat = json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "id": "call_custom_5",
+                    "function": {
+                        "name": "apply_patch",
+                        "arguments": "{\"input\":\"Patch follows\\n```patch\\n*** Begin Patch\\n*** Update File: a.txt\\n@@\\n-old   \\n+new   \\n*** End Patch\\n```\"}"
+                    }
+                }]
+            }
+        }]
+    });
+    let mut kinds = HashMap::new();
+    kinds.insert("apply_patch".to_string(), ResponsesToolCallKind::Custom);
+    let out = chat_json_to_responses_json(chat, "resp_5".to_string(), &kinds, false);
+    let output = out["output"].as_array().expect("output");
+    assert_eq!(
+        output[0]["input"],
+        "*** Begin Patch\n*** Update File: a.txt\n@@\n-old\n+new\n*** End Patch"
     );
-
-    let manager = RouterManager::new(
-        routers,
-        "https://api.openai.com/v1/chat/completions".to_string(),
-        WireApi::Chat,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        FeatureFlags::default(),
-    )
-    .expect("manager");
-
-    let exact_target = manager
-        .get_target_for_incoming_route("/claude/v1/messages", Some("localhost:8080"))
-        .expect("route lookup")
-        .expect("target");
-    assert_eq!(exact_target.router_name, "claude_messages");
-
-    let prefix_target = manager
-        .get_target_for_incoming_route("/claude/v1/messages/count_tokens", Some("localhost:8080"))
-        .expect("route lookup")
-        .expect("target");
-    assert_eq!(prefix_target.router_name, "claude_messages");
 }
 
 #[test]
-fn router_snapshot_marks_registered_route_as_active() {
-    let mut routers = BTreeMap::new();
-    routers.insert(
-        "gpt_oss".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://localhost:8080/gpt-oss".to_string()),
-            ..Default::default()
-        },
-    );
-
-    let manager = RouterManager::new(
-        routers,
-        "https://api.openai.com/v1/chat/completions".to_string(),
-        WireApi::Chat,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        FeatureFlags::default(),
-    )
-    .expect("manager");
-
-    let snapshots = manager.get_router_delta_log_snapshots();
-    assert_eq!(snapshots.len(), 1);
-    assert_eq!(snapshots[0].name, "gpt_oss");
-    assert!(snapshots[0].active);
-    assert_eq!(snapshots[0].non_local_incoming_host, None);
+fn chat_json_to_responses_json_keeps_non_apply_patch_custom_input_unchanged() {
+    let chat = json!({
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "id": "call_custom_6",
+                    "function": {
+                        "name": "shell",
+                        "arguments": "{\"input\":\"```bash\\necho hello\\n```\"}"
+                    }
+                }]
+            }
+        }]
+    });
+    let mut kinds = HashMap::new();
+    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
+    let out = chat_json_to_responses_json(chat, "resp_6".to_string(), &kinds, false);
+    let output = out["output"].as_array().expect("output");
+    assert_eq!(output[0]["input"], "```bash\necho hello\n```");
 }
 
 #[test]
-fn router_manager_infers_router_wire_from_upstream_url() {
-    let mut routers = BTreeMap::new();
-    routers.insert(
-        "gpt_oss".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://localhost:8080/gpt-oss".to_string()),
-            upstream_url: Some("http://upstream.local/v1/responses".to_string()),
-            ..Default::default()
-        },
-    );
-
-    let manager = RouterManager::new(
-        routers,
-        "https://api.openai.com/v1/chat/completions".to_string(),
-        WireApi::Chat,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        FeatureFlags::default(),
-    )
-    .expect("manager");
-    let target = manager
-        .get_target_for_incoming_route("/gpt-oss", Some("localhost:8080"))
-        .expect("route lookup")
-        .expect("target");
-
-    assert_eq!(target.upstream_wire, WireApi::Responses);
+fn chat_json_to_responses_json_maps_length_finish_reason_to_incomplete() {
+    let chat = json!({
+        "choices": [{
+            "finish_reason": "length",
+            "message": {
+                "content": "partial output"
+            }
+        }]
+    });
+    let out = chat_json_to_responses_json(chat, "resp_2".to_string(), &HashMap::new(), false);
+    assert_eq!(out["status"], "incomplete");
 }
 
 #[test]
-fn router_manager_resolves_router_upstream_model_override() {
-    let mut routers = BTreeMap::new();
-    routers.insert(
-        "claude".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://localhost:8080/claude/v1/messages".to_string()),
-            upstream_model: Some("openrouter/provider-model".to_string()),
-            ..Default::default()
+fn chat_json_to_responses_json_preserves_provider_specific_fields() {
+    let chat = json!({
+        "provider_specific_fields": {
+            "mcp_list_tools": [{"name":"shell"}]
         },
-    );
-
-    let manager = RouterManager::new(
-        routers,
-        "https://api.openai.com/v1/chat/completions".to_string(),
-        WireApi::Chat,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        FeatureFlags::default(),
-    )
-    .expect("manager");
-    let target = manager
-        .get_target_for_incoming_route("/claude/v1/messages", Some("localhost:8080"))
-        .expect("route lookup")
-        .expect("target");
-
+        "choices": [{
+            "message": {
+                "tool_calls": [{
+                    "id": "call_fn_1",
+                    "provider_specific_fields": {"source":"provider"},
+                    "function": {
+                        "name": "get_weather",
+                        "arguments": "{\"city\":\"seoul\"}"
+                    }
+                }]
+            }
+        }]
+    });
+    let out = chat_json_to_responses_json(chat, "resp_3".to_string(), &HashMap::new(), true);
     assert_eq!(
-        target.upstream_model,
-        Some("openrouter/provider-model".to_string())
+        out["provider_specific_fields"]["mcp_list_tools"][0]["name"],
+        "shell"
     );
-
-    let snapshots = manager.get_router_delta_log_snapshots();
     assert_eq!(
-        snapshots[0].override_upstream_model,
-        Some("openrouter/provider-model".to_string())
+        out["output"][0]["provider_specific_fields"]["source"],
+        "provider"
     );
 }
 
 #[test]
-fn router_manager_resolves_router_claude_family_model_overrides() {
-    let mut routers = BTreeMap::new();
-    routers.insert(
-        "claude".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://localhost:8080/claude/v1/messages".to_string()),
-            upstream_model_sonnet: Some("openrouter/sonnet".to_string()),
-            upstream_model_haiku: Some("openrouter/haiku".to_string()),
-            anthropic_preserve_thinking: Some(true),
-            anthropic_enable_openrouter_reasoning: Some(true),
-            ..Default::default()
-        },
-    );
-
-    let manager = RouterManager::new(
-        routers,
-        "https://api.openai.com/v1/chat/completions".to_string(),
-        WireApi::Chat,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        FeatureFlags::default(),
-    )
-    .expect("manager");
-    let target = manager
-        .get_target_for_incoming_route("/claude/v1/messages", Some("localhost:8080"))
-        .expect("route lookup")
-        .expect("target");
+fn responses_json_to_chat_json_maps_text_tool_calls_and_usage() {
+    let response = json!({
+        "id": "resp_1",
+        "model": "gpt-4.1",
+        "status": "completed",
+        "output": [
+            {
+                "type": "message",
+                "role": "assistant",
+                "content": [{"type":"output_text","text":"hello"}]
+            },
+            {
+                "type": "function_call",
+                "call_id": "call_1",
+                "name": "get_weather",
+                "arguments": "{\"city\":\"seoul\"}"
+            }
+        ],
+        "usage": {
+            "input_tokens": 3,
+            "output_tokens": 5,
+            "total_tokens": 8
+        }
+    });
+
+    let out = responses_json_to_chat_json(response, "fallback-model");
 
+    assert_eq!(out["object"], "chat.completion");
+    assert_eq!(out["id"], "resp_1");
+    assert_eq!(out["model"], "gpt-4.1");
+    assert_eq!(out["choices"][0]["message"]["role"], "assistant");
+    assert_eq!(out["choices"][0]["message"]["content"], "hello");
+    assert_eq!(out["choices"][0]["finish_reason"], "tool_calls");
     assert_eq!(
-        target.upstream_model_sonnet,
-        Some("openrouter/sonnet".to_string())
+        out["choices"][0]["message"]["tool_calls"][0]["function"]["name"],
+        "get_weather"
     );
     assert_eq!(
-        target.upstream_model_haiku,
-        Some("openrouter/haiku".to_string())
+        out["choices"][0]["message"]["tool_calls"][0]["function"]["arguments"],
+        "{\"city\":\"seoul\"}"
     );
-    assert!(target.anthropic_preserve_thinking);
-    assert!(target.anthropic_enable_openrouter_reasoning);
+    assert_eq!(out["usage"]["prompt_tokens"], 3);
+    assert_eq!(out["usage"]["completion_tokens"], 5);
+    assert_eq!(out["usage"]["total_tokens"], 8);
+}
 
-    let snapshots = manager.get_router_delta_log_snapshots();
+#[tokio::test]
+async fn responses_stream_to_chat_stream_emits_data_only_chunks_and_done() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "event: response.output_text.delta\n\
+         data: {\"type\":\"response.output_text.delta\",\"delta\":\"Hi\"}\n\n\
+         event: response.output_item.added\n\
+         data: {\"type\":\"response.output_item.added\",\"output_index\":1,\"item\":{\"type\":\"function_call\",\"call_id\":\"call_1\",\"name\":\"shell\",\"arguments\":\"\"}}\n\n\
+         event: response.function_call_arguments.delta\n\
+         data: {\"type\":\"response.function_call_arguments.delta\",\"item_id\":\"call_1\",\"output_index\":1,\"delta\":\"{\\\"cmd\\\":\"}\n\n\
+         event: response.function_call_arguments.delta\n\
+         data: {\"type\":\"response.function_call_arguments.delta\",\"item_id\":\"call_1\",\"output_index\":1,\"delta\":\"\\\"ls\\\"}\"}\n\n\
+         event: response.completed\n\
+         data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"status\":\"completed\",\"usage\":{\"input_tokens\":1,\"output_tokens\":2,\"total_tokens\":3}}}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_responses_stream_to_chat(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "gpt-4.1".to_string(),
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(!payload.contains("event:"));
+    assert!(payload.contains("\"object\":\"chat.completion.chunk\""));
+    assert!(payload.contains("\"content\":\"Hi\""));
+    assert!(payload.contains("\"tool_calls\""));
+    assert!(payload.contains("\"arguments\":\"{\\\"cmd\\\":\""));
+    assert!(payload.contains("\"arguments\":\"\\\"ls\\\"}\""));
+    assert!(payload.contains("\"finish_reason\":\"tool_calls\""));
+    assert!(payload.contains("\"prompt_tokens\":1"));
+    assert!(payload.ends_with("data: [DONE]\n\n"));
+}
+
+#[tokio::test]
+async fn responses_stream_to_anthropic_stream_translates_deltas_and_usage() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "event: response.created\n\
+         data: {\"type\":\"response.created\",\"response\":{\"id\":\"resp_1\"}}\n\n\
+         event: response.reasoning_summary_text.delta\n\
+         data: {\"type\":\"response.reasoning_summary_text.delta\",\"item_id\":\"rs_1\",\"delta\":\"plan\"}\n\n\
+         event: response.output_text.delta\n\
+         data: {\"type\":\"response.output_text.delta\",\"delta\":\"Hi\"}\n\n\
+         event: response.output_item.added\n\
+         data: {\"type\":\"response.output_item.added\",\"output_index\":2,\"item\":{\"type\":\"function_call\",\"id\":\"fc_1\",\"call_id\":\"call_1\",\"name\":\"shell\",\"arguments\":\"\"}}\n\n\
+         event: response.function_call_arguments.delta\n\
+         data: {\"type\":\"response.function_call_arguments.delta\",\"item_id\":\"fc_1\",\"output_index\":2,\"delta\":\"{\\\"cmd\\\":\"}\n\n\
+         event: response.function_call_arguments.delta\n\
+         data: {\"type\":\"response.function_call_arguments.delta\",\"item_id\":\"fc_1\",\"output_index\":2,\"delta\":\"\\\"ls\\\"}\"}\n\n\
+         event: response.output_item.done\n\
+         data: {\"type\":\"response.output_item.done\",\"output_index\":2,\"item\":{\"type\":\"function_call\",\"id\":\"fc_1\",\"call_id\":\"call_1\",\"name\":\"shell\",\"arguments\":\"{\\\"cmd\\\":\\\"ls\\\"}\"}}\n\n\
+         event: response.completed\n\
+         data: {\"type\":\"response.completed\",\"response\":{\"id\":\"resp_1\",\"status\":\"completed\",\"usage\":{\"input_tokens\":11,\"output_tokens\":7,\"total_tokens\":18}}}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_responses_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-sonnet".to_string(),
+        9,
+    ));
+    let mut events = Vec::new();
+    while let Some(event) = output.next().await {
+        let event = String::from_utf8_lossy(&event.expect("stream event")).to_string();
+        let data = event
+            .lines()
+            .find_map(|line| line.strip_prefix("data: "))
+            .expect("data line");
+        events.push(serde_json::from_str::<Value>(data).expect("json event"));
+    }
+
+    let types = events
+        .iter()
+        .map(|event| event["type"].as_str().unwrap_or_default())
+        .collect::<Vec<_>>();
     assert_eq!(
-        snapshots[0].override_upstream_model_sonnet,
-        Some("openrouter/sonnet".to_string())
+        types,
+        vec![
+            "message_start",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_stop",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_stop",
+            "content_block_start",
+            "content_block_delta",
+            "content_block_delta",
+            "content_block_stop",
+            "message_delta",
+            "message_stop",
+        ]
     );
-    assert_eq!(
-        snapshots[0].override_upstream_model_haiku,
-        Some("openrouter/haiku".to_string())
+    assert_eq!(events[0]["message"]["usage"]["input_tokens"], 9);
+    assert_eq!(events[1]["content_block"]["type"], "thinking");
+    assert_eq!(events[2]["delta"]["thinking"], "plan");
+    assert_eq!(events[5]["delta"]["text"], "Hi");
+    assert_eq!(events[7]["index"], 2);
+    assert_eq!(events[7]["content_block"]["id"], "call_1");
+    assert_eq!(events[7]["content_block"]["name"], "shell");
+    let arguments = format!(
+        "{}{}",
+        events[8]["delta"]["partial_json"]
+            .as_str()
+            .unwrap_or_default(),
+        events[9]["delta"]["partial_json"]
+            .as_str()
+            .unwrap_or_default()
     );
+    assert_eq!(arguments, "{\"cmd\":\"ls\"}");
+    assert_eq!(events[11]["delta"]["stop_reason"], "tool_use");
+    assert_eq!(events[11]["usage"]["input_tokens"], 11);
+    assert_eq!(events[11]["usage"]["output_tokens"], 7);
+}
+
+#[tokio::test]
+async fn responses_stream_to_anthropic_stream_wraps_custom_tool_input_and_reports_failures() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "event: response.output_item.done\n\
+         data: {\"type\":\"response.output_item.done\",\"output_index\":0,\"item\":{\"type\":\"custom_tool_call\",\"call_id\":\"call_1\",\"name\":\"apply_patch\",\"input\":\"*** Begin Patch\"}}\n\n\
+         event: response.failed\n\
+         data: {\"type\":\"response.failed\",\"response\":{\"id\":\"resp_1\",\"error\":{\"code\":\"server_error\",\"message\":\"boom\"}}}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_responses_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-sonnet".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"name\":\"apply_patch\""));
+    assert!(payload.contains("\"partial_json\":\"{\\\"input\\\":\\\"*** Begin Patch\\\"}\""));
+    assert!(payload.contains("event: error"));
+    assert!(payload.contains("\"message\":\"boom\""));
+    assert!(!payload.contains("message_stop"));
+}
+
+#[test]
+fn resolve_config_prefers_cli_over_file_and_defaults() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: None,
+        upstream_http_headers: vec![
+            parse_upstream_http_header_arg("x-trace-id=cli").expect("valid header"),
+        ],
+        forward_incoming_headers: vec![],
+        api_key_env: Some("CLI_API_KEY".to_string()),
+        server_info: None,
+        http_shutdown: true,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
+    };
+    let file = FileConfig {
+        upstream_url: Some("https://example.com/v1/chat/completions".to_string()),
+        upstream_wire: None,
+        upstream_http_headers: Some(BTreeMap::from([(
+            "x-trace-id".to_string(),
+            "file".to_string(),
+        )])),
+        forward_incoming_headers: None,
+        api_key_env: Some("FILE_API_KEY".to_string()),
+        server_info: Some(PathBuf::from("/tmp/server.json")),
+        http_shutdown: Some(false),
+        verbose_logging: Some(true),
+        capture_dir: Some(PathBuf::from("/tmp/captures")),
+        drop_tool_types: None,
+        drop_request_fields: None,
+        features: None,
+        sessions: None,
+        routers: None,
+    };
+
+    let resolved = resolve_config(args, Some(file)).expect("ok");
     assert_eq!(
-        snapshots[0].override_anthropic_preserve_thinking,
-        Some(true)
+        resolved.upstream_url,
+        "https://example.com/v1/chat/completions"
     );
+    assert_eq!(resolved.upstream_http_headers.len(), 1);
+    assert_eq!(resolved.upstream_http_headers[0].name, "x-trace-id");
+    assert_eq!(resolved.upstream_http_headers[0].value, "cli");
+    assert_eq!(resolved.api_key_env, "CLI_API_KEY");
     assert_eq!(
-        snapshots[0].override_anthropic_enable_openrouter_reasoning,
-        Some(true)
+        resolved.server_info,
+        Some(PathBuf::from("/tmp/server.json"))
     );
+    assert!(resolved.http_shutdown);
+    assert!(resolved.verbose_logging);
+    assert_eq!(resolved.capture_dir, Some(PathBuf::from("/tmp/captures")));
 }
 
 #[test]
-fn router_manager_rejects_mismatched_router_wire_and_upstream_url() {
-    let mut routers = BTreeMap::new();
-    routers.insert(
-        "gpt_oss".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://localhost:8080/gpt-oss".to_string()),
-            upstream_url: Some("http://upstream.local/v1/responses".to_string()),
-            upstream_wire: Some(WireApi::Chat),
-            ..Default::default()
-        },
-    );
+fn resolve_config_uses_defaults_when_missing() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: None,
+        upstream_http_headers: vec![],
+        forward_incoming_headers: vec![],
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: false,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
+    };
 
-    let result = RouterManager::new(
-        routers,
-        "https://api.openai.com/v1/chat/completions".to_string(),
-        WireApi::Chat,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        FeatureFlags::default(),
+    let resolved = resolve_config(args, None).expect("ok");
+    assert_eq!(
+        resolved.upstream_url,
+        "https://api.openai.com/v1/chat/completions"
     );
-    match result {
-        Ok(_) => panic!("must fail"),
-        Err(err) => assert!(err.to_string().contains("configuration mismatch")),
-    }
+    assert_eq!(resolved.api_key_env, "OPENAI_API_KEY");
+    assert_eq!(resolved.server_info, None);
+    assert!(!resolved.http_shutdown);
+    assert!(!resolved.verbose_logging);
 }
 
 #[test]
-fn apply_upstream_model_override_replaces_request_model() {
-    let mut payload = json!({
-        "model": "claude-sonnet-4-6",
-        "messages": [{"role":"user","content":"hi"}]
-    });
+fn resolve_config_path_prefers_cli_value() {
+    let path = resolve_config_path(Some(PathBuf::from("/tmp/custom.toml"))).expect("ok");
+    assert_eq!(path, PathBuf::from("/tmp/custom.toml"));
+}
 
-    let route_target = RouteTarget {
-        router_name: "claude".to_string(),
-        upstream_url: "http://localhost:8080/v1/chat/completions".to_string(),
-        upstream_wire: WireApi::Chat,
-        upstream_model: Some("openrouter/default-model".to_string()),
-        upstream_model_opus: Some("openrouter/opus-model".to_string()),
-        upstream_model_sonnet: Some("openrouter/sonnet-model".to_string()),
-        upstream_model_haiku: Some("openrouter/haiku-model".to_string()),
-        upstream_http_headers: Vec::new(),
-        forward_incoming_headers: Vec::new(),
-        drop_tool_types: HashSet::new(),
-        drop_request_fields: HashSet::new(),
-        feature_flags: FeatureFlags::default(),
-        anthropic_preserve_thinking: false,
-        anthropic_enable_openrouter_reasoning: false,
+#[test]
+fn resolve_config_defaults_upstream_url_by_wire() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: Some(WireApi::Responses),
+        upstream_http_headers: vec![],
+        forward_incoming_headers: vec![],
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: false,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
     };
 
-    apply_upstream_model_override(&mut payload, &route_target);
-
-    assert_eq!(payload["model"], "openrouter/sonnet-model");
+    let resolved = resolve_config(args, None).expect("ok");
+    assert_eq!(resolved.upstream_wire, WireApi::Responses);
+    assert_eq!(resolved.upstream_url, "https://api.openai.com/v1/responses");
 }
 
 #[test]
-fn apply_upstream_model_override_falls_back_to_generic_model() {
-    let mut payload = json!({
-        "model": "claude-sonnet-4-6",
-        "messages": [{"role":"user","content":"hi"}]
-    });
-
-    let route_target = RouteTarget {
-        router_name: "claude".to_string(),
-        upstream_url: "http://localhost:8080/v1/chat/completions".to_string(),
-        upstream_wire: WireApi::Chat,
-        upstream_model: Some("openrouter/default-model".to_string()),
-        upstream_model_opus: Some("openrouter/opus-model".to_string()),
-        upstream_model_sonnet: None,
-        upstream_model_haiku: None,
-        upstream_http_headers: Vec::new(),
-        forward_incoming_headers: Vec::new(),
-        drop_tool_types: HashSet::new(),
-        drop_request_fields: HashSet::new(),
-        feature_flags: FeatureFlags::default(),
-        anthropic_preserve_thinking: false,
-        anthropic_enable_openrouter_reasoning: false,
+fn resolve_config_defaults_upstream_url_by_messages_wire() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: Some(WireApi::Messages),
+        upstream_http_headers: vec![],
+        forward_incoming_headers: vec![],
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: false,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
     };
 
-    apply_upstream_model_override(&mut payload, &route_target);
-
-    assert_eq!(payload["model"], "openrouter/default-model");
+    let resolved = resolve_config(args, None).expect("ok");
+    assert_eq!(resolved.upstream_wire, WireApi::Messages);
+    assert_eq!(
+        resolved.upstream_url,
+        "https://api.anthropic.com/v1/messages"
+    );
 }
 
 #[test]
-fn apply_upstream_model_override_ignores_family_overrides_for_non_claude_models() {
-    let mut payload = json!({
-        "model": "gpt-4.1",
-        "messages": [{"role":"user","content":"hi"}]
-    });
-
-    let route_target = RouteTarget {
-        router_name: "default".to_string(),
-        upstream_url: "http://localhost:8080/v1/chat/completions".to_string(),
-        upstream_wire: WireApi::Chat,
-        upstream_model: Some("openrouter/default-model".to_string()),
-        upstream_model_opus: Some("openrouter/opus-model".to_string()),
-        upstream_model_sonnet: Some("openrouter/sonnet-model".to_string()),
-        upstream_model_haiku: Some("openrouter/haiku-model".to_string()),
-        upstream_http_headers: Vec::new(),
-        forward_incoming_headers: Vec::new(),
-        drop_tool_types: HashSet::new(),
-        drop_request_fields: HashSet::new(),
-        feature_flags: FeatureFlags::default(),
-        anthropic_preserve_thinking: false,
-        anthropic_enable_openrouter_reasoning: false,
+fn resolve_config_infers_upstream_wire_from_upstream_url() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: None,
+        upstream_http_headers: vec![],
+        forward_incoming_headers: vec![],
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: false,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
+    };
+    let file = FileConfig {
+        upstream_url: Some("https://api.openai.com/v1/responses".to_string()),
+        ..Default::default()
     };
 
-    apply_upstream_model_override(&mut payload, &route_target);
-
-    assert_eq!(payload["model"], "openrouter/default-model");
+    let resolved = resolve_config(args, Some(file)).expect("ok");
+    assert_eq!(resolved.upstream_wire, WireApi::Responses);
 }
 
 #[test]
-fn normalize_unsupported_chat_message_roles_rewrites_provider_rejected_roles() {
-    let mut payload = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {"role":"user","content":"first user"},
-            {"role":"developer","content":"developer instructions"},
-            {"role":"tool","tool_call_id":"call_1","content":"tool output"},
-            {"role":"function","name":"legacy_function","content":"function output"},
-            {"role":"system","content":"later system"},
-            {"role":"user","content":"hi"}
-        ]
-    });
-
-    normalize_unsupported_chat_message_roles(&mut payload);
+fn resolve_config_infers_messages_upstream_wire_from_upstream_url() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: None,
+        upstream_http_headers: vec![],
+        forward_incoming_headers: vec![],
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: false,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
+    };
+    let file = FileConfig {
+        upstream_url: Some("https://api.anthropic.com/v1/messages".to_string()),
+        ..Default::default()
+    };
 
-    let messages = payload["messages"].as_array().expect("messages");
-    assert_eq!(messages[0]["role"], "system");
-    assert_eq!(
-        messages[0]["content"],
-        "developer instructions\n\nlater system"
-    );
-    assert_eq!(messages[1]["role"], "user");
-    assert_eq!(messages[1]["content"], "first user");
-    assert_eq!(messages[2]["role"], "user");
-    assert!(messages[2].get("tool_call_id").is_none());
-    assert_eq!(messages[3]["role"], "user");
-    assert!(messages[3].get("name").is_none());
-    assert_eq!(messages[4]["role"], "user");
+    let resolved = resolve_config(args, Some(file)).expect("ok");
+    assert_eq!(resolved.upstream_wire, WireApi::Messages);
 }
 
 #[test]
-fn inject_openrouter_reasoning_sets_enabled_without_clobbering_other_fields() {
-    let mut payload = json!({
-        "model": "claude-sonnet-4-6",
-        "reasoning": {"effort": "medium"}
-    });
-
-    inject_openrouter_reasoning(&mut payload);
+fn resolve_config_rejects_mismatched_upstream_wire_and_url() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: Some(WireApi::Chat),
+        upstream_http_headers: vec![],
+        forward_incoming_headers: vec![],
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: false,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
+    };
+    let file = FileConfig {
+        upstream_url: Some("https://api.openai.com/v1/responses".to_string()),
+        ..Default::default()
+    };
 
-    assert_eq!(payload["reasoning"]["effort"], "medium");
-    assert_eq!(payload["reasoning"]["enabled"], true);
+    let err = resolve_config(args, Some(file)).expect_err("must fail");
+    assert!(err.to_string().contains("configuration mismatch"));
 }
 
 #[test]
-fn inject_openrouter_reasoning_creates_reasoning_field_when_missing() {
-    let mut payload = json!({
-        "model": "claude-sonnet-4-6"
-    });
-
-    inject_openrouter_reasoning(&mut payload);
-
-    assert_eq!(payload["reasoning"]["enabled"], true);
+fn parse_upstream_http_header_arg_rejects_invalid_input() {
+    let err = parse_upstream_http_header_arg("not-valid").expect_err("must fail");
+    assert!(err.contains("NAME=VALUE"));
 }
 
 #[test]
-fn anthropic_request_enables_thinking_detects_enabled_payloads() {
-    assert!(anthropic_request_enables_thinking(&json!({
-        "thinking": {"type": "enabled", "budget_tokens": 2048}
-    })));
-    assert!(anthropic_request_enables_thinking(&json!({
-        "thinking": {"type": "adaptive", "budget_tokens": 2048}
-    })));
-    assert!(anthropic_request_enables_thinking(&json!({
-        "thinking": {"enabled": true}
-    })));
-    assert!(anthropic_request_enables_thinking(&json!({
-        "thinking": true
-    })));
+fn file_config_accepts_http_headers_alias() {
+    let parsed: FileConfig = toml::from_str("http_headers = { \"x-test\" = \"1\" }").expect("ok");
+    let headers = parsed.upstream_http_headers.expect("headers");
+    assert_eq!(headers.get("x-test"), Some(&"1".to_string()));
 }
 
 #[test]
-fn anthropic_request_enables_thinking_rejects_disabled_payloads() {
-    assert!(!anthropic_request_enables_thinking(&json!({
-        "thinking": {"type": "disabled"}
-    })));
-    assert!(!anthropic_request_enables_thinking(&json!({
-        "thinking": {"enabled": false}
-    })));
-    assert!(!anthropic_request_enables_thinking(&json!({})));
+fn file_config_does_not_accept_profiles_alias_for_routers() {
+    let parsed: FileConfig =
+        toml::from_str("[profiles.gpt_oss]\nincoming_url = \"http://localhost:8080/gpt-oss\"")
+            .expect("ok");
+    assert!(parsed.routers.is_none());
 }
 
 #[test]
-fn estimate_anthropic_count_tokens_ignores_assistant_thinking_blocks() {
-    let base = json!({
-        "messages": [
-            {"role": "assistant", "content": []},
-            {"role": "user", "content": "hello there"}
-        ]
-    });
-    let with_thinking = json!({
-        "messages": [
-            {
-                "role": "assistant",
-                "content": [
-                    {"type": "thinking", "thinking": "internal chain"}
-                ]
-            },
-            {"role": "user", "content": "hello there"}
-        ]
-    });
-
+fn file_config_parses_router_auth_scheme() {
+    let parsed: FileConfig = toml::from_str(
+        "[routers.a]\nauth_scheme = \"x-api-key\"\n[routers.b]\nauth_scheme = \"Api-Key\"\n[routers.c]\nauth_scheme = \"none\"",
+    )
+    .expect("ok");
+    let routers = parsed.routers.expect("routers");
+    assert_eq!(routers["a"].auth_scheme, Some(AuthScheme::XApiKey));
     assert_eq!(
-        estimate_anthropic_count_tokens(&base),
-        estimate_anthropic_count_tokens(&with_thinking)
+        routers["b"].auth_scheme,
+        Some(AuthScheme::Header("api-key".to_string()))
     );
-}
+    assert_eq!(routers["c"].auth_scheme, Some(AuthScheme::None));
 
-#[tokio::test]
-async fn anthropic_json_error_response_includes_request_id() {
-    let response = anthropic_json_error_response("invalid_request_error", "boom");
-    let body = response.into_body();
-    let bytes = to_bytes(body, usize::MAX).await.expect("body");
-    let json: Value = serde_json::from_slice(&bytes).expect("json");
+    let err = toml::from_str::<FileConfig>("[routers.a]\nauth_scheme = \"bad header\"")
+        .expect_err("must fail");
+    assert!(err.to_string().contains("invalid auth_scheme"));
+}
 
-    assert_eq!(json["type"], "error");
-    assert_eq!(json["error"]["type"], "invalid_request_error");
-    assert_eq!(json["error"]["message"], "boom");
-    assert!(
-        json["request_id"]
-            .as_str()
-            .is_some_and(|v| v.starts_with("req_"))
+#[test]
+fn normalize_incoming_url_to_path_supports_full_url_and_path() {
+    assert_eq!(
+        normalize_incoming_url_to_path("http://localhost:8080/gpt-oss/").expect("normalized"),
+        "/gpt-oss"
+    );
+    assert_eq!(
+        normalize_incoming_url_to_path("/gpt-oss/").expect("normalized"),
+        "/gpt-oss"
     );
 }
 
 #[test]
-fn router_manager_collects_multiple_listen_ports_from_incoming_urls() {
+fn router_manager_routes_by_incoming_url_path() {
     let mut routers = BTreeMap::new();
     routers.insert(
-        "one".to_string(),
+        "gpt_oss".to_string(),
         RouterConfig {
-            incoming_url: Some("http://127.0.0.1:8787/one".to_string()),
+            incoming_url: Some("http://localhost:8080/gpt-oss".to_string()),
+            upstream_url: Some("http://upstream.local/v1/chat/completions".to_string()),
             ..Default::default()
         },
     );
+
+    let manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("manager");
+    let target = manager
+        .get_target_for_incoming_route("/gpt-oss", Some("localhost:8080"))
+        .expect("route lookup")
+        .expect("target");
+
+    assert_eq!(target.router_name, "gpt_oss");
+    assert_eq!(
+        target.upstream_url,
+        "http://upstream.local/v1/chat/completions"
+    );
+}
+
+#[test]
+fn router_manager_routes_prefix_paths_to_the_best_match() {
+    let mut routers = BTreeMap::new();
     routers.insert(
-        "two".to_string(),
+        "claude_root".to_string(),
         RouterConfig {
-            incoming_url: Some("http://127.0.0.1:8788/two".to_string()),
+            incoming_url: Some("http://localhost:8080/claude".to_string()),
+            upstream_url: Some("http://upstream.local/v1/chat/completions".to_string()),
+            ..Default::default()
+        },
+    );
+    routers.insert(
+        "claude_messages".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/claude/v1/messages".to_string()),
+            upstream_url: Some("http://upstream.local/v1/chat/completions".to_string()),
             ..Default::default()
         },
     );
@@ -2738,19 +3498,26 @@ fn router_manager_collects_multiple_listen_ports_from_incoming_urls() {
     )
     .expect("manager");
 
-    assert_eq!(
-        manager.get_listen_addrs(),
-        vec!["127.0.0.1:8787".to_string(), "127.0.0.1:8788".to_string()]
-    );
+    let exact_target = manager
+        .get_target_for_incoming_route("/claude/v1/messages", Some("localhost:8080"))
+        .expect("route lookup")
+        .expect("target");
+    assert_eq!(exact_target.router_name, "claude_messages");
+
+    let prefix_target = manager
+        .get_target_for_incoming_route("/claude/v1/messages/count_tokens", Some("localhost:8080"))
+        .expect("route lookup")
+        .expect("target");
+    assert_eq!(prefix_target.router_name, "claude_messages");
 }
 
 #[test]
-fn router_snapshot_flags_non_local_incoming_host() {
+fn router_snapshot_marks_registered_route_as_active() {
     let mut routers = BTreeMap::new();
     routers.insert(
-        "shared".to_string(),
+        "gpt_oss".to_string(),
         RouterConfig {
-            incoming_url: Some("http://0.0.0.0:8787/shared".to_string()),
+            incoming_url: Some("http://localhost:8080/gpt-oss".to_string()),
             ..Default::default()
         },
     );
@@ -2769,24 +3536,24 @@ fn router_snapshot_flags_non_local_incoming_host() {
 
     let snapshots = manager.get_router_delta_log_snapshots();
     assert_eq!(snapshots.len(), 1);
-    assert_eq!(
-        snapshots[0].non_local_incoming_host,
-        Some("0.0.0.0".to_string())
-    );
+    assert_eq!(snapshots[0].name, "gpt_oss");
+    assert!(snapshots[0].active);
+    assert_eq!(snapshots[0].non_local_incoming_host, None);
 }
 
 #[test]
-fn router_manager_rejects_router_without_incoming_url() {
+fn router_manager_infers_router_wire_from_upstream_url() {
     let mut routers = BTreeMap::new();
     routers.insert(
-        "missing_route".to_string(),
+        "gpt_oss".to_string(),
         RouterConfig {
-            upstream_url: Some("http://upstream.local/v1/chat/completions".to_string()),
+            incoming_url: Some("http://localhost:8080/gpt-oss".to_string()),
+            upstream_url: Some("http://upstream.local/v1/responses".to_string()),
             ..Default::default()
         },
     );
 
-    let result = RouterManager::new(
+    let manager = RouterManager::new(
         routers,
         "https://api.openai.com/v1/chat/completions".to_string(),
         WireApi::Chat,
@@ -2795,844 +3562,1058 @@ fn router_manager_rejects_router_without_incoming_url() {
         Vec::new(),
         Vec::new(),
         FeatureFlags::default(),
-    );
+    )
+    .expect("manager");
+    let target = manager
+        .get_target_for_incoming_route("/gpt-oss", Some("localhost:8080"))
+        .expect("route lookup")
+        .expect("target");
 
-    match result {
-        Ok(_) => panic!("must fail when incoming_url is missing"),
-        Err(err) => assert!(
-            err.to_string()
-                .contains("missing required incoming_url for [routers.missing_route]")
-        ),
-    }
+    assert_eq!(target.upstream_wire, WireApi::Responses);
 }
 
 #[test]
-fn resolve_config_rejects_invalid_file_upstream_http_header_name() {
-    let args = Args {
-        config: None,
-        upstream_url: None,
-        upstream_wire: None,
-        upstream_http_headers: vec![],
-        forward_incoming_headers: vec![],
-        api_key_env: None,
-        server_info: None,
-        http_shutdown: false,
-        verbose_logging: false,
-        drop_tool_types: vec![],
-        router: None,
-        list_routers: false,
-    };
-    let file = FileConfig {
-        upstream_url: None,
-        upstream_wire: None,
-        upstream_http_headers: Some(BTreeMap::from([(
-            "bad header".to_string(),
-            "value".to_string(),
-        )])),
-        forward_incoming_headers: None,
-        api_key_env: None,
-        server_info: None,
-        http_shutdown: None,
-        verbose_logging: None,
-        drop_tool_types: None,
-        drop_request_fields: None,
-        features: None,
-        routers: None,
-    };
+fn router_manager_resolves_router_upstream_model_override() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "claude".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/claude/v1/messages".to_string()),
+            upstream_model: Some("openrouter/provider-model".to_string()),
+            ..Default::default()
+        },
+    );
 
-    let err = resolve_config(args, Some(file)).expect_err("must fail");
-    assert!(err.to_string().contains("invalid upstream header name"));
-}
+    let manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("manager");
+    let target = manager
+        .get_target_for_incoming_route("/claude/v1/messages", Some("localhost:8080"))
+        .expect("route lookup")
+        .expect("target");
 
-#[test]
-fn apply_request_filters_drops_tool_type_for_chat_input() {
-    let mut request = json!({
-        "model": "gpt-4.1",
-        "tools": [
-            {"type":"web_search_preview"},
-            {"type":"function","function":{"name":"f","parameters":{"type":"object"}}}
-        ],
-        "tool_choice": "auto"
-    });
-    let mut drop = HashSet::new();
-    drop.insert("web_search_preview".to_string());
+    assert_eq!(
+        target.upstream_model,
+        Some("openrouter/provider-model".to_string())
+    );
 
-    apply_request_filters(IncomingApi::Chat, &mut request, &drop, &HashSet::new());
-    let tools = request["tools"].as_array().expect("tools");
-    assert_eq!(tools.len(), 1);
-    assert_eq!(tools[0]["type"], "function");
+    let snapshots = manager.get_router_delta_log_snapshots();
+    assert_eq!(
+        snapshots[0].override_upstream_model,
+        Some("openrouter/provider-model".to_string())
+    );
 }
 
 #[test]
-fn infer_incoming_api_from_prefixed_messages_path_returns_anthropic() {
-    let request = json!({
-        "model": "claude-sonnet-4-6",
-        "messages": [{"role":"user","content":[{"type":"text","text":"hi"}]}],
-        "stream": true
-    });
+fn router_manager_resolves_router_claude_family_model_overrides() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "claude".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/claude/v1/messages".to_string()),
+            upstream_model_sonnet: Some("openrouter/sonnet".to_string()),
+            upstream_model_haiku: Some("openrouter/haiku".to_string()),
+            anthropic_preserve_thinking: Some(true),
+            anthropic_enable_openrouter_reasoning: Some(true),
+            ..Default::default()
+        },
+    );
 
-    let incoming_api =
-        infer_incoming_api_from_hint_or_path(None, Some("/claude/v1/messages"), &request);
+    let manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("manager");
+    let target = manager
+        .get_target_for_incoming_route("/claude/v1/messages", Some("localhost:8080"))
+        .expect("route lookup")
+        .expect("target");
 
-    assert_eq!(incoming_api, IncomingApi::Anthropic);
-}
+    assert_eq!(
+        target.upstream_model_sonnet,
+        Some("openrouter/sonnet".to_string())
+    );
+    assert_eq!(
+        target.upstream_model_haiku,
+        Some("openrouter/haiku".to_string())
+    );
+    assert!(target.anthropic_preserve_thinking);
+    assert!(target.anthropic_enable_openrouter_reasoning);
+
+    let snapshots = manager.get_router_delta_log_snapshots();
+    assert_eq!(
+        snapshots[0].override_upstream_model_sonnet,
+        Some("openrouter/sonnet".to_string())
+    );
+    assert_eq!(
+        snapshots[0].override_upstream_model_haiku,
+        Some("openrouter/haiku".to_string())
+    );
+    assert_eq!(
+        snapshots[0].override_anthropic_preserve_thinking,
+        Some(true)
+    );
+    assert_eq!(
+        snapshots[0].override_anthropic_enable_openrouter_reasoning,
+        Some(true)
+    );
+}
 
 #[test]
-fn prefixed_anthropic_messages_path_maps_request_to_chat_payload() {
-    let request = json!({
-        "model": "claude-sonnet-4-6",
-        "messages": [
-            {"role":"user","content":[{"type":"text","text":"hi"}]}
-        ],
-        "system": [{"type":"text","text":"system prompt"}],
-        "tools": [
-            {
-                "name": "shell",
-                "description": "Run shell",
-                "input_schema": {"type":"object","properties":{"cmd":{"type":"string"}}}
-            }
-        ],
-        "stream": true,
-        "max_tokens": 128
-    });
+fn router_manager_resolves_router_api_key_sources() {
+    let key_file = std::env::temp_dir().join(format!("codex-chat-bridge-key-{}", Uuid::now_v7()));
+    fs::write(&key_file, "file-key\n").expect("write key file");
 
-    let incoming_api =
-        infer_incoming_api_from_hint_or_path(None, Some("/claude/v1/messages"), &request);
-    let out = build_upstream_payload(
-        &request,
-        incoming_api,
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "from_file".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/file".to_string()),
+            api_key_file: Some(key_file.clone()),
+            ..Default::default()
+        },
+    );
+    routers.insert(
+        "from_command".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/command".to_string()),
+            api_key_command: Some("echo command-key".to_string()),
+            ..Default::default()
+        },
+    );
+    routers.insert(
+        "inherited".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/inherited".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
         WireApi::Chat,
-        true,
-        true,
-        ToolTransformMode::LegacyConvert,
-        false,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
     )
-    .expect("should map anthropic request");
+    .expect("manager");
+    let _ = fs::remove_file(&key_file);
 
-    let messages = out
-        .get("messages")
-        .and_then(Value::as_array)
-        .expect("messages array");
-    assert_eq!(messages.len(), 2);
-    assert_eq!(messages[0]["role"], "system");
-    assert_eq!(messages[0]["content"], "system prompt");
-    assert_eq!(messages[1]["role"], "user");
-    assert_eq!(messages[1]["content"], "hi");
-    assert!(out.get("system").is_none());
+    let target_for = |path: &str| {
+        manager
+            .get_target_for_incoming_route(path, Some("localhost:8080"))
+            .expect("route lookup")
+            .expect("target")
+    };
+    assert_eq!(
+        target_for("/file").api_key,
+        Some(ApiKey::Resolved("file-key".to_string()))
+    );
+    assert_eq!(
+        target_for("/command").api_key,
+        Some(ApiKey::Resolved("command-key".to_string()))
+    );
+    assert_eq!(target_for("/inherited").api_key, None);
+    let debug = format!("{:?}", target_for("/file").api_key);
+    assert!(debug.contains("Resolved(<redacted>)"), "{debug}");
+    assert!(!debug.contains("file-key"), "{debug}");
 
-    let tools = out
-        .get("tools")
-        .and_then(Value::as_array)
-        .expect("tools array");
-    assert_eq!(tools.len(), 1);
-    assert_eq!(tools[0]["type"], "function");
-    assert_eq!(tools[0]["function"]["name"], "shell");
+    let snapshots = manager.get_router_delta_log_snapshots();
+    let sources = snapshots
+        .iter()
+        .map(|snapshot| {
+            (
+                snapshot.name.as_str(),
+                snapshot.override_api_key_source.clone(),
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(sources["from_command"], Some("command".to_string()));
+    assert_eq!(
+        sources["from_file"],
+        Some(format!("file:{}", key_file.display()))
+    );
+    assert_eq!(sources["inherited"], None);
 }
 
 #[test]
-fn prefixed_anthropic_messages_path_preserves_thinking_content_when_enabled() {
-    let request = json!({
-        "model": "claude-sonnet-4-6",
-        "messages": [
-            {
-                "role":"assistant",
-                "content":[
-                    {"type":"thinking","thinking":"internal chain"},
-                    {"type":"text","text":"hello"}
-                ]
-            }
-        ],
-        "stream": false
-    });
+fn router_manager_reports_unresolved_router_api_key_without_failing() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "broken".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/broken".to_string()),
+            api_key_env: Some("CODEX_CHAT_BRIDGE_TEST_UNSET_KEY".to_string()),
+            ..Default::default()
+        },
+    );
+    routers.insert(
+        "inherited".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/inherited".to_string()),
+            ..Default::default()
+        },
+    );
 
-    let incoming_api =
-        infer_incoming_api_from_hint_or_path(None, Some("/claude/v1/messages"), &request);
-    let out = build_upstream_payload(
-        &request,
-        incoming_api,
+    let manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
         WireApi::Chat,
-        false,
-        true,
-        ToolTransformMode::LegacyConvert,
-        true,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
     )
-    .expect("should map anthropic request");
+    .expect("manager");
 
-    let messages = out
-        .get("messages")
-        .and_then(Value::as_array)
-        .expect("messages array");
-    assert_eq!(messages.len(), 1);
-    assert_eq!(messages[0]["role"], "assistant");
-    assert_eq!(
-        messages[0]["content"],
-        "<thinking>\ninternal chain\n</thinking>\n\nhello"
-    );
-    assert_eq!(messages[0].get("reasoning_content"), None);
+    let default_key = ApiKey::Resolved("default-key".to_string());
+    let failures = manager.get_api_key_failures(&default_key);
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, "broken");
+    assert!(failures[0].1.contains("CODEX_CHAT_BRIDGE_TEST_UNSET_KEY"));
+    assert!(check_router_api_keys(&manager, &default_key).is_ok());
+
+    let missing_default =
+        ApiKey::Unavailable("missing or empty env var: OPENAI_API_KEY".to_string());
+    assert_eq!(manager.get_api_key_failures(&missing_default).len(), 2);
+    let err = check_router_api_keys(&manager, &missing_default).expect_err("no usable router");
+    assert!(err.to_string().contains("OPENAI_API_KEY"));
 }
 
 #[test]
-fn map_chat_to_responses_request_converts_messages() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {"role":"user","content":"hello"}
-        ],
-        "response_format": {
-            "type": "json_schema",
-            "json_schema": {
-                "name": "answer",
-                "schema": {"type":"object"}
-            }
+fn router_manager_defaults_auth_scheme_by_upstream_wire() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "chat".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/chat".to_string()),
+            ..Default::default()
         },
-        "stream": false
-    });
+    );
+    routers.insert(
+        "messages".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/messages".to_string()),
+            upstream_url: Some("https://api.anthropic.com/v1/messages".to_string()),
+            ..Default::default()
+        },
+    );
+    routers.insert(
+        "local".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/local".to_string()),
+            auth_scheme: Some(AuthScheme::None),
+            ..Default::default()
+        },
+    );
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    assert_eq!(out["model"], "gpt-4.1");
-    assert_eq!(out["stream"], false);
-    assert_eq!(out["input"][0]["type"], "message");
-    assert_eq!(out["input"][0]["content"][0]["text"], "hello");
-    assert_eq!(out["text"]["format"]["type"], "json_schema");
-    assert_eq!(out["text"]["format"]["json_schema"]["name"], "answer");
-}
+    let manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("manager");
+    let target_for = |path: &str| {
+        manager
+            .get_target_for_incoming_route(path, Some("localhost:8080"))
+            .expect("route lookup")
+            .expect("target")
+    };
+    assert_eq!(target_for("/chat").auth_scheme, AuthScheme::Bearer);
+    assert_eq!(target_for("/messages").auth_scheme, AuthScheme::XApiKey);
+    assert_eq!(target_for("/local").auth_scheme, AuthScheme::None);
+
+    let missing_default =
+        ApiKey::Unavailable("missing or empty env var: OPENAI_API_KEY".to_string());
+    let failures = manager
+        .get_api_key_failures(&missing_default)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    assert_eq!(failures, vec!["chat".to_string(), "messages".to_string()]);
+    assert!(check_router_api_keys(&manager, &missing_default).is_ok());
 
-#[test]
-fn map_chat_to_responses_request_normalizes_missing_type_named_tool() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {"role":"user","content":"hello"}
-        ],
-        "tools": [
-            {
-                "name": "spawn_agent",
-                "description": "Spawn a sub-agent"
-            }
-        ]
-    });
+    let snapshots = manager.get_router_delta_log_snapshots();
+    let local = snapshots
+        .iter()
+        .find(|snapshot| snapshot.name == "local")
+        .expect("local snapshot");
+    assert_eq!(local.override_auth_scheme, Some(AuthScheme::None));
+}
+
+#[test]
+fn router_manager_resolves_ordered_fallback_upstreams() {
+    let parsed: FileConfig = toml::from_str(
+        r#"
+[routers.default]
+incoming_url = "http://localhost:8080/default"
+upstream_url = "https://openrouter.ai/api/v1/chat/completions"
+upstream_model = "openrouter/model"
+upstream_http_headers = { "x-router" = "default" }
+auth_scheme = "bearer"
+
+[[routers.default.upstreams]]
+url = "https://api.openai.com/v1/responses"
+model = "gpt-4.1"
+api_key_env = "CODEX_CHAT_BRIDGE_TEST_UNSET_KEY"
+
+[[routers.default.upstreams]]
+upstream_url = "https://api.anthropic.com/v1/messages"
+http_headers = { "x-router" = "anthropic", "x-extra" = "1" }
+"#,
+    )
+    .expect("ok");
+    let manager = RouterManager::new(
+        parsed.routers.expect("routers"),
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("manager");
+    let target = manager
+        .get_target_for_incoming_route("/default", Some("localhost:8080"))
+        .expect("route lookup")
+        .expect("target");
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    assert_eq!(target.upstream_wire, WireApi::Chat);
+    assert_eq!(target.fallbacks.len(), 2);
+
+    let responses = &target.fallbacks[0];
     assert_eq!(
-        out["tools"][0],
-        json!({
-            "type": "function",
-            "name": "spawn_agent",
-            "description": "Spawn a sub-agent",
-            "parameters": {
-                "type": "object",
-                "properties": {},
-                "required": [],
-                "additionalProperties": false
-            }
-        })
+        responses.upstream_url,
+        "https://api.openai.com/v1/responses"
     );
-}
+    assert_eq!(responses.upstream_wire, WireApi::Responses);
+    assert_eq!(responses.upstream_model, Some("gpt-4.1".to_string()));
+    assert_eq!(responses.auth_scheme, AuthScheme::Bearer);
+    assert!(matches!(responses.api_key, Some(ApiKey::Unavailable(_))));
 
-#[test]
-fn map_chat_to_responses_request_normalizes_top_level_function_tool() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {"role":"user","content":"hello"}
-        ],
-        "tools": [
-            {
-                "type": "function",
-                "name": "spawn_agent",
-                "description": "Spawn a sub-agent"
-            }
+    let messages = &target.fallbacks[1];
+    assert_eq!(messages.upstream_wire, WireApi::Messages);
+    assert_eq!(
+        messages.upstream_model,
+        Some("openrouter/model".to_string())
+    );
+    assert_eq!(messages.auth_scheme, AuthScheme::XApiKey);
+    assert!(
+        matches!(&messages.api_key, Some(ApiKey::Unavailable(reason)) if reason.contains("upstreams[1] is on another host")),
+        "{:?}",
+        messages.api_key
+    );
+    assert!(messages.fallbacks.is_empty());
+    assert_eq!(
+        messages.upstream_http_headers,
+        vec![
+            UpstreamHeader {
+                name: "x-extra".to_string(),
+                value: "1".to_string(),
+            },
+            UpstreamHeader {
+                name: "x-router".to_string(),
+                value: "anthropic".to_string(),
+            },
         ]
-    });
-
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    assert_eq!(out["tools"][0]["type"], "function");
-    assert_eq!(out["tools"][0]["name"], "spawn_agent");
-    assert!(out["tools"][0].get("function").is_none());
-    assert_eq!(out["tools"][0]["parameters"]["required"], json!([]));
-}
+    );
 
-fn responses_input_items(out: &Value) -> &[Value] {
-    out.get("input")
-        .and_then(Value::as_array)
-        .expect("input array")
-}
+    let snapshots = manager.get_router_delta_log_snapshots();
+    assert_eq!(
+        snapshots[0].fallback_upstreams,
+        vec![
+            (
+                "https://api.openai.com/v1/responses".to_string(),
+                WireApi::Responses
+            ),
+            (
+                "https://api.anthropic.com/v1/messages".to_string(),
+                WireApi::Messages
+            ),
+        ]
+    );
 
-fn responses_input_item<'a>(items: &'a [Value], item_type: &str) -> Option<&'a Value> {
-    items
-        .iter()
-        .find(|item| item.get("type").and_then(Value::as_str) == Some(item_type))
+    let missing_default =
+        ApiKey::Unavailable("missing or empty env var: OPENAI_API_KEY".to_string());
+    let failures = manager.get_api_key_failures(&missing_default);
+    assert_eq!(failures.len(), 1);
+    assert!(failures[0].1.contains("CODEX_CHAT_BRIDGE_TEST_UNSET_KEY"));
+    assert!(
+        manager
+            .get_api_key_failures(&ApiKey::Resolved("default-key".to_string()))
+            .is_empty()
+    );
 }
 
 #[test]
-fn map_chat_to_responses_request_preserves_assistant_tool_calls() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {
-                "role": "assistant",
-                "content": "",
-                "tool_calls": [
-                    {
-                        "id": "call_1",
-                        "type": "function",
-                        "function": {
-                            "name": "get_weather",
-                            "arguments": "{\"city\":\"seoul\"}"
-                        }
-                    }
-                ]
-            }
-        ],
-        "stream": false
-    });
+fn fallback_upstreams_inherit_key_headers_and_auth_scheme_only_on_the_primary_host() {
+    let parsed: FileConfig = toml::from_str(
+        r#"
+[routers.default]
+incoming_url = "http://localhost:8080/default"
+upstream_url = "https://openrouter.ai/api/v1/chat/completions"
+upstream_http_headers = { "x-router" = "default" }
+api_key_command = "echo router-key"
+auth_scheme = "x-custom-key"
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    let items = responses_input_items(&out);
-    let item = responses_input_item(items, "function_call").expect("function_call item");
-    assert_eq!(item["call_id"], "call_1");
-    assert_eq!(item["name"], "get_weather");
-    assert_eq!(item["arguments"], "{\"city\":\"seoul\"}");
-}
+[[routers.default.upstreams]]
+url = "https://OpenRouter.ai/api/v1/messages"
 
-#[test]
-fn map_chat_to_responses_request_repairs_invalid_assistant_tool_call_arguments() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {
-                "role": "assistant",
-                "content": "",
-                "tool_calls": [
-                    {
-                        "id": "call_1",
-                        "type": "function",
-                        "function": {
-                            "name": "exec_command",
-                            "arguments": "{"
-                        }
-                    }
-                ]
-            }
-        ],
-        "stream": false
-    });
+[[routers.default.upstreams]]
+url = "https://api.anthropic.com/v1/messages"
+"#,
+    )
+    .expect("ok");
+    let manager = RouterManager::new(
+        parsed.routers.expect("routers"),
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        vec![UpstreamHeader {
+            name: "openai-organization".to_string(),
+            value: "org_123".to_string(),
+        }],
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("manager");
+    let target = manager
+        .resolve_target_for_router_name("default")
+        .expect("target");
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    let items = responses_input_items(&out);
-    let item = responses_input_item(items, "function_call").expect("function_call item");
-    assert_eq!(item["call_id"], "call_1");
-    assert_eq!(item["name"], "exec_command");
-    assert_eq!(item["arguments"], "{}");
+    let same_host = &target.fallbacks[0];
+    assert_eq!(
+        same_host.api_key,
+        Some(ApiKey::Resolved("router-key".to_string()))
+    );
+    assert_eq!(same_host.auth_scheme, target.auth_scheme);
+    assert_eq!(
+        same_host.upstream_http_headers,
+        target.upstream_http_headers
+    );
+
+    let other_host = &target.fallbacks[1];
+    assert!(matches!(other_host.api_key, Some(ApiKey::Unavailable(_))));
+    assert_eq!(other_host.auth_scheme, AuthScheme::XApiKey);
+    assert!(other_host.upstream_http_headers.is_empty());
 }
 
 #[test]
-fn map_chat_to_responses_request_generates_unique_tool_call_ids_when_missing() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {
-                "role": "assistant",
-                "content": "",
-                "tool_calls": [
-                    {
-                        "type": "function",
-                        "function": {
-                            "name": "tool_a",
-                            "arguments": "{}"
-                        }
-                    },
-                    {
-                        "type": "function",
-                        "function": {
-                            "name": "tool_b",
-                            "arguments": "{\"x\":1}"
-                        }
-                    }
-                ]
-            }
-        ],
-        "stream": false
-    });
+fn router_manager_rejects_mismatched_fallback_upstream_wire() {
+    let parsed: FileConfig = toml::from_str(
+        r#"
+[routers.default]
+incoming_url = "http://localhost:8080/default"
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    let items = responses_input_items(&out);
-    let first = responses_input_item(items, "function_call").expect("first function_call item");
-    assert_eq!(first["name"], "tool_a");
-    assert_eq!(first["call_id"], "call_m0_t0");
+[[routers.default.upstreams]]
+url = "https://api.openai.com/v1/responses"
+wire = "chat"
+"#,
+    )
+    .expect("ok");
 
-    let second = items
-        .iter()
-        .filter(|item| item.get("type").and_then(Value::as_str) == Some("function_call"))
-        .nth(1)
-        .expect("second function_call item");
-    assert_eq!(second["name"], "tool_b");
-    assert_eq!(second["call_id"], "call_m0_t1");
-    assert_ne!(first["call_id"], second["call_id"]);
+    let err = match RouterManager::new(
+        parsed.routers.expect("routers"),
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    ) {
+        Ok(_) => panic!("expected mismatched fallback wire to fail"),
+        Err(err) => err,
+    };
+    assert!(
+        err.to_string()
+            .contains("[routers.default].upstreams[0] configuration mismatch")
+    );
 }
 
 #[test]
-fn map_chat_to_responses_request_preserves_assistant_reasoning() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {
-                "role": "assistant",
-                "content": "final answer",
-                "reasoning_content": "internal chain"
-            }
-        ],
-        "stream": false
-    });
+fn router_manager_rejects_multiple_api_key_sources() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "ambiguous".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/ambiguous".to_string()),
+            api_key_env: Some("OPENROUTER_API_KEY".to_string()),
+            api_key_command: Some("echo key".to_string()),
+            ..Default::default()
+        },
+    );
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    let items = responses_input_items(&out);
-    let item = responses_input_item(items, "reasoning").expect("reasoning item");
-    assert_eq!(item["summary"][0]["type"], "summary_text");
-    assert_eq!(item["summary"][0]["text"], "internal chain");
+    let err = match RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    ) {
+        Ok(_) => panic!("expected multiple key sources to fail"),
+        Err(err) => err,
+    };
+    assert!(
+        err.to_string()
+            .contains("accepts only one of api_key_env, api_key_file, api_key_command")
+    );
 }
 
 #[test]
-fn map_chat_to_responses_request_preserves_multimodal_content() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {
-                "role": "user",
-                "content": [
-                    {"type":"text","text":"hello"},
-                    {"type":"image_url","image_url":{"url":"https://example.com/cat.png"}},
-                    {"type":"input_audio","input_audio":{"data":"aGVsbG8="}}
-                ]
-            }
-        ],
-        "stream": false
-    });
+fn router_manager_rejects_mismatched_router_wire_and_upstream_url() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "gpt_oss".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://localhost:8080/gpt-oss".to_string()),
+            upstream_url: Some("http://upstream.local/v1/responses".to_string()),
+            upstream_wire: Some(WireApi::Chat),
+            ..Default::default()
+        },
+    );
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    let items = responses_input_items(&out);
-    let message = responses_input_item(items, "message").expect("message item");
-    let content = message["content"].as_array().expect("content array");
-    assert_eq!(content[0]["type"], "input_text");
-    assert_eq!(content[0]["text"], "hello");
-    assert_eq!(content[1]["type"], "input_image");
-    assert_eq!(content[1]["image_url"], "https://example.com/cat.png");
-    assert_eq!(content[2]["type"], "input_audio");
-    assert_eq!(content[2]["input_audio"]["data"], "aGVsbG8=");
+    let result = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    );
+    match result {
+        Ok(_) => panic!("must fail"),
+        Err(err) => assert!(err.to_string().contains("configuration mismatch")),
+    }
 }
 
 #[test]
-fn map_chat_to_responses_request_maps_tool_messages_to_function_call_output() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {
-                "role": "tool",
-                "tool_call_id": "call_2",
-                "content": "{\"ok\":true}"
-            }
-        ],
-        "stream": false
+fn apply_upstream_model_override_replaces_request_model() {
+    let mut payload = json!({
+        "model": "claude-sonnet-4-6",
+        "messages": [{"role":"user","content":"hi"}]
     });
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    let items = responses_input_items(&out);
-    let item =
-        responses_input_item(items, "function_call_output").expect("function_call_output item");
-    assert_eq!(item["call_id"], "call_2");
-    assert_eq!(item["output"], "{\"ok\":true}");
-}
-
-#[test]
-fn map_chat_to_responses_request_preserves_mixed_assistant_content_and_tool_calls() {
-    let chat = json!({
-        "model": "gpt-4.1",
-        "messages": [
-            {
-                "role": "assistant",
-                "content": "I will check that now.",
-                "tool_calls": [
-                    {
-                        "id": "call_3",
-                        "type": "function",
-                        "function": {
-                            "name": "get_weather",
-                            "arguments": "{\"city\":\"seoul\"}"
-                        }
-                    }
-                ]
-            }
-        ],
-        "stream": false
-    });
+    let route_target = RouteTarget {
+        router_name: "claude".to_string(),
+        upstream_url: "http://localhost:8080/v1/chat/completions".to_string(),
+        upstream_wire: WireApi::Chat,
+        upstream_model: Some("openrouter/default-model".to_string()),
+        upstream_model_opus: Some("openrouter/opus-model".to_string()),
+        upstream_model_sonnet: Some("openrouter/sonnet-model".to_string()),
+        upstream_model_haiku: Some("openrouter/haiku-model".to_string()),
+        upstream_http_headers: Vec::new(),
+        forward_incoming_headers: Vec::new(),
+        drop_tool_types: HashSet::new(),
+        drop_request_fields: HashSet::new(),
+        feature_flags: FeatureFlags::default(),
+        anthropic_preserve_thinking: false,
+        anthropic_enable_openrouter_reasoning: false,
+        api_key: None,
+        auth_scheme: AuthScheme::Bearer,
+        retry: RetryPolicy::default(),
+        fallbacks: Vec::new(),
+        weight: 1,
+        load_balancing: None,
+        timeouts: TimeoutPolicy::default(),
+        client: None,
+        inbound_auth: None,
+        ollama: OllamaConfig::default(),
+        message_roles: MessageRolesConfig::default(),
+        models: Vec::new(),
+        merge_upstream_models: false,
+        tokenizer: None,
+    };
 
-    let out = map_chat_to_responses_request(&chat, false).expect("ok");
-    let items = responses_input_items(&out);
-    let message = responses_input_item(items, "message").expect("assistant message");
-    assert_eq!(message["role"], "assistant");
-    assert_eq!(message["content"][0]["text"], "I will check that now.");
+    apply_upstream_model_override(&mut payload, &route_target);
 
-    let tool_call = responses_input_item(items, "function_call").expect("function_call item");
-    assert_eq!(tool_call["call_id"], "call_3");
-    assert_eq!(tool_call["name"], "get_weather");
+    assert_eq!(payload["model"], "openrouter/sonnet-model");
 }
 
 #[test]
-fn previous_response_id_for_request_ignores_blank_values() {
-    let request = json!({
-        "previous_response_id": "   "
+fn apply_upstream_model_override_falls_back_to_generic_model() {
+    let mut payload = json!({
+        "model": "claude-sonnet-4-6",
+        "messages": [{"role":"user","content":"hi"}]
     });
-    assert_eq!(previous_response_id_for_request(&request), None);
 
-    let request = json!({
-        "previous_response_id": "resp_123"
-    });
-    assert_eq!(previous_response_id_for_request(&request), Some("resp_123"));
-}
+    let route_target = RouteTarget {
+        router_name: "claude".to_string(),
+        upstream_url: "http://localhost:8080/v1/chat/completions".to_string(),
+        upstream_wire: WireApi::Chat,
+        upstream_model: Some("openrouter/default-model".to_string()),
+        upstream_model_opus: Some("openrouter/opus-model".to_string()),
+        upstream_model_sonnet: None,
+        upstream_model_haiku: None,
+        upstream_http_headers: Vec::new(),
+        forward_incoming_headers: Vec::new(),
+        drop_tool_types: HashSet::new(),
+        drop_request_fields: HashSet::new(),
+        feature_flags: FeatureFlags::default(),
+        anthropic_preserve_thinking: false,
+        anthropic_enable_openrouter_reasoning: false,
+        api_key: None,
+        auth_scheme: AuthScheme::Bearer,
+        retry: RetryPolicy::default(),
+        fallbacks: Vec::new(),
+        weight: 1,
+        load_balancing: None,
+        timeouts: TimeoutPolicy::default(),
+        client: None,
+        inbound_auth: None,
+        ollama: OllamaConfig::default(),
+        message_roles: MessageRolesConfig::default(),
+        models: Vec::new(),
+        merge_upstream_models: false,
+        tokenizer: None,
+    };
 
-#[test]
-fn resolve_previous_messages_for_request_errors_when_not_found() {
-    let request = json!({
-        "previous_response_id": "resp_missing"
-    });
-    let sessions = SessionStore::default();
-    let err = resolve_previous_messages_for_request(&request, &sessions).expect_err("must fail");
-    assert!(err.to_string().contains("unknown `previous_response_id`"));
+    apply_upstream_model_override(&mut payload, &route_target);
+
+    assert_eq!(payload["model"], "openrouter/default-model");
 }
 
 #[test]
-fn merge_previous_messages_prepends_history_messages() {
+fn apply_upstream_model_override_ignores_family_overrides_for_non_claude_models() {
     let mut payload = json!({
-        "messages": [
-            {"role":"user","content":"new"}
-        ]
+        "model": "gpt-4.1",
+        "messages": [{"role":"user","content":"hi"}]
     });
-    let previous = vec![
-        json!({"role":"system","content":"old-system"}),
-        json!({"role":"user","content":"old-user"}),
-    ];
 
-    merge_previous_messages(&mut payload, previous).expect("ok");
+    let route_target = RouteTarget {
+        router_name: "default".to_string(),
+        upstream_url: "http://localhost:8080/v1/chat/completions".to_string(),
+        upstream_wire: WireApi::Chat,
+        upstream_model: Some("openrouter/default-model".to_string()),
+        upstream_model_opus: Some("openrouter/opus-model".to_string()),
+        upstream_model_sonnet: Some("openrouter/sonnet-model".to_string()),
+        upstream_model_haiku: Some("openrouter/haiku-model".to_string()),
+        upstream_http_headers: Vec::new(),
+        forward_incoming_headers: Vec::new(),
+        drop_tool_types: HashSet::new(),
+        drop_request_fields: HashSet::new(),
+        feature_flags: FeatureFlags::default(),
+        anthropic_preserve_thinking: false,
+        anthropic_enable_openrouter_reasoning: false,
+        api_key: None,
+        auth_scheme: AuthScheme::Bearer,
+        retry: RetryPolicy::default(),
+        fallbacks: Vec::new(),
+        weight: 1,
+        load_balancing: None,
+        timeouts: TimeoutPolicy::default(),
+        client: None,
+        inbound_auth: None,
+        ollama: OllamaConfig::default(),
+        message_roles: MessageRolesConfig::default(),
+        models: Vec::new(),
+        merge_upstream_models: false,
+        tokenizer: None,
+    };
 
-    let messages = payload["messages"].as_array().expect("array");
-    assert_eq!(messages.len(), 3);
-    assert_eq!(messages[0]["content"], "old-system");
-    assert_eq!(messages[1]["content"], "old-user");
-    assert_eq!(messages[2]["content"], "new");
+    apply_upstream_model_override(&mut payload, &route_target);
+
+    assert_eq!(payload["model"], "openrouter/default-model");
 }
 
 #[test]
-fn upstream_messages_for_logging_reads_chat_messages() {
-    let payload = json!({
+fn default_message_role_policy_rewrites_provider_rejected_roles() {
+    let mut payload = json!({
         "model": "gpt-4.1",
         "messages": [
-            {"role":"user","content":"hi"},
-            {"role":"assistant","content":"hello"}
+            {"role":"user","content":"first user"},
+            {"role":"developer","content":"developer instructions"},
+            {"role":"tool","tool_call_id":"call_1","content":"tool output"},
+            {"role":"function","name":"legacy_function","content":"function output"},
+            {"role":"system","content":"later system"},
+            {"role":"user","content":"hi"}
         ]
     });
 
-    let out = upstream_messages_for_logging(WireApi::Chat, &payload).expect("messages");
-    let messages = out.as_array().expect("array");
-    assert_eq!(messages.len(), 2);
-    assert_eq!(messages[0]["role"], "user");
+    apply_message_role_policy(&mut payload, &MessageRolesConfig::default());
+
+    let messages = payload["messages"].as_array().expect("messages");
+    assert_eq!(messages[0]["role"], "system");
+    assert_eq!(
+        messages[0]["content"],
+        "developer instructions\n\nlater system"
+    );
+    assert_eq!(messages[1]["role"], "user");
+    assert_eq!(messages[1]["content"], "first user");
+    assert_eq!(messages[2]["role"], "user");
+    assert!(messages[2].get("tool_call_id").is_none());
+    assert_eq!(messages[3]["role"], "user");
+    assert!(messages[3].get("name").is_none());
+    assert_eq!(messages[4]["role"], "user");
 }
 
-#[test]
-fn upstream_messages_for_logging_filters_responses_input_items() {
-    let payload = json!({
-        "model": "gpt-4.1",
-        "input": [
-            {"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]},
-            {"type":"function_call_output","call_id":"call_1","output":"ok"}
+fn tool_and_system_role_payload() -> Value {
+    json!({
+        "model": "qwen3-coder",
+        "messages": [
+            {"role":"system","content":"be brief"},
+            {"role":"user","content":"list files"},
+            {"role":"assistant","content":null,"tool_calls":[{"id":"call_1","type":"function","function":{"name":"ls","arguments":"{}"}}]},
+            {"role":"tool","tool_call_id":"call_1","content":"a.rs\nb.rs"},
+            {"role":"developer","content":"mention sizes"},
+            {"role":"user","content":"thanks"}
         ]
-    });
-
-    let out = upstream_messages_for_logging(WireApi::Responses, &payload).expect("messages");
-    let messages = out.as_array().expect("array");
-    assert_eq!(messages.len(), 1);
-    assert_eq!(messages[0]["type"], "message");
-    assert_eq!(messages[0]["role"], "user");
+    })
 }
 
 #[test]
-fn tool_types_for_logging_includes_type_and_name_labels() {
-    let payload = json!({
-        "tools": [
-            {"type":"function","name":"f1"},
-            {"type":"function","function":{"name":"f2"}},
-            {"type":"custom","name":"shell"},
-            {"type":"web_search_preview"},
-            {"name":"missing_type"},
-            {}
-        ]
-    });
+fn native_tool_roles_and_preserved_system_positions_pass_through() {
+    let mut payload = tool_and_system_role_payload();
+    let original = payload.clone();
 
-    let out = tool_types_for_logging(&payload);
+    apply_message_role_policy(
+        &mut payload,
+        &MessageRolesConfig {
+            tool: Some(ToolRoleMode::Native),
+            system: Some(SystemRoleMode::Preserve),
+            ..Default::default()
+        },
+    );
+
+    let messages = payload["messages"].as_array().expect("messages");
+    assert_eq!(messages.len(), 6);
+    assert_eq!(messages[0], original["messages"][0]);
+    assert_eq!(messages[3], original["messages"][3]);
     assert_eq!(
-        out,
-        json!([
-            "function(f1)",
-            "function(f2)",
-            "custom(shell)",
-            "web_search_preview",
-            "<missing_type>(missing_type)",
-            "<missing_type>"
-        ])
+        messages[4],
+        json!({"role":"system","content":"mention sizes"})
     );
 }
 
 #[test]
-fn tool_definitions_for_logging_defaults_required_and_normalizes_null() {
-    let payload = json!({
-        "tools": [
-            {"type":"function","function":{"name":"missing","parameters":{"type":"object"}}},
-            {"type":"function","function":{"name":"null","parameters":{"type":"object","required":null}}},
-            {"type":"function","function":{"name":"spawn_agent"}}
-        ]
-    });
+fn tagged_tool_roles_embed_results_with_their_call_ids() {
+    let mut payload = tool_and_system_role_payload();
+    payload["messages"]
+        .as_array_mut()
+        .expect("messages")
+        .push(json!({"role":"function","name":"legacy","content":[{"type":"text","text":"done"}]}));
 
-    let out = tool_definitions_for_logging(&payload);
-    assert_eq!(out[0]["required"], json!([]));
-    assert_eq!(out[1]["required"], json!([]));
+    apply_message_role_policy(
+        &mut payload,
+        &MessageRolesConfig {
+            tool: Some(ToolRoleMode::Tagged),
+            ..Default::default()
+        },
+    );
+
+    let messages = payload["messages"].as_array().expect("messages");
     assert_eq!(
-        out[2],
+        messages[0],
+        json!({"role":"system","content":"be brief\n\nmention sizes"})
+    );
+    assert_eq!(
+        messages[3],
         json!({
-            "name": "spawn_agent",
-            "required": [],
-            "additionalProperties": false
+            "role": "user",
+            "content": "<tool_result tool_call_id=\"call_1\">\na.rs\nb.rs\n</tool_result>"
         })
     );
+    assert_eq!(
+        messages[5],
+        json!({"role":"user","content":"<tool_result name=\"legacy\">\ndone\n</tool_result>"})
+    );
 }
 
 #[test]
-fn request_fields_for_logging_sorts_top_level_keys() {
-    let payload = json!({
-        "stream": true,
-        "model": "gpt-4.1",
-        "input": []
-    });
-
-    let out = request_fields_for_logging(&payload);
-    assert_eq!(out, json!(["input", "model", "stream"]));
-}
-
-#[test]
-fn split_log_chunks_splits_large_ascii_payloads() {
-    let chunks = split_log_chunks_by_bytes("abcdef", 2);
-
-    assert_eq!(chunks, vec!["ab", "cd", "ef"]);
-}
-
-#[test]
-fn split_log_chunks_keeps_utf8_boundaries() {
-    let input = "가나다";
-    let chunks = split_log_chunks_by_bytes(input, 4);
-
-    assert_eq!(chunks, vec!["가", "나", "다"]);
-    assert_eq!(chunks.join(""), input);
+fn message_role_modes_resolve_per_upstream_model() {
+    let config: MessageRolesConfig = toml::from_str(
+        r#"
+tool = "tagged"
+models = { "qwen3-coder" = { tool = "native", system = "preserve" }, "llama3" = { system = "preserve" } }
+"#,
+    )
+    .expect("message roles config");
+    assert_eq!(
+        config.models.get("llama3"),
+        Some(&MessageRoleModes {
+            tool: None,
+            system: Some(SystemRoleMode::Preserve),
+        })
+    );
+    assert_eq!(
+        config.modes_for_model("qwen3-coder"),
+        (ToolRoleMode::Native, SystemRoleMode::Preserve)
+    );
+    assert_eq!(
+        config.modes_for_model("llama3"),
+        (ToolRoleMode::Tagged, SystemRoleMode::Preserve)
+    );
+    assert_eq!(
+        config.modes_for_model("other"),
+        (ToolRoleMode::Tagged, SystemRoleMode::Merge)
+    );
+    assert_eq!(
+        MessageRolesConfig::default().modes_for_model("other"),
+        (ToolRoleMode::User, SystemRoleMode::Merge)
+    );
 }
 
-#[test]
-fn upstream_headers_for_logging_includes_forwarded_headers() {
-    let mut headers = HeaderMap::new();
-    headers.insert("openai-organization", HeaderValue::from_static("org_123"));
-    headers.insert("x-client-request-id", HeaderValue::from_static("trace_123"));
-    headers.insert("x-openai-subagent", HeaderValue::from_static("subagent_1"));
-    let configured_headers = vec![
-        UpstreamHeader {
-            name: "x-custom-header".to_string(),
-            value: "hello".to_string(),
-        },
-        UpstreamHeader {
-            name: "authorization".to_string(),
-            value: "Bearer secret".to_string(),
+#[tokio::test]
+async fn message_role_policy_follows_the_overridden_upstream_model() {
+    let state = test_state_with_routers(BTreeMap::from([(
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/chat/completions".to_string()),
+            upstream_url: Some("http://127.0.0.1:9/v1/chat/completions".to_string()),
+            upstream_model: Some("qwen3-coder".to_string()),
+            message_roles: Some(MessageRolesConfig {
+                models: BTreeMap::from([(
+                    "qwen3-coder".to_string(),
+                    MessageRoleModes {
+                        tool: Some(ToolRoleMode::Native),
+                        system: None,
+                    },
+                )]),
+                ..Default::default()
+            }),
+            ..Default::default()
         },
-    ];
-    let forwarded_headers = vec![
-        "openai-organization".to_string(),
-        "x-client-request-id".to_string(),
-        "x-openai-subagent".to_string(),
-    ];
-
-    let out =
-        upstream_headers_for_logging(&headers, "sk-test", &configured_headers, &forwarded_headers);
-    assert_eq!(out["authorization"], "<redacted>");
-    assert_eq!(out["content-type"], "application/json");
-    assert_eq!(out["openai-organization"], "org_123");
-    assert_eq!(out["x-client-request-id"], "trace_123");
-    assert_eq!(out["x-openai-subagent"], "subagent_1");
-    assert_eq!(out["x-custom-header"], "hello");
-}
-
-#[test]
-fn upstream_headers_for_logging_marks_empty_api_key() {
-    let headers = HeaderMap::new();
-
-    let out = upstream_headers_for_logging(&headers, "", &[], &[]);
-    assert_eq!(out["authorization"], "Bearer <empty>");
-    assert_eq!(out["content-type"], "application/json");
-}
+    )]));
+    let route_target = state
+        .routers
+        .read()
+        .await
+        .get_target_for_incoming_route("/v1/chat/completions", Some("127.0.0.1:8787"))
+        .expect("route")
+        .expect("router");
+    let mut request = tool_and_system_role_payload();
+    request["model"] = json!("gpt-4.1");
 
-#[test]
-fn headers_for_logging_redacts_sensitive_headers() {
-    let mut headers = HeaderMap::new();
-    headers.insert("authorization", HeaderValue::from_static("Bearer sk-test"));
-    headers.insert("cookie", HeaderValue::from_static("sid=abc"));
-    headers.insert("x-trace-id", HeaderValue::from_static("trace-1"));
+    let (payload, _) = build_upstream_payload_with_session(
+        &state,
+        &request,
+        IncomingApi::Chat,
+        &route_target,
+        false,
+        "resp_test",
+    )
+    .await
+    .expect("payload");
 
-    let out = headers_for_logging(&headers);
-    assert_eq!(out["authorization"], "<redacted>");
-    assert_eq!(out["cookie"], "<redacted>");
-    assert_eq!(out["x-trace-id"], "trace-1");
+    let messages = payload["messages"].as_array().expect("messages");
+    assert_eq!(messages[0]["content"], "be brief\n\nmention sizes");
+    assert_eq!(messages[3]["role"], "tool");
+    assert_eq!(messages[3]["tool_call_id"], "call_1");
 }
 
 #[test]
-fn capability_gate_allows_extended_responses_fields() {
-    let request = json!({
-        "model": "gpt-4.1",
-        "input": [],
-        "reasoning": {"effort":"medium"},
-        "include": ["reasoning.encrypted_content"],
-        "text": {"format":{"type":"json_object"}},
-        "service_tier": "default"
+fn inject_openrouter_reasoning_sets_enabled_without_clobbering_other_fields() {
+    let mut payload = json!({
+        "model": "claude-sonnet-4-6",
+        "reasoning": {"effort": "medium"}
     });
 
-    let out = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request);
-    assert!(out.is_ok());
-}
-
-#[test]
-fn capability_gate_rejects_invalid_max_output_tokens_type() {
-    let request = json!({
-        "model": "gpt-4.1",
-        "input": [],
-        "max_output_tokens": "bad"
-    });
+    inject_openrouter_reasoning(&mut payload);
 
-    let err = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request)
-        .expect_err("must fail");
-    assert!(err.to_string().contains("max_output_tokens"));
+    assert_eq!(payload["reasoning"]["effort"], "medium");
+    assert_eq!(payload["reasoning"]["enabled"], true);
 }
 
 #[test]
-fn capability_gate_rejects_invalid_metadata_type() {
-    let request = json!({
-        "model": "gpt-4.1",
-        "input": [],
-        "metadata": ["bad"]
+fn inject_openrouter_reasoning_creates_reasoning_field_when_missing() {
+    let mut payload = json!({
+        "model": "claude-sonnet-4-6"
     });
 
-    let err = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request)
-        .expect_err("must fail");
-    assert!(err.to_string().contains("metadata"));
+    inject_openrouter_reasoning(&mut payload);
+
+    assert_eq!(payload["reasoning"]["enabled"], true);
 }
 
 #[test]
-fn capability_gate_allows_basic_responses_request() {
-    let request = json!({
-        "model": "gpt-4.1",
-        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
-        "tools": [{"type":"function","name":"f","parameters":{"type":"object"}}]
-    });
-
-    let out = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request);
-    assert!(out.is_ok());
+fn anthropic_request_enables_thinking_detects_enabled_payloads() {
+    assert!(anthropic_request_enables_thinking(&json!({
+        "thinking": {"type": "enabled", "budget_tokens": 2048}
+    })));
+    assert!(anthropic_request_enables_thinking(&json!({
+        "thinking": {"type": "adaptive", "budget_tokens": 2048}
+    })));
+    assert!(anthropic_request_enables_thinking(&json!({
+        "thinking": {"enabled": true}
+    })));
+    assert!(anthropic_request_enables_thinking(&json!({
+        "thinking": true
+    })));
 }
 
 #[test]
-fn capability_gate_allows_prompt_cache_key() {
-    let request = json!({
-        "model": "gpt-4.1",
-        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
-        "prompt_cache_key": "session-key-1"
-    });
-
-    let out = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request);
-    assert!(out.is_ok());
+fn anthropic_request_enables_thinking_rejects_disabled_payloads() {
+    assert!(!anthropic_request_enables_thinking(&json!({
+        "thinking": {"type": "disabled"}
+    })));
+    assert!(!anthropic_request_enables_thinking(&json!({
+        "thinking": {"enabled": false}
+    })));
+    assert!(!anthropic_request_enables_thinking(&json!({})));
 }
 
 #[test]
-fn capability_gate_allows_mcp_and_web_search_tool_types() {
-    let request = json!({
-        "model": "gpt-4.1",
-        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
-        "tools": [
-            {"type":"namespace","namespace":"functions","tools":[]},
-            {"type":"mcp","server_label":"s","server_url":"http://localhost/mcp"},
-            {"type":"web_search"},
-            {"type":"web_search_preview"}
+fn estimate_anthropic_count_tokens_ignores_assistant_thinking_blocks() {
+    let base = json!({
+        "messages": [
+            {"role": "assistant", "content": []},
+            {"role": "user", "content": "hello there"}
         ]
     });
-
-    let out = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request);
-    assert!(out.is_ok());
-}
-
-#[test]
-fn maps_responses_request_with_namespace_tool() {
-    let input = json!({
-        "model": "gpt-4.1",
-        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
-        "tools": [{
-            "type": "namespace",
-            "namespace": "functions",
-            "tools": [{
-                "name": "exec_command",
-                "description": "Run a command",
-                "parameters": {"type":"object","properties":{"cmd":{"type":"string"}}}
-            }]
-        }]
+    let with_thinking = json!({
+        "messages": [
+            {
+                "role": "assistant",
+                "content": [
+                    {"type": "thinking", "thinking": "internal chain"}
+                ]
+            },
+            {"role": "user", "content": "hello there"}
+        ]
     });
 
-    let req = map_responses_to_chat_request_with_stream(
-        &input,
-        &HashSet::new(),
-        true,
-        true,
-        ToolTransformMode::LegacyConvert,
-    )
-    .expect("namespace tool should map");
-    assert_eq!(req.chat_request["tools"][0]["type"], "function");
-    assert_eq!(
-        req.chat_request["tools"][0]["function"]["name"],
-        "exec_command"
-    );
     assert_eq!(
-        req.chat_request["tools"][0]["function"]["parameters"]["required"],
-        json!([])
+        estimate_anthropic_count_tokens(&base),
+        estimate_anthropic_count_tokens(&with_thinking)
     );
 }
 
+const TEST_BPE_VOCAB: &str = "YQ== 0\nYg== 1\nYw== 2\nIA== 3\nYWI= 10\nYWJj 11\nIGFiYw== 12\n";
+
 #[test]
-fn namespace_tools_are_tracked_as_function_calls_by_name() {
-    let request = json!({
-        "tools": [{
-            "type": "namespace",
-            "namespace": "functions",
-            "tools": [{"name": "exec_command", "parameters": {"type": "object"}}]
-        }]
-    });
+fn bpe_vocab_merges_by_rank_within_pre_tokenized_pieces() {
+    let vocab = BpeVocab::parse(TEST_BPE_VOCAB).expect("vocab");
+    assert_eq!(vocab.count("abc"), 1);
+    assert_eq!(vocab.count("abcabc"), 2);
+    assert_eq!(vocab.count(" abc abc"), 2);
+    assert_eq!(vocab.count("abc\n\n"), 3);
+    // Digits split into groups of three; bytes outside the vocabulary count one each.
+    assert_eq!(vocab.count("12345"), 5);
+    assert_eq!(vocab.count(""), 0);
+    assert_eq!(vocab.count(&"ab".repeat(10_000)), 10_000);
 
-    let kinds = responses_tool_call_kind_by_name(&request);
-    assert_eq!(
-        kinds.get("exec_command"),
-        Some(&ResponsesToolCallKind::Function)
-    );
+    assert!(BpeVocab::parse("not-a-rank-line\n").is_err());
+    assert!(BpeVocab::parse("YQ== x\n").is_err());
+    assert!(BpeVocab::parse("\n").is_err());
 }
 
 #[test]
-fn normalize_upstream_error_maps_known_codes() {
-    let payload = r#"{"error":{"code":"context_length_exceeded","message":"too long"}}"#;
-    let err = normalize_upstream_error_payload(StatusCode::BAD_REQUEST, payload);
-    assert_eq!(err.code, "context_window_exceeded");
-    assert_eq!(err.message, "too long");
+fn tokenizer_from_config_requires_kind_specific_fields() {
+    let context = "[routers.claude]";
+    assert!(
+        Tokenizer::from_config(None, context)
+            .expect("unset")
+            .is_none()
+    );
+    assert!(
+        Tokenizer::from_config(Some(&TokenizerConfig::default()), context)
+            .expect("heuristic")
+            .is_none()
+    );
+    let missing_path = Tokenizer::from_config(
+        Some(&TokenizerConfig {
+            kind: Some(TokenizerKind::Bpe),
+            ..Default::default()
+        }),
+        context,
+    )
+    .expect_err("bpe without path");
+    assert!(missing_path.to_string().contains("tokenizer.path"));
+    let missing_url = Tokenizer::from_config(
+        Some(&TokenizerConfig {
+            kind: Some(TokenizerKind::Upstream),
+            url: Some(" ".to_string()),
+            ..Default::default()
+        }),
+        context,
+    )
+    .expect_err("upstream without url");
+    assert!(missing_url.to_string().contains("tokenizer.url"));
+
+    let config: RouterConfig = toml::from_str(
+        r#"
+incoming_url = "http://127.0.0.1:8787/claude/v1/messages"
+tokenizer = { kind = "upstream", url = "https://api.anthropic.com/v1/messages/count_tokens" }
+"#,
+    )
+    .expect("router config");
+    let tokenizer = Tokenizer::from_config(config.tokenizer.as_ref(), context)
+        .expect("upstream")
+        .expect("configured");
+    assert_eq!(
+        tokenizer.describe(),
+        "upstream:url=https://api.anthropic.com/v1/messages/count_tokens"
+    );
 }
 
-#[test]
-fn build_upstream_request_prefers_static_header_on_duplicate_key() {
-    let router_manager = RouterManager::new(
-        BTreeMap::new(),
-        "http://localhost:8080/v1/chat/completions".to_string(),
+#[tokio::test]
+async fn anthropic_json_error_response_includes_request_id() {
+    let response = anthropic_json_error_response("invalid_request_error", "boom");
+    let body = response.into_body();
+    let bytes = to_bytes(body, usize::MAX).await.expect("body");
+    let json: Value = serde_json::from_slice(&bytes).expect("json");
+
+    assert_eq!(json["type"], "error");
+    assert_eq!(json["error"]["type"], "invalid_request_error");
+    assert_eq!(json["error"]["message"], "boom");
+    assert!(
+        json["request_id"]
+            .as_str()
+            .is_some_and(|v| v.starts_with("req_"))
+    );
+}
+
+#[test]
+fn router_manager_collects_multiple_listen_ports_from_incoming_urls() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "one".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/one".to_string()),
+            ..Default::default()
+        },
+    );
+    routers.insert(
+        "two".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8788/two".to_string()),
+            ..Default::default()
+        },
+    );
+
+    let manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
         WireApi::Chat,
         Vec::new(),
         Vec::new(),
@@ -3640,193 +4621,3196 @@ fn build_upstream_request_prefers_static_header_on_duplicate_key() {
         Vec::new(),
         FeatureFlags::default(),
     )
-    .expect("router manager");
-    let state = Arc::new(AppState {
-        client: Client::new(),
-        api_key: "test-key".to_string(),
-        http_shutdown: false,
-        verbose_logging: false,
-        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
-        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
-    });
-    let route_target = RouteTarget {
-        router_name: "default".to_string(),
-        upstream_url: "http://localhost:8080/v1/chat/completions".to_string(),
-        upstream_wire: WireApi::Chat,
-        upstream_model: None,
-        upstream_model_opus: None,
-        upstream_model_sonnet: None,
-        upstream_model_haiku: None,
-        upstream_http_headers: vec![UpstreamHeader {
-            name: "x-request-id".to_string(),
-            value: "from-upstream-http-headers".to_string(),
-        }],
-        forward_incoming_headers: vec![
-            "x-request-id".to_string(),
-            "x-client-request-id".to_string(),
-        ],
-        drop_tool_types: HashSet::new(),
-        drop_request_fields: HashSet::new(),
-        feature_flags: FeatureFlags::default(),
-        anthropic_preserve_thinking: false,
-        anthropic_enable_openrouter_reasoning: false,
-    };
-    let mut incoming_headers = HeaderMap::new();
-    incoming_headers.insert(
-        "x-request-id",
-        HeaderValue::from_static("from-forward-incoming-headers"),
+    .expect("manager");
+
+    assert_eq!(
+        manager.get_listen_addrs(),
+        vec!["127.0.0.1:8787".to_string(), "127.0.0.1:8788".to_string()]
     );
-    incoming_headers.insert(
-        "x-client-request-id",
-        HeaderValue::from_static("client-trace-123"),
+}
+
+#[test]
+fn router_snapshot_flags_non_local_incoming_host() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "shared".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://0.0.0.0:8787/shared".to_string()),
+            ..Default::default()
+        },
     );
 
-    let request = build_upstream_request(
-        &state,
-        &route_target,
-        &incoming_headers,
-        &json!({"model":"gpt-4.1","messages":[]}),
-        None,
+    let manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
     )
-    .build()
-    .expect("request");
-    let values = request.headers().get_all("x-request-id");
-    let collected = values.iter().collect::<Vec<_>>();
+    .expect("manager");
 
-    assert_eq!(collected.len(), 1);
-    assert_eq!(collected[0], "from-upstream-http-headers");
+    let snapshots = manager.get_router_delta_log_snapshots();
+    assert_eq!(snapshots.len(), 1);
     assert_eq!(
-        request
-            .headers()
-            .get("x-client-request-id")
-            .and_then(|value| value.to_str().ok()),
-        Some("client-trace-123")
+        snapshots[0].non_local_incoming_host,
+        Some("0.0.0.0".to_string())
     );
 }
 
 #[test]
-fn upstream_url_for_messages_count_tokens_appends_subpath() {
-    let route_target = RouteTarget {
-        router_name: "messages".to_string(),
-        upstream_url: "https://api.anthropic.com/v1/messages".to_string(),
-        upstream_wire: WireApi::Messages,
-        upstream_model: Some("ignored-model".to_string()),
-        upstream_model_opus: None,
-        upstream_model_sonnet: None,
-        upstream_model_haiku: None,
-        upstream_http_headers: Vec::new(),
-        forward_incoming_headers: Vec::new(),
-        drop_tool_types: HashSet::new(),
-        drop_request_fields: HashSet::new(),
-        feature_flags: FeatureFlags::default(),
-        anthropic_preserve_thinking: false,
-        anthropic_enable_openrouter_reasoning: false,
-    };
-
-    assert_eq!(
-        upstream_url_for_request(&route_target, Some("/v1/messages/count_tokens")),
-        "https://api.anthropic.com/v1/messages/count_tokens"
+fn router_manager_rejects_router_without_incoming_url() {
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "missing_route".to_string(),
+        RouterConfig {
+            upstream_url: Some("http://upstream.local/v1/chat/completions".to_string()),
+            ..Default::default()
+        },
     );
-    assert_eq!(
-        upstream_url_for_request(&route_target, Some("/v1/messages")),
-        "https://api.anthropic.com/v1/messages"
+
+    let result = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
     );
+
+    match result {
+        Ok(_) => panic!("must fail when incoming_url is missing"),
+        Err(err) => assert!(
+            err.to_string()
+                .contains("missing required incoming_url for [routers.missing_route]")
+        ),
+    }
+}
+
+#[test]
+fn resolve_config_rejects_invalid_file_upstream_http_header_name() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: None,
+        upstream_http_headers: vec![],
+        forward_incoming_headers: vec![],
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: false,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
+    };
+    let file = FileConfig {
+        upstream_url: None,
+        upstream_wire: None,
+        upstream_http_headers: Some(BTreeMap::from([(
+            "bad header".to_string(),
+            "value".to_string(),
+        )])),
+        forward_incoming_headers: None,
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: None,
+        verbose_logging: None,
+        capture_dir: None,
+        drop_tool_types: None,
+        drop_request_fields: None,
+        features: None,
+        sessions: None,
+        routers: None,
+    };
+
+    let err = resolve_config(args, Some(file)).expect_err("must fail");
+    assert!(err.to_string().contains("invalid upstream header name"));
+}
+
+#[test]
+fn apply_request_filters_drops_tool_type_for_chat_input() {
+    let mut request = json!({
+        "model": "gpt-4.1",
+        "tools": [
+            {"type":"web_search_preview"},
+            {"type":"function","function":{"name":"f","parameters":{"type":"object"}}}
+        ],
+        "tool_choice": "auto"
+    });
+    let mut drop = HashSet::new();
+    drop.insert("web_search_preview".to_string());
+
+    apply_request_filters(IncomingApi::Chat, &mut request, &drop, &HashSet::new());
+    let tools = request["tools"].as_array().expect("tools");
+    assert_eq!(tools.len(), 1);
+    assert_eq!(tools[0]["type"], "function");
+}
+
+#[test]
+fn infer_incoming_api_from_prefixed_messages_path_returns_anthropic() {
+    let request = json!({
+        "model": "claude-sonnet-4-6",
+        "messages": [{"role":"user","content":[{"type":"text","text":"hi"}]}],
+        "stream": true
+    });
+
+    let incoming_api =
+        infer_incoming_api_from_hint_or_path(None, Some("/claude/v1/messages"), &request);
+
+    assert_eq!(incoming_api, IncomingApi::Anthropic);
+}
+
+#[test]
+fn prefixed_anthropic_messages_path_maps_request_to_chat_payload() {
+    let request = json!({
+        "model": "claude-sonnet-4-6",
+        "messages": [
+            {"role":"user","content":[{"type":"text","text":"hi"}]}
+        ],
+        "system": [{"type":"text","text":"system prompt"}],
+        "tools": [
+            {
+                "name": "shell",
+                "description": "Run shell",
+                "input_schema": {"type":"object","properties":{"cmd":{"type":"string"}}}
+            }
+        ],
+        "stream": true,
+        "max_tokens": 128
+    });
+
+    let incoming_api =
+        infer_incoming_api_from_hint_or_path(None, Some("/claude/v1/messages"), &request);
+    let out = build_upstream_payload(
+        &request,
+        incoming_api,
+        WireApi::Chat,
+        true,
+        true,
+        ToolTransformMode::LegacyConvert,
+        false,
+    )
+    .expect("should map anthropic request");
+
+    let messages = out
+        .get("messages")
+        .and_then(Value::as_array)
+        .expect("messages array");
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["role"], "system");
+    assert_eq!(messages[0]["content"], "system prompt");
+    assert_eq!(messages[1]["role"], "user");
+    assert_eq!(messages[1]["content"], "hi");
+    assert!(out.get("system").is_none());
+
+    let tools = out
+        .get("tools")
+        .and_then(Value::as_array)
+        .expect("tools array");
+    assert_eq!(tools.len(), 1);
+    assert_eq!(tools[0]["type"], "function");
+    assert_eq!(tools[0]["function"]["name"], "shell");
+}
+
+#[test]
+fn prefixed_anthropic_messages_path_preserves_thinking_content_when_enabled() {
+    let request = json!({
+        "model": "claude-sonnet-4-6",
+        "messages": [
+            {
+                "role":"assistant",
+                "content":[
+                    {"type":"thinking","thinking":"internal chain"},
+                    {"type":"text","text":"hello"}
+                ]
+            }
+        ],
+        "stream": false
+    });
+
+    let incoming_api =
+        infer_incoming_api_from_hint_or_path(None, Some("/claude/v1/messages"), &request);
+    let out = build_upstream_payload(
+        &request,
+        incoming_api,
+        WireApi::Chat,
+        false,
+        true,
+        ToolTransformMode::LegacyConvert,
+        true,
+    )
+    .expect("should map anthropic request");
+
+    let messages = out
+        .get("messages")
+        .and_then(Value::as_array)
+        .expect("messages array");
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["role"], "assistant");
+    assert_eq!(
+        messages[0]["content"],
+        "<thinking>\ninternal chain\n</thinking>\n\nhello"
+    );
+    assert_eq!(messages[0].get("reasoning_content"), None);
+}
+
+#[test]
+fn map_chat_to_responses_request_converts_messages() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {"role":"user","content":"hello"}
+        ],
+        "response_format": {
+            "type": "json_schema",
+            "json_schema": {
+                "name": "answer",
+                "schema": {"type":"object"}
+            }
+        },
+        "stream": false
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    assert_eq!(out["model"], "gpt-4.1");
+    assert_eq!(out["stream"], false);
+    assert_eq!(out["input"][0]["type"], "message");
+    assert_eq!(out["input"][0]["content"][0]["text"], "hello");
+    assert_eq!(out["text"]["format"]["type"], "json_schema");
+    assert_eq!(out["text"]["format"]["json_schema"]["name"], "answer");
+}
+
+#[test]
+fn map_chat_to_responses_request_normalizes_missing_type_named_tool() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {"role":"user","content":"hello"}
+        ],
+        "tools": [
+            {
+                "name": "spawn_agent",
+                "description": "Spawn a sub-agent"
+            }
+        ]
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    assert_eq!(
+        out["tools"][0],
+        json!({
+            "type": "function",
+            "name": "spawn_agent",
+            "description": "Spawn a sub-agent",
+            "parameters": {
+                "type": "object",
+                "properties": {},
+                "required": [],
+                "additionalProperties": false
+            }
+        })
+    );
+}
+
+#[test]
+fn map_chat_to_responses_request_normalizes_top_level_function_tool() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {"role":"user","content":"hello"}
+        ],
+        "tools": [
+            {
+                "type": "function",
+                "name": "spawn_agent",
+                "description": "Spawn a sub-agent"
+            }
+        ]
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    assert_eq!(out["tools"][0]["type"], "function");
+    assert_eq!(out["tools"][0]["name"], "spawn_agent");
+    assert!(out["tools"][0].get("function").is_none());
+    assert_eq!(out["tools"][0]["parameters"]["required"], json!([]));
+}
+
+fn responses_input_items(out: &Value) -> &[Value] {
+    out.get("input")
+        .and_then(Value::as_array)
+        .expect("input array")
+}
+
+fn responses_input_item<'a>(items: &'a [Value], item_type: &str) -> Option<&'a Value> {
+    items
+        .iter()
+        .find(|item| item.get("type").and_then(Value::as_str) == Some(item_type))
+}
+
+#[test]
+fn map_chat_to_responses_request_preserves_assistant_tool_calls() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [
+                    {
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {
+                            "name": "get_weather",
+                            "arguments": "{\"city\":\"seoul\"}"
+                        }
+                    }
+                ]
+            }
+        ],
+        "stream": false
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    let items = responses_input_items(&out);
+    let item = responses_input_item(items, "function_call").expect("function_call item");
+    assert_eq!(item["call_id"], "call_1");
+    assert_eq!(item["name"], "get_weather");
+    assert_eq!(item["arguments"], "{\"city\":\"seoul\"}");
+}
+
+#[test]
+fn map_chat_to_responses_request_repairs_invalid_assistant_tool_call_arguments() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [
+                    {
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {
+                            "name": "exec_command",
+                            "arguments": "{"
+                        }
+                    }
+                ]
+            }
+        ],
+        "stream": false
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    let items = responses_input_items(&out);
+    let item = responses_input_item(items, "function_call").expect("function_call item");
+    assert_eq!(item["call_id"], "call_1");
+    assert_eq!(item["name"], "exec_command");
+    assert_eq!(item["arguments"], "{}");
+}
+
+#[test]
+fn map_chat_to_responses_request_generates_unique_tool_call_ids_when_missing() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {
+                "role": "assistant",
+                "content": "",
+                "tool_calls": [
+                    {
+                        "type": "function",
+                        "function": {
+                            "name": "tool_a",
+                            "arguments": "{}"
+                        }
+                    },
+                    {
+                        "type": "function",
+                        "function": {
+                            "name": "tool_b",
+                            "arguments": "{\"x\":1}"
+                        }
+                    }
+                ]
+            }
+        ],
+        "stream": false
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    let items = responses_input_items(&out);
+    let first = responses_input_item(items, "function_call").expect("first function_call item");
+    assert_eq!(first["name"], "tool_a");
+    assert_eq!(first["call_id"], "call_m0_t0");
+
+    let second = items
+        .iter()
+        .filter(|item| item.get("type").and_then(Value::as_str) == Some("function_call"))
+        .nth(1)
+        .expect("second function_call item");
+    assert_eq!(second["name"], "tool_b");
+    assert_eq!(second["call_id"], "call_m0_t1");
+    assert_ne!(first["call_id"], second["call_id"]);
+}
+
+#[test]
+fn map_chat_to_responses_request_preserves_assistant_reasoning() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {
+                "role": "assistant",
+                "content": "final answer",
+                "reasoning_content": "internal chain"
+            }
+        ],
+        "stream": false
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    let items = responses_input_items(&out);
+    let item = responses_input_item(items, "reasoning").expect("reasoning item");
+    assert_eq!(item["summary"][0]["type"], "summary_text");
+    assert_eq!(item["summary"][0]["text"], "internal chain");
+}
+
+#[test]
+fn map_chat_to_responses_request_preserves_multimodal_content() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {
+                "role": "user",
+                "content": [
+                    {"type":"text","text":"hello"},
+                    {"type":"image_url","image_url":{"url":"https://example.com/cat.png"}},
+                    {"type":"input_audio","input_audio":{"data":"aGVsbG8="}}
+                ]
+            }
+        ],
+        "stream": false
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    let items = responses_input_items(&out);
+    let message = responses_input_item(items, "message").expect("message item");
+    let content = message["content"].as_array().expect("content array");
+    assert_eq!(content[0]["type"], "input_text");
+    assert_eq!(content[0]["text"], "hello");
+    assert_eq!(content[1]["type"], "input_image");
+    assert_eq!(content[1]["image_url"], "https://example.com/cat.png");
+    assert_eq!(content[2]["type"], "input_audio");
+    assert_eq!(content[2]["input_audio"]["data"], "aGVsbG8=");
+}
+
+#[test]
+fn map_chat_to_responses_request_maps_tool_messages_to_function_call_output() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {
+                "role": "tool",
+                "tool_call_id": "call_2",
+                "content": "{\"ok\":true}"
+            }
+        ],
+        "stream": false
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    let items = responses_input_items(&out);
+    let item =
+        responses_input_item(items, "function_call_output").expect("function_call_output item");
+    assert_eq!(item["call_id"], "call_2");
+    assert_eq!(item["output"], "{\"ok\":true}");
+}
+
+#[test]
+fn map_chat_to_responses_request_preserves_mixed_assistant_content_and_tool_calls() {
+    let chat = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {
+                "role": "assistant",
+                "content": "I will check that now.",
+                "tool_calls": [
+                    {
+                        "id": "call_3",
+                        "type": "function",
+                        "function": {
+                            "name": "get_weather",
+                            "arguments": "{\"city\":\"seoul\"}"
+                        }
+                    }
+                ]
+            }
+        ],
+        "stream": false
+    });
+
+    let out = map_chat_to_responses_request(&chat, false).expect("ok");
+    let items = responses_input_items(&out);
+    let message = responses_input_item(items, "message").expect("assistant message");
+    assert_eq!(message["role"], "assistant");
+    assert_eq!(message["content"][0]["text"], "I will check that now.");
+
+    let tool_call = responses_input_item(items, "function_call").expect("function_call item");
+    assert_eq!(tool_call["call_id"], "call_3");
+    assert_eq!(tool_call["name"], "get_weather");
+}
+
+#[test]
+fn previous_response_id_for_request_ignores_blank_values() {
+    let request = json!({
+        "previous_response_id": "   "
+    });
+    assert_eq!(previous_response_id_for_request(&request), None);
+
+    let request = json!({
+        "previous_response_id": "resp_123"
+    });
+    assert_eq!(previous_response_id_for_request(&request), Some("resp_123"));
+}
+
+#[test]
+fn resolve_previous_messages_for_request_errors_when_not_found() {
+    let request = json!({
+        "previous_response_id": "resp_missing"
+    });
+    let sessions = SessionStore::default();
+    let err = resolve_previous_messages_for_request(&request, &sessions).expect_err("must fail");
+    assert!(err.to_string().contains("unknown `previous_response_id`"));
+}
+
+#[test]
+fn memory_session_backend_evicts_oldest_sessions_past_capacity() {
+    let mut sessions = SessionStore::new(MemorySessionBackend::new(2, None));
+    for id in ["resp_1", "resp_2", "resp_3"] {
+        sessions
+            .insert_messages(id.to_string(), vec![json!({"role":"user","content":id})])
+            .expect("insert");
+    }
+
+    assert!(sessions.get_messages("resp_1").is_none());
+    assert_eq!(
+        sessions.get_messages("resp_3").expect("kept")[0]["content"],
+        "resp_3"
+    );
+}
+
+#[test]
+fn session_policy_validates_backend_capacity_and_ttl() {
+    assert_eq!(
+        SessionPolicy::from_config(None).expect("default"),
+        SessionPolicy::default()
+    );
+    let policy = SessionPolicy::from_config(Some(&SessionConfig {
+        backend: Some(SessionBackendKind::Jsonl),
+        path: Some(PathBuf::from("/tmp/sessions.jsonl")),
+        capacity: Some(10),
+        ttl_secs: Some(60),
+    }))
+    .expect("jsonl policy");
+    assert_eq!(policy.capacity, 10);
+    assert_eq!(policy.ttl, Some(Duration::from_secs(60)));
+
+    for (config, message) in [
+        (
+            SessionConfig {
+                backend: Some(SessionBackendKind::Jsonl),
+                ..Default::default()
+            },
+            "requires `path`",
+        ),
+        (
+            SessionConfig {
+                capacity: Some(0),
+                ..Default::default()
+            },
+            "capacity must be greater than 0",
+        ),
+        (
+            SessionConfig {
+                ttl_secs: Some(0),
+                ..Default::default()
+            },
+            "ttl_secs must be greater than 0",
+        ),
+    ] {
+        let err = SessionPolicy::from_config(Some(&config)).expect_err("invalid policy");
+        assert!(err.to_string().contains(message), "{err}");
+    }
+}
+
+#[test]
+fn jsonl_session_backend_restores_sessions_after_restart() {
+    let dir = std::env::temp_dir().join(format!("codex-chat-bridge-sessions-{}", Uuid::now_v7()));
+    let path = dir.join("sessions.jsonl");
+    let policy = SessionPolicy {
+        backend: SessionBackendKind::Jsonl,
+        path: Some(path.clone()),
+        capacity: 2,
+        ttl: Some(Duration::from_secs(3600)),
+    };
+
+    let mut sessions = SessionStore::open(&policy).expect("open");
+    for id in ["resp_1", "resp_2", "resp_3", "resp_4", "resp_5"] {
+        sessions
+            .insert_messages(id.to_string(), vec![json!({"role":"user","content":id})])
+            .expect("insert");
+    }
+    drop(sessions);
+    let mut raw = fs::read_to_string(&path).expect("session file");
+    assert!(
+        raw.lines().count() <= 4,
+        "file should be compacted past twice the capacity:\n{raw}"
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path).expect("metadata").permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    raw.push_str("{\"response_id\":\"resp_expired\",\"created_at_ms\":0,\"messages\":[]}\n");
+    raw.push_str("{truncated\n");
+    fs::write(&path, raw).expect("rewrite session file");
+
+    let sessions = SessionStore::open(&policy).expect("reopen");
+    assert!(sessions.get_messages("resp_3").is_none());
+    assert!(sessions.get_messages("resp_expired").is_none());
+    assert!(sessions.get_messages("resp_4").is_some());
+    assert_eq!(
+        sessions.get_messages("resp_5").expect("restored")[0]["content"],
+        "resp_5"
+    );
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn jsonl_session_backend_replays_deletions() {
+    let dir = std::env::temp_dir().join(format!("codex-chat-bridge-sessions-{}", Uuid::now_v7()));
+    let policy = SessionPolicy {
+        backend: SessionBackendKind::Jsonl,
+        path: Some(dir.join("sessions.jsonl")),
+        capacity: 8,
+        ttl: None,
+    };
+
+    let mut sessions = SessionStore::open(&policy).expect("open");
+    for id in ["resp_1", "resp_2"] {
+        sessions
+            .insert_messages(id.to_string(), vec![json!({"role":"user","content":id})])
+            .expect("insert");
+    }
+    assert!(sessions.remove_session("resp_1").expect("remove"));
+    assert!(!sessions.remove_session("resp_1").expect("remove again"));
+    drop(sessions);
+
+    let sessions = SessionStore::open(&policy).expect("reopen");
+    assert!(sessions.get_messages("resp_1").is_none());
+    assert!(sessions.get_messages("resp_2").is_some());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[test]
+fn merge_previous_messages_prepends_history_messages() {
+    let mut payload = json!({
+        "messages": [
+            {"role":"user","content":"new"}
+        ]
+    });
+    let previous = vec![
+        json!({"role":"system","content":"old-system"}),
+        json!({"role":"user","content":"old-user"}),
+    ];
+
+    merge_previous_messages(&mut payload, previous).expect("ok");
+
+    let messages = payload["messages"].as_array().expect("array");
+    assert_eq!(messages.len(), 3);
+    assert_eq!(messages[0]["content"], "old-system");
+    assert_eq!(messages[1]["content"], "old-user");
+    assert_eq!(messages[2]["content"], "new");
+}
+
+#[test]
+fn upstream_messages_for_logging_reads_chat_messages() {
+    let payload = json!({
+        "model": "gpt-4.1",
+        "messages": [
+            {"role":"user","content":"hi"},
+            {"role":"assistant","content":"hello"}
+        ]
+    });
+
+    let out = upstream_messages_for_logging(WireApi::Chat, &payload).expect("messages");
+    let messages = out.as_array().expect("array");
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["role"], "user");
+}
+
+#[test]
+fn upstream_messages_for_logging_filters_responses_input_items() {
+    let payload = json!({
+        "model": "gpt-4.1",
+        "input": [
+            {"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]},
+            {"type":"function_call_output","call_id":"call_1","output":"ok"}
+        ]
+    });
+
+    let out = upstream_messages_for_logging(WireApi::Responses, &payload).expect("messages");
+    let messages = out.as_array().expect("array");
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["type"], "message");
+    assert_eq!(messages[0]["role"], "user");
+}
+
+#[test]
+fn tool_types_for_logging_includes_type_and_name_labels() {
+    let payload = json!({
+        "tools": [
+            {"type":"function","name":"f1"},
+            {"type":"function","function":{"name":"f2"}},
+            {"type":"custom","name":"shell"},
+            {"type":"web_search_preview"},
+            {"name":"missing_type"},
+            {}
+        ]
+    });
+
+    let out = tool_types_for_logging(&payload);
+    assert_eq!(
+        out,
+        json!([
+            "function(f1)",
+            "function(f2)",
+            "custom(shell)",
+            "web_search_preview",
+            "<missing_type>(missing_type)",
+            "<missing_type>"
+        ])
+    );
+}
+
+#[test]
+fn tool_definitions_for_logging_defaults_required_and_normalizes_null() {
+    let payload = json!({
+        "tools": [
+            {"type":"function","function":{"name":"missing","parameters":{"type":"object"}}},
+            {"type":"function","function":{"name":"null","parameters":{"type":"object","required":null}}},
+            {"type":"function","function":{"name":"spawn_agent"}}
+        ]
+    });
+
+    let out = tool_definitions_for_logging(&payload);
+    assert_eq!(out[0]["required"], json!([]));
+    assert_eq!(out[1]["required"], json!([]));
+    assert_eq!(
+        out[2],
+        json!({
+            "name": "spawn_agent",
+            "required": [],
+            "additionalProperties": false
+        })
+    );
+}
+
+#[test]
+fn request_fields_for_logging_sorts_top_level_keys() {
+    let payload = json!({
+        "stream": true,
+        "model": "gpt-4.1",
+        "input": []
+    });
+
+    let out = request_fields_for_logging(&payload);
+    assert_eq!(out, json!(["input", "model", "stream"]));
+}
+
+#[test]
+fn split_log_chunks_splits_large_ascii_payloads() {
+    let chunks = split_log_chunks_by_bytes("abcdef", 2);
+
+    assert_eq!(chunks, vec!["ab", "cd", "ef"]);
+}
+
+#[test]
+fn split_log_chunks_keeps_utf8_boundaries() {
+    let input = "가나다";
+    let chunks = split_log_chunks_by_bytes(input, 4);
+
+    assert_eq!(chunks, vec!["가", "나", "다"]);
+    assert_eq!(chunks.join(""), input);
+}
+
+#[test]
+fn upstream_headers_for_logging_includes_forwarded_headers() {
+    let mut headers = HeaderMap::new();
+    headers.insert("openai-organization", HeaderValue::from_static("org_123"));
+    headers.insert("x-client-request-id", HeaderValue::from_static("trace_123"));
+    headers.insert("x-openai-subagent", HeaderValue::from_static("subagent_1"));
+    let configured_headers = vec![
+        UpstreamHeader {
+            name: "x-custom-header".to_string(),
+            value: "hello".to_string(),
+        },
+        UpstreamHeader {
+            name: "authorization".to_string(),
+            value: "Bearer secret".to_string(),
+        },
+    ];
+    let forwarded_headers = vec![
+        "openai-organization".to_string(),
+        "x-client-request-id".to_string(),
+        "x-openai-subagent".to_string(),
+    ];
+
+    let out = upstream_headers_for_logging(
+        &headers,
+        &AuthScheme::Bearer,
+        "sk-test",
+        WireApi::Chat,
+        &configured_headers,
+        &forwarded_headers,
+    );
+    assert_eq!(out["authorization"], "<redacted>");
+    assert_eq!(out["content-type"], "application/json");
+    assert_eq!(out["openai-organization"], "org_123");
+    assert_eq!(out["x-client-request-id"], "trace_123");
+    assert_eq!(out["x-openai-subagent"], "subagent_1");
+    assert_eq!(out["x-custom-header"], "hello");
+}
+
+#[test]
+fn upstream_headers_for_logging_marks_empty_api_key() {
+    let headers = HeaderMap::new();
+
+    let out =
+        upstream_headers_for_logging(&headers, &AuthScheme::Bearer, "", WireApi::Chat, &[], &[]);
+    assert_eq!(out["authorization"], "Bearer <empty>");
+    assert_eq!(out["content-type"], "application/json");
+}
+
+#[test]
+fn upstream_headers_for_logging_uses_router_auth_scheme() {
+    let headers = HeaderMap::new();
+
+    let out = upstream_headers_for_logging(
+        &headers,
+        &AuthScheme::XApiKey,
+        "sk-ant",
+        WireApi::Messages,
+        &[],
+        &[],
+    );
+    assert_eq!(out["x-api-key"], "<redacted>");
+    assert_eq!(out["anthropic-version"], DEFAULT_ANTHROPIC_VERSION);
+    assert!(out.get("authorization").is_none());
+
+    let out =
+        upstream_headers_for_logging(&headers, &AuthScheme::None, "", WireApi::Chat, &[], &[]);
+    assert!(out.get("authorization").is_none());
+}
+
+#[test]
+fn headers_for_logging_redacts_sensitive_headers() {
+    let mut headers = HeaderMap::new();
+    headers.insert("authorization", HeaderValue::from_static("Bearer sk-test"));
+    headers.insert("cookie", HeaderValue::from_static("sid=abc"));
+    headers.insert("x-trace-id", HeaderValue::from_static("trace-1"));
+
+    let out = headers_for_logging(&headers);
+    assert_eq!(out["authorization"], "<redacted>");
+    assert_eq!(out["cookie"], "<redacted>");
+    assert_eq!(out["x-trace-id"], "trace-1");
+}
+
+#[test]
+fn capability_gate_allows_extended_responses_fields() {
+    let request = json!({
+        "model": "gpt-4.1",
+        "input": [],
+        "reasoning": {"effort":"medium"},
+        "include": ["reasoning.encrypted_content"],
+        "text": {"format":{"type":"json_object"}},
+        "service_tier": "default"
+    });
+
+    let out = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request);
+    assert!(out.is_ok());
+}
+
+#[test]
+fn capability_gate_rejects_invalid_max_output_tokens_type() {
+    let request = json!({
+        "model": "gpt-4.1",
+        "input": [],
+        "max_output_tokens": "bad"
+    });
+
+    let err = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request)
+        .expect_err("must fail");
+    assert!(err.to_string().contains("max_output_tokens"));
+}
+
+#[test]
+fn capability_gate_rejects_invalid_metadata_type() {
+    let request = json!({
+        "model": "gpt-4.1",
+        "input": [],
+        "metadata": ["bad"]
+    });
+
+    let err = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request)
+        .expect_err("must fail");
+    assert!(err.to_string().contains("metadata"));
+}
+
+#[test]
+fn capability_gate_allows_basic_responses_request() {
+    let request = json!({
+        "model": "gpt-4.1",
+        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
+        "tools": [{"type":"function","name":"f","parameters":{"type":"object"}}]
+    });
+
+    let out = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request);
+    assert!(out.is_ok());
+}
+
+#[test]
+fn capability_gate_allows_prompt_cache_key() {
+    let request = json!({
+        "model": "gpt-4.1",
+        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
+        "prompt_cache_key": "session-key-1"
+    });
+
+    let out = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request);
+    assert!(out.is_ok());
+}
+
+#[test]
+fn capability_gate_allows_mcp_and_web_search_tool_types() {
+    let request = json!({
+        "model": "gpt-4.1",
+        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
+        "tools": [
+            {"type":"namespace","namespace":"functions","tools":[]},
+            {"type":"mcp","server_label":"s","server_url":"http://localhost/mcp"},
+            {"type":"web_search"},
+            {"type":"web_search_preview"}
+        ]
+    });
+
+    let out = validate_capability_gate(IncomingApi::Responses, WireApi::Chat, true, &request);
+    assert!(out.is_ok());
+}
+
+#[test]
+fn maps_responses_request_with_namespace_tool() {
+    let input = json!({
+        "model": "gpt-4.1",
+        "input": [{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}],
+        "tools": [{
+            "type": "namespace",
+            "namespace": "functions",
+            "tools": [{
+                "name": "exec_command",
+                "description": "Run a command",
+                "parameters": {"type":"object","properties":{"cmd":{"type":"string"}}}
+            }]
+        }]
+    });
+
+    let req = map_responses_to_chat_request_with_stream(
+        &input,
+        &HashSet::new(),
+        true,
+        true,
+        ToolTransformMode::LegacyConvert,
+    )
+    .expect("namespace tool should map");
+    assert_eq!(req.chat_request["tools"][0]["type"], "function");
+    assert_eq!(
+        req.chat_request["tools"][0]["function"]["name"],
+        "exec_command"
+    );
+    assert_eq!(
+        req.chat_request["tools"][0]["function"]["parameters"]["required"],
+        json!([])
+    );
+}
+
+#[test]
+fn namespace_tools_are_tracked_as_function_calls_by_name() {
+    let request = json!({
+        "tools": [{
+            "type": "namespace",
+            "namespace": "functions",
+            "tools": [{"name": "exec_command", "parameters": {"type": "object"}}]
+        }]
+    });
+
+    let kinds = responses_tool_call_kind_by_name(&request);
+    assert_eq!(
+        kinds.get("exec_command"),
+        Some(&ResponsesToolCallKind::Function)
+    );
+}
+
+#[test]
+fn normalize_upstream_error_maps_known_codes() {
+    let payload = r#"{"error":{"code":"context_length_exceeded","message":"too long"}}"#;
+    let err = normalize_upstream_error_payload(StatusCode::BAD_REQUEST, payload);
+    assert_eq!(err.code, "context_window_exceeded");
+    assert_eq!(err.message, "too long");
+}
+
+#[test]
+fn build_upstream_request_prefers_static_header_on_duplicate_key() {
+    let router_manager = RouterManager::new(
+        BTreeMap::new(),
+        "http://localhost:8080/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager");
+    let state = Arc::new(AppState {
+        client: Client::new(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
+        http_shutdown: false,
+        verbose_logging: false,
+        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
+        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    });
+    let route_target = RouteTarget {
+        router_name: "default".to_string(),
+        upstream_url: "http://localhost:8080/v1/chat/completions".to_string(),
+        upstream_wire: WireApi::Chat,
+        upstream_model: None,
+        upstream_model_opus: None,
+        upstream_model_sonnet: None,
+        upstream_model_haiku: None,
+        upstream_http_headers: vec![UpstreamHeader {
+            name: "x-request-id".to_string(),
+            value: "from-upstream-http-headers".to_string(),
+        }],
+        forward_incoming_headers: vec![
+            "x-request-id".to_string(),
+            "x-client-request-id".to_string(),
+        ],
+        drop_tool_types: HashSet::new(),
+        drop_request_fields: HashSet::new(),
+        feature_flags: FeatureFlags::default(),
+        anthropic_preserve_thinking: false,
+        anthropic_enable_openrouter_reasoning: false,
+        api_key: None,
+        auth_scheme: AuthScheme::Bearer,
+        retry: RetryPolicy::default(),
+        fallbacks: Vec::new(),
+        weight: 1,
+        load_balancing: None,
+        timeouts: TimeoutPolicy::default(),
+        client: None,
+        inbound_auth: None,
+        ollama: OllamaConfig::default(),
+        message_roles: MessageRolesConfig::default(),
+        models: Vec::new(),
+        merge_upstream_models: false,
+        tokenizer: None,
+    };
+    let mut incoming_headers = HeaderMap::new();
+    incoming_headers.insert(
+        "x-request-id",
+        HeaderValue::from_static("from-forward-incoming-headers"),
+    );
+    incoming_headers.insert(
+        "x-client-request-id",
+        HeaderValue::from_static("client-trace-123"),
+    );
+
+    let request = build_upstream_request(
+        &state,
+        &route_target,
+        "test-key",
+        &incoming_headers,
+        &json!({"model":"gpt-4.1","messages":[]}),
+        None,
+    )
+    .build()
+    .expect("request");
+    let values = request.headers().get_all("x-request-id");
+    let collected = values.iter().collect::<Vec<_>>();
+
+    assert_eq!(collected.len(), 1);
+    assert_eq!(collected[0], "from-upstream-http-headers");
+    assert_eq!(
+        request
+            .headers()
+            .get("x-client-request-id")
+            .and_then(|value| value.to_str().ok()),
+        Some("client-trace-123")
+    );
+}
+
+#[test]
+fn upstream_url_for_messages_count_tokens_appends_subpath() {
+    let route_target = RouteTarget {
+        router_name: "messages".to_string(),
+        upstream_url: "https://api.anthropic.com/v1/messages".to_string(),
+        upstream_wire: WireApi::Messages,
+        upstream_model: Some("ignored-model".to_string()),
+        upstream_model_opus: None,
+        upstream_model_sonnet: None,
+        upstream_model_haiku: None,
+        upstream_http_headers: Vec::new(),
+        forward_incoming_headers: Vec::new(),
+        drop_tool_types: HashSet::new(),
+        drop_request_fields: HashSet::new(),
+        feature_flags: FeatureFlags::default(),
+        anthropic_preserve_thinking: false,
+        anthropic_enable_openrouter_reasoning: false,
+        api_key: None,
+        auth_scheme: AuthScheme::XApiKey,
+        retry: RetryPolicy::default(),
+        fallbacks: Vec::new(),
+        weight: 1,
+        load_balancing: None,
+        timeouts: TimeoutPolicy::default(),
+        client: None,
+        inbound_auth: None,
+        ollama: OllamaConfig::default(),
+        message_roles: MessageRolesConfig::default(),
+        models: Vec::new(),
+        merge_upstream_models: false,
+        tokenizer: None,
+    };
+
+    assert_eq!(
+        upstream_url_for_request(&route_target, Some("/v1/messages/count_tokens")),
+        "https://api.anthropic.com/v1/messages/count_tokens"
+    );
+    assert_eq!(
+        upstream_url_for_request(&route_target, Some("/v1/messages")),
+        "https://api.anthropic.com/v1/messages"
+    );
+}
+
+#[test]
+fn build_upstream_request_uses_messages_count_tokens_url_and_existing_headers() {
+    let router_manager = RouterManager::new(
+        BTreeMap::new(),
+        "http://localhost:8080/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager");
+    let state = Arc::new(AppState {
+        client: Client::new(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
+        http_shutdown: false,
+        verbose_logging: false,
+        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
+        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    });
+    let route_target = RouteTarget {
+        router_name: "messages".to_string(),
+        upstream_url: "https://api.anthropic.com/v1/messages".to_string(),
+        upstream_wire: WireApi::Messages,
+        upstream_model: None,
+        upstream_model_opus: None,
+        upstream_model_sonnet: None,
+        upstream_model_haiku: None,
+        upstream_http_headers: vec![UpstreamHeader {
+            name: "x-request-id".to_string(),
+            value: "from-upstream-http-headers".to_string(),
+        }],
+        forward_incoming_headers: vec!["x-request-id".to_string(), "anthropic-version".to_string()],
+        drop_tool_types: HashSet::new(),
+        drop_request_fields: HashSet::new(),
+        feature_flags: FeatureFlags::default(),
+        anthropic_preserve_thinking: false,
+        anthropic_enable_openrouter_reasoning: false,
+        api_key: None,
+        auth_scheme: AuthScheme::XApiKey,
+        retry: RetryPolicy::default(),
+        fallbacks: Vec::new(),
+        weight: 1,
+        load_balancing: None,
+        timeouts: TimeoutPolicy::default(),
+        client: None,
+        inbound_auth: None,
+        ollama: OllamaConfig::default(),
+        message_roles: MessageRolesConfig::default(),
+        models: Vec::new(),
+        merge_upstream_models: false,
+        tokenizer: None,
+    };
+    let mut incoming_headers = HeaderMap::new();
+    incoming_headers.insert(
+        "x-request-id",
+        HeaderValue::from_static("from-forward-incoming-headers"),
+    );
+    incoming_headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+
+    let request = build_upstream_request(
+        &state,
+        &route_target,
+        "test-key",
+        &incoming_headers,
+        &json!({"model":"claude","messages":[]}),
+        Some("/v1/messages/count_tokens"),
+    )
+    .build()
+    .expect("request");
+
+    assert_eq!(
+        request.url().as_str(),
+        "https://api.anthropic.com/v1/messages/count_tokens"
+    );
+    assert_eq!(
+        request
+            .headers()
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok()),
+        Some("from-upstream-http-headers")
+    );
+    assert_eq!(
+        request
+            .headers()
+            .get("anthropic-version")
+            .and_then(|value| value.to_str().ok()),
+        Some("2023-06-01")
+    );
+}
+
+#[test]
+fn build_upstream_request_applies_router_auth_scheme() {
+    let router_manager = RouterManager::new(
+        BTreeMap::new(),
+        "http://localhost:8080/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager");
+    let state = Arc::new(AppState {
+        client: Client::new(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
+        http_shutdown: false,
+        verbose_logging: false,
+        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
+        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    });
+    let mut route_target = RouteTarget {
+        router_name: "messages".to_string(),
+        upstream_url: "https://api.anthropic.com/v1/messages".to_string(),
+        upstream_wire: WireApi::Messages,
+        upstream_model: None,
+        upstream_model_opus: None,
+        upstream_model_sonnet: None,
+        upstream_model_haiku: None,
+        upstream_http_headers: Vec::new(),
+        forward_incoming_headers: Vec::new(),
+        drop_tool_types: HashSet::new(),
+        drop_request_fields: HashSet::new(),
+        feature_flags: FeatureFlags::default(),
+        anthropic_preserve_thinking: false,
+        anthropic_enable_openrouter_reasoning: false,
+        api_key: None,
+        auth_scheme: AuthScheme::XApiKey,
+        retry: RetryPolicy::default(),
+        fallbacks: Vec::new(),
+        weight: 1,
+        load_balancing: None,
+        timeouts: TimeoutPolicy::default(),
+        client: None,
+        inbound_auth: None,
+        ollama: OllamaConfig::default(),
+        message_roles: MessageRolesConfig::default(),
+        models: Vec::new(),
+        merge_upstream_models: false,
+        tokenizer: None,
+    };
+    let header = |request: &reqwest::Request, name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string)
+    };
+
+    let request = build_upstream_request(
+        &state,
+        &route_target,
+        "sk-ant",
+        &HeaderMap::new(),
+        &json!({"model":"claude","messages":[]}),
+        Some("/v1/messages"),
+    )
+    .build()
+    .expect("request");
+    assert_eq!(header(&request, "x-api-key"), Some("sk-ant".to_string()));
+    assert_eq!(header(&request, "authorization"), None);
+    assert_eq!(
+        header(&request, "anthropic-version"),
+        Some(DEFAULT_ANTHROPIC_VERSION.to_string())
+    );
+
+    route_target.upstream_wire = WireApi::Chat;
+    route_target.auth_scheme = AuthScheme::Header("api-key".to_string());
+    let request = build_upstream_request(
+        &state,
+        &route_target,
+        "azure-key",
+        &HeaderMap::new(),
+        &json!({"model":"gpt","messages":[]}),
+        None,
+    )
+    .build()
+    .expect("request");
+    assert_eq!(header(&request, "api-key"), Some("azure-key".to_string()));
+    assert_eq!(header(&request, "authorization"), None);
+    assert_eq!(header(&request, "anthropic-version"), None);
+
+    route_target.auth_scheme = AuthScheme::None;
+    let request = build_upstream_request(
+        &state,
+        &route_target,
+        "",
+        &HeaderMap::new(),
+        &json!({"model":"gpt","messages":[]}),
+        None,
+    )
+    .build()
+    .expect("request");
+    assert_eq!(header(&request, "authorization"), None);
+}
+
+fn test_state_with_router(
+    incoming_url: &str,
+    upstream_url: &str,
+    upstream_wire: WireApi,
+) -> Arc<AppState> {
+    test_state_with_routers(BTreeMap::from([(
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some(incoming_url.to_string()),
+            upstream_url: Some(upstream_url.to_string()),
+            upstream_wire: Some(upstream_wire),
+            ..Default::default()
+        },
+    )]))
+}
+
+fn test_state_with_routers(routers: BTreeMap<String, RouterConfig>) -> Arc<AppState> {
+    let router_manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager");
+
+    Arc::new(AppState {
+        client: Client::new(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
+        http_shutdown: false,
+        verbose_logging: true,
+        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
+        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    })
+}
+
+#[derive(Clone)]
+struct MockJsonUpstreamState {
+    response: Value,
+    captured_request: Arc<tokio::sync::Mutex<Option<Value>>>,
+}
+
+async fn mock_json_upstream_handler(
+    AxumState(state): AxumState<MockJsonUpstreamState>,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    *state.captured_request.lock().await = Some(body);
+    Json(state.response)
+}
+
+async fn spawn_mock_json_upstream(
+    path: &str,
+    response: Value,
+) -> (
+    String,
+    tokio::task::JoinHandle<()>,
+    Arc<tokio::sync::Mutex<Option<Value>>>,
+) {
+    let captured_request = Arc::new(tokio::sync::Mutex::new(None));
+    let state = MockJsonUpstreamState {
+        response,
+        captured_request: captured_request.clone(),
+    };
+    let app = axum::Router::new()
+        .route(path, post(mock_json_upstream_handler))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind mock upstream");
+    let addr = listener.local_addr().expect("mock upstream address");
+    let handle = tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    (format!("http://{addr}{path}"), handle, captured_request)
+}
+
+#[derive(Clone, Debug)]
+struct CapturedJsonRequest {
+    headers: HeaderMap,
+    body: Value,
+}
+
+#[derive(Clone)]
+struct MockJsonUpstreamWithHeadersState {
+    response: Value,
+    captured_request: Arc<tokio::sync::Mutex<Option<CapturedJsonRequest>>>,
+}
+
+async fn mock_json_upstream_with_headers_handler(
+    AxumState(state): AxumState<MockJsonUpstreamWithHeadersState>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Json<Value> {
+    *state.captured_request.lock().await = Some(CapturedJsonRequest { headers, body });
+    Json(state.response)
+}
+
+async fn spawn_mock_json_upstream_with_headers(
+    path: &str,
+    response: Value,
+) -> (
+    String,
+    tokio::task::JoinHandle<()>,
+    Arc<tokio::sync::Mutex<Option<CapturedJsonRequest>>>,
+) {
+    let captured_request = Arc::new(tokio::sync::Mutex::new(None));
+    let state = MockJsonUpstreamWithHeadersState {
+        response,
+        captured_request: captured_request.clone(),
+    };
+    let app = axum::Router::new()
+        .route(path, post(mock_json_upstream_with_headers_handler))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind mock upstream");
+    let addr = listener.local_addr().expect("mock upstream address");
+    let handle = tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+    (format!("http://{addr}{path}"), handle, captured_request)
+}
+
+#[tokio::test]
+async fn direct_anthropic_endpoint_routes_using_request_path() {
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/v1/messages",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
+    ));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/messages")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"model":"claude-sonnet","messages":[{"role":"user","content":"hi"}],"stream":false}"#,
+                ))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+
+    assert_eq!(json["type"], "error");
+    assert_eq!(json["error"]["type"], "upstream_transport_error");
+}
+
+#[tokio::test]
+async fn routed_request_without_resolved_api_key_returns_auth_error() {
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/v1/chat/completions",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
+    );
+    let state = Arc::new(AppState {
+        api_key: ApiKey::Unavailable("missing or empty env var: OPENAI_API_KEY".to_string()),
+        ..(*state).clone()
+    });
+
+    let response = build_app(state)
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"model":"gpt-4.1","messages":[{"role":"user","content":"hi"}],"stream":false}"#,
+                ))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+
+    assert_eq!(json["error"]["type"], "upstream_auth_error");
+    assert!(
+        json["error"]["message"]
+            .as_str()
+            .is_some_and(|message| message.contains("router `default`"))
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn messages_wire_overrides_model_and_preserves_header_behavior() {
+    let upstream_response = json!({
+        "id": "msg_1",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-native",
+        "content": [{"type":"text","text":"hello"}],
+        "usage": {"input_tokens": 1, "output_tokens": 2}
+    });
+    let (upstream_url, upstream_handle, captured_request) =
+        spawn_mock_json_upstream_with_headers("/v1/messages", upstream_response.clone()).await;
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "native_messages".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/messages".to_string()),
+            upstream_url: Some(upstream_url),
+            upstream_wire: Some(WireApi::Messages),
+            upstream_model: Some("upstream-claude-model".to_string()),
+            upstream_http_headers: Some(BTreeMap::from([
+                ("x-request-id".to_string(), "static-request-id".to_string()),
+                ("x-static".to_string(), "static-value".to_string()),
+            ])),
+            forward_incoming_headers: Some(vec![
+                "x-request-id".to_string(),
+                "x-client-request-id".to_string(),
+                "anthropic-version".to_string(),
+            ]),
+            ..Default::default()
+        },
+    );
+    let router_manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager");
+    let app = build_app(Arc::new(AppState {
+        client: Client::new(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
+        http_shutdown: false,
+        verbose_logging: false,
+        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
+        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    }));
+    let request_body = json!({
+        "model": "claude-original",
+        "messages": [{
+            "role": "assistant",
+            "content": [
+                {"type":"thinking","thinking":"internal"},
+                {"type":"text","text":"hi"}
+            ],
+            "reasoning_content": "provider-field"
+        }],
+        "tools": [{"name":"shell","input_schema":{"type":"object"}}]
+    });
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/messages")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .header("x-request-id", "incoming-request-id")
+                .header("x-client-request-id", "client-trace-123")
+                .header("anthropic-version", "2023-06-01")
+                .body(Body::from(request_body.to_string()))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    upstream_handle.abort();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+    let captured = captured_request
+        .lock()
+        .await
+        .clone()
+        .expect("captured request");
+
+    let mut expected_body = request_body.clone();
+    expected_body["model"] = json!("upstream-claude-model");
+    assert_eq!(captured.body, expected_body);
+    assert_eq!(json, upstream_response);
+    assert_eq!(
+        captured
+            .headers
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok()),
+        Some("static-request-id")
+    );
+    assert_eq!(
+        captured
+            .headers
+            .get("x-client-request-id")
+            .and_then(|value| value.to_str().ok()),
+        Some("client-trace-123")
+    );
+    assert_eq!(
+        captured
+            .headers
+            .get("anthropic-version")
+            .and_then(|value| value.to_str().ok()),
+        Some("2023-06-01")
+    );
+    assert_eq!(
+        captured
+            .headers
+            .get("x-static")
+            .and_then(|value| value.to_str().ok()),
+        Some("static-value")
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn messages_wire_bypasses_count_tokens_to_upstream() {
+    let upstream_response = json!({"input_tokens": 42});
+    let (upstream_base_url, upstream_handle, captured_request) =
+        spawn_mock_json_upstream_with_headers(
+            "/v1/messages/count_tokens",
+            upstream_response.clone(),
+        )
+        .await;
+    let upstream_url = upstream_base_url
+        .strip_suffix("/count_tokens")
+        .expect("count_tokens suffix")
+        .to_string();
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/v1/messages",
+        &upstream_url,
+        WireApi::Messages,
+    ));
+    let request_body = json!({
+        "model": "claude-sonnet",
+        "messages": [{"role":"user","content":"hello there"}]
+    });
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/messages/count_tokens")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(request_body.to_string()))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    upstream_handle.abort();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+    let captured = captured_request
+        .lock()
+        .await
+        .clone()
+        .expect("captured request");
+
+    assert_eq!(json, upstream_response);
+    assert_eq!(captured.body, request_body);
+}
+
+#[tokio::test]
+async fn anthropic_count_tokens_is_handled_locally_for_subpaths() {
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/claude/v1/messages",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
+    ));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/claude/v1/messages/count_tokens")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"model":"claude-sonnet","messages":[{"role":"user","content":"hello there"}]}"#,
+                ))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+
+    assert_eq!(json["input_tokens"].as_i64().is_some(), true);
+    assert_eq!(json.get("error"), None);
+}
+
+#[tokio::test]
+async fn bpe_tokenizer_counts_tokens_locally_even_for_messages_upstreams() {
+    let dir = std::env::temp_dir().join(format!("codex-chat-bridge-bpe-{}", Uuid::now_v7()));
+    fs::create_dir_all(&dir).expect("temp dir");
+    let vocab_path = dir.join("test.tiktoken");
+    fs::write(&vocab_path, TEST_BPE_VOCAB).expect("write vocab");
+    let state = test_state_with_routers(BTreeMap::from([(
+        "claude".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/claude/v1/messages".to_string()),
+            upstream_url: Some("http://127.0.0.1:9/v1/messages".to_string()),
+            tokenizer: Some(TokenizerConfig {
+                kind: Some(TokenizerKind::Bpe),
+                path: Some(vocab_path),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )]));
+    let _ = fs::remove_dir_all(dir);
+
+    let body = post_routed(
+        state,
+        "/claude/v1/messages/count_tokens",
+        None,
+        r#"{"model":"claude-sonnet","max_tokens":16,"messages":[{"role":"user","content":"abc"}]}"#,
+    )
+    .await;
+    let json: Value = serde_json::from_str(&body).expect("json");
+    // "user" is four bytes outside the vocabulary, "abc" is a single token.
+    assert_eq!(json, json!({"input_tokens": 5}));
+}
+
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn upstream_tokenizer_forwards_count_fields_and_fills_message_start() {
+    let (count_url, count_handle, captured_count) =
+        spawn_mock_json_upstream("/v1/messages/count_tokens", json!({"input_tokens": 1234})).await;
+    let chat_app = axum::Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            (
+                [("content-type", "text/event-stream")],
+                "data: {\"choices\":[{\"delta\":{\"content\":\"hello\"},\"finish_reason\":\"stop\"}]}\n\n\
+                 data: [DONE]\n\n",
+            )
+        }),
+    );
+    let chat_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind mock upstream");
+    let chat_addr = chat_listener.local_addr().expect("mock upstream address");
+    let chat_handle = tokio::spawn(async move {
+        let _ = axum::serve(chat_listener, chat_app).await;
+    });
+    let state = test_state_with_routers(BTreeMap::from([(
+        "claude".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/claude/v1/messages".to_string()),
+            upstream_url: Some(format!("http://{chat_addr}/v1/chat/completions")),
+            tokenizer: Some(TokenizerConfig {
+                kind: Some(TokenizerKind::Upstream),
+                url: Some(count_url),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )]));
+    let request = r#"{"model":"claude-sonnet","max_tokens":16,"stream":true,"messages":[{"role":"user","content":"hi"}]}"#;
+
+    let count = post_routed(
+        state.clone(),
+        "/claude/v1/messages/count_tokens",
+        None,
+        request,
+    )
+    .await;
+    assert_eq!(
+        serde_json::from_str::<Value>(&count).expect("json"),
+        json!({"input_tokens": 1234})
+    );
+    let forwarded = captured_count.lock().await.clone().expect("count request");
+    assert_eq!(
+        forwarded,
+        json!({"model":"claude-sonnet","messages":[{"role":"user","content":"hi"}]})
+    );
+
+    let stream = post_routed(state, "/claude/v1/messages", None, request).await;
+    count_handle.abort();
+    chat_handle.abort();
+    assert!(stream.contains("\"input_tokens\":1234"), "{stream}");
+}
+
+#[tokio::test]
+async fn direct_responses_endpoint_routes_using_request_path() {
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        "http://127.0.0.1:9/v1/responses",
+        WireApi::Responses,
+    ));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/responses")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"model":"gpt-4.1","stream":false,"input":[{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}]}"#,
+                ))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+
+    assert_eq!(json["error"]["type"], "upstream_transport_error");
+}
+
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn responses_request_to_chat_upstream_returns_responses_json() {
+    let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream(
+        "/v1/chat/completions",
+        json!({
+            "id": "chatcmpl_1",
+            "model": "gpt-4.1",
+            "choices": [{
+                "finish_reason": "stop",
+                "message": {
+                    "role": "assistant",
+                    "content": "hello"
+                }
+            }],
+            "usage": {
+                "prompt_tokens": 1,
+                "completion_tokens": 2,
+                "total_tokens": 3
+            }
+        }),
+    )
+    .await;
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        &upstream_url,
+        WireApi::Chat,
+    ));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/responses")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"model":"gpt-4.1","stream":false,"input":[{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}]}"#,
+                ))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    upstream_handle.abort();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+    let captured = captured_request
+        .lock()
+        .await
+        .clone()
+        .expect("captured request");
+
+    assert_eq!(captured["messages"][0]["role"], "user");
+    assert_eq!(json["object"], "response");
+    assert_eq!(json["output"][0]["content"][0]["text"], "hello");
+    assert_eq!(json["usage"]["input_tokens"], 1);
+}
+
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn chat_request_to_responses_upstream_returns_chat_json() {
+    let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream(
+        "/v1/responses",
+        json!({
+            "id": "resp_1",
+            "model": "gpt-4.1",
+            "status": "completed",
+            "output": [{
+                "type": "message",
+                "role": "assistant",
+                "content": [{"type":"output_text","text":"hello chat"}]
+            }],
+            "usage": {
+                "input_tokens": 4,
+                "output_tokens": 5,
+                "total_tokens": 9
+            }
+        }),
+    )
+    .await;
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/v1/chat/completions",
+        &upstream_url,
+        WireApi::Responses,
+    ));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/chat/completions")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"model":"gpt-4.1","messages":[{"role":"user","content":"hi"}],"stream":false}"#,
+                ))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    upstream_handle.abort();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+    let captured = captured_request
+        .lock()
+        .await
+        .clone()
+        .expect("captured request");
+
+    assert_eq!(captured["input"][0]["type"], "message");
+    assert_eq!(json["object"], "chat.completion");
+    assert_eq!(json["choices"][0]["message"]["content"], "hello chat");
+    assert_eq!(json["usage"]["prompt_tokens"], 4);
+}
+
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn responses_request_to_gemini_upstream_returns_responses_json() {
+    let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream_with_headers(
+        "/v1beta/models/gemini-2.5-pro:generateContent",
+        json!({
+            "candidates": [{
+                "content": {"role":"model","parts":[{"text":"hello gemini"}]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 6, "candidatesTokenCount": 2, "totalTokenCount": 8}
+        }),
+    )
+    .await;
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        &upstream_url.replace("gemini-2.5-pro", "{model}"),
+        WireApi::Gemini,
+    ));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/responses")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"model":"gemini-2.5-pro","stream":false,"instructions":"be brief","input":[{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}]}"#,
+                ))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    upstream_handle.abort();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+    let captured = captured_request
+        .lock()
+        .await
+        .clone()
+        .expect("captured request");
+
+    assert_eq!(captured.headers["x-goog-api-key"], "test-key");
+    assert!(captured.body.get("model").is_none());
+    assert!(captured.body.get("stream").is_none());
+    assert_eq!(
+        captured.body["systemInstruction"]["parts"][0]["text"],
+        "be brief"
+    );
+    assert_eq!(captured.body["contents"][0]["parts"][0]["text"], "hi");
+    assert_eq!(json["object"], "response");
+    assert_eq!(json["output"][0]["content"][0]["text"], "hello gemini");
+    assert_eq!(json["usage"]["input_tokens"], 6);
+}
+
+#[tokio::test]
+async fn responses_history_is_merged_before_mapping_to_native_wires() {
+    for (upstream_url, upstream_wire) in [
+        (
+            "https://generativelanguage.googleapis.com/v1beta/models/{model}:generateContent",
+            WireApi::Gemini,
+        ),
+        ("http://127.0.0.1:11434/api/chat", WireApi::Ollama),
+        ("https://api.anthropic.com/v1/messages", WireApi::Messages),
+    ] {
+        let state = test_state_with_router(
+            "http://127.0.0.1:8787/v1/responses",
+            upstream_url,
+            upstream_wire,
+        );
+        state
+            .sessions
+            .write()
+            .await
+            .insert_messages(
+                "resp_prev".to_string(),
+                vec![
+                    json!({"role":"user","content":"earlier"}),
+                    json!({"role":"assistant","content":"noted"}),
+                ],
+            )
+            .expect("insert");
+        let route_target = state
+            .routers
+            .read()
+            .await
+            .resolve_target_for_router_name("default")
+            .expect("route target");
+
+        let request = json!({
+            "model": "m",
+            "input": "again",
+            "previous_response_id": "resp_prev",
+        });
+        let Ok((payload, session_turn)) = build_upstream_payload_with_session(
+            &state,
+            &request,
+            IncomingApi::Responses,
+            &route_target,
+            false,
+            "resp_test",
+        )
+        .await
+        else {
+            panic!("{upstream_wire:?} payload");
+        };
+        let turns = payload
+            .get("contents")
+            .or_else(|| payload.get("messages"))
+            .and_then(Value::as_array)
+            .expect("turns")
+            .len();
+        assert_eq!(turns, 3, "{upstream_wire:?}: {payload}");
+        assert!(session_turn.is_some(), "{upstream_wire:?}");
+
+        let unknown =
+            json!({"model": "m", "input": "again", "previous_response_id": "resp_missing"});
+        let Err(response) = build_upstream_payload_with_session(
+            &state,
+            &unknown,
+            IncomingApi::Responses,
+            &route_target,
+            false,
+            "resp_test",
+        )
+        .await
+        else {
+            panic!("{upstream_wire:?} accepted an unknown previous_response_id");
+        };
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("invalid_request"), "{body}");
+        assert!(body.contains("resp_missing"), "{body}");
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn responses_history_is_replayed_to_messages_upstream() {
+    let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream(
+        "/v1/messages",
+        json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type":"text","text":"first answer"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 4, "output_tokens": 2}
+        }),
+    )
+    .await;
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        &upstream_url,
+        WireApi::Messages,
+    );
+
+    let first: Value = serde_json::from_str(
+        &post_routed(
+            state.clone(),
+            "/v1/responses",
+            None,
+            r#"{"model":"claude-test","stream":false,"instructions":"be brief","input":"first question"}"#,
+        )
+        .await,
+    )
+    .expect("first json");
+    let response_id = first["id"].as_str().expect("response id");
+    let second: Value = serde_json::from_str(
+        &post_routed(
+            state,
+            "/v1/responses",
+            None,
+            &format!(
+                r#"{{"model":"claude-test","stream":false,"input":"second question","previous_response_id":"{response_id}"}}"#
+            ),
+        )
+        .await,
+    )
+    .expect("second json");
+
+    upstream_handle.abort();
+    assert_eq!(second["output"][0]["content"][0]["text"], "first answer");
+    let captured = captured_request
+        .lock()
+        .await
+        .clone()
+        .expect("captured request");
+    assert!(
+        captured["system"].to_string().contains("be brief"),
+        "{captured}"
+    );
+    let messages = captured["messages"].as_array().expect("messages");
+    assert_eq!(messages.len(), 3, "{captured}");
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(messages[0]["content"][0]["text"], "first question");
+    assert_eq!(messages[1]["role"], "assistant");
+    assert_eq!(messages[1]["content"][0]["text"], "first answer");
+    assert_eq!(messages[2]["role"], "user");
+    assert_eq!(messages[2]["content"][0]["text"], "second question");
+}
+
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn anthropic_request_to_ollama_upstream_sends_router_options() {
+    let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream_with_headers(
+        "/api/chat",
+        json!({
+            "model": "qwen3-coder:30b",
+            "message": {"role":"assistant","content":"hello ollama"},
+            "done": true,
+            "done_reason": "stop",
+            "prompt_eval_count": 9,
+            "eval_count": 2
+        }),
+    )
+    .await;
+    let app = build_app(test_state_with_routers(BTreeMap::from([(
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/messages".to_string()),
+            upstream_url: Some(upstream_url.clone()),
+            upstream_model: Some("qwen3-coder:30b".to_string()),
+            ollama: Some(OllamaConfig {
+                options: Some(serde_json::Map::from_iter([(
+                    "num_ctx".to_string(),
+                    json!(65536),
+                )])),
+                keep_alive: Some(json!("30m")),
+                think: None,
+            }),
+            ..Default::default()
+        },
+    )])));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/v1/messages")
+                .header("host", "127.0.0.1:8787")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    r#"{"model":"claude-sonnet-4-5","max_tokens":128,"stream":false,"messages":[{"role":"user","content":"hi"}]}"#,
+                ))
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+
+    upstream_handle.abort();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let json: Value = serde_json::from_slice(&body).expect("json");
+    let captured = captured_request
+        .lock()
+        .await
+        .clone()
+        .expect("captured request");
+
+    assert!(captured.headers.get("authorization").is_none());
+    assert_eq!(captured.body["model"], "qwen3-coder:30b");
+    assert_eq!(captured.body["stream"], false);
+    assert_eq!(captured.body["messages"][0]["content"], "hi");
+    assert_eq!(
+        captured.body["options"],
+        json!({"num_predict": 128, "num_ctx": 65536})
+    );
+    assert_eq!(captured.body["keep_alive"], "30m");
+    assert_eq!(json["type"], "message");
+    assert_eq!(json["content"][0]["text"], "hello ollama");
+    assert_eq!(json["usage"]["input_tokens"], 9);
+}
+
+#[tokio::test]
+async fn stream_emits_failed_when_done_marker_missing() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream(
+        upstream,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        HashMap::new(),
+        FeatureFlags::default(),
+        None,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"type\":\"response.failed\""));
+    assert!(!payload.contains("event: response.completed"));
+}
+
+#[tokio::test]
+async fn stream_emits_added_and_done_for_tool_calls() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"name\":\"shell\",\"arguments\":\"echo hi\"}}]}}],\"usage\":{\"prompt_tokens\":1,\"completion_tokens\":1,\"total_tokens\":2}}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut kinds = HashMap::new();
+    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
+    let mut output = Box::pin(translate_chat_stream(
+        upstream,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        kinds,
+        FeatureFlags::default(),
+        None,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    let added_idx = payload
+        .find("event: response.output_item.added")
+        .expect("added event");
+    let done_idx = payload
+        .rfind("event: response.output_item.done")
+        .expect("done event");
+    assert!(added_idx < done_idx);
+    assert!(payload.contains("\"call_id\":\"call_resp_1_0\""));
+    assert!(payload.contains("event: response.function_call_arguments.delta"));
+    assert!(payload.contains("event: response.function_call_arguments.done"));
+    assert!(payload.contains("\"arguments\":\"echo hi\""));
+}
+
+#[tokio::test]
+async fn stream_tool_call_added_event_does_not_include_partial_arguments() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"exec_command\",\"arguments\":\"{\"}}]}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"cmd\\\":\\\"pwd\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream(
+        upstream,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        HashMap::new(),
+        FeatureFlags::default(),
+        None,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    let added = sse_payloads_for_event(&payload, "response.output_item.added");
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0]["item"]["type"], "function_call");
+    assert_eq!(added[0]["item"]["name"], "exec_command");
+    assert_eq!(added[0]["item"]["arguments"], "");
+
+    let done = sse_payloads_for_event(&payload, "response.output_item.done");
+    assert_eq!(done.len(), 1);
+    assert_eq!(done[0]["item"]["arguments"], "{\"cmd\":\"pwd\"}");
+
+    let argument_done = sse_payloads_for_event(&payload, "response.function_call_arguments.done");
+    assert_eq!(argument_done.len(), 1);
+    assert_eq!(argument_done[0]["arguments"], "{\"cmd\":\"pwd\"}");
+}
+
+#[tokio::test]
+async fn stream_emits_reasoning_summary_events() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"think \"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"step\"}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream(
+        upstream,
+        "resp_1".to_string(),
+        "test_router".to_string(),
+        false,
+        HashMap::new(),
+        FeatureFlags::default(),
+        None,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("event: response.reasoning_summary_text.delta"));
+    assert!(payload.contains("event: response.reasoning_summary_text.done"));
+    assert!(payload.contains("\"text\":\"think step\""));
+    assert!(!payload.contains("[reasoning_summary]"));
+}
+
+#[tokio::test]
+async fn anthropic_stream_message_start_uses_model_and_input_tokens_without_null_stop_fields() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "gpt-4.1".to_string(),
+        17,
+    ));
+    let first = output
+        .next()
+        .await
+        .expect("message_start event")
+        .expect("stream event");
+    let payload = String::from_utf8_lossy(&first);
+
+    assert!(payload.contains("event: message_start"));
+    assert!(payload.contains("\"model\":\"gpt-4.1\""));
+    assert!(payload.contains("\"input_tokens\":17"));
+    assert!(!payload.contains("\"stop_reason\":null"));
+    assert!(!payload.contains("\"stop_sequence\":null"));
+}
+
+#[tokio::test]
+async fn anthropic_stream_maps_native_tool_call_delta() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"shell\",\"arguments\":\"{\\\"cmd\\\":\"}}]}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"pwd\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "gpt-4.1".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"type\":\"tool_use\""));
+    assert!(payload.contains("\"id\":\"call_1\""));
+    assert!(payload.contains("\"name\":\"shell\""));
+    assert!(payload.contains("\"partial_json\":\"{\\\"cmd\\\":\""));
+    assert!(payload.contains("\"partial_json\":\"\\\"pwd\\\"}\""));
+    assert!(payload.contains("\"stop_reason\":\"tool_use\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_handles_split_think_tags_across_chunks() {
+    let upstream = stream::iter(vec![
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"before <thi\"}}]}\n\n",
+        )),
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"nk>internal</thi\"}}]}\n\n",
+        )),
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"nk> after\"},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+        )),
+    ]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"text\":\"before <thi\""));
+    assert!(payload.contains("\"text\":\"nk>internal</thi\""));
+    assert!(payload.contains("\"text\":\"nk> after\""));
+    assert!(!payload.contains("\"type\":\"thinking_delta\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_ignores_signature_delta_from_thinking_blocks() {
+    let upstream = stream::iter(vec![
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"thinking_blocks\":[{\"type\":\"thinking\",\"thinking\":\"step one\",\"signature\":\"sig_1\"}]}}]}\n\n",
+        )),
+        Ok::<Bytes, reqwest::Error>(Bytes::from("data: [DONE]\n\n")),
+    ]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(!payload.contains("\"type\":\"thinking_delta\""));
+    assert!(!payload.contains("\"type\":\"signature_delta\""));
+    assert_eq!(payload.matches("event: content_block_start").count(), 0);
+    assert!(payload.contains("event: message_stop"));
+}
+
+#[tokio::test]
+async fn anthropic_stream_ignores_redacted_thinking_blocks() {
+    let upstream = stream::iter(vec![
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"thinking_blocks\":[{\"type\":\"redacted_thinking\",\"data\":\"secret\"}]}}]}\n\n",
+        )),
+        Ok::<Bytes, reqwest::Error>(Bytes::from("data: [DONE]\n\n")),
+    ]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(!payload.contains("\"type\":\"redacted_thinking\""));
+    assert_eq!(payload.matches("event: content_block_start").count(), 0);
+    assert!(!payload.contains("response.failed"));
+}
+
+#[tokio::test]
+async fn anthropic_stream_accepts_terminal_finish_reason_without_done_marker() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("event: message_start"));
+    assert_eq!(payload.matches("event: content_block_start").count(), 1);
+    assert!(payload.contains("event: content_block_delta"));
+    assert!(payload.contains("\"text\":\"Hi\""));
+    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
+    assert!(payload.contains("event: message_delta"));
+    assert!(payload.contains("event: message_stop"));
+    assert!(!payload.contains("event: error"));
+}
+
+#[tokio::test]
+async fn anthropic_stream_splits_think_tags_in_content() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"before <think>internal</think> after\"}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"type\":\"text_delta\""));
+    assert!(payload.contains("\"text\":\"before <think>internal</think> after\""));
+    assert!(!payload.contains("\"type\":\"thinking_delta\""));
+    assert_eq!(payload.matches("event: content_block_start").count(), 1);
+    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
+}
+
+#[tokio::test]
+async fn anthropic_stream_ignores_reasoning_details() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"reasoning_details\":[{\"text\":\"step one\"}]}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(!payload.contains("\"thinking\":\"step one\""));
+    assert_eq!(payload.matches("event: content_block_start").count(), 0);
+    assert_eq!(payload.matches("event: content_block_stop").count(), 0);
+    assert!(payload.contains("event: message_stop"));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_heuristic_tool_call_text_as_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"before shell(command='pwd') after\"}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"text\":\"before shell(command='pwd') after\""));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+    assert!(payload.contains("\"stop_reason\":\"end_turn\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_exit_plan_mode_python_call_as_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"ExitPlanMode()\"}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"text\":\"ExitPlanMode()\""));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+    assert!(payload.contains("\"stop_reason\":\"end_turn\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_exit_plan_mode_harmony_call_as_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"to=functions.ExitPlanMode<|constrain|>json<|message|>{}\"}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("to=functions.ExitPlanMode"));
+    assert!(payload.contains("<|constrain|>"));
+    assert!(payload.contains("<|message|>"));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+    assert!(payload.contains("\"stop_reason\":\"end_turn\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_unallowed_harmony_call_as_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"to=functions.ExitPlanMode<|constrain|>json<|message|>{}\"},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("to=functions.ExitPlanMode"));
+    assert!(payload.contains("<|constrain|>"));
+    assert!(payload.contains("<|message|>"));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+    assert!(payload.contains("\"stop_reason\":\"end_turn\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_preserves_control_tokens_in_text_content() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"alpha<|message|>beta<|constrain|>gamma\"}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("alpha<|message|>beta<|constrain|>gamma"));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_non_tool_thinking_trace_as_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"● <thinking<|message|>Create next tasks.\\n\\n● <thinking<|message|>Create remaining tasks similarly.<thinking\\n\\nto=functions.TaskCreate<|constrain|>json<|message|>{\\\"description\\\":\\\"Create a guide\\\"}\"}}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("● <thinking"));
+    assert!(payload.contains("Create next tasks."));
+    assert!(payload.contains("Create remaining tasks similarly."));
+    assert!(payload.contains("to=functions.TaskCreate"));
+    assert!(payload.contains("<|mes"));
+    assert!(payload.contains("sage|>"));
+    assert!(payload.contains("<|con"));
+    assert!(payload.contains("strain|>"));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_split_non_tool_thinking_trace_as_text() {
+    let upstream = stream::iter(vec![
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"● <thinking<|mes\"}}]}\n\n",
+        )),
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"sage|>Create next tasks. to=functions.TaskCreate<|con\"}}]}\n\n",
+        )),
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"strain|>json\"}}]}\n\n\
+             data: [DONE]\n\n",
+        )),
+    ]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"text\":\"● <thinking"));
+    assert!(payload.contains("Create next tasks."));
+    assert!(payload.contains("to=functions.TaskCreate"));
+    assert!(payload.contains("<|mes"));
+    assert!(payload.contains("sage|>"));
+    assert!(payload.contains("<|con"));
+    assert!(payload.contains("strain|>"));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_bare_python_style_ask_user_question_as_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"[AskUserQuestion(question=\\\"What's up?\\\", headers=['Hello!'], options=['A','B'], multiSelect=False, metadata={'priority': 1, 'active': True})]\"},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("AskUserQuestion(question="));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+    assert!(payload.contains("\"type\":\"text_delta\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_ask_user_question_xml_wrapper_as_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"<AskUserQuestion>{\\\"questions\\\":[{\\\"question\\\":\\\"작업 분할 계획을 저장할 파일 경로를 선택해 주세요.\\\",\\\"multiSelect\\\":false,\\\"options\\\":[{\\\"label\\\":\\\"docs/plans/unit-test-execution-plan.md (추천)\\\",\\\"description\\\":\\\"문서 전용 폴더에 마크다운 파일로 보관\\\"},{\\\"label\\\":\\\"README.md에 추가\\\",\\\"description\\\":\\\"프로젝트 루트 README에 삽입\\\"},{\\\"label\\\":\\\"src/main/resources/unit-test-execution-plan.md\\\",\\\"description\\\":\\\"리소스 경로에 저장해 빌드에 포함\\\"}]}]}\"},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("<AskUserQuestion>"));
+    assert!(payload.contains("작업 분할 계획을 저장할 파일 경로를 선택해 주세요."));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_keeps_unknown_bare_python_style_call_as_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"AskUserQuestion(question=\\\"What's up?\\\")\"},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"text\":\"AskUserQuestion(question=\\\"What's up?\\\")\""));
+    assert!(!payload.contains("\"type\":\"tool_use\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_handles_trailing_done_without_newline() {
+    let upstream = stream::iter(vec![
+        Ok::<Bytes, reqwest::Error>(Bytes::from(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+        )),
+        Ok::<Bytes, reqwest::Error>(Bytes::from("data: [DONE]")),
+    ]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert!(payload.contains("\"text\":\"Hi\""));
+    assert_eq!(payload.matches("event: content_block_start").count(), 1);
+    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
+    assert!(payload.contains("event: message_stop"));
+    assert!(!payload.contains("event: error"));
+}
+
+#[tokio::test]
+async fn anthropic_stream_opens_text_block_only_once_across_multiple_deltas() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"He\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"content\":\"llo\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert_eq!(payload.matches("event: content_block_start").count(), 1);
+    assert_eq!(payload.matches("\"type\":\"text_delta\"").count(), 2);
+    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
+    assert!(payload.contains("\"text\":\"He\""));
+    assert!(payload.contains("\"text\":\"llo\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_ignores_empty_tool_calls_when_streaming_text() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"He\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"content\":\"llo\",\"tool_calls\":[]}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"content\":\"!\"},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert_eq!(payload.matches("event: content_block_start").count(), 1);
+    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
+    assert_eq!(payload.matches("\"type\":\"text_delta\"").count(), 3);
+    assert!(payload.contains("\"text\":\"He\""));
+    assert!(payload.contains("\"text\":\"llo\""));
+    assert!(payload.contains("\"text\":\"!\""));
+}
+
+#[tokio::test]
+async fn anthropic_stream_opens_thinking_block_only_once_across_multiple_deltas() {
+    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
+        "data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"step \"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"one\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n\
+             data: [DONE]\n\n",
+    ))]);
+    let mut output = Box::pin(translate_chat_stream_to_anthropic(
+        upstream,
+        "test_router".to_string(),
+        false,
+        "claude-bridge".to_string(),
+        0,
+    ));
+    let mut payload = String::new();
+
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+
+    assert_eq!(payload.matches("event: content_block_start").count(), 1);
+    assert_eq!(payload.matches("\"type\":\"thinking_delta\"").count(), 2);
+    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
+    assert!(payload.contains("\"thinking\":\"step \""));
+    assert!(payload.contains("\"thinking\":\"one\""));
 }
 
 #[test]
-fn build_upstream_request_uses_messages_count_tokens_url_and_existing_headers() {
-    let router_manager = RouterManager::new(
-        BTreeMap::new(),
-        "http://localhost:8080/v1/chat/completions".to_string(),
-        WireApi::Chat,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        FeatureFlags::default(),
+fn retry_policy_is_disabled_without_router_retry_section() {
+    let policy = RetryPolicy::from_config(None, "[routers.default]").expect("policy");
+    assert_eq!(policy.max_attempts, 1);
+    assert!(!policy.is_enabled());
+
+    let policy = RetryPolicy::from_config(Some(&RetryConfig::default()), "[routers.default]")
+        .expect("policy");
+    assert_eq!(policy.max_attempts, 3);
+    assert!(policy.is_enabled());
+    assert!(policy.is_retryable_failure(StatusCode::BAD_GATEWAY, "upstream_error"));
+    assert!(policy.is_retryable_failure(StatusCode::BAD_REQUEST, "rate_limit_exceeded"));
+    assert!(!policy.is_retryable_failure(StatusCode::BAD_REQUEST, "invalid_request"));
+}
+
+#[test]
+fn retry_policy_rejects_invalid_router_retry_section() {
+    let err = RetryPolicy::from_config(
+        Some(&RetryConfig {
+            max_attempts: Some(0),
+            ..Default::default()
+        }),
+        "[routers.default]",
     )
-    .expect("router manager");
-    let state = Arc::new(AppState {
-        client: Client::new(),
-        api_key: "test-key".to_string(),
-        http_shutdown: false,
-        verbose_logging: false,
-        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
-        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
-    });
-    let route_target = RouteTarget {
-        router_name: "messages".to_string(),
-        upstream_url: "https://api.anthropic.com/v1/messages".to_string(),
-        upstream_wire: WireApi::Messages,
-        upstream_model: None,
-        upstream_model_opus: None,
-        upstream_model_sonnet: None,
-        upstream_model_haiku: None,
-        upstream_http_headers: vec![UpstreamHeader {
-            name: "x-request-id".to_string(),
-            value: "from-upstream-http-headers".to_string(),
-        }],
-        forward_incoming_headers: vec!["x-request-id".to_string(), "anthropic-version".to_string()],
-        drop_tool_types: HashSet::new(),
-        drop_request_fields: HashSet::new(),
-        feature_flags: FeatureFlags::default(),
-        anthropic_preserve_thinking: false,
-        anthropic_enable_openrouter_reasoning: false,
-    };
-    let mut incoming_headers = HeaderMap::new();
-    incoming_headers.insert(
-        "x-request-id",
-        HeaderValue::from_static("from-forward-incoming-headers"),
-    );
-    incoming_headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+    .expect_err("must fail");
+    assert!(err.to_string().contains("max_attempts"));
 
-    let request = build_upstream_request(
-        &state,
-        &route_target,
-        &incoming_headers,
-        &json!({"model":"claude","messages":[]}),
-        Some("/v1/messages/count_tokens"),
+    let err = RetryPolicy::from_config(
+        Some(&RetryConfig {
+            retryable_status_codes: Some(vec![1000]),
+            ..Default::default()
+        }),
+        "[routers.default]",
     )
-    .build()
-    .expect("request");
+    .expect_err("must fail");
+    assert!(err.to_string().contains("invalid status `1000`"));
+}
+
+#[test]
+fn retry_policy_backs_off_exponentially_and_honors_retry_after() {
+    let policy = RetryPolicy::from_config(
+        Some(&RetryConfig {
+            initial_backoff_ms: Some(100),
+            max_backoff_ms: Some(350),
+            jitter: Some(false),
+            ..Default::default()
+        }),
+        "[routers.default]",
+    )
+    .expect("policy");
+    assert_eq!(
+        policy.delay_for_attempt(1, None),
+        Duration::from_millis(100)
+    );
+    assert_eq!(
+        policy.delay_for_attempt(2, None),
+        Duration::from_millis(200)
+    );
+    assert_eq!(
+        policy.delay_for_attempt(3, None),
+        Duration::from_millis(350)
+    );
 
+    let mut headers = HeaderMap::new();
+    headers.insert("retry-after", HeaderValue::from_static("0.25"));
     assert_eq!(
-        request.url().as_str(),
-        "https://api.anthropic.com/v1/messages/count_tokens"
+        retry_after_from_headers(&headers),
+        Some(Duration::from_millis(250))
     );
     assert_eq!(
-        request
-            .headers()
-            .get("x-request-id")
-            .and_then(|value| value.to_str().ok()),
-        Some("from-upstream-http-headers")
+        policy.delay_for_attempt(1, Some(&headers)),
+        Duration::from_millis(250)
     );
+    headers.insert("retry-after", HeaderValue::from_static("30"));
     assert_eq!(
-        request
-            .headers()
-            .get("anthropic-version")
-            .and_then(|value| value.to_str().ok()),
-        Some("2023-06-01")
+        policy.delay_for_attempt(1, Some(&headers)),
+        Duration::from_millis(350)
+    );
+    headers.insert("retry-after-ms", HeaderValue::from_static("20"));
+    assert_eq!(
+        policy.delay_for_attempt(1, Some(&headers)),
+        Duration::from_millis(20)
     );
-}
 
-fn test_state_with_router(
-    incoming_url: &str,
-    upstream_url: &str,
-    upstream_wire: WireApi,
-) -> Arc<AppState> {
-    let mut routers = BTreeMap::new();
-    routers.insert(
-        "default".to_string(),
-        RouterConfig {
-            incoming_url: Some(incoming_url.to_string()),
-            upstream_url: Some(upstream_url.to_string()),
-            upstream_wire: Some(upstream_wire),
-            ..Default::default()
-        },
+    let mut dated = HeaderMap::new();
+    let in_ten_seconds =
+        httpdate::fmt_http_date(std::time::SystemTime::now() + Duration::from_secs(10));
+    dated.insert(
+        "retry-after",
+        HeaderValue::from_str(&in_ten_seconds).expect("date"),
     );
-    let router_manager = RouterManager::new(
-        routers,
+    let delay = retry_after_from_headers(&dated).expect("http-date");
+    assert!(
+        delay > Duration::from_secs(8) && delay <= Duration::from_secs(10),
+        "{delay:?}"
+    );
+    dated.insert(
+        "retry-after",
+        HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+    );
+    assert_eq!(retry_after_from_headers(&dated), Some(Duration::ZERO));
+    dated.insert("retry-after", HeaderValue::from_static("soon"));
+    assert_eq!(retry_after_from_headers(&dated), None);
+
+    let jittered = RetryPolicy::from_config(
+        Some(&RetryConfig {
+            initial_backoff_ms: Some(100),
+            ..Default::default()
+        }),
+        "[routers.default]",
+    )
+    .expect("policy");
+    let delay = jittered.delay_for_attempt(1, None);
+    assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+}
+
+#[test]
+fn router_manager_rejects_invalid_retry_section() {
+    let parsed: FileConfig = toml::from_str(
+        "[routers.default]\nincoming_url = \"http://localhost:8080/default\"\n[routers.default.retry]\nmax_attempts = 0",
+    )
+    .expect("ok");
+
+    let err = match RouterManager::new(
+        parsed.routers.expect("routers"),
         "https://api.openai.com/v1/chat/completions".to_string(),
         WireApi::Chat,
         Vec::new(),
@@ -3834,95 +7818,57 @@ fn test_state_with_router(
         Vec::new(),
         Vec::new(),
         FeatureFlags::default(),
-    )
-    .expect("router manager");
-
-    Arc::new(AppState {
-        client: Client::new(),
-        api_key: "test-key".to_string(),
-        http_shutdown: false,
-        verbose_logging: true,
-        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
-        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
-    })
-}
-
-#[derive(Clone)]
-struct MockJsonUpstreamState {
-    response: Value,
-    captured_request: Arc<tokio::sync::Mutex<Option<Value>>>,
-}
-
-async fn mock_json_upstream_handler(
-    AxumState(state): AxumState<MockJsonUpstreamState>,
-    Json(body): Json<Value>,
-) -> Json<Value> {
-    *state.captured_request.lock().await = Some(body);
-    Json(state.response)
-}
-
-async fn spawn_mock_json_upstream(
-    path: &str,
-    response: Value,
-) -> (
-    String,
-    tokio::task::JoinHandle<()>,
-    Arc<tokio::sync::Mutex<Option<Value>>>,
-) {
-    let captured_request = Arc::new(tokio::sync::Mutex::new(None));
-    let state = MockJsonUpstreamState {
-        response,
-        captured_request: captured_request.clone(),
+    ) {
+        Ok(_) => panic!("expected invalid retry section to fail"),
+        Err(err) => err,
     };
-    let app = axum::Router::new()
-        .route(path, post(mock_json_upstream_handler))
-        .with_state(state);
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
-        .await
-        .expect("bind mock upstream");
-    let addr = listener.local_addr().expect("mock upstream address");
-    let handle = tokio::spawn(async move {
-        let _ = axum::serve(listener, app).await;
-    });
-    (format!("http://{addr}{path}"), handle, captured_request)
-}
-
-#[derive(Clone, Debug)]
-struct CapturedJsonRequest {
-    headers: HeaderMap,
-    body: Value,
+    assert!(
+        err.to_string()
+            .contains("[routers.default] retry.max_attempts must be at least 1")
+    );
 }
 
 #[derive(Clone)]
-struct MockJsonUpstreamWithHeadersState {
+struct MockFlakyUpstreamState {
+    failures_before_success: usize,
+    calls: Arc<std::sync::atomic::AtomicUsize>,
     response: Value,
-    captured_request: Arc<tokio::sync::Mutex<Option<CapturedJsonRequest>>>,
 }
 
-async fn mock_json_upstream_with_headers_handler(
-    AxumState(state): AxumState<MockJsonUpstreamWithHeadersState>,
-    headers: HeaderMap,
-    Json(body): Json<Value>,
-) -> Json<Value> {
-    *state.captured_request.lock().await = Some(CapturedJsonRequest { headers, body });
-    Json(state.response)
+async fn mock_flaky_upstream_handler(
+    AxumState(state): AxumState<MockFlakyUpstreamState>,
+) -> axum::response::Response {
+    let call = state
+        .calls
+        .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    if call < state.failures_before_success {
+        return (
+            StatusCode::BAD_GATEWAY,
+            [("retry-after", "0")],
+            Json(json!({"error":{"code":"bad_gateway","message":"provider unavailable"}})),
+        )
+            .into_response();
+    }
+    Json(state.response).into_response()
 }
 
-async fn spawn_mock_json_upstream_with_headers(
+async fn spawn_mock_flaky_upstream(
     path: &str,
+    failures_before_success: usize,
     response: Value,
 ) -> (
     String,
     tokio::task::JoinHandle<()>,
-    Arc<tokio::sync::Mutex<Option<CapturedJsonRequest>>>,
+    Arc<std::sync::atomic::AtomicUsize>,
 ) {
-    let captured_request = Arc::new(tokio::sync::Mutex::new(None));
-    let state = MockJsonUpstreamWithHeadersState {
+    let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let state = MockFlakyUpstreamState {
+        failures_before_success,
+        calls: calls.clone(),
         response,
-        captured_request: captured_request.clone(),
     };
     let app = axum::Router::new()
-        .route(path, post(mock_json_upstream_with_headers_handler))
+        .route(path, post(mock_flaky_upstream_handler))
         .with_state(state);
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
         .await
@@ -3931,72 +7877,18 @@ async fn spawn_mock_json_upstream_with_headers(
     let handle = tokio::spawn(async move {
         let _ = axum::serve(listener, app).await;
     });
-    (format!("http://{addr}{path}"), handle, captured_request)
-}
-
-#[tokio::test]
-async fn direct_anthropic_endpoint_routes_using_request_path() {
-    let app = build_app(test_state_with_router(
-        "http://127.0.0.1:8787/v1/messages",
-        "http://127.0.0.1:9/v1/chat/completions",
-        WireApi::Chat,
-    ));
-
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/v1/messages")
-                .header("host", "127.0.0.1:8787")
-                .header("content-type", "application/json")
-                .body(Body::from(
-                    r#"{"model":"claude-sonnet","messages":[{"role":"user","content":"hi"}],"stream":false}"#,
-                ))
-                .expect("request"),
-        )
-        .await
-        .expect("response");
-
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_bytes(response.into_body(), usize::MAX)
-        .await
-        .expect("body");
-    let json: Value = serde_json::from_slice(&body).expect("json");
-
-    assert_eq!(json["type"], "error");
-    assert_eq!(json["error"]["type"], "upstream_transport_error");
+    (format!("http://{addr}{path}"), handle, calls)
 }
 
-#[tokio::test]
-#[ignore = "requires binding a local TCP listener"]
-async fn messages_wire_overrides_model_and_preserves_header_behavior() {
-    let upstream_response = json!({
-        "id": "msg_1",
-        "type": "message",
-        "role": "assistant",
-        "model": "claude-native",
-        "content": [{"type":"text","text":"hello"}],
-        "usage": {"input_tokens": 1, "output_tokens": 2}
-    });
-    let (upstream_url, upstream_handle, captured_request) =
-        spawn_mock_json_upstream_with_headers("/v1/messages", upstream_response.clone()).await;
+fn test_state_with_retry_router(upstream_url: &str, retry: RetryConfig) -> Arc<AppState> {
     let mut routers = BTreeMap::new();
     routers.insert(
-        "native_messages".to_string(),
-        RouterConfig {
-            incoming_url: Some("http://127.0.0.1:8787/v1/messages".to_string()),
-            upstream_url: Some(upstream_url),
-            upstream_wire: Some(WireApi::Messages),
-            upstream_model: Some("upstream-claude-model".to_string()),
-            upstream_http_headers: Some(BTreeMap::from([
-                ("x-request-id".to_string(), "static-request-id".to_string()),
-                ("x-static".to_string(), "static-value".to_string()),
-            ])),
-            forward_incoming_headers: Some(vec![
-                "x-request-id".to_string(),
-                "x-client-request-id".to_string(),
-                "anthropic-version".to_string(),
-            ]),
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/chat/completions".to_string()),
+            upstream_url: Some(upstream_url.to_string()),
+            upstream_wire: Some(WireApi::Chat),
+            retry: Some(retry),
             ..Default::default()
         },
     );
@@ -4011,1042 +7903,2461 @@ async fn messages_wire_overrides_model_and_preserves_header_behavior() {
         FeatureFlags::default(),
     )
     .expect("router manager");
-    let app = build_app(Arc::new(AppState {
+
+    Arc::new(AppState {
         client: Client::new(),
-        api_key: "test-key".to_string(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
         http_shutdown: false,
         verbose_logging: false,
         routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
         sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
-    }));
-    let request_body = json!({
-        "model": "claude-original",
-        "messages": [{
-            "role": "assistant",
-            "content": [
-                {"type":"thinking","thinking":"internal"},
-                {"type":"text","text":"hi"}
-            ],
-            "reasoning_content": "provider-field"
-        }],
-        "tools": [{"name":"shell","input_schema":{"type":"object"}}]
-    });
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    })
+}
 
-    let response = app
+async fn post_chat_completion(state: Arc<AppState>) -> Value {
+    let response = build_app(state)
         .oneshot(
             Request::builder()
                 .method("POST")
-                .uri("/v1/messages")
+                .uri("/v1/chat/completions")
                 .header("host", "127.0.0.1:8787")
                 .header("content-type", "application/json")
-                .header("x-request-id", "incoming-request-id")
-                .header("x-client-request-id", "client-trace-123")
-                .header("anthropic-version", "2023-06-01")
-                .body(Body::from(request_body.to_string()))
+                .body(Body::from(
+                    r#"{"model":"gpt-4.1","stream":false,"messages":[{"role":"user","content":"hi"}]}"#,
+                ))
                 .expect("request"),
         )
         .await
         .expect("response");
-
-    upstream_handle.abort();
     assert_eq!(response.status(), StatusCode::OK);
     let body = to_bytes(response.into_body(), usize::MAX)
         .await
         .expect("body");
-    let json: Value = serde_json::from_slice(&body).expect("json");
-    let captured = captured_request
-        .lock()
-        .await
-        .clone()
-        .expect("captured request");
-
-    let mut expected_body = request_body.clone();
-    expected_body["model"] = json!("upstream-claude-model");
-    assert_eq!(captured.body, expected_body);
-    assert_eq!(json, upstream_response);
-    assert_eq!(
-        captured
-            .headers
-            .get("x-request-id")
-            .and_then(|value| value.to_str().ok()),
-        Some("static-request-id")
-    );
-    assert_eq!(
-        captured
-            .headers
-            .get("x-client-request-id")
-            .and_then(|value| value.to_str().ok()),
-        Some("client-trace-123")
-    );
-    assert_eq!(
-        captured
-            .headers
-            .get("anthropic-version")
-            .and_then(|value| value.to_str().ok()),
-        Some("2023-06-01")
-    );
-    assert_eq!(
-        captured
-            .headers
-            .get("x-static")
-            .and_then(|value| value.to_str().ok()),
-        Some("static-value")
-    );
+    serde_json::from_slice(&body).expect("json")
 }
 
 #[tokio::test]
 #[ignore = "requires binding a local TCP listener"]
-async fn messages_wire_bypasses_count_tokens_to_upstream() {
-    let upstream_response = json!({"input_tokens": 42});
-    let (upstream_base_url, upstream_handle, captured_request) =
-        spawn_mock_json_upstream_with_headers(
-            "/v1/messages/count_tokens",
-            upstream_response.clone(),
-        )
-        .await;
-    let upstream_url = upstream_base_url
-        .strip_suffix("/count_tokens")
-        .expect("count_tokens suffix")
-        .to_string();
-    let app = build_app(test_state_with_router(
-        "http://127.0.0.1:8787/v1/messages",
-        &upstream_url,
-        WireApi::Messages,
-    ));
-    let request_body = json!({
-        "model": "claude-sonnet",
-        "messages": [{"role":"user","content":"hello there"}]
+async fn retry_policy_retries_retryable_upstream_status_before_responding() {
+    let upstream_response = json!({
+        "id": "chatcmpl_1",
+        "object": "chat.completion",
+        "model": "gpt-4.1",
+        "choices": [{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"hello"}}]
     });
+    let (upstream_url, upstream_handle, calls) =
+        spawn_mock_flaky_upstream("/v1/chat/completions", 2, upstream_response.clone()).await;
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/v1/messages/count_tokens")
-                .header("host", "127.0.0.1:8787")
-                .header("content-type", "application/json")
-                .body(Body::from(request_body.to_string()))
-                .expect("request"),
-        )
-        .await
-        .expect("response");
-
-    upstream_handle.abort();
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_bytes(response.into_body(), usize::MAX)
-        .await
-        .expect("body");
-    let json: Value = serde_json::from_slice(&body).expect("json");
-    let captured = captured_request
-        .lock()
-        .await
-        .clone()
-        .expect("captured request");
-
+    let json = post_chat_completion(test_state_with_retry_router(
+        &upstream_url,
+        RetryConfig {
+            max_attempts: Some(3),
+            initial_backoff_ms: Some(1),
+            max_backoff_ms: Some(5),
+            ..Default::default()
+        },
+    ))
+    .await;
     assert_eq!(json, upstream_response);
-    assert_eq!(captured.body, request_body);
-}
-
-#[tokio::test]
-async fn anthropic_count_tokens_is_handled_locally_for_subpaths() {
-    let app = build_app(test_state_with_router(
-        "http://127.0.0.1:8787/claude/v1/messages",
-        "http://127.0.0.1:9/v1/chat/completions",
-        WireApi::Chat,
-    ));
-
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/claude/v1/messages/count_tokens")
-                .header("host", "127.0.0.1:8787")
-                .header("content-type", "application/json")
-                .body(Body::from(
-                    r#"{"model":"claude-sonnet","messages":[{"role":"user","content":"hello there"}]}"#,
-                ))
-                .expect("request"),
-        )
-        .await
-        .expect("response");
-
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_bytes(response.into_body(), usize::MAX)
-        .await
-        .expect("body");
-    let json: Value = serde_json::from_slice(&body).expect("json");
+    assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
 
-    assert_eq!(json["input_tokens"].as_i64().is_some(), true);
-    assert_eq!(json.get("error"), None);
+    calls.store(0, std::sync::atomic::Ordering::SeqCst);
+    let json = post_chat_completion(test_state_with_retry_router(
+        &upstream_url,
+        RetryConfig {
+            max_attempts: Some(2),
+            initial_backoff_ms: Some(1),
+            max_backoff_ms: Some(5),
+            ..Default::default()
+        },
+    ))
+    .await;
+    upstream_handle.abort();
+    assert_eq!(json["error"]["type"], "upstream_error");
+    assert_eq!(json["error"]["message"], "provider unavailable");
+    assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
 }
 
 #[tokio::test]
-async fn direct_responses_endpoint_routes_using_request_path() {
-    let app = build_app(test_state_with_router(
-        "http://127.0.0.1:8787/v1/responses",
-        "http://127.0.0.1:9/v1/responses",
-        WireApi::Responses,
-    ));
-
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/v1/responses")
-                .header("host", "127.0.0.1:8787")
-                .header("content-type", "application/json")
-                .body(Body::from(
-                    r#"{"model":"gpt-4.1","stream":false,"input":[{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}]}"#,
-                ))
-                .expect("request"),
-        )
-        .await
-        .expect("response");
-
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_bytes(response.into_body(), usize::MAX)
-        .await
-        .expect("body");
-    let json: Value = serde_json::from_slice(&body).expect("json");
+#[ignore = "requires binding a local TCP listener"]
+async fn retry_policy_stops_retrying_once_total_ms_is_spent() {
+    let (upstream_url, upstream_handle, calls) =
+        spawn_mock_flaky_upstream("/v1/chat/completions", 100, json!({})).await;
+    let state = test_state_with_routers(BTreeMap::from([(
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/chat/completions".to_string()),
+            upstream_url: Some(upstream_url),
+            retry: Some(RetryConfig {
+                max_attempts: Some(10),
+                initial_backoff_ms: Some(100),
+                max_backoff_ms: Some(100),
+                jitter: Some(false),
+                respect_retry_after: Some(false),
+                ..Default::default()
+            }),
+            timeouts: Some(TimeoutConfig {
+                total_ms: Some(250),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )]));
 
-    assert_eq!(json["error"]["type"], "upstream_transport_error");
+    let json = post_chat_completion(state).await;
+    upstream_handle.abort();
+    assert_eq!(json["error"]["type"], "upstream_error", "{json}");
+    let calls = calls.load(std::sync::atomic::Ordering::SeqCst);
+    assert!((2..=3).contains(&calls), "{calls}");
 }
 
 #[tokio::test]
 #[ignore = "requires binding a local TCP listener"]
-async fn responses_request_to_chat_upstream_returns_responses_json() {
-    let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream(
+async fn capability_gate_checks_each_candidate_in_balancer_order() {
+    let (responses_url, responses_handle, responses_request) = spawn_mock_json_upstream(
+        "/v1/responses",
+        json!({
+            "id": "resp_upstream",
+            "object": "response",
+            "model": "gpt-4.1",
+            "status": "completed",
+            "output": [{
+                "type": "message",
+                "role": "assistant",
+                "content": [{"type": "output_text", "text": "searched"}]
+            }]
+        }),
+    )
+    .await;
+    let (chat_url, chat_handle, chat_request) = spawn_mock_json_upstream(
         "/v1/chat/completions",
         json!({
             "id": "chatcmpl_1",
-            "model": "gpt-4.1",
             "choices": [{
-                "finish_reason": "stop",
-                "message": {
-                    "role": "assistant",
-                    "content": "hello"
-                }
-            }],
-            "usage": {
-                "prompt_tokens": 1,
-                "completion_tokens": 2,
-                "total_tokens": 3
-            }
+                "index": 0,
+                "message": {"role":"assistant","content":"from chat"},
+                "finish_reason": "stop"
+            }]
         }),
     )
     .await;
-    let app = build_app(test_state_with_router(
-        "http://127.0.0.1:8787/v1/responses",
-        &upstream_url,
-        WireApi::Chat,
-    ));
-
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/v1/responses")
-                .header("host", "127.0.0.1:8787")
-                .header("content-type", "application/json")
-                .body(Body::from(
-                    r#"{"model":"gpt-4.1","stream":false,"input":[{"type":"message","role":"user","content":[{"type":"input_text","text":"hi"}]}]}"#,
-                ))
-                .expect("request"),
-        )
-        .await
-        .expect("response");
-
-    upstream_handle.abort();
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_bytes(response.into_body(), usize::MAX)
-        .await
-        .expect("body");
-    let json: Value = serde_json::from_slice(&body).expect("json");
-    let captured = captured_request
-        .lock()
-        .await
-        .clone()
-        .expect("captured request");
+    let router = |incoming: &str, primary: &str, fallback: &str| RouterConfig {
+        incoming_url: Some(format!("http://127.0.0.1:8787/{incoming}/v1/responses")),
+        upstream_url: Some(primary.to_string()),
+        weight: Some(1),
+        upstreams: Some(vec![UpstreamConfig {
+            upstream_url: fallback.to_string(),
+            weight: Some(1000),
+            ..Default::default()
+        }]),
+        load_balancing: Some(LoadBalancingConfig {
+            strategy: Some(LoadBalancingStrategy::Weighted),
+            ..Default::default()
+        }),
+        features: Some(FeatureFlagsConfig {
+            enable_extended_input_types: Some(false),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let state = test_state_with_routers(BTreeMap::from([
+        (
+            "chat_fallback".to_string(),
+            router("chat-fallback", &responses_url, &chat_url),
+        ),
+        (
+            "chat_primary".to_string(),
+            router("chat-primary", &chat_url, &responses_url),
+        ),
+    ]));
+    let body =
+        r#"{"model":"gpt-4.1","stream":false,"input":"news?","tools":[{"type":"web_search"}]}"#;
 
-    assert_eq!(captured["messages"][0]["role"], "user");
-    assert_eq!(json["object"], "response");
-    assert_eq!(json["output"][0]["content"][0]["text"], "hello");
-    assert_eq!(json["usage"]["input_tokens"], 1);
+    let served = post_routed(state.clone(), "/chat-fallback/v1/responses", None, body).await;
+    assert!(served.contains("searched"), "{served}");
+    assert!(chat_request.lock().await.is_none());
+
+    *responses_request.lock().await = None;
+    let served = post_routed(state, "/chat-primary/v1/responses", None, body).await;
+    responses_handle.abort();
+    chat_handle.abort();
+    assert!(served.contains("searched"), "{served}");
+    assert!(responses_request.lock().await.is_some());
+    assert!(chat_request.lock().await.is_none());
 }
 
 #[tokio::test]
 #[ignore = "requires binding a local TCP listener"]
-async fn chat_request_to_responses_upstream_returns_chat_json() {
+async fn failover_remaps_request_for_fallback_upstream_wire() {
     let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream(
         "/v1/responses",
         json!({
             "id": "resp_1",
+            "object": "response",
             "model": "gpt-4.1",
             "status": "completed",
             "output": [{
                 "type": "message",
                 "role": "assistant",
-                "content": [{"type":"output_text","text":"hello chat"}]
+                "content": [{"type": "output_text", "text": "from fallback"}]
             }],
-            "usage": {
-                "input_tokens": 4,
-                "output_tokens": 5,
-                "total_tokens": 9
-            }
+            "usage": {"input_tokens": 1, "output_tokens": 2, "total_tokens": 3}
         }),
     )
     .await;
-    let app = build_app(test_state_with_router(
-        "http://127.0.0.1:8787/v1/chat/completions",
-        &upstream_url,
-        WireApi::Responses,
-    ));
-
-    let response = app
-        .oneshot(
-            Request::builder()
-                .method("POST")
-                .uri("/v1/chat/completions")
-                .header("host", "127.0.0.1:8787")
-                .header("content-type", "application/json")
-                .body(Body::from(
-                    r#"{"model":"gpt-4.1","messages":[{"role":"user","content":"hi"}],"stream":false}"#,
-                ))
-                .expect("request"),
-        )
-        .await
-        .expect("response");
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/chat/completions".to_string()),
+            upstream_url: Some("http://127.0.0.1:9/v1/chat/completions".to_string()),
+            upstreams: Some(vec![UpstreamConfig {
+                upstream_url,
+                upstream_model: Some("gpt-4.1".to_string()),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+    );
+    let router_manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager");
 
+    let json = post_chat_completion(Arc::new(AppState {
+        client: Client::new(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
+        http_shutdown: false,
+        verbose_logging: false,
+        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
+        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    }))
+    .await;
     upstream_handle.abort();
-    assert_eq!(response.status(), StatusCode::OK);
-    let body = to_bytes(response.into_body(), usize::MAX)
-        .await
-        .expect("body");
-    let json: Value = serde_json::from_slice(&body).expect("json");
     let captured = captured_request
         .lock()
         .await
         .clone()
         .expect("captured request");
 
-    assert_eq!(captured["input"][0]["type"], "message");
+    assert_eq!(captured["model"], "gpt-4.1");
+    assert!(captured.get("input").is_some());
+    assert!(captured.get("messages").is_none());
     assert_eq!(json["object"], "chat.completion");
-    assert_eq!(json["choices"][0]["message"]["content"], "hello chat");
-    assert_eq!(json["usage"]["prompt_tokens"], 4);
+    assert_eq!(json["choices"][0]["message"]["content"], "from fallback");
 }
 
 #[tokio::test]
-async fn stream_emits_failed_when_done_marker_missing() {
+async fn payload_mapping_errors_are_returned_without_failing_over() {
+    let state = test_state_with_routers(BTreeMap::from([(
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/responses".to_string()),
+            upstream_url: Some("http://127.0.0.1:9/v1/chat/completions".to_string()),
+            upstreams: Some(vec![UpstreamConfig {
+                upstream_url: "http://127.0.0.1:9/v1/responses".to_string(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+    )]));
+
+    let response = post_routed(
+        state,
+        "/v1/responses",
+        None,
+        r#"{"model":"gpt-4.1","stream":false,"input":"hi","previous_response_id":"resp_unknown"}"#,
+    )
+    .await;
+
+    assert!(response.contains("invalid_request"), "{response}");
+    assert!(!response.contains("upstream_transport_error"), "{response}");
+}
+
+fn load_balanced_route_target(load_balancing: &str) -> RouteTarget {
+    let parsed: FileConfig = toml::from_str(&format!(
+        r#"
+[routers.default]
+incoming_url = "http://localhost:8080/default"
+upstream_url = "https://a.example/v1/chat/completions"
+weight = 2
+upstreams = [
+  {{ upstream_url = "https://b.example/v1/chat/completions" }},
+  {{ upstream_url = "https://c.example/v1/chat/completions", weight = 0 }},
+]
+[routers.default.load_balancing]
+{load_balancing}
+"#
+    ))
+    .expect("ok");
+    RouterManager::new(
+        parsed.routers.expect("routers"),
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager")
+    .get_target_for_incoming_route("/default", Some("localhost:8080"))
+    .expect("route")
+    .expect("target")
+}
+
+fn first_candidate_host(
+    balancer: &LoadBalancer,
+    target: &RouteTarget,
+    previous_response_id: Option<&str>,
+) -> String {
+    let candidates = balancer.order_candidates(target, previous_response_id);
+    assert!(
+        candidates
+            .iter()
+            .all(|candidate| candidate.fallbacks.is_empty())
+    );
+    candidates[0]
+        .upstream_url
+        .trim_start_matches("https://")
+        .split('.')
+        .next()
+        .expect("host")
+        .to_string()
+}
+
+#[test]
+fn router_manager_parses_load_balancing_policy_and_weights() {
+    let target = load_balanced_route_target("strategy = \"weighted\"\ncooldown_ms = 50");
+    let policy = target.load_balancing.clone().expect("policy");
+
+    assert_eq!(policy.strategy, LoadBalancingStrategy::Weighted);
+    assert_eq!(policy.eject_after_failures, 3);
+    assert_eq!(policy.cooldown, Duration::from_millis(50));
+    assert!(policy.sticky_sessions);
+    assert_eq!(target.weight, 2);
+    assert_eq!(target.fallbacks[0].weight, 1);
+    assert_eq!(target.fallbacks[1].weight, 0);
+    assert_eq!(target.fallbacks[0].load_balancing, Some(policy));
+
+    let err = LoadBalancingPolicy::from_config(
+        Some(&LoadBalancingConfig {
+            eject_after_failures: Some(0),
+            ..Default::default()
+        }),
+        "[routers.default]",
+    )
+    .expect_err("zero eject threshold must fail");
+    assert!(
+        err.to_string()
+            .contains("[routers.default] load_balancing.eject_after_failures must be at least 1")
+    );
+}
+
+#[test]
+fn load_balancer_keeps_failover_order_without_policy() {
+    let mut target = load_balanced_route_target("strategy = \"round_robin\"");
+    target.load_balancing = None;
+    let balancer = LoadBalancer::default();
+
+    for _ in 0..3 {
+        assert_eq!(first_candidate_host(&balancer, &target, None), "a");
+    }
+    assert_eq!(balancer.order_candidates(&target, None).len(), 3);
+}
+
+#[test]
+fn load_balancer_rotates_round_robin_and_honors_weights() {
+    let balancer = LoadBalancer::default();
+    let target = load_balanced_route_target("strategy = \"round_robin\"");
+    let picks = (0..4)
+        .map(|_| first_candidate_host(&balancer, &target, None))
+        .collect::<Vec<_>>();
+    assert_eq!(picks, vec!["a", "b", "c", "a"]);
+
+    let balancer = LoadBalancer::default();
+    let target = load_balanced_route_target("strategy = \"weighted\"");
+    let picks = (0..6)
+        .map(|_| first_candidate_host(&balancer, &target, None))
+        .collect::<Vec<_>>();
+    assert_eq!(picks.iter().filter(|host| *host == "a").count(), 4);
+    assert_eq!(picks.iter().filter(|host| *host == "b").count(), 2);
+    assert!(!picks.iter().any(|host| host == "c"));
+}
+
+#[test]
+fn load_balancer_prefers_endpoint_with_least_in_flight_requests() {
+    let balancer = Arc::new(LoadBalancer::default());
+    let target = load_balanced_route_target("strategy = \"least_in_flight\"");
+
+    let first = balancer.start_request("default", "https://a.example/v1/chat/completions");
+    let _second = balancer.start_request("default", "https://b.example/v1/chat/completions");
+    assert_eq!(first_candidate_host(&balancer, &target, None), "c");
+
+    let _third = balancer.start_request("default", "https://c.example/v1/chat/completions");
+    let _fourth = balancer.start_request("default", "https://c.example/v1/chat/completions");
+    drop(first);
+    assert_eq!(first_candidate_host(&balancer, &target, None), "a");
+}
+
+#[test]
+fn load_balancer_ejects_failing_endpoint_until_cooldown_expires() {
+    let balancer = LoadBalancer::default();
+    let target = load_balanced_route_target(
+        "strategy = \"failover\"\neject_after_failures = 2\ncooldown_ms = 20",
+    );
+    let policy = target.load_balancing.clone().expect("policy");
+    let url = "https://a.example/v1/chat/completions";
+
+    assert!(!balancer.record_failure("default", url, &policy));
+    balancer.record_success("default", url);
+    assert!(!balancer.record_failure("default", url, &policy));
+    assert_eq!(first_candidate_host(&balancer, &target, None), "a");
+    assert!(balancer.record_failure("default", url, &policy));
+
+    let candidates = balancer.order_candidates(&target, None);
+    assert_eq!(
+        candidates[0].upstream_url,
+        "https://b.example/v1/chat/completions"
+    );
+    assert_eq!(candidates[2].upstream_url, url);
+
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(first_candidate_host(&balancer, &target, None), "a");
+}
+
+#[test]
+fn load_balancer_pins_follow_up_turns_to_previous_upstream() {
+    let balancer = LoadBalancer::default();
+    let target = load_balanced_route_target("strategy = \"failover\"");
+    balancer.remember_response("resp_1", "default", "https://b.example/v1/chat/completions");
+    balancer.remember_response("resp_2", "other", "https://c.example/v1/chat/completions");
+
+    assert_eq!(
+        first_candidate_host(&balancer, &target, Some("resp_1")),
+        "b"
+    );
+    assert_eq!(
+        first_candidate_host(&balancer, &target, Some("resp_2")),
+        "a"
+    );
+    assert_eq!(
+        first_candidate_host(&balancer, &target, Some("unknown")),
+        "a"
+    );
+
+    let target = load_balanced_route_target("strategy = \"failover\"\nsticky_sessions = false");
+    assert_eq!(
+        first_candidate_host(&balancer, &target, Some("resp_1")),
+        "a"
+    );
+}
+
+#[test]
+fn router_manager_parses_and_validates_timeouts_section() {
+    let parsed: FileConfig = toml::from_str(
+        "[routers.default]\nincoming_url = \"http://localhost:8080/default\"\n[routers.default.timeouts]\nconnect_ms = 1000\nfirst_byte_ms = 2000\nstream_idle_ms = 3000",
+    )
+    .expect("ok");
+    let target = RouterManager::new(
+        parsed.routers.expect("routers"),
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager")
+    .get_target_for_incoming_route("/default", Some("localhost:8080"))
+    .expect("route")
+    .expect("target");
+
+    assert_eq!(
+        target.timeouts,
+        TimeoutPolicy {
+            connect: Some(Duration::from_millis(1000)),
+            first_byte: Some(Duration::from_millis(2000)),
+            total: None,
+            stream_idle: Some(Duration::from_millis(3000)),
+        }
+    );
+    assert!(target.client.is_some());
+
+    let err = TimeoutPolicy::from_config(
+        Some(&TimeoutConfig {
+            stream_idle_ms: Some(0),
+            ..Default::default()
+        }),
+        "[routers.default]",
+    )
+    .expect_err("zero timeout must fail");
+    assert!(
+        err.to_string()
+            .contains("[routers.default] timeouts.stream_idle_ms must be greater than 0")
+    );
+}
+
+fn stalled_chat_stream(
+    policy: TimeoutPolicy,
+    deadline: Option<tokio::time::Instant>,
+) -> impl futures::Stream<Item = Result<Bytes, crate::timeouts::UpstreamStreamError>> {
     let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
         "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream(
-        upstream,
+    ))])
+    .chain(stream::pending());
+    crate::timeouts::with_stream_timeouts(upstream, policy, deadline)
+}
+
+async fn collect_stream_text(
+    output: impl futures::Stream<Item = Result<Bytes, std::convert::Infallible>>,
+) -> String {
+    let mut output = Box::pin(output);
+    let mut payload = String::new();
+    while let Some(event) = output.next().await {
+        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    }
+    payload
+}
+
+#[tokio::test]
+async fn stream_idle_timeout_ends_responses_stream_with_response_failed() {
+    let payload = collect_stream_text(translate_chat_stream(
+        stalled_chat_stream(
+            TimeoutPolicy {
+                stream_idle: Some(Duration::from_millis(20)),
+                ..Default::default()
+            },
+            None,
+        ),
         "resp_1".to_string(),
         "test_router".to_string(),
         false,
         HashMap::new(),
         FeatureFlags::default(),
-    ));
-    let mut payload = String::new();
-
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+        None,
+    ))
+    .await;
 
-    assert!(payload.contains("\"type\":\"response.failed\""));
+    assert!(payload.contains("event: response.output_text.delta"));
+    assert!(payload.contains("event: response.failed"));
+    assert!(payload.contains("\"code\":\"upstream_timeout\""));
+    assert!(payload.contains("stream_idle_ms=20"));
     assert!(!payload.contains("event: response.completed"));
 }
 
 #[tokio::test]
-async fn stream_emits_added_and_done_for_tool_calls() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"name\":\"shell\",\"arguments\":\"echo hi\"}}]}}],\"usage\":{\"prompt_tokens\":1,\"completion_tokens\":1,\"total_tokens\":2}}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut kinds = HashMap::new();
-    kinds.insert("shell".to_string(), ResponsesToolCallKind::Custom);
-    let mut output = Box::pin(translate_chat_stream(
-        upstream,
-        "resp_1".to_string(),
+async fn stream_total_timeout_ends_anthropic_stream_with_error_event() {
+    let policy = TimeoutPolicy {
+        total: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let deadline = policy.deadline_from(tokio::time::Instant::now());
+    let payload = collect_stream_text(translate_chat_stream_to_anthropic(
+        stalled_chat_stream(policy, deadline),
         "test_router".to_string(),
         false,
-        kinds,
-        FeatureFlags::default(),
-    ));
-    let mut payload = String::new();
+        "claude-sonnet".to_string(),
+        1,
+    ))
+    .await;
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    assert!(payload.contains("event: message_start"));
+    assert!(payload.contains("event: error"));
+    assert!(payload.contains("total_ms=20"));
+    assert!(!payload.contains("event: message_stop"));
+}
 
-    let added_idx = payload
-        .find("event: response.output_item.added")
-        .expect("added event");
-    let done_idx = payload
-        .rfind("event: response.output_item.done")
-        .expect("done event");
-    assert!(added_idx < done_idx);
-    assert!(payload.contains("\"call_id\":\"call_resp_1_0\""));
-    assert!(payload.contains("event: response.function_call_arguments.delta"));
-    assert!(payload.contains("event: response.function_call_arguments.done"));
-    assert!(payload.contains("\"arguments\":\"echo hi\""));
+#[tokio::test]
+#[ignore = "requires binding a local TCP listener"]
+async fn first_byte_timeout_fails_request_with_upstream_timeout() {
+    let app = axum::Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Json(json!({}))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind mock upstream");
+    let addr = listener.local_addr().expect("mock upstream address");
+    let upstream_handle = tokio::spawn(async move {
+        let _ = axum::serve(listener, app).await;
+    });
+
+    let mut routers = BTreeMap::new();
+    routers.insert(
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/chat/completions".to_string()),
+            upstream_url: Some(format!("http://{addr}/v1/chat/completions")),
+            timeouts: Some(TimeoutConfig {
+                first_byte_ms: Some(50),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    let router_manager = RouterManager::new(
+        routers,
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        FeatureFlags::default(),
+    )
+    .expect("router manager");
+
+    let json = post_chat_completion(Arc::new(AppState {
+        client: Client::new(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
+        http_shutdown: false,
+        verbose_logging: false,
+        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
+        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    }))
+    .await;
+    upstream_handle.abort();
+
+    assert_eq!(json["error"]["type"], "upstream_timeout");
+    assert!(
+        json["error"]["message"]
+            .as_str()
+            .expect("message")
+            .contains("first_byte_ms=50")
+    );
 }
 
-#[tokio::test]
-async fn stream_tool_call_added_event_does_not_include_partial_arguments() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"exec_command\",\"arguments\":\"{\"}}]}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"cmd\\\":\\\"pwd\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream(
-        upstream,
-        "resp_1".to_string(),
-        "test_router".to_string(),
-        false,
-        HashMap::new(),
+fn router_manager_from_toml(raw: &str) -> RouterManager {
+    let parsed: FileConfig = toml::from_str(raw).expect("ok");
+    RouterManager::new(
+        parsed.routers.expect("routers"),
+        "https://api.openai.com/v1/chat/completions".to_string(),
+        WireApi::Chat,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
         FeatureFlags::default(),
-    ));
-    let mut payload = String::new();
+    )
+    .expect("router manager")
+}
+
+#[test]
+fn reloaded_router_manager_keeps_balancer_state_for_remaining_upstreams() {
+    let balanced = |upstreams: &str| {
+        router_manager_from_toml(&format!(
+            r#"
+[routers.default]
+incoming_url = "http://127.0.0.1:8787/default"
+upstream_url = "https://a.example/v1/chat/completions"
+upstreams = [{upstreams}]
+[routers.default.load_balancing]
+strategy = "failover"
+eject_after_failures = 1
+[routers.other]
+incoming_url = "http://127.0.0.1:8787/other"
+"#
+        ))
+    };
+    let previous = balanced(r#"{ url = "https://b.example/v1/chat/completions" }"#);
+    let balancer = previous.load_balancer();
+    let target = previous
+        .resolve_target_for_router_name("default")
+        .expect("target");
+    let policy = target.load_balancing.clone().expect("policy");
+    assert!(balancer.record_failure("default", "https://a.example/v1/chat/completions", &policy));
+    balancer.remember_response("resp_b", "default", "https://b.example/v1/chat/completions");
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let mut current = balanced(
+        r#"{ url = "https://c.example/v1/chat/completions" }, { url = "https://b.example/v1/chat/completions" }"#,
+    );
+    current.reuse_load_balancer(balancer.clone());
+    assert!(Arc::ptr_eq(&current.load_balancer(), &balancer));
+    let target = current
+        .resolve_target_for_router_name("default")
+        .expect("target");
+    assert_eq!(first_candidate_host(&balancer, &target, None), "c");
+    assert_eq!(
+        first_candidate_host(&balancer, &target, Some("resp_b")),
+        "b"
+    );
 
-    let added = sse_payloads_for_event(&payload, "response.output_item.added");
-    assert_eq!(added.len(), 1);
-    assert_eq!(added[0]["item"]["type"], "function_call");
-    assert_eq!(added[0]["item"]["name"], "exec_command");
-    assert_eq!(added[0]["item"]["arguments"], "");
+    let mut without_b = balanced(r#"{ url = "https://c.example/v1/chat/completions" }"#);
+    without_b.reuse_load_balancer(balancer.clone());
+    current.reuse_load_balancer(balancer.clone());
+    let target = current
+        .resolve_target_for_router_name("default")
+        .expect("target");
+    assert_eq!(
+        first_candidate_host(&balancer, &target, Some("resp_b")),
+        "c"
+    );
+}
 
-    let done = sse_payloads_for_event(&payload, "response.output_item.done");
-    assert_eq!(done.len(), 1);
-    assert_eq!(done[0]["item"]["arguments"], "{\"cmd\":\"pwd\"}");
+#[test]
+fn router_reload_changes_report_added_changed_and_removed_routers() {
+    let previous = router_manager_from_toml(
+        r#"
+[routers.kept]
+incoming_url = "http://127.0.0.1:8787/kept"
+[routers.changed]
+incoming_url = "http://127.0.0.1:8787/changed"
+upstream_model = "old-model"
+[routers.removed]
+incoming_url = "http://127.0.0.1:8787/removed"
+"#,
+    );
+    let current = router_manager_from_toml(
+        r#"
+[routers.kept]
+incoming_url = "http://127.0.0.1:8787/kept"
+[routers.changed]
+incoming_url = "http://127.0.0.1:8787/changed"
+upstream_model = "new-model"
+[routers.added]
+incoming_url = "http://127.0.0.1:8788/added"
+"#,
+    );
 
-    let argument_done = sse_payloads_for_event(&payload, "response.function_call_arguments.done");
-    assert_eq!(argument_done.len(), 1);
-    assert_eq!(argument_done[0]["arguments"], "{\"cmd\":\"pwd\"}");
+    let changes = router_reload_changes(&previous, &current);
+    assert_eq!(changes.len(), 3, "{changes:?}");
+    assert!(changes[0].starts_with("router added: name=added"));
+    assert!(changes[1].starts_with("router changed: name=changed"));
+    assert!(changes[1].contains("upstream_model=new-model"));
+    assert!(changes[1].contains("(was: name=changed"));
+    assert_eq!(changes[2], "router removed: name=removed");
+    assert!(router_reload_changes(&current, &current).is_empty());
 }
 
-#[tokio::test]
-async fn stream_emits_reasoning_summary_events() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"think \"}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"step\"}}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream(
-        upstream,
-        "resp_1".to_string(),
-        "test_router".to_string(),
-        false,
-        HashMap::new(),
-        FeatureFlags::default(),
-    ));
-    let mut payload = String::new();
+#[test]
+fn config_reload_rejects_invalid_or_missing_config() {
+    let args = Args {
+        config: None,
+        upstream_url: None,
+        upstream_wire: None,
+        upstream_http_headers: vec![],
+        forward_incoming_headers: vec![],
+        api_key_env: None,
+        server_info: None,
+        http_shutdown: false,
+        verbose_logging: false,
+        drop_tool_types: vec![],
+        router: None,
+        list_routers: false,
+        capture_dir: None,
+        command: None,
+    };
+    let api_key = ApiKey::Resolved("test-key".to_string());
+    let config_path =
+        std::env::temp_dir().join(format!("codex-chat-bridge-reload-{}.toml", Uuid::now_v7()));
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let err = match load_reloaded_router_manager(&args, &config_path, &api_key) {
+        Ok(_) => panic!("missing config must be rejected"),
+        Err(err) => format!("{err:#}"),
+    };
+    assert!(err.contains("no longer exists"));
 
-    assert!(payload.contains("event: response.reasoning_summary_text.delta"));
-    assert!(payload.contains("event: response.reasoning_summary_text.done"));
-    assert!(payload.contains("\"text\":\"think step\""));
-    assert!(!payload.contains("[reasoning_summary]"));
-}
+    std::fs::write(&config_path, "[routers.default\nincoming_url = ").expect("write config");
+    let err = match load_reloaded_router_manager(&args, &config_path, &api_key) {
+        Ok(_) => panic!("invalid toml must be rejected"),
+        Err(err) => format!("{err:#}"),
+    };
+    assert!(err.contains("parsing config file"));
 
-#[tokio::test]
-async fn anthropic_stream_message_start_uses_model_and_input_tokens_without_null_stop_fields() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "gpt-4.1".to_string(),
-        17,
-    ));
-    let first = output
-        .next()
-        .await
-        .expect("message_start event")
-        .expect("stream event");
-    let payload = String::from_utf8_lossy(&first);
+    std::fs::write(
+        &config_path,
+        "[routers.default]\nincoming_url = \"/default\"\n",
+    )
+    .expect("write config");
+    let err = match load_reloaded_router_manager(&args, &config_path, &api_key) {
+        Ok(_) => panic!("config without listen address must be rejected"),
+        Err(err) => format!("{err:#}"),
+    };
+    assert!(err.contains("no listenable incoming_url"));
 
-    assert!(payload.contains("event: message_start"));
-    assert!(payload.contains("\"model\":\"gpt-4.1\""));
-    assert!(payload.contains("\"input_tokens\":17"));
-    assert!(!payload.contains("\"stop_reason\":null"));
-    assert!(!payload.contains("\"stop_sequence\":null"));
+    std::fs::write(
+        &config_path,
+        "[routers.default]\nincoming_url = \"http://127.0.0.1:8787/default\"\n",
+    )
+    .expect("write config");
+    let (_, router_manager) =
+        load_reloaded_router_manager(&args, &config_path, &api_key).expect("valid reload");
+    let _ = std::fs::remove_file(&config_path);
+    assert_eq!(router_manager.get_listen_addrs(), vec!["127.0.0.1:8787"]);
 }
 
 #[tokio::test]
-async fn anthropic_stream_maps_native_tool_call_delta() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"shell\",\"arguments\":\"{\\\"cmd\\\":\"}}]}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"pwd\\\"}\"}}]},\"finish_reason\":\"tool_calls\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "gpt-4.1".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[ignore = "requires binding a local TCP listener"]
+async fn listener_set_starts_new_addresses_and_drains_removed_ones() {
+    let mut listeners = crate::reload::ListenerSet::new(
+        axum::Router::new().route("/healthz", axum::routing::get(|| async { "ok" })),
+        None,
+    );
+    let listen_addrs = vec!["127.0.0.1:0".to_string()];
+    listeners
+        .start_missing(&listen_addrs)
+        .await
+        .expect("start listener");
+    listeners
+        .start_missing(&listen_addrs)
+        .await
+        .expect("already running listeners are kept");
+    let ports = listeners.ports();
+    assert_eq!(ports.len(), 1);
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let body = Client::new()
+        .get(format!("http://127.0.0.1:{}/healthz", ports[0]))
+        .send()
+        .await
+        .expect("request")
+        .text()
+        .await
+        .expect("body");
+    assert_eq!(body, "ok");
 
-    assert!(payload.contains("\"type\":\"tool_use\""));
-    assert!(payload.contains("\"id\":\"call_1\""));
-    assert!(payload.contains("\"name\":\"shell\""));
-    assert!(payload.contains("\"partial_json\":\"{\\\"cmd\\\":\""));
-    assert!(payload.contains("\"partial_json\":\"\\\"pwd\\\"}\""));
-    assert!(payload.contains("\"stop_reason\":\"tool_use\""));
+    listeners.drain_removed(&[]);
+    assert!(listeners.ports().is_empty());
+    let err = tokio::time::timeout(Duration::from_secs(5), listeners.wait_for_unexpected_exit())
+        .await
+        .expect("drained listener exits")
+        .expect_err("no listener remains");
+    assert_eq!(err.to_string(), "no listener task started");
 }
 
-#[tokio::test]
-async fn anthropic_stream_handles_split_think_tags_across_chunks() {
-    let upstream = stream::iter(vec![
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"content\":\"before <thi\"}}]}\n\n",
-        )),
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"content\":\"nk>internal</thi\"}}]}\n\n",
-        )),
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"content\":\"nk> after\"},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-        )),
-    ]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[test]
+fn inbound_auth_accepts_configured_tokens_per_header() {
+    let tokens_file =
+        std::env::temp_dir().join(format!("codex-chat-bridge-tokens-{}", Uuid::now_v7()));
+    std::fs::write(&tokens_file, "# team box\nfile-token\n\n").expect("write tokens file");
+    let auth = InboundAuth::from_config(
+        Some(&InboundAuthConfig {
+            bearer_tokens: Some(vec![" bearer-token ".to_string()]),
+            x_api_key_tokens: Some(vec!["anthropic-token".to_string()]),
+            tokens_file: Some(tokens_file.clone()),
+        }),
+        "[routers.default]",
+    )
+    .expect("valid config")
+    .expect("auth");
+    let _ = std::fs::remove_file(&tokens_file);
+    assert_eq!(auth.describe(), "bearer_tokens:2,x_api_key_tokens:2");
+
+    let headers_with = |name: &str, value: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).expect("header name"),
+            HeaderValue::from_str(value).expect("header value"),
+        );
+        headers
+    };
+    assert!(auth.authorizes(&headers_with("authorization", "Bearer bearer-token")));
+    assert!(auth.authorizes(&headers_with("authorization", "bearer file-token")));
+    assert!(auth.authorizes(&headers_with("x-api-key", "anthropic-token")));
+    assert!(auth.authorizes(&headers_with("x-api-key", "file-token")));
+    assert!(!auth.authorizes(&headers_with("x-api-key", "bearer-token")));
+    assert!(!auth.authorizes(&headers_with("authorization", "Bearer anthropic-token")));
+    assert!(!auth.authorizes(&headers_with("authorization", "bearer-token")));
+    assert!(!auth.authorizes(&HeaderMap::new()));
+
+    let err = InboundAuth::from_config(Some(&InboundAuthConfig::default()), "[routers.default]")
+        .expect_err("inbound_auth without tokens must fail");
+    assert!(
+        err.to_string()
+            .contains("[routers.default] inbound_auth must configure at least one token")
+    );
+}
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+fn test_state_with_inbound_auth() -> Arc<AppState> {
+    let router_manager = router_manager_from_toml(
+        r#"
+[routers.claude]
+incoming_url = "http://127.0.0.1:8787/claude/v1/messages"
+[routers.claude.inbound_auth]
+x_api_key_tokens = ["team-token"]
+[routers.codex]
+incoming_url = "http://127.0.0.1:8787/codex/v1/responses"
+[routers.codex.inbound_auth]
+bearer_tokens = ["team-token"]
+"#,
+    );
+    Arc::new(AppState {
+        client: Client::new(),
+        api_key: ApiKey::Resolved("test-key".to_string()),
+        http_shutdown: false,
+        verbose_logging: false,
+        routers: Arc::new(tokio::sync::RwLock::new(router_manager)),
+        sessions: Arc::new(tokio::sync::RwLock::new(SessionStore::default())),
+        metrics: Arc::new(Metrics::default()),
+        background: Arc::new(BackgroundResponses::default()),
+        capture_dir: None,
+    })
+}
 
-    assert!(payload.contains("\"text\":\"before <thi\""));
-    assert!(payload.contains("\"text\":\"nk>internal</thi\""));
-    assert!(payload.contains("\"text\":\"nk> after\""));
-    assert!(!payload.contains("\"type\":\"thinking_delta\""));
+async fn post_routed(
+    state: Arc<AppState>,
+    uri: &str,
+    auth_header: Option<(&str, &str)>,
+    body: &str,
+) -> String {
+    let mut request = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("host", "127.0.0.1:8787")
+        .header("content-type", "application/json");
+    if let Some((name, value)) = auth_header {
+        request = request.header(name, value);
+    }
+    let response = build_app(state)
+        .oneshot(request.body(Body::from(body.to_string())).expect("request"))
+        .await
+        .expect("response");
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    String::from_utf8(body.to_vec()).expect("utf8 body")
 }
 
 #[tokio::test]
-async fn anthropic_stream_ignores_signature_delta_from_thinking_blocks() {
-    let upstream = stream::iter(vec![
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"thinking_blocks\":[{\"type\":\"thinking\",\"thinking\":\"step one\",\"signature\":\"sig_1\"}]}}]}\n\n",
-        )),
-        Ok::<Bytes, reqwest::Error>(Bytes::from("data: [DONE]\n\n")),
-    ]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+async fn inbound_auth_rejects_unauthenticated_requests_in_client_error_shape() {
+    let state = test_state_with_inbound_auth();
+    let count_tokens_body =
+        r#"{"model":"claude-sonnet","messages":[{"role":"user","content":"hello"}]}"#;
+
+    let rejected = post_routed(
+        state.clone(),
+        "/claude/v1/messages/count_tokens",
+        Some(("x-api-key", "wrong-token")),
+        count_tokens_body,
+    )
+    .await;
+    let json: Value = serde_json::from_str(&rejected).expect("json");
+    assert_eq!(json["type"], "error");
+    assert_eq!(json["error"]["type"], "authentication_error");
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let accepted = post_routed(
+        state.clone(),
+        "/claude/v1/messages/count_tokens",
+        Some(("x-api-key", "team-token")),
+        count_tokens_body,
+    )
+    .await;
+    let json: Value = serde_json::from_str(&accepted).expect("json");
+    assert!(json["input_tokens"].as_i64().is_some(), "{json}");
 
-    assert!(!payload.contains("\"type\":\"thinking_delta\""));
-    assert!(!payload.contains("\"type\":\"signature_delta\""));
-    assert_eq!(payload.matches("event: content_block_start").count(), 0);
-    assert!(payload.contains("event: message_stop"));
+    let rejected = post_routed(
+        state,
+        "/codex/v1/responses",
+        None,
+        r#"{"model":"gpt-4.1","input":"hi"}"#,
+    )
+    .await;
+    assert!(rejected.contains("event: response.failed"));
+    assert!(rejected.contains("\"code\":\"invalid_api_key\""));
+}
+
+#[test]
+fn usage_tokens_reads_chat_responses_and_anthropic_usage_blocks() {
+    let usage = |prompt_tokens, completion_tokens| {
+        Some(UsageTokens {
+            prompt_tokens,
+            completion_tokens,
+        })
+    };
+    assert_eq!(
+        usage_tokens(&json!({"usage":{"prompt_tokens":3,"completion_tokens":5}})),
+        usage(3, 5)
+    );
+    assert_eq!(
+        usage_tokens(
+            &json!({"type":"response.completed","response":{"usage":{"input_tokens":7,"output_tokens":2}}})
+        ),
+        usage(7, 2)
+    );
+    assert_eq!(
+        usage_tokens(
+            &json!({"type":"message_start","message":{"usage":{"input_tokens":11,"output_tokens":1}}})
+        ),
+        usage(11, 1)
+    );
+    assert_eq!(
+        usage_tokens(&json!({"type":"response.created","response":{"usage":null}})),
+        None
+    );
 }
 
 #[tokio::test]
-async fn anthropic_stream_ignores_redacted_thinking_blocks() {
+async fn observe_stream_metrics_counts_usage_once_per_stream() {
+    let metrics = Arc::new(Metrics::default());
     let upstream = stream::iter(vec![
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"thinking_blocks\":[{\"type\":\"redacted_thinking\",\"data\":\"secret\"}]}}]}\n\n",
+        Ok::<Bytes, UpstreamStreamError>(Bytes::from(
+            "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n",
+        )),
+        Ok(Bytes::from(
+            "event: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":9}}\n\n",
         )),
-        Ok::<Bytes, reqwest::Error>(Bytes::from("data: [DONE]\n\n")),
     ]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+    let chunks = observe_stream_metrics(upstream, metrics.clone(), "claude".to_string())
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(chunks.len(), 2);
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let rendered = metrics.render();
+    assert!(rendered.contains("codex_chat_bridge_prompt_tokens_total{router=\"claude\"} 12"));
+    assert!(rendered.contains("codex_chat_bridge_completion_tokens_total{router=\"claude\"} 9"));
+}
 
-    assert!(!payload.contains("\"type\":\"redacted_thinking\""));
-    assert_eq!(payload.matches("event: content_block_start").count(), 0);
-    assert!(!payload.contains("response.failed"));
+#[test]
+fn metrics_render_histograms_and_release_in_flight_streams() {
+    let metrics = Arc::new(Metrics::default());
+    let mut guard = metrics.start_request("default", IncomingApi::Responses, WireApi::Chat, true);
+    metrics.observe_time_to_first_byte("default", Duration::from_millis(70));
+    assert!(
+        !metrics
+            .render()
+            .contains("codex_chat_bridge_requests_total{")
+    );
+    guard.set_upstream_wire(WireApi::Messages);
+    assert!(
+        metrics
+            .render()
+            .contains("codex_chat_bridge_in_flight_streams{router=\"default\"} 1")
+    );
+    drop(guard);
+
+    let rendered = metrics.render();
+    assert!(rendered.contains("# TYPE codex_chat_bridge_request_duration_seconds histogram"));
+    assert!(rendered.contains(
+        "codex_chat_bridge_requests_total{router=\"default\",incoming_api=\"responses\",upstream_wire=\"messages\"} 1"
+    ));
+    assert!(rendered.contains(
+        "codex_chat_bridge_upstream_time_to_first_byte_seconds_bucket{router=\"default\",le=\"0.05\"} 0"
+    ));
+    assert!(rendered.contains(
+        "codex_chat_bridge_upstream_time_to_first_byte_seconds_bucket{router=\"default\",le=\"0.1\"} 1"
+    ));
+    assert!(
+        rendered.contains("codex_chat_bridge_request_duration_seconds_count{router=\"default\"} 1")
+    );
+    assert!(rendered.contains("codex_chat_bridge_in_flight_streams{router=\"default\"} 0"));
 }
 
 #[tokio::test]
-async fn anthropic_stream_accepts_terminal_finish_reason_without_done_marker() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[ignore = "requires binding a local TCP listener"]
+async fn metrics_endpoint_reports_upstream_statuses_errors_and_tokens() {
+    let (upstream_url, upstream_handle, _calls) = spawn_mock_flaky_upstream(
+        "/v1/chat/completions",
+        1,
+        json!({
+            "id": "chatcmpl_1",
+            "object": "chat.completion",
+            "model": "gpt-4.1",
+            "choices": [{"index":0,"finish_reason":"stop","message":{"role":"assistant","content":"hello"}}],
+            "usage": {"prompt_tokens": 4, "completion_tokens": 6, "total_tokens": 10}
+        }),
+    )
+    .await;
+    let state = test_state_with_retry_router(
+        &upstream_url,
+        RetryConfig {
+            max_attempts: Some(2),
+            initial_backoff_ms: Some(1),
+            max_backoff_ms: Some(5),
+            ..Default::default()
+        },
+    );
+    post_chat_completion(state.clone()).await;
+    upstream_handle.abort();
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    let response = build_app(state)
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))))
+                .body(Body::empty())
+                .expect("request"),
+        )
+        .await
+        .expect("response");
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).expect("content type"),
+        "text/plain; version=0.0.4"
+    );
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    let rendered = String::from_utf8(body.to_vec()).expect("utf8 body");
+    for expected in [
+        "codex_chat_bridge_requests_total{router=\"default\",incoming_api=\"chat\",upstream_wire=\"chat\"} 1",
+        "codex_chat_bridge_upstream_responses_total{router=\"default\",status=\"200\"} 1",
+        "codex_chat_bridge_upstream_responses_total{router=\"default\",status=\"502\"} 1",
+        "codex_chat_bridge_upstream_errors_total{router=\"default\",code=\"upstream_error\"} 1",
+        "codex_chat_bridge_upstream_time_to_first_byte_seconds_count{router=\"default\"} 1",
+        "codex_chat_bridge_request_duration_seconds_count{router=\"default\"} 1",
+        "codex_chat_bridge_prompt_tokens_total{router=\"default\"} 4",
+        "codex_chat_bridge_completion_tokens_total{router=\"default\"} 6",
+    ] {
+        assert!(
+            rendered.contains(expected),
+            "missing `{expected}` in:\n{rendered}"
+        );
     }
-
-    assert!(payload.contains("event: message_start"));
-    assert_eq!(payload.matches("event: content_block_start").count(), 1);
-    assert!(payload.contains("event: content_block_delta"));
-    assert!(payload.contains("\"text\":\"Hi\""));
-    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
-    assert!(payload.contains("event: message_delta"));
-    assert!(payload.contains("event: message_stop"));
-    assert!(!payload.contains("event: error"));
 }
 
 #[tokio::test]
-async fn anthropic_stream_splits_think_tags_in_content() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"before <think>internal</think> after\"}}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
+async fn metrics_endpoint_only_answers_loopback_peers() {
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
     ));
-    let mut payload = String::new();
-
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    for (peer, expected) in [
+        (None, StatusCode::NOT_FOUND),
+        (
+            Some(SocketAddr::from(([192, 168, 1, 20], 40000))),
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            Some(SocketAddr::from(([127, 0, 0, 1], 40000))),
+            StatusCode::OK,
+        ),
+        (
+            Some("[::ffff:127.0.0.1]:40000".parse().expect("socket addr")),
+            StatusCode::OK,
+        ),
+    ] {
+        let mut request = Request::builder().uri("/metrics");
+        if let Some(peer) = peer {
+            request = request.extension(ConnectInfo(peer));
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).expect("request"))
+            .await
+            .expect("response");
+        assert_eq!(response.status(), expected, "peer {peer:?}");
     }
+}
 
-    assert!(payload.contains("\"type\":\"text_delta\""));
-    assert!(payload.contains("\"text\":\"before <think>internal</think> after\""));
-    assert!(!payload.contains("\"type\":\"thinking_delta\""));
-    assert_eq!(payload.matches("event: content_block_start").count(), 1);
-    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
+#[test]
+fn parse_models_path_splits_router_base_and_model_id() {
+    assert_eq!(
+        parse_models_path("chat-bridge/v1/models"),
+        Some(("/chat-bridge".to_string(), None))
+    );
+    assert_eq!(
+        parse_models_path("/v1/models/openrouter/anthropic/claude-sonnet-4.5"),
+        Some((
+            String::new(),
+            Some("openrouter/anthropic/claude-sonnet-4.5".to_string())
+        ))
+    );
+    assert_eq!(parse_models_path("/v1/modelsx"), None);
+    assert_eq!(parse_models_path("/v1/responses"), None);
 }
 
-#[tokio::test]
-async fn anthropic_stream_ignores_reasoning_details() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"reasoning_details\":[{\"text\":\"step one\"}]}}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[test]
+fn upstream_models_url_and_ids_follow_each_wire() {
+    assert_eq!(
+        upstream_models_url(
+            "https://openrouter.ai/api/v1/chat/completions",
+            WireApi::Chat
+        )
+        .as_deref(),
+        Some("https://openrouter.ai/api/v1/models")
+    );
+    assert_eq!(
+        upstream_models_url(
+            "https://generativelanguage.googleapis.com/v1beta/models/{model}:generateContent",
+            WireApi::Gemini
+        )
+        .as_deref(),
+        Some("https://generativelanguage.googleapis.com/v1beta/models")
+    );
+    assert_eq!(
+        upstream_models_url("http://127.0.0.1:11434/api/chat", WireApi::Ollama).as_deref(),
+        Some("http://127.0.0.1:11434/api/tags")
+    );
+    assert_eq!(
+        upstream_model_ids(
+            &json!({"data":[{"id":"gpt-5"}],"models":[{"name":"models/gemini-2.5-pro"},{"name":"qwen3:8b"}]})
+        ),
+        vec!["gpt-5", "gemini-2.5-pro", "qwen3:8b"]
+    );
+}
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+fn models_test_app() -> Router {
+    build_app(test_state_with_routers(BTreeMap::from([
+        (
+            "codex".to_string(),
+            RouterConfig {
+                incoming_url: Some("http://127.0.0.1:8787/bridge/v1/responses".to_string()),
+                upstream_url: Some("http://127.0.0.1:9/v1/chat/completions".to_string()),
+                upstream_model: Some("gpt-oss-120b".to_string()),
+                models: Some(vec!["gpt-5".to_string(), " gpt-oss-120b ".to_string()]),
+                ..Default::default()
+            },
+        ),
+        (
+            "claude".to_string(),
+            RouterConfig {
+                incoming_url: Some("http://127.0.0.1:8787/bridge/v1/messages".to_string()),
+                upstream_url: Some("http://127.0.0.1:9/v1/chat/completions".to_string()),
+                upstream_model_sonnet: Some("qwen3-coder".to_string()),
+                upstream_model_haiku: Some("qwen3-small".to_string()),
+                ..Default::default()
+            },
+        ),
+    ])))
+}
+
+async fn get_json(app: Router, uri: &str, anthropic: bool) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method("GET")
+        .uri(uri)
+        .header("host", "127.0.0.1:8787");
+    if anthropic {
+        request = request.header("anthropic-version", "2023-06-01");
     }
+    let response = app
+        .oneshot(request.body(Body::empty()).expect("request"))
+        .await
+        .expect("response");
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("body");
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
 
-    assert!(!payload.contains("\"thinking\":\"step one\""));
-    assert_eq!(payload.matches("event: content_block_start").count(), 0);
-    assert_eq!(payload.matches("event: content_block_stop").count(), 0);
-    assert!(payload.contains("event: message_stop"));
+#[tokio::test]
+async fn models_endpoint_lists_router_models_in_client_shape() {
+    let (status, openai) = get_json(models_test_app(), "/bridge/v1/models", false).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(openai["object"], "list");
+    assert_eq!(
+        openai["data"]
+            .as_array()
+            .expect("data")
+            .iter()
+            .map(|model| model["id"].as_str().expect("id"))
+            .collect::<Vec<_>>(),
+        vec!["gpt-5", "gpt-oss-120b"]
+    );
+    assert_eq!(openai["data"][0]["object"], "model");
+    assert_eq!(openai["data"][0]["owned_by"], "codex");
+
+    let (status, anthropic) = get_json(models_test_app(), "/bridge/v1/models", true).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(anthropic["data"][0]["type"], "model");
+    assert_eq!(anthropic["data"][0]["id"], "qwen3-coder");
+    assert_eq!(anthropic["first_id"], "qwen3-coder");
+    assert_eq!(anthropic["last_id"], "qwen3-small");
+    assert_eq!(anthropic["has_more"], false);
+
+    let (status, model) = get_json(models_test_app(), "/bridge/v1/models/gpt-5", false).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(model["id"], "gpt-5");
+
+    let (status, missing) =
+        get_json(models_test_app(), "/bridge/v1/models/claude-opus-4", true).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(missing["error"]["type"], "not_found_error");
+
+    let (status, _) = get_json(models_test_app(), "/elsewhere/v1/models", false).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
 }
 
 #[tokio::test]
-async fn anthropic_stream_keeps_heuristic_tool_call_text_as_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"before shell(command='pwd') after\"}}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[ignore = "requires binding a local TCP listener"]
+async fn models_endpoint_merges_upstream_listing() {
+    let captured_auth = Arc::new(tokio::sync::Mutex::new(None::<String>));
+    let upstream_app = {
+        let captured_auth = captured_auth.clone();
+        axum::Router::new().route(
+            "/v1/models",
+            axum::routing::get(move |headers: HeaderMap| async move {
+                *captured_auth.lock().await = headers
+                    .get("authorization")
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToString::to_string);
+                Json(json!({"object":"list","data":[{"id":"gpt-5"},{"id":"o4-mini"}]}))
+            }),
+        )
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind mock upstream");
+    let addr = listener.local_addr().expect("mock upstream address");
+    let upstream_handle = tokio::spawn(async move {
+        let _ = axum::serve(listener, upstream_app).await;
+    });
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let app = build_app(test_state_with_routers(BTreeMap::from([(
+        "codex".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/responses".to_string()),
+            upstream_url: Some(format!("http://{addr}/v1/chat/completions")),
+            upstream_model: Some("gpt-5".to_string()),
+            merge_upstream_models: Some(true),
+            ..Default::default()
+        },
+    )])));
+    let (status, listing) = get_json(app, "/v1/models", false).await;
 
-    assert!(payload.contains("\"text\":\"before shell(command='pwd') after\""));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
-    assert!(payload.contains("\"stop_reason\":\"end_turn\""));
+    upstream_handle.abort();
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        listing["data"]
+            .as_array()
+            .expect("data")
+            .iter()
+            .map(|model| model["id"].as_str().expect("id"))
+            .collect::<Vec<_>>(),
+        vec!["gpt-5", "o4-mini"]
+    );
+    assert_eq!(
+        captured_auth.lock().await.as_deref(),
+        Some("Bearer test-key")
+    );
+}
+
+#[test]
+fn parse_stored_response_path_splits_base_id_and_resource() {
+    assert_eq!(
+        parse_stored_response_path("bridge/v1/responses/resp_bridge_1"),
+        Some((
+            "/bridge".to_string(),
+            "resp_bridge_1".to_string(),
+            StoredResponseResource::Response
+        ))
+    );
+    assert_eq!(
+        parse_stored_response_path("/v1/responses/resp_bridge_1/input_items"),
+        Some((
+            String::new(),
+            "resp_bridge_1".to_string(),
+            StoredResponseResource::InputItems
+        ))
+    );
+    assert_eq!(parse_stored_response_path("/v1/responses"), None);
+    assert_eq!(parse_stored_response_path("/v1/responses/"), None);
+    assert_eq!(
+        parse_stored_response_path("/v1/responses/resp_1/other"),
+        None
+    );
 }
 
 #[tokio::test]
-async fn anthropic_stream_keeps_exit_plan_mode_python_call_as_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"ExitPlanMode()\"}}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
+async fn stored_response_endpoints_read_and_delete_session_turns() {
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/bridge/v1/responses",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
+    );
+    SessionTurn::new(
+        state.sessions.clone(),
+        "default".to_string(),
+        "resp_bridge_1".to_string(),
+        "gpt-test".to_string(),
+        vec![
+            json!({"role":"system","content":"be brief"}),
+            json!({"role":"user","content":"hi"}),
+        ],
+    )
+    .complete(
+        &[json!({
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type":"output_text","text":"hello"}],
+        })],
+        Some(&json!({"input_tokens":3,"output_tokens":1,"total_tokens":4})),
+    )
+    .await;
+    let app = build_app(state);
+
+    let (status, response) =
+        get_json(app.clone(), "/bridge/v1/responses/resp_bridge_1", false).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["object"], "response");
+    assert_eq!(response["status"], "completed");
+    assert_eq!(response["output"][0]["content"][0]["text"], "hello");
+    assert_eq!(response["usage"]["total_tokens"], 4);
+
+    let (status, items) = get_json(
+        app.clone(),
+        "/bridge/v1/responses/resp_bridge_1/input_items",
         false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(items["object"], "list");
+    assert_eq!(items["data"].as_array().expect("data").len(), 2);
+    assert_eq!(items["data"][0]["role"], "user");
+    assert_eq!(items["first_id"], "resp_bridge_1_item_1");
+    let (_, ascending) = get_json(
+        app.clone(),
+        "/bridge/v1/responses/resp_bridge_1/input_items?order=asc",
+        false,
+    )
+    .await;
+    assert_eq!(ascending["data"][0]["role"], "system");
+
+    let delete = |app: Router| async move {
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/bridge/v1/responses/resp_bridge_1")
+                    .header("host", "127.0.0.1:8787")
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body");
+        (
+            status,
+            serde_json::from_slice::<Value>(&body).expect("json"),
+        )
+    };
+    let (status, deleted) = delete(app.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(deleted["deleted"], true);
+    let (status, _) = delete(app.clone()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+    let (status, missing) = get_json(app, "/bridge/v1/responses/resp_bridge_1", false).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(missing["error"]["type"], "not_found");
+}
+
+#[tokio::test]
+async fn stored_response_endpoints_hide_turns_from_other_routers() {
+    let router = |path: &str| RouterConfig {
+        incoming_url: Some(format!("http://127.0.0.1:8787{path}/v1/responses")),
+        upstream_url: Some("http://127.0.0.1:9/v1/chat/completions".to_string()),
+        upstream_wire: Some(WireApi::Chat),
+        ..Default::default()
+    };
+    let state = test_state_with_routers(BTreeMap::from([
+        ("a".to_string(), router("/a")),
+        ("b".to_string(), router("/b")),
+    ]));
+    SessionTurn::new(
+        state.sessions.clone(),
+        "a".to_string(),
+        "resp_a".to_string(),
+        "gpt-test".to_string(),
+        vec![json!({"role":"user","content":"hi"})],
+    )
+    .store_pending(json!({
+        "id": "resp_a",
+        "object": "response",
+        "status": "in_progress",
+        "background": true,
+        "output": [],
+    }))
+    .await;
+    state
+        .background
+        .spawn("resp_a".to_string(), std::future::pending());
+    let app = build_app(state.clone());
+
+    for (method, uri) in [
+        ("GET", "/b/v1/responses/resp_a"),
+        ("GET", "/b/v1/responses/resp_a/input_items"),
+        ("POST", "/b/v1/responses/resp_a/cancel"),
+        ("DELETE", "/b/v1/responses/resp_a"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("host", "127.0.0.1:8787")
+                    .body(Body::empty())
+                    .expect("request"),
+            )
+            .await
+            .expect("response");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{method} {uri}");
     }
 
-    assert!(payload.contains("\"text\":\"ExitPlanMode()\""));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
-    assert!(payload.contains("\"stop_reason\":\"end_turn\""));
+    let (status, response) = get_json(app, "/a/v1/responses/resp_a", false).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["status"], "in_progress");
+    assert!(state.background.cancel("resp_a"));
 }
 
 #[tokio::test]
-async fn anthropic_stream_keeps_exit_plan_mode_harmony_call_as_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"to=functions.ExitPlanMode<|constrain|>json<|message|>{}\"}}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
+async fn stored_response_endpoints_reject_non_chat_routers() {
+    let app = build_app(test_state_with_router(
+        "http://127.0.0.1:8787/bridge/v1/responses",
+        "http://127.0.0.1:9/v1/responses",
+        WireApi::Responses,
     ));
-    let mut payload = String::new();
+    let (status, body) = get_json(app, "/bridge/v1/responses/resp_1", false).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["type"], "unsupported_feature");
+}
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+async fn poll_stored_response(app: Router, uri: &str, until: &[&str]) -> Value {
+    for _ in 0..250 {
+        let (_, response) = get_json(app.clone(), uri, false).await;
+        if until.contains(&response["status"].as_str().unwrap_or_default()) {
+            return response;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
     }
-
-    assert!(payload.contains("to=functions.ExitPlanMode"));
-    assert!(payload.contains("<|constrain|>"));
-    assert!(payload.contains("<|message|>"));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
-    assert!(payload.contains("\"stop_reason\":\"end_turn\""));
+    panic!("{uri} never reached {until:?}");
 }
 
 #[tokio::test]
-async fn anthropic_stream_keeps_unallowed_harmony_call_as_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"to=functions.ExitPlanMode<|constrain|>json<|message|>{}\"},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
-
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+async fn background_request_is_queued_and_stores_upstream_failure() {
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
+    );
+    let queued: Value = serde_json::from_str(
+        &post_routed(
+            state.clone(),
+            "/v1/responses",
+            None,
+            r#"{"model":"gpt-4.1","input":"hi","background":true,"stream":false}"#,
+        )
+        .await,
+    )
+    .expect("queued json");
+    assert_eq!(queued["object"], "response");
+    assert_eq!(queued["status"], "queued");
+    assert_eq!(queued["background"], true);
+    let response_id = queued["id"].as_str().expect("id");
+
+    let failed = poll_stored_response(
+        build_app(state.clone()),
+        &format!("/v1/responses/{response_id}"),
+        &["failed"],
+    )
+    .await;
+    assert_eq!(failed["error"]["code"], "upstream_transport_error");
+    let follow_up = post_routed(
+        state,
+        "/v1/responses",
+        None,
+        &format!(
+            r#"{{"model":"gpt-4.1","input":"again","previous_response_id":"{response_id}","stream":false}}"#
+        ),
+    )
+    .await;
+    assert!(follow_up.contains("has status `failed`"), "{follow_up}");
+}
 
-    assert!(payload.contains("to=functions.ExitPlanMode"));
-    assert!(payload.contains("<|constrain|>"));
-    assert!(payload.contains("<|message|>"));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
-    assert!(payload.contains("\"stop_reason\":\"end_turn\""));
+#[tokio::test]
+async fn background_request_rejects_streaming() {
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
+    );
+    let body = post_routed(
+        state,
+        "/v1/responses",
+        None,
+        r#"{"model":"gpt-4.1","input":"hi","background":true,"stream":true}"#,
+    )
+    .await;
+    assert!(body.contains("unsupported_feature"), "{body}");
 }
 
 #[tokio::test]
-async fn anthropic_stream_preserves_control_tokens_in_text_content() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"alpha<|message|>beta<|constrain|>gamma\"}}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+async fn cancel_endpoint_aborts_running_background_responses_only() {
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
+    );
+    let turn = SessionTurn::new(
+        state.sessions.clone(),
+        "default".to_string(),
+        "resp_bg".to_string(),
+        "gpt-test".to_string(),
+        vec![json!({"role":"user","content":"hi"})],
+    );
+    turn.store_pending(json!({
+        "id": "resp_bg",
+        "object": "response",
+        "status": "in_progress",
+        "background": true,
+        "output": [],
+    }))
+    .await;
+    state
+        .background
+        .spawn("resp_bg".to_string(), std::future::pending());
+    SessionTurn::new(
+        state.sessions.clone(),
+        "default".to_string(),
+        "resp_fg".to_string(),
+        "gpt-test".to_string(),
+        vec![json!({"role":"user","content":"hi"})],
+    )
+    .complete(&[], None)
+    .await;
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let post_cancel = |id: &'static str| {
+        let app = build_app(state.clone());
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri(format!("/v1/responses/{id}/cancel"))
+                        .header("host", "127.0.0.1:8787")
+                        .body(Body::empty())
+                        .expect("request"),
+                )
+                .await
+                .expect("response");
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("body");
+            (
+                status,
+                serde_json::from_slice::<Value>(&body).expect("json"),
+            )
+        }
+    };
+    let (status, cancelled) = post_cancel("resp_bg").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(cancelled["status"], "cancelled");
+    let (status, again) = post_cancel("resp_bg").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(again["status"], "cancelled");
+    assert!(!state.background.cancel("resp_bg"));
 
-    assert!(payload.contains("alpha<|message|>beta<|constrain|>gamma"));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
+    let (status, rejected) = post_cancel("resp_fg").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(rejected["error"]["type"], "invalid_request");
+    let (status, _) = post_cancel("resp_missing").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
 }
 
 #[tokio::test]
-async fn anthropic_stream_keeps_non_tool_thinking_trace_as_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"● <thinking<|message|>Create next tasks.\\n\\n● <thinking<|message|>Create remaining tasks similarly.<thinking\\n\\nto=functions.TaskCreate<|constrain|>json<|message|>{\\\"description\\\":\\\"Create a guide\\\"}\"}}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
-
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+#[ignore = "requires binding a local TCP listener"]
+async fn background_request_completes_for_polling() {
+    let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream(
+        "/v1/chat/completions",
+        json!({
+            "id": "chatcmpl_1",
+            "choices": [{
+                "index": 0,
+                "message": {"role":"assistant","content":"done"},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4}
+        }),
+    )
+    .await;
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        &upstream_url,
+        WireApi::Chat,
+    );
+    let queued: Value = serde_json::from_str(
+        &post_routed(
+            state.clone(),
+            "/v1/responses",
+            None,
+            r#"{"model":"gpt-4.1","input":"hi","background":true,"stream":false}"#,
+        )
+        .await,
+    )
+    .expect("queued json");
+    let response_id = queued["id"].as_str().expect("id");
 
-    assert!(payload.contains("● <thinking"));
-    assert!(payload.contains("Create next tasks."));
-    assert!(payload.contains("Create remaining tasks similarly."));
-    assert!(payload.contains("to=functions.TaskCreate"));
-    assert!(payload.contains("<|mes"));
-    assert!(payload.contains("sage|>"));
-    assert!(payload.contains("<|con"));
-    assert!(payload.contains("strain|>"));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
+    let completed = poll_stored_response(
+        build_app(state),
+        &format!("/v1/responses/{response_id}"),
+        &["completed", "failed"],
+    )
+    .await;
+    upstream_handle.abort();
+    assert_eq!(completed["status"], "completed");
+    assert_eq!(completed["id"], response_id);
+    assert_eq!(completed["background"], true);
+    assert_eq!(completed["created_at"], queued["created_at"]);
+    assert_eq!(completed["output"][0]["content"][0]["text"], "done");
+    let upstream_request = captured_request
+        .lock()
+        .await
+        .clone()
+        .expect("upstream request");
+    assert_eq!(upstream_request["stream"], false);
+    assert!(upstream_request.get("background").is_none());
 }
 
 #[tokio::test]
-async fn anthropic_stream_keeps_split_non_tool_thinking_trace_as_text() {
-    let upstream = stream::iter(vec![
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"content\":\"● <thinking<|mes\"}}]}\n\n",
-        )),
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"content\":\"sage|>Create next tasks. to=functions.TaskCreate<|con\"}}]}\n\n",
-        )),
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"content\":\"strain|>json\"}}]}\n\n\
-             data: [DONE]\n\n",
-        )),
-    ]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[ignore = "requires binding a local TCP listener"]
+async fn background_request_fails_over_to_fallback_upstreams() {
+    let (upstream_url, upstream_handle, captured_request) = spawn_mock_json_upstream(
+        "/v1/chat/completions",
+        json!({
+            "id": "chatcmpl_1",
+            "choices": [{
+                "index": 0,
+                "message": {"role":"assistant","content":"from fallback"},
+                "finish_reason": "stop"
+            }]
+        }),
+    )
+    .await;
+    let state = test_state_with_routers(BTreeMap::from([(
+        "default".to_string(),
+        RouterConfig {
+            incoming_url: Some("http://127.0.0.1:8787/v1/responses".to_string()),
+            upstream_url: Some("http://127.0.0.1:9/v1/chat/completions".to_string()),
+            upstreams: Some(vec![UpstreamConfig {
+                upstream_url,
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+    )]));
+    let capture_root = capture_test_dir("background-capture");
+    let state = Arc::new(AppState {
+        capture_dir: Some(capture_root.clone()),
+        ..(*state).clone()
+    });
+    let queued: Value = serde_json::from_str(
+        &post_routed(
+            state.clone(),
+            "/v1/responses",
+            None,
+            r#"{"model":"gpt-4.1","input":"hi","background":true,"stream":false}"#,
+        )
+        .await,
+    )
+    .expect("queued json");
+    let response_id = queued["id"].as_str().expect("id");
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let completed = poll_stored_response(
+        build_app(state),
+        &format!("/v1/responses/{response_id}"),
+        &["completed", "failed"],
+    )
+    .await;
+    upstream_handle.abort();
+    assert_eq!(completed["status"], "completed", "{completed}");
+    assert_eq!(completed["id"], response_id);
+    assert_eq!(
+        completed["output"][0]["content"][0]["text"],
+        "from fallback"
+    );
+    assert!(captured_request.lock().await.is_some());
+
+    let capture = only_capture_dir(&capture_root);
+    let exchange = read_capture_json(&capture, "exchange.json");
+    assert_eq!(exchange["response_id"], response_id);
+    assert_ne!(
+        exchange["upstream_url"],
+        "http://127.0.0.1:9/v1/chat/completions"
+    );
+    assert_eq!(
+        read_capture_json(&capture, "client_response.json")["output"][0]["content"][0]["text"],
+        "from fallback"
+    );
+    let _ = fs::remove_dir_all(&capture_root);
+}
 
-    assert!(payload.contains("\"text\":\"● <thinking"));
-    assert!(payload.contains("Create next tasks."));
-    assert!(payload.contains("to=functions.TaskCreate"));
-    assert!(payload.contains("<|mes"));
-    assert!(payload.contains("sage|>"));
-    assert!(payload.contains("<|con"));
-    assert!(payload.contains("strain|>"));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
+#[test]
+fn websocket_request_body_unwraps_response_create_messages() {
+    let body: Value = serde_json::from_str(
+        &websocket_request_body(
+            r#"{"type":"response.create","model":"gpt-5","input":"hi","stream":false}"#,
+        )
+        .expect("flat request"),
+    )
+    .expect("json");
+    assert_eq!(body, json!({"model":"gpt-5","input":"hi","stream":true}));
+
+    let nested: Value = serde_json::from_str(
+        &websocket_request_body(
+            r#"{"type":"response.create","response":{"model":"gpt-5","previous_response_id":"resp_1"}}"#,
+        )
+        .expect("nested request"),
+    )
+    .expect("json");
+    assert_eq!(nested["previous_response_id"], "resp_1");
+    assert_eq!(nested["stream"], true);
+
+    assert!(
+        websocket_request_body(r#"{"type":"response.cancel"}"#)
+            .expect_err("unsupported type")
+            .contains("response.cancel")
+    );
+    assert!(websocket_request_body("[]").is_err());
 }
 
 #[tokio::test]
-async fn anthropic_stream_keeps_bare_python_style_ask_user_question_as_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"[AskUserQuestion(question=\\\"What's up?\\\", headers=['Hello!'], options=['A','B'], multiSelect=False, metadata={'priority': 1, 'active': True})]\"},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[ignore = "requires binding a local TCP listener"]
+async fn responses_websocket_streams_events_into_shared_sessions() {
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let upstream_messages = Arc::new(tokio::sync::Mutex::new(Vec::<Value>::new()));
+    let upstream_app = {
+        let upstream_messages = upstream_messages.clone();
+        axum::Router::new().route(
+            "/v1/chat/completions",
+            post(move |Json(body): Json<Value>| async move {
+                upstream_messages.lock().await.push(body["messages"].clone());
+                (
+                    [("content-type", "text/event-stream")],
+                    "data: {\"choices\":[{\"delta\":{\"content\":\"pong\"},\"finish_reason\":\"stop\"}]}\n\n\
+                     data: [DONE]\n\n",
+                )
+            }),
+        )
+    };
+    let upstream_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind mock upstream");
+    let upstream_addr = upstream_listener.local_addr().expect("upstream address");
+    let upstream_handle = tokio::spawn(async move {
+        let _ = axum::serve(upstream_listener, upstream_app).await;
+    });
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let bridge_listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind bridge");
+    let bridge_addr = bridge_listener.local_addr().expect("bridge address");
+    let state = test_state_with_routers(BTreeMap::from([(
+        "codex".to_string(),
+        RouterConfig {
+            incoming_url: Some(format!("http://{bridge_addr}/codex/v1/responses")),
+            upstream_url: Some(format!("http://{upstream_addr}/v1/chat/completions")),
+            features: Some(FeatureFlagsConfig {
+                enable_responses_websocket: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    )]));
+    let capture_root = capture_test_dir("websocket-capture");
+    let state = Arc::new(AppState {
+        capture_dir: Some(capture_root.clone()),
+        ..(*state).clone()
+    });
+    let app = build_app(state.clone());
+    let bridge_handle = tokio::spawn(async move {
+        let _ = axum::serve(bridge_listener, app).await;
+    });
+
+    let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://{bridge_addr}/codex/v1/responses"))
+            .await
+            .expect("connect websocket");
+    let mut turn = async |request: Value| {
+        socket
+            .send(WsMessage::Text(request.to_string().into()))
+            .await
+            .expect("send");
+        let mut events = Vec::new();
+        while let Some(message) = socket.next().await {
+            let event: Value =
+                serde_json::from_str(message.expect("frame").to_text().expect("text"))
+                    .expect("event json");
+            let done = event["type"] == "response.completed" || event["type"] == "response.failed";
+            events.push(event);
+            if done {
+                break;
+            }
+        }
+        events
+    };
+
+    let first = turn(json!({"type":"response.create","model":"gpt-5","input":"ping"})).await;
+    assert_eq!(first[0]["type"], "response.created");
+    let completed = first.last().expect("completed");
+    assert_eq!(completed["type"], "response.completed");
+    let response_id = completed["response"]["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let second = turn(json!({
+        "type": "response.create",
+        "model": "gpt-5",
+        "input": "again",
+        "previous_response_id": response_id,
+    }))
+    .await;
+    assert_eq!(
+        second.last().expect("completed")["type"],
+        "response.completed"
+    );
+    let second_id = second.last().expect("completed")["response"]["id"]
+        .as_str()
+        .expect("id")
+        .to_string();
+    let sessions = state.sessions.read().await;
+    assert!(sessions.get_session(&response_id).is_some());
+    assert_eq!(
+        sessions
+            .get_session(&second_id)
+            .expect("second turn stored")
+            .router_name,
+        "codex"
+    );
+    drop(sessions);
+    let captures = fs::read_dir(&capture_root)
+        .expect("capture root")
+        .map(|entry| entry.expect("entry").path())
+        .collect::<Vec<_>>();
+    assert_eq!(captures.len(), 2, "{captures:?}");
+    assert!(
+        captures
+            .iter()
+            .all(|dir| dir.join("exchange.json").exists())
+    );
+    let _ = fs::remove_dir_all(&capture_root);
 
-    assert!(payload.contains("AskUserQuestion(question="));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
-    assert!(payload.contains("\"type\":\"text_delta\""));
+    upstream_handle.abort();
+    bridge_handle.abort();
+    let upstream_messages = upstream_messages.lock().await;
+    assert_eq!(upstream_messages.len(), 2);
+    assert_eq!(upstream_messages[1][1]["content"], "pong");
+    assert_eq!(upstream_messages[1][2]["content"], "again");
 }
 
-#[tokio::test]
-async fn anthropic_stream_keeps_ask_user_question_xml_wrapper_as_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"<AskUserQuestion>{\\\"questions\\\":[{\\\"question\\\":\\\"작업 분할 계획을 저장할 파일 경로를 선택해 주세요.\\\",\\\"multiSelect\\\":false,\\\"options\\\":[{\\\"label\\\":\\\"docs/plans/unit-test-execution-plan.md (추천)\\\",\\\"description\\\":\\\"문서 전용 폴더에 마크다운 파일로 보관\\\"},{\\\"label\\\":\\\"README.md에 추가\\\",\\\"description\\\":\\\"프로젝트 루트 README에 삽입\\\"},{\\\"label\\\":\\\"src/main/resources/unit-test-execution-plan.md\\\",\\\"description\\\":\\\"리소스 경로에 저장해 빌드에 포함\\\"}]}]}\"},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+fn capture_test_dir(label: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("codex-chat-bridge-{label}-{}", Uuid::now_v7()));
+    fs::create_dir_all(&dir).expect("temp dir");
+    dir
+}
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+fn only_capture_dir(root: &Path) -> PathBuf {
+    let mut entries = fs::read_dir(root)
+        .expect("capture root")
+        .map(|entry| entry.expect("entry").path())
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 1, "{entries:?}");
+    entries.pop().expect("capture dir")
+}
 
-    assert!(payload.contains("<AskUserQuestion>"));
-    assert!(payload.contains("작업 분할 계획을 저장할 파일 경로를 선택해 주세요."));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
+fn read_capture_json(dir: &Path, file: &str) -> Value {
+    serde_json::from_slice(&fs::read(dir.join(file)).expect(file)).expect("capture json")
 }
 
 #[tokio::test]
-async fn anthropic_stream_keeps_unknown_bare_python_style_call_as_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"AskUserQuestion(question=\\\"What's up?\\\")\"},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+async fn capture_dir_records_redacted_headers_and_mapped_payload() {
+    let root = capture_test_dir("capture");
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/capture/v1/messages",
+        "http://127.0.0.1:9/v1/chat/completions",
+        WireApi::Chat,
+    );
+    let state = Arc::new(AppState {
+        capture_dir: Some(root.clone()),
+        ..(*state).clone()
+    });
+    let body = r#"{"model":"claude-sonnet","max_tokens":16,"stream":false,"messages":[{"role":"user","content":"hi"}]}"#;
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let response = post_routed(
+        state,
+        "/capture/v1/messages",
+        Some(("x-api-key", "client-secret")),
+        body,
+    )
+    .await;
+    assert!(response.contains("upstream_transport_error"), "{response}");
 
-    assert!(payload.contains("\"text\":\"AskUserQuestion(question=\\\"What's up?\\\")\""));
-    assert!(!payload.contains("\"type\":\"tool_use\""));
+    let dir = only_capture_dir(&root);
+    assert!(
+        dir.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with("-default"))
+    );
+    let incoming = read_capture_json(&dir, "incoming_headers.json");
+    assert_eq!(incoming["incoming_route"], "/capture/v1/messages");
+    assert_eq!(incoming["headers"]["x-api-key"], "<redacted>");
+    assert_eq!(
+        fs::read_to_string(dir.join("incoming_body.json")).expect("body"),
+        body
+    );
+    let exchange = read_capture_json(&dir, "exchange.json");
+    assert_eq!(exchange["incoming_api"], "anthropic");
+    assert_eq!(exchange["upstream_wire"], "chat");
+    assert_eq!(exchange["stream"], false);
+    assert_eq!(exchange["features"]["enable_previous_response_id"], true);
+    let upstream_headers = read_capture_json(&dir, "upstream_request_headers.json");
+    assert_eq!(upstream_headers["authorization"], "Bearer <redacted>");
+    let payload = read_capture_json(&dir, "upstream_payload.json");
+    assert_eq!(payload["messages"][0]["content"], "hi");
+    assert!(!dir.join("client_response.json").exists());
+    let _ = fs::remove_dir_all(root);
 }
 
 #[tokio::test]
-async fn anthropic_stream_handles_trailing_done_without_newline() {
-    let upstream = stream::iter(vec![
-        Ok::<Bytes, reqwest::Error>(Bytes::from(
-            "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\n",
-        )),
-        Ok::<Bytes, reqwest::Error>(Bytes::from("data: [DONE]")),
-    ]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
-
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+async fn replay_translates_captured_chat_stream_for_the_captured_client_api() {
+    let dir = capture_test_dir("replay");
+    fs::write(
+        dir.join("upstream_response.sse"),
+        "data: {\"choices\":[{\"delta\":{\"content\":\"hel\"}}]}\n\n\
+         data: {\"choices\":[{\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n\
+         data: [DONE]\n\n",
+    )
+    .expect("write stream");
+    let write_exchange = |incoming_api: &str| {
+        fs::write(
+            dir.join("exchange.json"),
+            json!({
+                "router": "default",
+                "incoming_api": incoming_api,
+                "upstream_wire": "chat",
+                "upstream_model": "gpt-4.1",
+                "response_id": "resp_replayed",
+                "anthropic_input_tokens": 42,
+                "features": {"enable_extended_stream_events": false},
+            })
+            .to_string(),
+        )
+        .expect("write exchange");
+    };
 
-    assert!(payload.contains("\"text\":\"Hi\""));
-    assert_eq!(payload.matches("event: content_block_start").count(), 1);
-    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
-    assert!(payload.contains("event: message_stop"));
-    assert!(!payload.contains("event: error"));
+    write_exchange("anthropic");
+    let mut anthropic = Vec::new();
+    replay_capture(&dir, &mut anthropic)
+        .await
+        .expect("anthropic replay");
+    let anthropic = String::from_utf8(anthropic).expect("utf8");
+    assert!(anthropic.starts_with("event: message_start"), "{anthropic}");
+    assert!(anthropic.contains("\"input_tokens\":42"));
+    assert!(anthropic.contains("\"text\":\"hel\""));
+    assert!(anthropic.contains("event: message_stop"));
+
+    write_exchange("responses");
+    let mut responses = Vec::new();
+    replay_capture(&dir, &mut responses)
+        .await
+        .expect("responses replay");
+    let responses = String::from_utf8(responses).expect("utf8");
+    assert!(responses.contains("\"id\":\"resp_replayed\""));
+    assert!(responses.contains("event: response.completed"));
+    assert!(responses.contains("\"text\":\"hello\""), "{responses}");
+
+    fs::remove_file(dir.join("upstream_response.sse")).expect("remove stream");
+    let err = replay_capture(&dir, &mut Vec::new())
+        .await
+        .expect_err("non-stream capture");
+    assert!(err.to_string().contains("only streaming exchanges"));
+    let _ = fs::remove_dir_all(dir);
 }
 
 #[tokio::test]
-async fn anthropic_stream_opens_text_block_only_once_across_multiple_deltas() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"He\"}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{\"content\":\"llo\"}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[ignore = "requires binding a local TCP listener"]
+async fn captured_stream_replays_to_the_same_client_events() {
+    let upstream_app = axum::Router::new().route(
+        "/v1/chat/completions",
+        post(|| async {
+            (
+                [("content-type", "text/event-stream")],
+                "data: {\"choices\":[{\"delta\":{\"content\":\"pong\"},\"finish_reason\":\"stop\"}]}\n\n\
+                 data: [DONE]\n\n",
+            )
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind mock upstream");
+    let addr = listener.local_addr().expect("mock upstream address");
+    let handle = tokio::spawn(async move {
+        let _ = axum::serve(listener, upstream_app).await;
+    });
+    let root = capture_test_dir("capture-stream");
+    let state = test_state_with_router(
+        "http://127.0.0.1:8787/v1/responses",
+        &format!("http://{addr}/v1/chat/completions"),
+        WireApi::Chat,
+    );
+    let state = Arc::new(AppState {
+        capture_dir: Some(root.clone()),
+        ..(*state).clone()
+    });
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+    let streamed = post_routed(
+        state,
+        "/v1/responses",
+        None,
+        r#"{"model":"gpt-4.1","stream":true,"input":"ping"}"#,
+    )
+    .await;
+    handle.abort();
 
-    assert_eq!(payload.matches("event: content_block_start").count(), 1);
-    assert_eq!(payload.matches("\"type\":\"text_delta\"").count(), 2);
-    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
-    assert!(payload.contains("\"text\":\"He\""));
-    assert!(payload.contains("\"text\":\"llo\""));
+    let dir = only_capture_dir(&root);
+    let captured_client = fs::read_to_string(dir.join("client_response.sse")).expect("client");
+    assert_eq!(captured_client, streamed);
+    assert!(
+        fs::read_to_string(dir.join("upstream_response.sse"))
+            .expect("upstream")
+            .contains("pong")
+    );
+    let mut replayed = Vec::new();
+    replay_capture(&dir, &mut replayed).await.expect("replay");
+    let event_names = |stream: &str| {
+        stream
+            .lines()
+            .filter(|line| line.starts_with("event: "))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        event_names(&String::from_utf8(replayed).expect("utf8")),
+        event_names(&streamed)
+    );
+    let _ = fs::remove_dir_all(root);
+}
+
+fn check_test_routers(file: &str) -> crate::check::CheckReport {
+    let parsed: FileConfig = toml::from_str(file).expect("config");
+    crate::check::check_routers(
+        &parsed.routers.unwrap_or_default(),
+        &ApiKey::Resolved("test-key".to_string()),
+        |routers| {
+            RouterManager::new(
+                routers,
+                "https://api.openai.com/v1/chat/completions".to_string(),
+                WireApi::Chat,
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                FeatureFlags::default(),
+            )
+        },
+    )
 }
 
-#[tokio::test]
-async fn anthropic_stream_ignores_empty_tool_calls_when_streaming_text() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"content\":\"He\"}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{\"content\":\"llo\",\"tool_calls\":[]}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{\"content\":\"!\"},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
-
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
-    }
+#[test]
+fn check_prints_merged_targets_with_redacted_headers() {
+    let report = check_test_routers(
+        r#"
+[routers.codex]
+incoming_url = "http://127.0.0.1:8787/codex/v1/responses"
+upstream_model = "provider/model"
+http_headers = { "x-team" = "core", "authorization" = "Bearer client-secret" }
+drop_request_fields = ["store", "prompt_cache_key"]
+features = { enable_responses_websocket = true }
+"#,
+    );
 
-    assert_eq!(payload.matches("event: content_block_start").count(), 1);
-    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
-    assert_eq!(payload.matches("\"type\":\"text_delta\"").count(), 3);
-    assert!(payload.contains("\"text\":\"He\""));
-    assert!(payload.contains("\"text\":\"llo\""));
-    assert!(payload.contains("\"text\":\"!\""));
+    assert!(report.problems.is_empty(), "{:?}", report.problems);
+    assert_eq!(report.error_count(), 0);
+    let routes = &report.routes;
+    assert!(
+        routes.contains("incoming_url = http://127.0.0.1:8787/codex/v1/responses (responses)"),
+        "{routes}"
+    );
+    assert!(
+        routes.contains("upstream_url = https://api.openai.com/v1/chat/completions (chat)"),
+        "{routes}"
+    );
+    assert!(
+        routes.contains("models: upstream_model=provider/model"),
+        "{routes}"
+    );
+    assert!(routes.contains(r#""x-team":"core""#), "{routes}");
+    assert!(routes.contains("<redacted>"), "{routes}");
+    assert!(!routes.contains("client-secret"), "{routes}");
+    assert!(
+        routes.contains(r#"drop_request_fields = ["prompt_cache_key", "store"]"#),
+        "{routes}"
+    );
+    assert!(
+        routes.contains(r#""enable_responses_websocket":true"#),
+        "{routes}"
+    );
 }
 
-#[tokio::test]
-async fn anthropic_stream_opens_thinking_block_only_once_across_multiple_deltas() {
-    let upstream = stream::iter(vec![Ok::<Bytes, reqwest::Error>(Bytes::from(
-        "data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"step \"}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{\"reasoning_content\":\"one\"}}]}\n\n\
-             data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n\
-             data: [DONE]\n\n",
-    ))]);
-    let mut output = Box::pin(translate_chat_stream_to_anthropic(
-        upstream,
-        "test_router".to_string(),
-        false,
-        "claude-bridge".to_string(),
-        0,
-    ));
-    let mut payload = String::new();
+#[test]
+fn check_flags_duplicate_routes_wire_mismatches_and_unusable_settings() {
+    let report = check_test_routers(
+        r#"
+[routers.a]
+incoming_url = "http://127.0.0.1:8787/shared/v1/responses"
 
-    while let Some(event) = output.next().await {
-        payload.push_str(&String::from_utf8_lossy(&event.expect("stream event")));
+[routers.b]
+incoming_url = "http://127.0.0.1:8787/shared/v1/responses"
+
+[routers.claude]
+incoming_url = "http://127.0.0.1:8787/claude/v1/messages"
+upstream_url = "https://api.anthropic.com/v1/messages"
+upstream_wire = "chat"
+
+[routers.messages]
+incoming_url = "http://127.0.0.1:8787/messages/v1/messages"
+features = { enable_responses_websocket = true }
+
+[routers.codex_claude]
+incoming_url = "http://127.0.0.1:8787/codex-claude/v1/responses"
+upstream_url = "https://api.anthropic.com/v1/messages"
+tokenizer = { kind = "heuristic" }
+"#,
+    );
+
+    let messages = |severity: crate::check::Severity| {
+        report
+            .problems
+            .iter()
+            .filter(|problem| problem.severity == severity)
+            .map(|problem| {
+                (
+                    problem.router.clone().unwrap_or_default(),
+                    problem.message.clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let errors = messages(crate::check::Severity::Error);
+    assert_eq!(report.error_count(), 2, "{errors:?}");
+    assert!(
+        errors
+            .iter()
+            .any(|(router, message)| router == "b"
+                && message.contains("already used by [routers.a]")),
+        "{errors:?}"
+    );
+    assert!(
+        errors
+            .iter()
+            .any(|(router, message)| router == "claude" && message.contains("mismatch")),
+        "{errors:?}"
+    );
+    let warnings = messages(crate::check::Severity::Warning);
+    assert!(
+        warnings.iter().any(|(router, message)| router == "messages"
+            && message.contains("enable_responses_websocket")),
+        "{warnings:?}"
+    );
+    assert!(
+        !warnings
+            .iter()
+            .any(|(_, message)| message.contains("previous_response_id")),
+        "{warnings:?}"
+    );
+    assert!(
+        warnings
+            .iter()
+            .any(|(router, message)| router == "codex_claude" && message.contains("tokenizer")),
+        "{warnings:?}"
+    );
+    assert!(report.routes.contains("[routers.a]"));
+    assert!(!report.routes.contains("[routers.b]"));
+    assert!(!report.routes.contains("[routers.claude]"));
+}
+
+fn init_test_paths(root: &Path) -> crate::init::InitPaths {
+    crate::init::InitPaths {
+        bridge_config: root.join("bridge").join("conf.toml"),
+        codex_profile: root.join("codex").join("chat-bridge.config.toml"),
+        claude_env: root.join("bridge").join("claude-code.env"),
     }
+}
 
-    assert_eq!(payload.matches("event: content_block_start").count(), 1);
-    assert_eq!(payload.matches("\"type\":\"thinking_delta\"").count(), 2);
-    assert_eq!(payload.matches("event: content_block_stop").count(), 1);
-    assert!(payload.contains("\"thinking\":\"step \""));
-    assert!(payload.contains("\"thinking\":\"one\""));
+#[test]
+fn init_writes_routers_codex_profile_and_claude_env_that_agree() {
+    let root = capture_test_dir("init");
+    let paths = init_test_paths(&root);
+    let args = Args::parse_from(["codex-chat-bridge"]);
+    let mut out = Vec::new();
+
+    crate::init::init_with(
+        &args,
+        &crate::config::InitArgs::default(),
+        &paths,
+        &mut "nope\nopenrouter\nqwen/qwen3-coder\n0\n9911\n".as_bytes(),
+        &mut out,
+    )
+    .expect("init");
+
+    let out = String::from_utf8(out).expect("utf8");
+    assert!(out.contains("unknown provider `nope`"), "{out}");
+    assert!(out.contains("port must be a number"), "{out}");
+    let bridge = fs::read_to_string(&paths.bridge_config).expect("bridge config");
+    assert!(bridge.starts_with(DEFAULT_CONFIG_TEMPLATE));
+    let parsed: FileConfig = toml::from_str(&bridge).expect("bridge toml");
+    let routers = parsed.routers.expect("routers");
+    let codex_router = &routers["chat_bridge"];
+    assert_eq!(
+        codex_router.upstream_url.as_deref(),
+        Some("https://openrouter.ai/api/v1/chat/completions")
+    );
+    assert_eq!(
+        codex_router.upstream_model.as_deref(),
+        Some("qwen/qwen3-coder")
+    );
+    assert_eq!(
+        codex_router.api_key_env.as_deref(),
+        Some("OPENROUTER_API_KEY")
+    );
+
+    let profile: Value = toml::from_str::<toml::Value>(
+        &fs::read_to_string(&paths.codex_profile).expect("codex profile"),
+    )
+    .map(|value| serde_json::to_value(value).expect("json"))
+    .expect("profile toml");
+    assert_eq!(profile["model"], "qwen/qwen3-coder");
+    assert_eq!(profile["model_provider"], "chat-bridge");
+    let provider = &profile["model_providers"]["chat-bridge"];
+    assert_eq!(provider["base_url"], "http://127.0.0.1:9911/chat-bridge/v1");
+    assert_eq!(provider["wire_api"], "responses");
+    assert_eq!(provider["env_key"], "OPENROUTER_API_KEY");
+    assert_eq!(
+        format!(
+            "{}/responses",
+            provider["base_url"].as_str().expect("base_url")
+        )
+        .as_str(),
+        codex_router.incoming_url.as_deref().expect("incoming_url")
+    );
+
+    let claude_env = fs::read_to_string(&paths.claude_env).expect("claude env");
+    let claude_base_url = claude_env
+        .lines()
+        .find_map(|line| line.strip_prefix("export ANTHROPIC_BASE_URL="))
+        .expect("ANTHROPIC_BASE_URL")
+        .trim_matches('\'');
+    assert_eq!(
+        format!("{claude_base_url}/v1/messages").as_str(),
+        routers["claude_code"]
+            .incoming_url
+            .as_deref()
+            .expect("incoming_url")
+    );
+
+    let report = crate::check::check_routers(
+        &routers,
+        &ApiKey::Resolved("test-key".to_string()),
+        |routers| {
+            build_router_manager(
+                &resolve_config(args.clone(), Some(FileConfig::default())).expect("config"),
+                routers,
+            )
+        },
+    );
+    assert_eq!(report.error_count(), 0, "{:?}", report.problems);
+    let _ = fs::remove_dir_all(root);
+}
+
+#[test]
+#[cfg(unix)]
+fn init_claude_env_quotes_the_model_for_the_shell() {
+    let root = capture_test_dir("init-shell");
+    let paths = init_test_paths(&root);
+    let model = r#"it's "$(touch pwned)"`id`"#;
+    crate::init::init_with(
+        &Args::parse_from(["codex-chat-bridge"]),
+        &crate::config::InitArgs {
+            provider: Some("openai".to_string()),
+            model: Some(model.to_string()),
+            port: Some(9912),
+            force: false,
+        },
+        &paths,
+        &mut "".as_bytes(),
+        &mut Vec::new(),
+    )
+    .expect("init");
+
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(r#". "$1" && printf %s "$ANTHROPIC_MODEL""#)
+        .arg("sh")
+        .arg(&paths.claude_env)
+        .current_dir(&root)
+        .output()
+        .expect("run sh");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), model);
+    assert!(!root.join("pwned").exists());
+    let _ = fs::remove_dir_all(root);
+}
+
+#[test]
+fn init_refuses_clashing_routes_and_existing_client_files_without_writing() {
+    let root = capture_test_dir("init-refuse");
+    let paths = init_test_paths(&root);
+    let args = Args::parse_from(["codex-chat-bridge"]);
+    fs::create_dir_all(paths.bridge_config.parent().expect("parent")).expect("dir");
+    let existing = "[routers.native]\nincoming_url = \"http://127.0.0.1:8787/claude/v1/messages\"\nupstream_url = \"https://api.anthropic.com/v1/messages\"\n";
+    fs::write(&paths.bridge_config, existing).expect("existing config");
+    let init = |port: u16, force: bool| {
+        crate::init::init_with(
+            &args,
+            &crate::config::InitArgs {
+                provider: Some("ollama".to_string()),
+                model: None,
+                port: Some(port),
+                force,
+            },
+            &paths,
+            &mut "\n".as_bytes(),
+            &mut Vec::new(),
+        )
+    };
+
+    let err = init(8787, false).expect_err("route clash");
+    assert!(err.to_string().contains("[routers.native]"), "{err}");
+    assert!(!paths.codex_profile.exists());
+
+    fs::create_dir_all(paths.codex_profile.parent().expect("parent")).expect("dir");
+    fs::write(&paths.codex_profile, "model = \"mine\"\n").expect("profile");
+    let err = init(8788, false).expect_err("existing profile");
+    assert!(err.to_string().contains("--force"), "{err}");
+    assert_eq!(
+        fs::read_to_string(&paths.bridge_config).expect("config"),
+        existing
+    );
+
+    init(8788, true).expect("forced init");
+    let bridge = fs::read_to_string(&paths.bridge_config).expect("config");
+    assert!(
+        bridge.contains("upstream_model = \"qwen3-coder:30b\""),
+        "{bridge}"
+    );
+    assert!(
+        !bridge.contains("enable_previous_response_id = false"),
+        "{bridge}"
+    );
+    let profile = fs::read_to_string(&paths.codex_profile).expect("profile");
+    assert!(!profile.contains("env_key"), "{profile}");
+    let err = init(8789, true).expect_err("routers already present");
+    assert!(err.to_string().contains("[routers.chat_bridge]"), "{err}");
+    let _ = fs::remove_dir_all(root);
 }
@@ -0,0 +1,185 @@
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use async_stream::stream;
+use axum::body::Bytes;
+use futures::Stream;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct TimeoutConfig {
+    pub(crate) connect_ms: Option<u64>,
+    pub(crate) first_byte_ms: Option<u64>,
+    pub(crate) total_ms: Option<u64>,
+    pub(crate) stream_idle_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct TimeoutPolicy {
+    pub(crate) connect: Option<Duration>,
+    pub(crate) first_byte: Option<Duration>,
+    pub(crate) total: Option<Duration>,
+    pub(crate) stream_idle: Option<Duration>,
+}
+
+impl TimeoutPolicy {
+    pub(crate) fn from_config(config: Option<&TimeoutConfig>, context: &str) -> Result<Self> {
+        let Some(config) = config else {
+            return Ok(Self::default());
+        };
+        let duration = |value: Option<u64>, field: &str| match value {
+            Some(0) => Err(anyhow!("{context} timeouts.{field} must be greater than 0")),
+            value => Ok(value.map(Duration::from_millis)),
+        };
+        Ok(Self {
+            connect: duration(config.connect_ms, "connect_ms")?,
+            first_byte: duration(config.first_byte_ms, "first_byte_ms")?,
+            total: duration(config.total_ms, "total_ms")?,
+            stream_idle: duration(config.stream_idle_ms, "stream_idle_ms")?,
+        })
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        *self != Self::default()
+    }
+
+    pub(crate) fn build_client(&self) -> Result<Option<Client>> {
+        let Some(connect) = self.connect else {
+            return Ok(None);
+        };
+        Client::builder()
+            .connect_timeout(connect)
+            .build()
+            .map(Some)
+            .context("building reqwest client")
+    }
+
+    pub(crate) fn deadline_from(&self, started_at: Instant) -> Option<Instant> {
+        self.total.map(|total| started_at + total)
+    }
+
+    pub(crate) fn first_byte_wait(&self, deadline: Option<Instant>) -> Option<(Duration, String)> {
+        earliest_wait(
+            self.first_byte.map(|first_byte| {
+                (
+                    first_byte,
+                    format!(
+                        "upstream sent no response within first_byte_ms={}",
+                        first_byte.as_millis()
+                    ),
+                )
+            }),
+            self.total_wait(deadline),
+        )
+    }
+
+    fn total_wait(&self, deadline: Option<Instant>) -> Option<(Duration, String)> {
+        let total = self.total?;
+        let deadline = deadline?;
+        Some((
+            deadline.saturating_duration_since(Instant::now()),
+            format!("upstream response exceeded total_ms={}", total.as_millis()),
+        ))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum UpstreamStreamError {
+    Transport(reqwest::Error),
+    Timeout(String),
+}
+
+impl UpstreamStreamError {
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            Self::Transport(_) => "upstream_stream_error",
+            Self::Timeout(_) => "upstream_timeout",
+        }
+    }
+}
+
+impl fmt::Display for UpstreamStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "{err}"),
+            Self::Timeout(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<reqwest::Error> for UpstreamStreamError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Transport(err)
+    }
+}
+
+pub(crate) fn with_stream_timeouts<S>(
+    upstream_stream: S,
+    policy: TimeoutPolicy,
+    deadline: Option<Instant>,
+) -> impl Stream<Item = Result<Bytes, UpstreamStreamError>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes, reqwest::Error>> + Send + 'static,
+{
+    stream! {
+        let mut upstream_stream = Box::pin(upstream_stream);
+        loop {
+            let idle_wait = policy.stream_idle.map(|idle| {
+                (
+                    idle,
+                    format!(
+                        "upstream stream was idle for stream_idle_ms={}",
+                        idle.as_millis()
+                    ),
+                )
+            });
+            let next = match earliest_wait(idle_wait, policy.total_wait(deadline)) {
+                Some((wait, message)) => {
+                    match tokio::time::timeout(wait, upstream_stream.next()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            yield Err(UpstreamStreamError::Timeout(message));
+                            return;
+                        }
+                    }
+                }
+                None => upstream_stream.next().await,
+            };
+            match next {
+                Some(Ok(chunk)) => yield Ok(chunk),
+                Some(Err(err)) => {
+                    yield Err(UpstreamStreamError::Transport(err));
+                    return;
+                }
+                None => return,
+            }
+        }
+    }
+}
+
+fn earliest_wait(
+    left: Option<(Duration, String)>,
+    right: Option<(Duration, String)>,
+) -> Option<(Duration, String)> {
+    match (left, right) {
+        (Some(left), Some(right)) => Some(if right.0 < left.0 { right } else { left }),
+        (left, right) => left.or(right),
+    }
+}
+
+pub(crate) async fn collect_body<S>(upstream_stream: S) -> Result<Vec<u8>, UpstreamStreamError>
+where
+    S: Stream<Item = Result<Bytes, UpstreamStreamError>>,
+{
+    let mut upstream_stream = Box::pin(upstream_stream);
+    let mut body = Vec::new();
+    while let Some(chunk) = upstream_stream.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    Ok(body)
+}
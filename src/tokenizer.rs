@@ -0,0 +1,438 @@
+use anyhow::Context;
+use anyhow::Result;
+use anyhow::anyhow;
+use axum::http::HeaderMap;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::routing::RouteTarget;
+use crate::state::AppState;
+
+const UPSTREAM_COUNT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const MAX_PIECE_BYTES: usize = 256;
+
+const COUNT_TOKENS_FIELDS: [&str; 6] = [
+    "model",
+    "messages",
+    "system",
+    "tools",
+    "tool_choice",
+    "thinking",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TokenizerKind {
+    #[default]
+    Heuristic,
+    Bpe,
+    Upstream,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct TokenizerConfig {
+    pub(crate) kind: Option<TokenizerKind>,
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) url: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Tokenizer {
+    Bpe(BpeVocab),
+    Upstream { url: String },
+}
+
+impl Tokenizer {
+    pub(crate) fn from_config(
+        config: Option<&TokenizerConfig>,
+        context: &str,
+    ) -> Result<Option<Self>> {
+        let Some(config) = config else {
+            return Ok(None);
+        };
+        match config.kind.unwrap_or_default() {
+            TokenizerKind::Heuristic => Ok(None),
+            TokenizerKind::Bpe => {
+                let path = config
+                    .path
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("{context} tokenizer.path is required for bpe"))?;
+                let raw = std::fs::read_to_string(path).with_context(|| {
+                    format!("{context} reading tokenizer.path {}", path.display())
+                })?;
+                let vocab = BpeVocab::parse(&raw).with_context(|| {
+                    format!("{context} parsing tokenizer.path {}", path.display())
+                })?;
+                Ok(Some(Self::Bpe(vocab)))
+            }
+            TokenizerKind::Upstream => {
+                let url = config
+                    .url
+                    .as_deref()
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .ok_or_else(|| anyhow!("{context} tokenizer.url is required for upstream"))?;
+                Ok(Some(Self::Upstream {
+                    url: url.to_string(),
+                }))
+            }
+        }
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Self::Bpe(vocab) => format!("bpe:tokens={}", vocab.ranks.len()),
+            Self::Upstream { url } => format!("upstream:url={url}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct BpeVocab {
+    ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl BpeVocab {
+    pub(crate) fn parse(raw: &str) -> Result<Self> {
+        let mut ranks = HashMap::new();
+        for (index, line) in raw.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("line {}: expected `<base64 token> <rank>`", index + 1))?;
+            let token = STANDARD
+                .decode(token)
+                .with_context(|| format!("line {}: invalid base64 token", index + 1))?;
+            let rank = rank
+                .trim()
+                .parse::<u32>()
+                .with_context(|| format!("line {}: invalid rank", index + 1))?;
+            ranks.insert(token, rank);
+        }
+        if ranks.is_empty() {
+            return Err(anyhow!("vocabulary is empty"));
+        }
+        Ok(Self { ranks })
+    }
+
+    pub(crate) fn count(&self, text: &str) -> usize {
+        pre_tokenize(text)
+            .into_iter()
+            .map(|piece| self.count_piece(piece.as_bytes()))
+            .sum()
+    }
+
+    fn count_piece(&self, piece: &[u8]) -> usize {
+        if piece.len() > MAX_PIECE_BYTES {
+            return piece
+                .chunks(MAX_PIECE_BYTES)
+                .map(|chunk| self.count_piece(chunk))
+                .sum();
+        }
+        if piece.is_empty() {
+            return 0;
+        }
+        if self.ranks.contains_key(piece) {
+            return 1;
+        }
+        let len = piece.len();
+        let mut next = (1..=len).collect::<Vec<_>>();
+        let mut prev = (0..len)
+            .map(|start| start.checked_sub(1))
+            .collect::<Vec<_>>();
+        let mut alive = vec![true; len];
+        let pair_at = |start: usize, next: &[usize]| {
+            let mid = next[start];
+            let end = *next.get(mid)?;
+            self.ranks
+                .get(&piece[start..end])
+                .map(|rank| Reverse((*rank, start, end)))
+        };
+        let mut pairs = (0..len)
+            .filter_map(|start| pair_at(start, &next))
+            .collect::<BinaryHeap<_>>();
+        let mut symbols = len;
+        while let Some(Reverse((_, start, end))) = pairs.pop() {
+            let mid = next[start];
+            if !alive[start] || next.get(mid) != Some(&end) {
+                continue;
+            }
+            alive[mid] = false;
+            next[start] = end;
+            if end < len {
+                prev[end] = Some(start);
+            }
+            symbols -= 1;
+            if let Some(before) = prev[start] {
+                pairs.extend(pair_at(before, &next));
+            }
+            pairs.extend(pair_at(start, &next));
+        }
+        symbols
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Letter,
+    Digit,
+    Space,
+    Other,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_alphabetic() {
+        CharClass::Letter
+    } else if c.is_numeric() {
+        CharClass::Digit
+    } else if c.is_whitespace() {
+        CharClass::Space
+    } else {
+        CharClass::Other
+    }
+}
+
+fn pre_tokenize(text: &str) -> Vec<&str> {
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let end_of = |index: usize| chars.get(index).map_or(text.len(), |(offset, _)| *offset);
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut head = start;
+        if chars[head].1 == ' '
+            && chars
+                .get(head + 1)
+                .is_some_and(|(_, next)| !next.is_whitespace())
+        {
+            head += 1;
+        }
+        let class = char_class(chars[head].1);
+        let mut end = head + 1;
+        while end < chars.len()
+            && char_class(chars[end].1) == class
+            && !(class == CharClass::Digit && end - head >= 3)
+        {
+            end += 1;
+        }
+        pieces.push(&text[chars[start].0..end_of(end)]);
+        start = end;
+    }
+    pieces
+}
+
+pub(crate) async fn count_anthropic_input_tokens(
+    state: &Arc<AppState>,
+    route_target: &RouteTarget,
+    headers: &HeaderMap,
+    request: &Value,
+) -> i64 {
+    match route_target.tokenizer.as_deref() {
+        None => estimate_anthropic_count_tokens(request),
+        Some(Tokenizer::Bpe(_)) => {
+            let tokenizer = route_target.tokenizer.clone();
+            let body = request.clone();
+            let counted = tokio::task::spawn_blocking(move || {
+                let Some(Tokenizer::Bpe(vocab)) = tokenizer.as_deref() else {
+                    return 0;
+                };
+                let mut tokens = 0usize;
+                visit_anthropic_count_text(&body, &mut |text| tokens += vocab.count(text));
+                tokens as i64
+            })
+            .await;
+            counted.unwrap_or_else(|err| {
+                warn!(
+                    "bpe token count failed, using estimate: router={}, error={err}",
+                    route_target.router_name
+                );
+                estimate_anthropic_count_tokens(request)
+            })
+        }
+        Some(Tokenizer::Upstream { url }) => {
+            match fetch_upstream_count(state, route_target, headers, url, request).await {
+                Ok(tokens) => tokens,
+                Err(err) => {
+                    warn!(
+                        "upstream token count failed, using estimate: router={}, url={url}, error={err:#}",
+                        route_target.router_name
+                    );
+                    estimate_anthropic_count_tokens(request)
+                }
+            }
+        }
+    }
+}
+
+async fn fetch_upstream_count(
+    state: &Arc<AppState>,
+    route_target: &RouteTarget,
+    headers: &HeaderMap,
+    url: &str,
+    request: &Value,
+) -> Result<i64> {
+    let api_key = match route_target
+        .api_key
+        .as_ref()
+        .unwrap_or(&state.api_key)
+        .value()
+    {
+        Ok(api_key) => api_key.to_string(),
+        Err(_) if !route_target.auth_scheme.requires_api_key() => String::new(),
+        Err(reason) => return Err(anyhow!("no upstream API key: {reason}")),
+    };
+    let body = request
+        .as_object()
+        .map(|request| {
+            request
+                .iter()
+                .filter(|(key, _)| COUNT_TOKENS_FIELDS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Map<_, _>>()
+        })
+        .unwrap_or_default();
+
+    let response = route_target
+        .client
+        .as_ref()
+        .unwrap_or(&state.client)
+        .post(url)
+        .headers(crate::upstream_request_headers(
+            route_target,
+            &api_key,
+            headers,
+        ))
+        .json(&body)
+        .timeout(UPSTREAM_COUNT_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+    ["input_tokens", "total_tokens", "totalTokens"]
+        .iter()
+        .find_map(|field| response.get(field).and_then(Value::as_i64))
+        .ok_or_else(|| anyhow!("response has no `input_tokens`: {response}"))
+}
+
+pub(crate) fn estimate_anthropic_count_tokens(request: &Value) -> i64 {
+    let mut total = 0usize;
+    visit_anthropic_count_text(request, &mut |text| total += text.len());
+
+    let tokens = total / 4;
+    if tokens == 0 && request.get("messages").is_some() {
+        1
+    } else {
+        tokens as i64
+    }
+}
+
+fn visit_anthropic_count_text(request: &Value, sink: &mut dyn FnMut(&str)) {
+    visit_anthropic_token_value(request.get("system"), sink);
+
+    if let Some(messages) = request.get("messages").and_then(Value::as_array) {
+        for message in messages {
+            visit_anthropic_message(message, sink);
+        }
+    }
+
+    if let Some(tools) = request.get("tools").and_then(Value::as_array) {
+        for tool in tools {
+            visit_anthropic_token_value(tool.get("name"), sink);
+            visit_anthropic_token_value(tool.get("description"), sink);
+            visit_anthropic_token_value(tool.get("input_schema"), sink);
+        }
+    }
+}
+
+fn visit_anthropic_message(message: &Value, sink: &mut dyn FnMut(&str)) {
+    visit_anthropic_token_value(message.get("role"), sink);
+    visit_anthropic_visible_content(message.get("content"), sink);
+    visit_anthropic_token_value(message.get("name"), sink);
+    visit_anthropic_token_value(message.get("tool_use_id"), sink);
+    visit_anthropic_token_value(message.get("tool_call_id"), sink);
+}
+
+fn visit_anthropic_visible_content(value: Option<&Value>, sink: &mut dyn FnMut(&str)) {
+    let Some(value) = value else {
+        return;
+    };
+
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                visit_anthropic_content_block(item, sink);
+            }
+        }
+        Value::Object(_) => visit_anthropic_content_block(value, sink),
+        _ => visit_anthropic_token_value(Some(value), sink),
+    }
+}
+
+fn visit_anthropic_content_block(block: &Value, sink: &mut dyn FnMut(&str)) {
+    let Some(obj) = block.as_object() else {
+        visit_anthropic_token_value(Some(block), sink);
+        return;
+    };
+
+    let block_type = obj
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    if matches!(
+        block_type.as_str(),
+        "thinking" | "redacted_thinking" | "signature"
+    ) {
+        return;
+    }
+
+    if !block_type.is_empty() {
+        sink(&block_type);
+    }
+
+    visit_anthropic_token_value(obj.get("text"), sink);
+    visit_anthropic_token_value(obj.get("content"), sink);
+    visit_anthropic_token_value(obj.get("input"), sink);
+    visit_anthropic_token_value(obj.get("name"), sink);
+    visit_anthropic_token_value(obj.get("id"), sink);
+    visit_anthropic_token_value(obj.get("tool_use_id"), sink);
+    visit_anthropic_token_value(obj.get("data"), sink);
+}
+
+fn visit_anthropic_token_value(value: Option<&Value>, sink: &mut dyn FnMut(&str)) {
+    let Some(value) = value else {
+        return;
+    };
+
+    match value {
+        Value::Null => {}
+        Value::Bool(v) => sink(if *v { "true" } else { "false" }),
+        Value::Number(n) => sink(&n.to_string()),
+        Value::String(s) => sink(s),
+        Value::Array(items) => {
+            for item in items {
+                visit_anthropic_content_block(item, sink);
+            }
+        }
+        Value::Object(_) => {
+            if let Ok(serialized) = serde_json::to_string(value) {
+                sink(&serialized);
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use axum::extract::ws::Message;
+use axum::extract::ws::WebSocket;
+use axum::extract::ws::WebSocketUpgrade;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::http::header::HOST;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use futures::StreamExt;
+use serde_json::Value;
+use serde_json::json;
+use std::sync::Arc;
+use tracing::debug;
+use tracing::warn;
+
+use crate::bridge_types::SseParser;
+use crate::model::IncomingApi;
+use crate::state::AppState;
+
+pub(crate) async fn handle_responses_websocket(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    incoming_path: String,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let host_header = headers.get(HOST).and_then(|h| h.to_str().ok());
+    let resolved = {
+        let routers = state.routers.read().await;
+        routers.get_target_for_incoming_route(&incoming_path, host_header)
+    };
+    let route_target = match resolved {
+        Ok(Some(route_target)) if route_target.feature_flags.enable_responses_websocket => {
+            route_target
+        }
+        Ok(_) => {
+            warn!("router miss for websocket: host={host_header:?}, path={incoming_path}");
+            return (StatusCode::NOT_FOUND, "not found").into_response();
+        }
+        Err(err) => {
+            warn!("websocket route resolution failed: path={incoming_path}, error={err}");
+            return (StatusCode::NOT_FOUND, "not found").into_response();
+        }
+    };
+    if let Some(inbound_auth) = route_target.inbound_auth.as_ref()
+        && !inbound_auth.authorizes(&headers)
+    {
+        warn!(
+            "inbound auth rejected: router={}, incoming_route={incoming_path} (websocket), host={:?}",
+            route_target.router_name, host_header
+        );
+        return (
+            StatusCode::UNAUTHORIZED,
+            "missing or invalid bridge API key",
+        )
+            .into_response();
+    }
+
+    debug!(
+        "responses websocket accepted: router={}, incoming_route={incoming_path}",
+        route_target.router_name
+    );
+    ws.on_upgrade(move |socket| serve_responses_socket(socket, state, headers, incoming_path))
+}
+
+async fn serve_responses_socket(
+    mut socket: WebSocket,
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    incoming_path: String,
+) {
+    while let Some(message) = socket.recv().await {
+        let text = match message {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) | Err(_) => break,
+            Ok(_) => continue,
+        };
+        let body = match websocket_request_body(text.as_str()) {
+            Ok(body) => body,
+            Err(message) => {
+                let event = json!({
+                    "type": "error",
+                    "error": {
+                        "type": "invalid_request",
+                        "message": message,
+                    },
+                });
+                if send_text(&mut socket, event.to_string()).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let response = crate::handle_incoming(
+            state.clone(),
+            headers.clone(),
+            body,
+            Some(IncomingApi::Responses),
+            Some(incoming_path.clone()),
+        )
+        .await;
+        if forward_sse_events(&mut socket, response).await.is_err() {
+            break;
+        }
+    }
+    debug!("responses websocket closed: incoming_route={incoming_path}");
+}
+
+pub(crate) fn websocket_request_body(text: &str) -> Result<String, String> {
+    let mut request = serde_json::from_str::<Value>(text)
+        .map_err(|err| format!("failed to parse websocket message: {err}"))?;
+    let object = request
+        .as_object_mut()
+        .ok_or_else(|| "websocket message must be a JSON object".to_string())?;
+    match object.remove("type").as_ref().and_then(Value::as_str) {
+        Some("response.create") => {}
+        Some(other) => return Err(format!("unsupported websocket message type `{other}`")),
+        None => return Err("websocket message is missing `type`".to_string()),
+    }
+    // Some clients nest the request under `response`, as in the Realtime API.
+    if let Some(Value::Object(inner)) = object.remove("response") {
+        object.extend(inner);
+    }
+    object.insert("stream".to_string(), Value::Bool(true));
+    Ok(request.to_string())
+}
+
+async fn forward_sse_events(socket: &mut WebSocket, response: Response) -> Result<(), ()> {
+    let is_stream = response
+        .headers()
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    let status = response.status();
+    let mut body = response.into_body().into_data_stream();
+    if !is_stream {
+        let mut text = Vec::new();
+        while let Some(Ok(chunk)) = body.next().await {
+            text.extend_from_slice(&chunk);
+        }
+        let event = json!({
+            "type": "error",
+            "status": status.as_u16(),
+            "error": {
+                "type": "request_failed",
+                "message": String::from_utf8_lossy(&text),
+            },
+        });
+        return send_text(socket, event.to_string()).await;
+    }
+
+    let mut parser = SseParser::default();
+    while let Some(chunk) = body.next().await {
+        let Ok(chunk) = chunk else {
+            break;
+        };
+        for data in parser.feed(&String::from_utf8_lossy(&chunk)) {
+            if data != "[DONE]" {
+                send_text(socket, data).await?;
+            }
+        }
+    }
+    if let Some(data) = parser.finish()
+        && data != "[DONE]"
+    {
+        send_text(socket, data).await?;
+    }
+    Ok(())
+}
+
+async fn send_text(socket: &mut WebSocket, text: String) -> Result<(), ()> {
+    socket
+        .send(Message::Text(text.into()))
+        .await
+        .map_err(|_| ())
+}